@@ -90,6 +90,8 @@ const customTunnelEndpointSchema = oneOf(
         public_key: string,
         allowed_ips: arrayOf(string),
         endpoint: string,
+        preshared_key: maybe(string),
+        persistent_keepalive: maybe(number),
       }),
       ipv4_gateway: string,
       ipv6_gateway: maybe(string),
@@ -104,6 +106,7 @@ const relaySettingsSchema = oneOf(
       tunnel_protocol: constraint(enumeration('wireguard', 'openvpn')),
       wireguard_constraints: partialObject({
         port: constraint(number),
+        protocol: constraint(enumeration('udp', 'tcp')),
       }),
       openvpn_constraints: partialObject({
         port: constraint(number),
@@ -220,6 +223,13 @@ const tunnelOptionsSchema = partialObject({
   }),
 });
 
+const lanPolicySchema = partialObject({
+  allowed_networks: arrayOf(string),
+  allow_multicast: boolean,
+  allow_dhcp_server: boolean,
+  allow_link_local: boolean,
+});
+
 const accountDataSchema = partialObject({
   expiry: string,
 });
@@ -231,7 +241,7 @@ const tunnelStateSchema = oneOf(
   }),
   object({
     state: enumeration('connecting', 'connected'),
-    details: object({
+    details: partialObject({
       endpoint: partialObject({
         address: string,
         protocol: enumeration('tcp', 'udp'),
@@ -259,6 +269,7 @@ const tunnelStateSchema = oneOf(
           'no_matching_relay',
           'is_offline',
           'tap_adapter_problem',
+          'captive_portal',
         ),
       }),
       object({
@@ -315,7 +326,7 @@ export class SubscriptionListener<T> {
 
 const settingsSchema = partialObject({
   account_token: maybe(string),
-  allow_lan: boolean,
+  lan_policy: lanPolicySchema,
   auto_connect: boolean,
   block_when_disconnected: boolean,
   bridge_settings: bridgeSettingsSchema,
@@ -331,6 +342,26 @@ const keygenEventSchema = oneOf(
   }),
 );
 
+const captivePortalEventSchema = oneOf(
+  enumeration('expired', 'ended'),
+  object({
+    started: object({
+      expires: string,
+    }),
+  }),
+);
+
+const tunnelStatsSchema = object({
+  tx_bytes: number,
+  rx_bytes: number,
+  last_handshake: maybe(
+    object({
+      secs_since_epoch: number,
+      nanos_since_epoch: number,
+    }),
+  ),
+});
+
 const daemonEventSchema = oneOf(
   object({
     tunnel_state: tunnelStateSchema,
@@ -344,6 +375,12 @@ const daemonEventSchema = oneOf(
   object({
     wireguard_key: keygenEventSchema,
   }),
+  object({
+    captive_portal: captivePortalEventSchema,
+  }),
+  object({
+    tunnel_stats: tunnelStatsSchema,
+  }),
 );
 
 export class ResponseParseError extends Error {
@@ -77,7 +77,12 @@ class ApplicationMain {
   private tunnelState: TunnelState = { state: 'disconnected' };
   private settings: ISettings = {
     accountToken: undefined,
-    allowLan: false,
+    lanPolicy: {
+      allowedNetworks: [],
+      allowMulticast: false,
+      allowDhcpServer: false,
+      allowLinkLocal: false,
+    },
     autoConnect: false,
     blockWhenDisconnected: false,
     relaySettings: {
@@ -90,6 +95,7 @@ class ApplicationMain {
         },
         wireguardConstraints: {
           port: 'any',
+          protocol: 'any',
         },
       },
     },
@@ -391,13 +391,13 @@ export default class AppRenderer {
           },
         });
       } else {
-        const { port } = normal.wireguardConstraints;
+        const { port, protocol } = normal.wireguardConstraints;
         actions.settings.updateRelay({
           normal: {
             tunnelProtocol: liftConstraint(tunnelProtocol),
             location: relayLocation,
             port: port === 'any' ? port : port.only,
-            protocol: 'udp',
+            protocol: protocol === 'any' ? protocol : protocol.only,
           },
         });
       }
@@ -509,7 +509,7 @@ export default class AppRenderer {
     const reduxSettings = this.reduxActions.settings;
     const reduxAccount = this.reduxActions.account;
 
-    reduxSettings.updateAllowLan(newSettings.allowLan);
+    reduxSettings.updateAllowLan(newSettings.lanPolicy.allowedNetworks.length > 0);
     reduxSettings.updateEnableIpv6(newSettings.tunnelOptions.generic.enableIpv6);
     reduxSettings.updateBlockWhenDisconnected(newSettings.blockWhenDisconnected);
     reduxSettings.updateOpenVpnMssfix(newSettings.tunnelOptions.openvpn.mssfix);
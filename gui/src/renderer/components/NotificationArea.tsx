@@ -77,6 +77,11 @@ function getBlockReasonMessage(blockReason: BlockReason): string {
         'in-app-notifications',
         "Unable to detect a working TAP adapter on this device. If you've disabled it, enable it again. Otherwise, please reinstall the app",
       );
+    case 'captive_portal':
+      return messages.pgettext(
+        'in-app-notifications',
+        'The network requires logging in through a captive portal',
+      );
   }
 }
 
@@ -24,7 +24,8 @@ export type BlockReason =
         | 'start_tunnel_error'
         | 'no_matching_relay'
         | 'is_offline'
-        | 'tap_adapter_problem';
+        | 'tap_adapter_problem'
+        | 'captive_portal';
     }
   | { reason: 'auth_failed'; details?: string };
 
@@ -80,7 +81,9 @@ export type DaemonEvent =
   | { tunnelState: TunnelState }
   | { settings: ISettings }
   | { relayList: IRelayList }
-  | { wireguardKey: KeygenEvent };
+  | { wireguardKey: KeygenEvent }
+  | { captivePortal: CaptivePortalEvent }
+  | { tunnelStats: ITunnelStats };
 
 export interface ITunnelStateRelayInfo {
   endpoint: ITunnelEndpoint;
@@ -106,6 +109,7 @@ export interface IOpenVpnConstraints {
 
 export interface IWireguardConstraints {
   port: 'any' | { only: number };
+  protocol: 'any' | { only: RelayProtocol };
 }
 
 export type TunnelProtocol = 'wireguard' | 'openvpn';
@@ -278,9 +282,16 @@ export interface IAppVersionInfo {
   latest: string;
 }
 
+export interface ILanPolicy {
+  allowedNetworks: string[];
+  allowMulticast: boolean;
+  allowDhcpServer: boolean;
+  allowLinkLocal: boolean;
+}
+
 export interface ISettings {
   accountToken?: AccountToken;
-  allowLan: boolean;
+  lanPolicy: ILanPolicy;
   autoConnect: boolean;
   blockWhenDisconnected: boolean;
   relaySettings: RelaySettings;
@@ -291,6 +302,18 @@ export interface ISettings {
 
 export type KeygenEvent = INewWireguardKey | 'too_many_keys' | 'generation_failure';
 
+export type CaptivePortalEvent = ICaptivePortalStarted | 'expired' | 'ended';
+
+export interface ICaptivePortalStarted {
+  started: { expires: string };
+}
+
+export interface ITunnelStats {
+  txBytes: number;
+  rxBytes: number;
+  lastHandshake?: { secsSinceEpoch: number; nanosSinceEpoch: number };
+}
+
 export interface INewWireguardKey {
   newKey: string;
 }
@@ -1,5 +1,8 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{new_rpc_client, Command, Error, Result};
+use chrono::NaiveTime;
 use clap::value_t_or_exit;
+use mullvad_types::auto_connect::{AutoConnectRules, NetworkMatcher, Schedule};
+use talpid_types::net::network_info::InterfaceKind;
 
 pub struct AutoConnect;
 
@@ -23,22 +26,172 @@ impl Command for AutoConnect {
             )
             .subcommand(
                 clap::SubCommand::with_name("get")
-                    .about("Display the current auto-connect setting"),
+                    .about("Display the current auto-connect setting and rules"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("rules")
+                    .about("Connect or disconnect automatically when the connected networks change")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("trust")
+                    .about("Disconnect automatically on a network")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommands(create_matcher_subcommands())
+                    .subcommand(
+                        clap::SubCommand::with_name("current")
+                            .about("Trust the networks the device is currently connected to"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("untrust")
+                    .about("Stop trusting a network")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommands(create_matcher_subcommands()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("schedule")
+                    .about("Only secure untrusted networks during part of the day")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Set the time of day untrusted networks are secured")
+                            .arg(
+                                clap::Arg::with_name("start")
+                                    .help("Start of the window, as HH:MM in local time")
+                                    .required(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("end")
+                                    .help("End of the window, as HH:MM in local time")
+                                    .required(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear")
+                            .about("Secure untrusted networks at all times"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("networks")
+                    .about("Display the networks the device is connected to"),
             )
     }
 
     fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
-        if let Some(set_matches) = matches.subcommand_matches("set") {
-            let auto_connect = value_t_or_exit!(set_matches.value_of("policy"), String);
-            self.set(auto_connect == "on")
-        } else if let Some(_matches) = matches.subcommand_matches("get") {
-            self.get()
-        } else {
-            unreachable!("No auto-connect command given");
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let auto_connect = value_t_or_exit!(set_matches.value_of("policy"), String);
+                self.set(auto_connect == "on")
+            }
+            ("get", Some(_)) => self.get(),
+            ("rules", Some(rules_matches)) => {
+                let enabled = value_t_or_exit!(rules_matches.value_of("policy"), String) == "on";
+                self.update_rules(|rules| rules.enabled = enabled)
+            }
+            ("trust", Some(trust_matches)) => match trust_matches.subcommand() {
+                ("current", Some(_)) => self.trust_current(),
+                (name, Some(matcher_matches)) => {
+                    let matcher = parse_matcher(name, matcher_matches)?;
+                    self.update_rules(|rules| {
+                        if !rules.trusted_networks.contains(&matcher) {
+                            rules.trusted_networks.push(matcher);
+                        }
+                    })
+                }
+                _ => unreachable!("No trust command given"),
+            },
+            ("untrust", Some(untrust_matches)) => match untrust_matches.subcommand() {
+                (name, Some(matcher_matches)) => {
+                    let matcher = parse_matcher(name, matcher_matches)?;
+                    self.update_rules(|rules| {
+                        rules.trusted_networks.retain(|trusted| *trusted != matcher)
+                    })
+                }
+                _ => unreachable!("No untrust command given"),
+            },
+            ("schedule", Some(schedule_matches)) => match schedule_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let schedule = Schedule {
+                        start: parse_time(set_matches.value_of("start").unwrap())?,
+                        end: parse_time(set_matches.value_of("end").unwrap())?,
+                    };
+                    self.update_rules(|rules| rules.schedule = Some(schedule))
+                }
+                ("clear", Some(_)) => self.update_rules(|rules| rules.schedule = None),
+                _ => unreachable!("No schedule command given"),
+            },
+            ("networks", Some(_)) => self.list_networks(),
+            _ => unreachable!("No auto-connect command given"),
         }
     }
 }
 
+fn create_matcher_subcommands() -> Vec<clap::App<'static, 'static>> {
+    vec![
+        clap::SubCommand::with_name("ssid")
+            .about("A wireless network")
+            .arg(
+                clap::Arg::with_name("ssid")
+                    .help("Name of the wireless network")
+                    .required(true),
+            ),
+        clap::SubCommand::with_name("gateway")
+            .about("Any network behind a specific default gateway")
+            .arg(
+                clap::Arg::with_name("mac")
+                    .help("Hardware address of the gateway, e.g. aa:bb:cc:dd:ee:ff")
+                    .required(true),
+            ),
+        clap::SubCommand::with_name("interface")
+            .about("All networks reached through a type of interface")
+            .arg(
+                clap::Arg::with_name("kind")
+                    .required(true)
+                    .possible_values(&["wired", "wireless", "other"]),
+            ),
+    ]
+}
+
+fn parse_matcher(name: &str, matches: &clap::ArgMatches<'_>) -> Result<NetworkMatcher> {
+    match name {
+        "ssid" => Ok(NetworkMatcher::Ssid(value_t_or_exit!(
+            matches.value_of("ssid"),
+            String
+        ))),
+        "gateway" => {
+            let mac = value_t_or_exit!(matches.value_of("mac"), String);
+            let is_valid = mac.split(':').count() == 6
+                && mac
+                    .split(':')
+                    .all(|octet| octet.len() == 2 && u8::from_str_radix(octet, 16).is_ok());
+            if is_valid {
+                Ok(NetworkMatcher::GatewayMac(mac.to_lowercase()))
+            } else {
+                Err(Error::InvalidCommand("Invalid hardware address"))
+            }
+        }
+        "interface" => {
+            let kind = match matches.value_of("kind").unwrap() {
+                "wired" => InterfaceKind::Wired,
+                "wireless" => InterfaceKind::Wireless,
+                _ => InterfaceKind::Other,
+            };
+            Ok(NetworkMatcher::InterfaceKind(kind))
+        }
+        _ => unreachable!("Unknown network type"),
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| Error::InvalidCommand("Times must be given as HH:MM"))
+}
+
 impl AutoConnect {
     fn set(&self, auto_connect: bool) -> Result<()> {
         let mut rpc = new_rpc_client()?;
@@ -49,8 +202,68 @@ impl AutoConnect {
 
     fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
-        let auto_connect = rpc.get_settings()?.get_auto_connect();
+        let settings = rpc.get_settings()?;
+        let auto_connect = settings.get_auto_connect();
         println!("Autoconnect: {}", if auto_connect { "on" } else { "off" });
+
+        let rules = settings.get_auto_connect_rules();
+        println!(
+            "Auto-connect rules: {}",
+            if rules.enabled { "on" } else { "off" }
+        );
+        if rules.trusted_networks.is_empty() {
+            println!("No trusted networks");
+        }
+        for matcher in &rules.trusted_networks {
+            println!("Trusted: {}", matcher);
+        }
+        match rules.schedule {
+            Some(schedule) => println!("Securing untrusted networks during {}", schedule),
+            None => println!("Securing untrusted networks at all times"),
+        }
+        Ok(())
+    }
+
+    fn update_rules(&self, update: impl FnOnce(&mut AutoConnectRules)) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut rules = rpc.get_settings()?.get_auto_connect_rules().clone();
+        update(&mut rules);
+        rpc.set_auto_connect_rules(rules)?;
+        println!("Changed auto-connect rules");
+        Ok(())
+    }
+
+    fn trust_current(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let network_info = rpc.get_network_info()?;
+        let mut rules = rpc.get_settings()?.get_auto_connect_rules().clone();
+        for network in network_info.networks {
+            let matcher = match (network.ssid, network.gateway_mac) {
+                (Some(ssid), _) => NetworkMatcher::Ssid(ssid),
+                (None, Some(gateway_mac)) => NetworkMatcher::GatewayMac(gateway_mac),
+                (None, None) => {
+                    eprintln!("Unable to identify the network on {}", network.interface);
+                    continue;
+                }
+            };
+            println!("Trusting {}", matcher);
+            if !rules.trusted_networks.contains(&matcher) {
+                rules.trusted_networks.push(matcher);
+            }
+        }
+        rpc.set_auto_connect_rules(rules)?;
+        Ok(())
+    }
+
+    fn list_networks(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let network_info = rpc.get_network_info()?;
+        if network_info.is_empty() {
+            println!("Not connected to any network");
+        }
+        for network in network_info.networks {
+            println!("{}", network);
+        }
         Ok(())
     }
 }
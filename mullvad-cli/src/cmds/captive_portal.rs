@@ -0,0 +1,68 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t;
+use mullvad_types::captive_portal::{CaptivePortalEvent, MAX_MINUTES};
+
+pub struct CaptivePortal;
+
+impl Command for CaptivePortal {
+    fn name(&self) -> &'static str {
+        "captive-portal"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Let the traffic needed to log in to a captive portal through while blocking")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("allow")
+                    .about(
+                        "Allow HTTP, HTTPS and DNS to the local network for a limited time. \
+                         Disconnects any open tunnel",
+                    )
+                    .arg(
+                        clap::Arg::with_name("minutes")
+                            .help("For how many minutes to allow the traffic, at most 30")
+                            .default_value("5"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("end")
+                    .about("Stop allowing captive portal login traffic before the time runs out"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(allow_matches) = matches.subcommand_matches("allow") {
+            let minutes =
+                value_t!(allow_matches.value_of("minutes"), u32).unwrap_or_else(|e| e.exit());
+            if minutes == 0 || minutes > MAX_MINUTES {
+                clap::Error::with_description(
+                    &format!("minutes must be between 1 and {}", MAX_MINUTES),
+                    clap::ErrorKind::ValueValidation,
+                )
+                .exit();
+            }
+            self.allow(minutes)
+        } else if let Some(_matches) = matches.subcommand_matches("end") {
+            self.end()
+        } else {
+            unreachable!("No captive-portal command given");
+        }
+    }
+}
+
+impl CaptivePortal {
+    fn allow(&self, minutes: u32) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let expires = rpc.allow_captive_portal(minutes)?;
+        println!("{}", CaptivePortalEvent::Started { expires });
+        Ok(())
+    }
+
+    fn end(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.end_captive_portal()?;
+        println!("{}", CaptivePortalEvent::Ended);
+        Ok(())
+    }
+}
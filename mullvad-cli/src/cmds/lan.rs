@@ -1,5 +1,7 @@
 use crate::{new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
+use ipnetwork::IpNetwork;
+use talpid_types::net::lan::LanPolicy;
 
 pub struct Lan;
 
@@ -14,7 +16,7 @@ impl Command for Lan {
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(
                 clap::SubCommand::with_name("set")
-                    .about("Change allow LAN setting")
+                    .about("Allow or block all local network sharing")
                     .arg(
                         clap::Arg::with_name("policy")
                             .required(true)
@@ -25,20 +27,102 @@ impl Command for Lan {
                 clap::SubCommand::with_name("get")
                     .about("Display the current local network sharing setting"),
             )
+            .subcommand(
+                clap::SubCommand::with_name("network")
+                    .about("Manage the private networks that local network sharing is allowed for")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Allow sharing with a private network")
+                            .arg(create_network_arg()),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Stop allowing sharing with a private network")
+                            .arg(create_network_arg()),
+                    ),
+            )
+            .subcommand(create_toggle_subcommand(
+                "multicast",
+                "Allow or block outgoing multicast traffic, such as mDNS and SSDP",
+            ))
+            .subcommand(create_toggle_subcommand(
+                "dhcp-server",
+                "Allow or block acting as a DHCP server on the local network",
+            ))
+            .subcommand(create_toggle_subcommand(
+                "link-local",
+                "Allow or block traffic to and from link-local addresses",
+            ))
     }
 
     fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
-        if let Some(set_matches) = matches.subcommand_matches("set") {
-            let allow_lan = value_t_or_exit!(set_matches.value_of("policy"), String);
-            self.set(allow_lan == "allow")
-        } else if let Some(_matches) = matches.subcommand_matches("get") {
-            self.get()
-        } else {
-            unreachable!("No lan command given");
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let allow_lan = value_t_or_exit!(set_matches.value_of("policy"), String);
+                self.set(allow_lan == "allow")
+            }
+            ("get", Some(_)) => self.get(),
+            ("network", Some(network_matches)) => match network_matches.subcommand() {
+                ("add", Some(add_matches)) => {
+                    let network = value_t_or_exit!(add_matches.value_of("network"), IpNetwork);
+                    self.update_policy(|policy| {
+                        if !policy.allowed_networks.contains(&network) {
+                            policy.allowed_networks.push(network);
+                        }
+                    })
+                }
+                ("remove", Some(remove_matches)) => {
+                    let network = value_t_or_exit!(remove_matches.value_of("network"), IpNetwork);
+                    self.update_policy(|policy| {
+                        policy
+                            .allowed_networks
+                            .retain(|allowed| *allowed != network)
+                    })
+                }
+                _ => unreachable!("No lan network command given"),
+            },
+            ("multicast", Some(toggle_matches)) => {
+                let allow = is_allow(toggle_matches);
+                self.update_policy(|policy| policy.allow_multicast = allow)
+            }
+            ("dhcp-server", Some(toggle_matches)) => {
+                let allow = is_allow(toggle_matches);
+                self.update_policy(|policy| policy.allow_dhcp_server = allow)
+            }
+            ("link-local", Some(toggle_matches)) => {
+                let allow = is_allow(toggle_matches);
+                self.update_policy(|policy| policy.allow_link_local = allow)
+            }
+            _ => unreachable!("No lan command given"),
         }
     }
 }
 
+fn create_network_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("network")
+        .help(
+            "A private network in CIDR notation, e.g. 192.168.1.0/24. Unique local IPv6 networks, \
+             e.g. fd00::/8, are only allowed when added here",
+        )
+        .required(true)
+}
+
+fn create_toggle_subcommand(
+    name: &'static str,
+    about: &'static str,
+) -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(name).about(about).arg(
+        clap::Arg::with_name("policy")
+            .required(true)
+            .possible_values(&["allow", "block"]),
+    )
+}
+
+fn is_allow(matches: &clap::ArgMatches<'_>) -> bool {
+    value_t_or_exit!(matches.value_of("policy"), String) == "allow"
+}
+
 impl Lan {
     fn set(&self, allow_lan: bool) -> Result<()> {
         let mut rpc = new_rpc_client()?;
@@ -47,13 +131,19 @@ impl Lan {
         Ok(())
     }
 
+    fn update_policy(&self, update: impl FnOnce(&mut LanPolicy)) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut lan_policy = rpc.get_settings()?.get_lan_policy().clone();
+        update(&mut lan_policy);
+        rpc.set_lan_policy(lan_policy)?;
+        println!("Changed local network sharing setting");
+        Ok(())
+    }
+
     fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
-        let allow_lan = rpc.get_settings()?.get_allow_lan();
-        println!(
-            "Local network sharing setting: {}",
-            if allow_lan { "allow" } else { "block" }
-        );
+        let lan_policy = rpc.get_settings()?.get_lan_policy().clone();
+        println!("Local network sharing setting: {}", lan_policy);
         Ok(())
     }
 }
@@ -0,0 +1,117 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use std::path::PathBuf;
+use talpid_types::net::dns::LocalResolverSettings;
+
+pub struct LocalResolver;
+
+impl Command for LocalResolver {
+    fn name(&self) -> &'static str {
+        "local-resolver"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Resolve DNS with a local resolver that forwards queries through the tunnel and \
+                 blocks domains listed in blocklists",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Enable or disable the local resolver")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("blocklist")
+                    .about("Manage the blocklists used by the local resolver")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about(
+                                "Block the domains listed in a file. Either in hosts file format \
+                                 or with one domain per line",
+                            )
+                            .arg(create_path_arg()),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Stop using a blocklist")
+                            .arg(create_path_arg()),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear").about("Stop using all blocklists"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current local resolver settings"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let enabled = value_t_or_exit!(set_matches.value_of("policy"), String) == "on";
+                self.update(|local_resolver| local_resolver.enabled = enabled)
+            }
+            ("blocklist", Some(blocklist_matches)) => self.run_blocklist(blocklist_matches),
+            ("get", Some(_)) => self.get(),
+            _ => unreachable!("No local-resolver command given"),
+        }
+    }
+}
+
+fn create_path_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("path")
+        .help("Path to the blocklist file")
+        .required(true)
+}
+
+impl LocalResolver {
+    fn run_blocklist(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => {
+                let path = value_t_or_exit!(add_matches.value_of("path"), PathBuf);
+                // The daemon runs in another directory, so it needs the absolute path.
+                let path = path
+                    .canonicalize()
+                    .map_err(|_| Error::InvalidCommand("The blocklist file does not exist"))?;
+                self.update(|local_resolver| {
+                    if !local_resolver.blocklists.contains(&path) {
+                        local_resolver.blocklists.push(path);
+                    }
+                })
+            }
+            ("remove", Some(remove_matches)) => {
+                let path = value_t_or_exit!(remove_matches.value_of("path"), PathBuf);
+                let path = path.canonicalize().unwrap_or(path);
+                self.update(|local_resolver| {
+                    local_resolver.blocklists.retain(|other| *other != path)
+                })
+            }
+            ("clear", Some(_)) => self.update(|local_resolver| local_resolver.blocklists.clear()),
+            _ => unreachable!("No blocklist command given"),
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut LocalResolverSettings)) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut local_resolver = rpc.get_settings()?.get_local_resolver().clone();
+        update(&mut local_resolver);
+        rpc.set_local_resolver(local_resolver)?;
+        println!("Changed local resolver setting");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let local_resolver = rpc.get_settings()?.get_local_resolver().clone();
+        println!("Local resolver: {}", local_resolver);
+        Ok(())
+    }
+}
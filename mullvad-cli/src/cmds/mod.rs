@@ -10,6 +10,9 @@ pub use self::auto_connect::AutoConnect;
 mod bridge;
 pub use self::bridge::Bridge;
 
+mod captive_portal;
+pub use self::captive_portal::CaptivePortal;
+
 mod status;
 pub use self::status::Status;
 
@@ -28,12 +31,24 @@ pub use self::relay::Relay;
 mod lan;
 pub use self::lan::Lan;
 
+mod local_resolver;
+pub use self::local_resolver::LocalResolver;
+
+mod port_forward;
+pub use self::port_forward::PortForward;
+
 mod reset;
 pub use self::reset::Reset;
 
+mod split_dns;
+pub use self::split_dns::SplitDns;
+
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
+mod tunnel_stats;
+pub use self::tunnel_stats::TunnelStats;
+
 mod version;
 pub use self::version::Version;
 
@@ -44,13 +59,18 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(AutoConnect),
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
+        Box::new(CaptivePortal),
         Box::new(Connect),
         Box::new(Disconnect),
         Box::new(Lan),
+        Box::new(LocalResolver),
+        Box::new(PortForward),
         Box::new(Relay),
         Box::new(Reset),
+        Box::new(SplitDns),
         Box::new(Status),
         Box::new(Tunnel),
+        Box::new(TunnelStats),
         Box::new(Version),
     ];
     let mut map = HashMap::new();
@@ -0,0 +1,101 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use mullvad_types::port_forward;
+
+pub struct PortForward;
+
+impl Command for PortForward {
+    fn name(&self) -> &'static str {
+        "port-forward"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage ports forwarded through the tunnel to this device")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Request a new port to be forwarded on the relays in a city")
+                    .arg(
+                        clap::Arg::with_name("country")
+                            .help("The two letter country code of the relays")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("city")
+                            .help("The three letter city code of the relays")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("Display the ports forwarded for the current account"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Release a forwarded port")
+                    .arg(
+                        clap::Arg::with_name("country")
+                            .help("The two letter country code of the relays")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("city")
+                            .help("The three letter city code of the relays")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("port")
+                            .help("The forwarded port to release")
+                            .required(true),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(add_matches) = matches.subcommand_matches("add") {
+            let country_code = value_t_or_exit!(add_matches.value_of("country"), String);
+            let city_code = value_t_or_exit!(add_matches.value_of("city"), String);
+            self.add(country_code, city_code)
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list()
+        } else if let Some(remove_matches) = matches.subcommand_matches("remove") {
+            let port_forward = port_forward::PortForward {
+                port: value_t_or_exit!(remove_matches.value_of("port"), u16),
+                country_code: value_t_or_exit!(remove_matches.value_of("country"), String),
+                city_code: value_t_or_exit!(remove_matches.value_of("city"), String),
+            };
+            self.remove(port_forward)
+        } else {
+            unreachable!("No port-forward command given");
+        }
+    }
+}
+
+impl PortForward {
+    fn add(&self, country_code: String, city_code: String) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let port_forward = rpc.request_port_forward(country_code, city_code)?;
+        println!("Forwarded {}", port_forward);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let port_forwards = rpc.list_port_forwards()?;
+        if port_forwards.is_empty() {
+            println!("No ports are forwarded");
+        }
+        for port_forward in port_forwards {
+            println!("{}", port_forward);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, port_forward: port_forward::PortForward) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.release_port_forward(port_forward.clone())?;
+        println!("Released {}", port_forward);
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{location, new_rpc_client, Command, Error, Result};
 use clap::{value_t, values_t};
 use std::{
+    fs,
     io::{self, BufRead},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
@@ -11,6 +12,7 @@ use mullvad_types::{
         Constraint, OpenVpnConstraints, RelayConstraintsUpdate, RelaySettingsUpdate,
         TunnelProtocol, WireguardConstraints,
     },
+    wg_quick::WgQuickConfig,
     ConnectionConfig, CustomTunnelEndpoint,
 };
 use talpid_types::net::{all_of_the_internet, openvpn, wireguard, Endpoint, TransportProtocol};
@@ -39,13 +41,13 @@ impl Command for Relay {
                                 .arg(
                                     clap::Arg::with_name("host")
                                         .help("Hostname or IP")
-                                        .required(true)
+                                        .required_unless("from-file")
                                         .index(1),
                                 )
                                 .arg(
                                     clap::Arg::with_name("port")
                                         .help("Remote network port")
-                                        .required(true)
+                                        .required_unless("from-file")
                                         .index(2),
                                 )
                                 .arg(
@@ -74,6 +76,36 @@ impl Command for Relay {
                                         .takes_value(true)
                                         .multiple(true)
                                         .required(false),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("keepalive")
+                                        .help("Seconds between keepalive packets sent to the relay")
+                                        .long("keepalive")
+                                        .takes_value(true)
+                                        .required(false),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("preshared-key")
+                                        .help("Read a base64 encoded preshared key from standard \
+                                               input, after the private key")
+                                        .long("preshared-key")
+                                        .required(false),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("from-file")
+                                        .help("Read the relay from a wg-quick configuration file")
+                                        .long("from-file")
+                                        .takes_value(true)
+                                        .conflicts_with_all(&[
+                                            "host",
+                                            "port",
+                                            "peer-key",
+                                            "v4-gateway",
+                                            "v6-gateway",
+                                            "addr",
+                                            "keepalive",
+                                            "preshared-key",
+                                        ]),
                                 ),
                             )
                             .subcommand(clap::SubCommand::with_name("openvpn")
@@ -134,6 +166,10 @@ impl Command for Relay {
                     ),
             )
             .subcommand(clap::SubCommand::with_name("get"))
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about("Print the custom WireGuard relay as a wg-quick configuration file"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("list").about("List available countries and cities"),
             )
@@ -148,6 +184,8 @@ impl Command for Relay {
             self.set(set_matches)
         } else if matches.subcommand_matches("get").is_some() {
             self.get()
+        } else if matches.subcommand_matches("export").is_some() {
+            self.export()
         } else if matches.subcommand_matches("list").is_some() {
             self.list()
         } else if matches.subcommand_matches("update").is_some() {
@@ -179,6 +217,11 @@ impl Relay {
     }
 
     fn set_custom(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let ("wireguard", Some(wg_matches)) = matches.subcommand() {
+            if let Some(path) = wg_matches.value_of("from-file") {
+                return self.import_wireguard_config(path);
+            }
+        }
         let custom_endpoint = match matches.subcommand() {
             ("openvpn", Some(openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
             ("wireguard", Some(wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
@@ -227,6 +270,22 @@ impl Relay {
         }
         let private_key = Self::validate_wireguard_key(&private_key_str).into();
         let peer_public_key = Self::validate_wireguard_key(&peer_key_str).into();
+        let preshared_key = if matches.is_present("preshared-key") {
+            let mut preshared_key_str = String::new();
+            println!("Reading preshared key from standard input");
+            let _ = io::stdin().lock().read_line(&mut preshared_key_str);
+            Some(Self::validate_wireguard_key(&preshared_key_str).into())
+        } else {
+            None
+        };
+        let persistent_keepalive = match value_t!(matches.value_of("keepalive"), u16) {
+            Ok(0) => None,
+            Ok(seconds) => Some(seconds),
+            Err(e) => match e.kind {
+                clap::ErrorKind::ArgumentNotFound => None,
+                _ => e.exit(),
+            },
+        };
 
 
         CustomTunnelEndpoint::new(
@@ -240,13 +299,27 @@ impl Relay {
                     public_key: peer_public_key,
                     allowed_ips: all_of_the_internet(),
                     endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                    preshared_key,
+                    persistent_keepalive,
                 },
                 ipv4_gateway,
                 ipv6_gateway,
+                protocol: TransportProtocol::Udp,
             }),
         )
     }
 
+    fn import_wireguard_config(&self, path: &str) -> Result<()> {
+        let config_str =
+            fs::read_to_string(path).map_err(|e| Error::ReadFile(path.to_owned(), e))?;
+        WgQuickConfig::from_str(&config_str).map_err(Error::InvalidWireguardConfig)?;
+
+        let mut rpc = new_rpc_client()?;
+        rpc.import_wireguard_config(config_str)?;
+        println!("Relay constraints updated");
+        Ok(())
+    }
+
     fn validate_wireguard_key(key_str: &str) -> [u8; 32] {
         let key_bytes = base64::decode(key_str.trim()).unwrap_or_else(|e| {
             eprintln!("Failed to decode wireguard key: {}", e);
@@ -282,13 +355,10 @@ impl Relay {
 
         match vpn_protocol {
             "wireguard" => {
-                if let Constraint::Only(TransportProtocol::Tcp) = protocol {
-                    return Err(Error::InvalidCommand("WireGuard does not support TCP"));
-                }
                 self.update_constraints(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
                     location: None,
                     tunnel_protocol: Some(Constraint::Only(TunnelProtocol::Wireguard)),
-                    wireguard_constraints: Some(WireguardConstraints { port }),
+                    wireguard_constraints: Some(WireguardConstraints { port, protocol }),
                     ..Default::default()
                 }))
             }
@@ -312,6 +382,12 @@ impl Relay {
         Ok(())
     }
 
+    fn export(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        print!("{}", rpc.export_wireguard_config()?);
+        Ok(())
+    }
+
     fn list(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
         let mut locations = rpc.get_relay_locations()?;
@@ -0,0 +1,91 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use talpid_types::net::dns::SplitDns as SplitDnsConfig;
+
+pub struct SplitDns;
+
+impl Command for SplitDns {
+    fn name(&self) -> &'static str {
+        "split-dns"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Resolve some domains with the DNS servers of the physical network instead of \
+                 through the tunnel. Requires systemd-resolved",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Resolve a domain, and all names below it, outside the tunnel")
+                    .arg(create_domain_arg()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Resolve a domain through the tunnel again")
+                    .arg(create_domain_arg()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("clear")
+                    .about("Resolve all domains through the tunnel"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the domains that are resolved outside the tunnel"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => {
+                let domain = parse_domain(add_matches);
+                self.update(|split_dns| {
+                    if !split_dns.domains.contains(&domain) {
+                        split_dns.domains.push(domain);
+                    }
+                })
+            }
+            ("remove", Some(remove_matches)) => {
+                let domain = parse_domain(remove_matches);
+                self.update(|split_dns| split_dns.domains.retain(|other| *other != domain))
+            }
+            ("clear", Some(_)) => self.update(|split_dns| split_dns.domains.clear()),
+            ("get", Some(_)) => self.get(),
+            _ => unreachable!("No split-dns command given"),
+        }
+    }
+}
+
+fn create_domain_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("domain")
+        .help("A domain name, e.g. corp.example")
+        .required(true)
+}
+
+/// Accepts domains written as routing domains or fully qualified names, such as `~corp.example`
+/// and `corp.example.`, and returns them in the form the daemon expects.
+fn parse_domain(matches: &clap::ArgMatches<'_>) -> String {
+    value_t_or_exit!(matches.value_of("domain"), String)
+        .trim_start_matches('~')
+        .trim_matches('.')
+        .to_lowercase()
+}
+
+impl SplitDns {
+    fn update(&self, update: impl FnOnce(&mut SplitDnsConfig)) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut split_dns = rpc.get_settings()?.get_split_dns().clone();
+        update(&mut split_dns);
+        rpc.set_split_dns(split_dns)?;
+        println!("Changed split DNS setting");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let split_dns = rpc.get_settings()?.get_split_dns().clone();
+        println!("Split DNS: {}", split_dns);
+        Ok(())
+    }
+}
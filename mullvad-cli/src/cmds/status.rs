@@ -2,7 +2,8 @@ use crate::{new_rpc_client, Command, Error, Result};
 use futures::{Future, Stream};
 use mullvad_ipc_client::DaemonRpcClient;
 use mullvad_types::{auth_failed::AuthFailed, states::TunnelState, DaemonEvent};
-use talpid_types::tunnel::BlockReason;
+use std::time::Duration;
+use talpid_types::tunnel::{BlockReason, ConnectionDiagnostics};
 
 pub struct Status;
 
@@ -14,6 +15,12 @@ impl Command for Status {
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
             .about("View the state of the VPN tunnel")
+            .arg(
+                clap::Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("Also show connection attempts, timings and the last error"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("listen")
                     .about("Listen for VPN tunnel state changes")
@@ -29,8 +36,11 @@ impl Command for Status {
         let mut rpc = new_rpc_client()?;
         let state = rpc.get_state()?;
 
-        print_state(&state);
+        print_state(&state, matches.is_present("verbose"));
         print_location(&mut rpc)?;
+        if matches.is_present("verbose") {
+            print_diagnostics(&mut rpc)?;
+        }
         if let Some(listen_matches) = matches.subcommand_matches("listen") {
             let verbose = listen_matches.is_present("verbose");
             let subscription = rpc
@@ -40,7 +50,7 @@ impl Command for Status {
             for event in subscription.wait() {
                 match event? {
                     DaemonEvent::TunnelState(new_state) => {
-                        print_state(&new_state);
+                        print_state(&new_state, verbose);
                         use self::TunnelState::*;
                         match new_state {
                             Connected { .. } | Disconnected => print_location(&mut rpc)?,
@@ -62,6 +72,14 @@ impl Command for Status {
                             println!("{}", key_event);
                         }
                     }
+                    DaemonEvent::CaptivePortal(event) => {
+                        println!("{}", event);
+                    }
+                    DaemonEvent::TunnelStats(stats) => {
+                        if verbose {
+                            println!("Tunnel statistics: {:?}", stats);
+                        }
+                    }
                 }
             }
         }
@@ -69,20 +87,85 @@ impl Command for Status {
     }
 }
 
-fn print_state(state: &TunnelState) {
+fn print_state(state: &TunnelState, verbose: bool) {
     use self::TunnelState::*;
     print!("Tunnel status: ");
     match state {
         Blocked(reason) => print_blocked_reason(reason),
-        Connected { endpoint, .. } => {
+        Connected {
+            endpoint,
+            forwarded_ports,
+            diagnostics,
+            ..
+        } => {
             println!("Connected to {}", endpoint);
+            for port_forward in forwarded_ports {
+                println!("Forwarded port: {}", port_forward.port);
+            }
+            if verbose {
+                print_connection_diagnostics(diagnostics);
+            }
+        }
+        Connecting {
+            endpoint,
+            diagnostics,
+            ..
+        } => {
+            println!("Connecting to {}...", endpoint);
+            if verbose {
+                print_connection_diagnostics(diagnostics);
+            }
         }
-        Connecting { endpoint, .. } => println!("Connecting to {}...", endpoint),
         Disconnected => println!("Disconnected"),
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
 
+fn print_connection_diagnostics(diagnostics: &ConnectionDiagnostics) {
+    println!("Attempt: {}", diagnostics.retry_attempt + 1);
+    match diagnostics.connect_duration {
+        Some(duration) => println!("Connected after: {}", format_duration(duration)),
+        None => println!("Connecting for: {}", format_duration(diagnostics.elapsed())),
+    }
+    if let Some(failure) = &diagnostics.previous_failure {
+        println!("Previous failure: {}", failure);
+    }
+    if let Some(ipv6_leak_check) = &diagnostics.ipv6_leak_check {
+        println!("IPv6 leak check: {}", ipv6_leak_check);
+    }
+    if let Some(backend) = diagnostics.wireguard_backend {
+        println!("WireGuard backend: {}", backend);
+    }
+}
+
+fn print_diagnostics(rpc: &mut DaemonRpcClient) -> Result<()> {
+    let diagnostics = rpc.get_diagnostics()?;
+    println!("Target state: {:?}", diagnostics.target_state);
+    println!(
+        "Time in state: {}",
+        format_duration(diagnostics.time_in_state)
+    );
+    match diagnostics.last_error {
+        Some(error) => println!("Last error: {}", error),
+        None => println!("Last error: none"),
+    }
+    if let Some(dns_backend) = diagnostics.dns_backend {
+        println!("DNS backend: {}", dns_backend);
+    }
+    Ok(())
+}
+
+pub(super) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 * 60 {
+        format!("{}h {}m {}s", secs / (60 * 60), secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}.{}s", secs, duration.subsec_millis() / 100)
+    }
+}
+
 fn print_blocked_reason(reason: &BlockReason) {
     match reason {
         BlockReason::AuthFailed(ref auth_failure) => {
@@ -1,7 +1,12 @@
 use crate::{new_rpc_client, Command, Result};
-use clap::value_t;
+use clap::{value_t, value_t_or_exit};
 
 use mullvad_types::settings::TunnelOptions;
+use std::net::IpAddr;
+use talpid_types::net::{
+    dns::{DnsUpstream, EncryptedDnsProtocol, EncryptedDnsServer},
+    wireguard::{Backend, PublicKey},
+};
 
 pub struct Tunnel;
 
@@ -17,6 +22,7 @@ impl Command for Tunnel {
             .subcommand(create_openvpn_subcommand())
             .subcommand(create_wireguard_subcommand())
             .subcommand(create_ipv6_subcommand())
+            .subcommand(create_dns_subcommand())
     }
 
     fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -24,6 +30,7 @@ impl Command for Tunnel {
             ("openvpn", Some(openvpn_matches)) => Self::handle_openvpn_cmd(openvpn_matches),
             ("wireguard", Some(wg_matches)) => Self::handle_wireguard_cmd(wg_matches),
             ("ipv6", Some(ipv6_matches)) => Self::handle_ipv6_cmd(ipv6_matches),
+            ("dns", Some(dns_matches)) => Self::handle_dns_cmd(dns_matches),
             _ => {
                 unreachable!("unhandled comand");
             }
@@ -37,6 +44,9 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
         .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_rotation_interval_subcommand())
+        .subcommand(create_wireguard_backend_subcommand())
+        .subcommand(create_wireguard_keepalive_subcommand())
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
@@ -48,6 +58,16 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
         .subcommand(
             clap::SubCommand::with_name("set").arg(clap::Arg::with_name("mtu").required(true)),
         )
+        .subcommand(
+            clap::SubCommand::with_name("auto")
+                .about("Pick the MTU from the path to the relay while no MTU is set")
+                .arg(
+                    clap::Arg::with_name("enable")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
 }
 
 fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
@@ -56,8 +76,78 @@ fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::SubCommand::with_name("check"))
         .subcommand(clap::SubCommand::with_name("generate"))
+        .subcommand(
+            clap::SubCommand::with_name("list").about("List the keys registered to the account"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("revoke")
+                .about("Remove a key from the account")
+                .arg(
+                    clap::Arg::with_name("public_key")
+                        .help("The public key to remove")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("regenerate")
+                .about("Generate a new key for this device that replaces an existing key")
+                .arg(
+                    clap::Arg::with_name("replace")
+                        .long("replace")
+                        .takes_value(true)
+                        .value_name("PUBLIC_KEY")
+                        .help("The key to replace. Defaults to the key of this device"),
+                ),
+        )
+}
+
+fn create_wireguard_rotation_interval_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("rotation-interval")
+        .about("Configure how often the wireguard key is replaced")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset").about("Never replace the key"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("days")
+                    .help("Number of days after which the key is replaced")
+                    .required(true),
+            ),
+        )
+}
+
+fn create_wireguard_backend_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("backend")
+        .about("Configure which WireGuard implementation runs the tunnel")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("unset")
+                .about("Use the kernel module when it is available, and wireguard-go otherwise"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("backend")
+                    .required(true)
+                    .possible_values(&["kernel", "wireguard-go"]),
+            ),
+        )
 }
 
+fn create_wireguard_keepalive_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("keepalive")
+        .about("Configure how often keepalive packets are sent to the relay")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset").about("Don't send keepalive packets"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("seconds")
+                    .help("Number of seconds between the keepalive packets")
+                    .required(true),
+            ),
+        )
+}
 
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("openvpn")
@@ -91,6 +181,51 @@ fn create_ipv6_subcommand() -> clap::App<'static, 'static> {
         )
 }
 
+fn create_dns_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("dns")
+        .about("Configure where DNS queries are sent while connected")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    clap::SubCommand::with_name("gateway")
+                        .about("Send plain DNS queries to the DNS server of the relay"),
+                )
+                .subcommand(create_encrypted_dns_subcommand(
+                    "https",
+                    "Send DNS-over-HTTPS queries through the tunnel to the given server",
+                ))
+                .subcommand(create_encrypted_dns_subcommand(
+                    "tls",
+                    "Send DNS-over-TLS queries through the tunnel to the given server",
+                )),
+        )
+}
+
+fn create_encrypted_dns_subcommand(
+    name: &'static str,
+    about: &'static str,
+) -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            clap::Arg::with_name("hostname")
+                .help("The name the certificate of the server is issued for")
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("address")
+                .help("The IP address of the server")
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("port")
+                .help("The port of the server, if it doesn't use the default port"),
+        )
+}
+
 impl Tunnel {
     fn handle_openvpn_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
@@ -114,12 +249,37 @@ impl Tunnel {
                 ("get", _) => Self::process_wireguard_mtu_get(),
                 ("set", Some(matches)) => Self::process_wireguard_mtu_set(matches),
                 ("unset", _) => Self::process_wireguard_mtu_unset(),
+                ("auto", Some(matches)) => Self::process_wireguard_mtu_auto(matches),
                 _ => unreachable!("unhandled command"),
             },
 
             ("key", Some(matches)) => match matches.subcommand() {
                 ("check", _) => Self::process_wireguard_key_check(),
                 ("generate", _) => Self::process_wireguard_key_generate(),
+                ("list", _) => Self::process_wireguard_key_list(),
+                ("revoke", Some(matches)) => Self::process_wireguard_key_revoke(matches),
+                ("regenerate", Some(matches)) => Self::process_wireguard_key_regenerate(matches),
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("rotation-interval", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_rotation_interval_get(),
+                ("set", Some(matches)) => Self::process_wireguard_rotation_interval_set(matches),
+                ("unset", _) => Self::process_wireguard_rotation_interval_unset(),
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("backend", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_backend_get(),
+                ("set", Some(matches)) => Self::process_wireguard_backend_set(matches),
+                ("unset", _) => Self::process_wireguard_backend_unset(),
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("keepalive", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_keepalive_get(),
+                ("set", Some(matches)) => Self::process_wireguard_keepalive_set(matches),
+                ("unset", _) => Self::process_wireguard_keepalive_unset(),
                 _ => unreachable!("unhandled command"),
             },
             _ => unreachable!("unhandled command"),
@@ -130,11 +290,11 @@ impl Tunnel {
         let tunnel_options = Self::get_tunnel_options()?;
         println!(
             "mtu: {}",
-            tunnel_options
-                .wireguard
-                .mtu
-                .map(|mtu| mtu.to_string())
-                .unwrap_or_else(|| "unset".to_owned())
+            match tunnel_options.wireguard.mtu {
+                Some(mtu) => mtu.to_string(),
+                None if tunnel_options.wireguard.auto_mtu => "auto".to_owned(),
+                None => "unset".to_owned(),
+            }
         );
         Ok(())
     }
@@ -154,6 +314,70 @@ impl Tunnel {
         Ok(())
     }
 
+    fn process_wireguard_mtu_auto(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let enabled = matches.value_of("enable").unwrap() == "on";
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_auto_mtu(enabled)?;
+        println!("Wireguard auto MTU setting has been updated");
+        Ok(())
+    }
+
+    fn process_wireguard_backend_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!(
+            "backend: {}",
+            tunnel_options
+                .wireguard
+                .backend
+                .map(|backend| backend.to_string())
+                .unwrap_or_else(|| "unset".to_owned())
+        );
+        Ok(())
+    }
+
+    fn process_wireguard_backend_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let backend = match matches.value_of("backend").unwrap() {
+            "kernel" => Backend::Kernel,
+            "wireguard-go" => Backend::WireguardGo,
+            _ => unreachable!("invalid backend"),
+        };
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_backend(Some(backend))?;
+        println!("Wireguard backend has been updated");
+        Ok(())
+    }
+
+    fn process_wireguard_backend_unset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_backend(None)?;
+        println!("Wireguard backend has been unset");
+        Ok(())
+    }
+
+    fn process_wireguard_keepalive_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        match tunnel_options.wireguard.persistent_keepalive {
+            Some(seconds) => println!("keepalive: {} seconds", seconds),
+            None => println!("keepalive: unset"),
+        }
+        Ok(())
+    }
+
+    fn process_wireguard_keepalive_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let seconds = value_t!(matches.value_of("seconds"), u16).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_keepalive(Some(seconds))?;
+        println!("Wireguard keepalive interval has been updated");
+        Ok(())
+    }
+
+    fn process_wireguard_keepalive_unset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_keepalive(None)?;
+        println!("Wireguard keepalive interval has been unset");
+        Ok(())
+    }
+
     fn process_wireguard_key_check() -> Result<()> {
         let mut rpc = new_rpc_client()?;
         match rpc.get_wireguard_key()? {
@@ -180,6 +404,66 @@ impl Tunnel {
         Ok(())
     }
 
+    fn process_wireguard_key_list() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let current_key = rpc.get_wireguard_key()?;
+        for key in rpc.list_wireguard_keys()? {
+            let marker = if Some(&key.public_key) == current_key.as_ref() {
+                " (this device)"
+            } else {
+                ""
+            };
+            println!("{}  created {}{}", key.public_key, key.created, marker);
+        }
+        Ok(())
+    }
+
+    fn process_wireguard_key_revoke(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let public_key = value_t_or_exit!(matches.value_of("public_key"), PublicKey);
+        let mut rpc = new_rpc_client()?;
+        rpc.revoke_wireguard_key(public_key)?;
+        println!("Key has been removed from the account");
+        Ok(())
+    }
+
+    fn process_wireguard_key_regenerate(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let old_key = match matches.value_of("replace") {
+            Some(_) => Some(value_t_or_exit!(matches.value_of("replace"), PublicKey)),
+            None => rpc.get_wireguard_key()?,
+        };
+        let result = match old_key {
+            Some(old_key) => rpc.replace_wireguard_key(old_key)?,
+            None => rpc.generate_wireguard_key()?,
+        };
+        println!("{}", result);
+        Ok(())
+    }
+
+    fn process_wireguard_rotation_interval_get() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        match rpc.get_settings()?.get_wireguard_key_rotation_interval() {
+            Some(days) => println!("Rotation interval: {} days", days),
+            None => println!("Rotation interval: unset"),
+        }
+        Ok(())
+    }
+
+    fn process_wireguard_rotation_interval_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let days = value_t!(matches.value_of("days"), u32).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_rotation_interval(Some(days))?;
+        println!("Wireguard key rotation interval has been updated");
+        Ok(())
+    }
+
+    fn process_wireguard_rotation_interval_unset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_rotation_interval(None)?;
+        println!("Wireguard key rotation interval has been unset");
+        Ok(())
+    }
+
     fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get()
@@ -190,6 +474,14 @@ impl Tunnel {
         }
     }
 
+    fn handle_dns_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => Self::process_dns_get(),
+            ("set", Some(set_matches)) => Self::process_dns_set(set_matches),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
     fn process_openvpn_mssfix_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options()?;
         println!(
@@ -243,4 +535,44 @@ impl Tunnel {
         println!("IPv6 setting has been updated");
         Ok(())
     }
+
+    fn process_dns_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!("DNS upstream: {}", tunnel_options.generic.dns_upstream);
+        Ok(())
+    }
+
+    fn process_dns_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let dns_upstream = match matches.subcommand() {
+            ("gateway", Some(_)) => DnsUpstream::Gateway,
+            ("https", Some(server_matches)) => DnsUpstream::Encrypted(Self::parse_dns_server(
+                EncryptedDnsProtocol::Https,
+                server_matches,
+            )),
+            ("tls", Some(server_matches)) => DnsUpstream::Encrypted(Self::parse_dns_server(
+                EncryptedDnsProtocol::Tls,
+                server_matches,
+            )),
+            _ => unreachable!("unhandled command"),
+        };
+
+        let mut rpc = new_rpc_client()?;
+        rpc.set_dns_upstream(dns_upstream)?;
+        println!("DNS upstream has been updated");
+        Ok(())
+    }
+
+    fn parse_dns_server(
+        protocol: EncryptedDnsProtocol,
+        matches: &clap::ArgMatches<'_>,
+    ) -> EncryptedDnsServer {
+        EncryptedDnsServer {
+            protocol,
+            address: value_t_or_exit!(matches.value_of("address"), IpAddr),
+            port: matches
+                .value_of("port")
+                .map(|_| value_t_or_exit!(matches.value_of("port"), u16)),
+            hostname: value_t_or_exit!(matches.value_of("hostname"), String),
+        }
+    }
 }
@@ -0,0 +1,147 @@
+use super::status::format_duration;
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t;
+use futures::{Future, Stream};
+use mullvad_types::DaemonEvent;
+use std::time::{Instant, SystemTime};
+use talpid_types::tunnel;
+
+pub struct TunnelStats;
+
+impl Command for TunnelStats {
+    fn name(&self) -> &'static str {
+        "tunnel-stats"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("View the traffic counters of the current tunnel")
+            .subcommand(
+                clap::SubCommand::with_name("listen")
+                    .about("Print the counters and transfer rates each time the daemon sends them"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("interval")
+                    .about("Configure how often the daemon sends the counters to clients")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(clap::SubCommand::with_name("get"))
+                    .subcommand(clap::SubCommand::with_name("unset").about("Stop sending them"))
+                    .subcommand(
+                        clap::SubCommand::with_name("set").arg(
+                            clap::Arg::with_name("seconds")
+                                .help("Number of seconds between the updates")
+                                .required(true),
+                        ),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("listen", _) => self.listen(),
+            ("interval", Some(matches)) => match matches.subcommand() {
+                ("get", _) => self.get_interval(),
+                ("set", Some(matches)) => {
+                    let seconds =
+                        value_t!(matches.value_of("seconds"), u32).unwrap_or_else(|e| e.exit());
+                    if seconds == 0 {
+                        clap::Error::with_description(
+                            "seconds must be at least 1",
+                            clap::ErrorKind::ValueValidation,
+                        )
+                        .exit();
+                    }
+                    self.set_interval(Some(seconds))
+                }
+                ("unset", _) => self.set_interval(None),
+                _ => unreachable!("unhandled command"),
+            },
+            _ => self.show(),
+        }
+    }
+}
+
+impl TunnelStats {
+    fn show(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        match rpc.get_tunnel_stats()? {
+            Some(stats) => print_stats(&stats),
+            None => println!("No tunnel statistics available"),
+        }
+        Ok(())
+    }
+
+    fn listen(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        if rpc.get_settings()?.get_tunnel_stats_interval().is_none() {
+            println!("The daemon doesn't send tunnel statistics until an interval has been set");
+        }
+        let subscription = rpc
+            .daemon_event_subscribe()
+            .wait()
+            .map_err(Error::CantSubscribe)?;
+
+        let mut previous: Option<(tunnel::TunnelStats, Instant)> = None;
+        for event in subscription.wait() {
+            if let DaemonEvent::TunnelStats(stats) = event? {
+                let now = Instant::now();
+                print_stats(&stats);
+                if let Some((previous_stats, previous_time)) = previous {
+                    let elapsed = now.duration_since(previous_time).as_millis().max(1) as u64;
+                    // Counters start over when the tunnel is reconnected.
+                    let rate = |current: u64, previous: u64| {
+                        current.saturating_sub(previous) * 1000 / elapsed
+                    };
+                    println!(
+                        "Rate: {}/s sent, {}/s received",
+                        format_bytes(rate(stats.tx_bytes, previous_stats.tx_bytes)),
+                        format_bytes(rate(stats.rx_bytes, previous_stats.rx_bytes)),
+                    );
+                }
+                previous = Some((stats, now));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_interval(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        match rpc.get_settings()?.get_tunnel_stats_interval() {
+            Some(seconds) => println!("Tunnel statistics interval: {} seconds", seconds),
+            None => println!("Tunnel statistics interval: unset"),
+        }
+        Ok(())
+    }
+
+    fn set_interval(&self, seconds: Option<u32>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_tunnel_stats_interval(seconds)?;
+        println!("Tunnel statistics interval has been updated");
+        Ok(())
+    }
+}
+
+fn print_stats(stats: &tunnel::TunnelStats) {
+    println!("Sent: {}", format_bytes(stats.tx_bytes));
+    println!("Received: {}", format_bytes(stats.rx_bytes));
+    if let Some(handshake) = stats.last_handshake {
+        match SystemTime::now().duration_since(handshake) {
+            Ok(elapsed) => println!("Last handshake: {} ago", format_duration(elapsed)),
+            Err(_) => println!("Last handshake: just now"),
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
@@ -31,6 +31,12 @@ pub enum Error {
     #[error(display = "Failed to communicate with mullvad-daemon over RPC")]
     RpcClientError(#[error(cause)] mullvad_ipc_client::Error),
 
+    #[error(display = "Failed to read {}", _0)]
+    ReadFile(String, #[error(cause)] io::Error),
+
+    #[error(display = "Invalid WireGuard configuration")]
+    InvalidWireguardConfig(#[error(cause)] mullvad_types::wg_quick::Error),
+
     /// The given command is not correct in some way
     #[error(display = "Invalid command: {}", _0)]
     InvalidCommand(&'static str),
@@ -1,10 +1,6 @@
+use crate::secret_storage::{self, SecretStorage};
 use mullvad_types::{account::AccountToken, wireguard::WireguardData};
-use std::{
-    collections::VecDeque,
-    fs,
-    io::{self, Seek, Write},
-    path::Path,
-};
+use std::{collections::VecDeque, fs, io, path::Path};
 use talpid_types::ErrorExt;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -17,51 +13,83 @@ pub enum Error {
     #[error(display = "Failed to serialize account history")]
     Serialize(#[error(cause)] serde_json::Error),
 
-    #[error(display = "Unable to write account history file")]
-    Write(#[error(cause)] io::Error),
+    #[error(display = "Unable to open account history storage")]
+    Open(#[error(cause)] secret_storage::Error),
+
+    #[error(display = "Unable to write account history")]
+    Write(#[error(cause)] secret_storage::Error),
 }
 
-static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
+/// Name of the account history in the secret storage.
+static ACCOUNT_HISTORY_SECRET: &str = "account-history";
+/// The account history used to be kept in this file, unencrypted.
+static PLAINTEXT_ACCOUNT_HISTORY_FILE: &str = "account-history.json";
 static ACCOUNT_HISTORY_LIMIT: usize = 3;
 
-/// A trivial MRU cache of account data
+/// A trivial MRU cache of account data. It contains private WireGuard keys, so it is kept in a
+/// secret storage.
 pub struct AccountHistory {
-    file: io::BufWriter<fs::File>,
+    storage: Box<dyn SecretStorage>,
     accounts: VecDeque<AccountEntry>,
 }
 
 
 impl AccountHistory {
     pub fn new(cache_dir: &Path) -> Result<AccountHistory> {
-        let mut options = fs::OpenOptions::new();
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::OpenOptionsExt;
-            options.mode(0o600);
+        let mut storage =
+            secret_storage::open(cache_dir, ACCOUNT_HISTORY_SECRET).map_err(Error::Open)?;
+        log::info!("Opening account history in {}", storage);
+
+        let accounts = match storage.read() {
+            Ok(Some(data)) => Self::parse(&data),
+            Ok(None) => VecDeque::new(),
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg(
+                        "Failed to read account history, it will be replaced by an empty one"
+                    )
+                );
+                VecDeque::new()
+            }
+        };
+        let mut history = AccountHistory { storage, accounts };
+        history.migrate_plaintext_file(cache_dir)?;
+        Ok(history)
+    }
+
+    /// Moves the accounts from the unencrypted file that older versions wrote into the secret
+    /// storage, and removes the file.
+    fn migrate_plaintext_file(&mut self, cache_dir: &Path) -> Result<()> {
+        let path = cache_dir.join(PLAINTEXT_ACCOUNT_HISTORY_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::Read(e)),
+        };
+        log::info!("Moving account history from {}", path.display());
+        if self.accounts.is_empty() {
+            self.accounts = Self::parse(&data);
+            self.save()?;
         }
-        #[cfg(windows)]
-        {
-            use std::os::windows::fs::OpenOptionsExt;
-            // a share mode of zero ensures exclusive access to the file to *this* process
-            options.share_mode(0);
+        if let Err(e) = fs::remove_file(&path) {
+            log::error!(
+                "{}",
+                e.display_chain_with_msg("Failed to remove unencrypted account history file")
+            );
         }
-        let path = cache_dir.join(ACCOUNT_HISTORY_FILE);
-        log::info!("Opening account history file in {}", path.display());
-        let mut reader = options
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(path)
-            .map(io::BufReader::new)
-            .map_err(Error::Read)?;
-
-        let accounts: VecDeque<AccountEntry> = match serde_json::from_reader(&mut reader) {
+        Ok(())
+    }
+
+    fn parse(data: &[u8]) -> VecDeque<AccountEntry> {
+        match serde_json::from_slice(data) {
+            Ok(accounts) => accounts,
             Err(e) => {
                 log::warn!(
                     "{}",
-                    e.display_chain_with_msg("Failed to read+deserialize account history")
+                    e.display_chain_with_msg("Failed to deserialize account history")
                 );
-                Self::try_old_format(&mut reader)?
+                Self::try_old_format(data)
                     .into_iter()
                     .map(|account| AccountEntry {
                         account,
@@ -69,21 +97,17 @@ impl AccountHistory {
                     })
                     .collect()
             }
-            Ok(accounts) => accounts,
-        };
-        let file = io::BufWriter::new(reader.into_inner());
-        Ok(AccountHistory { file, accounts })
+        }
     }
 
-    fn try_old_format(reader: &mut io::BufReader<fs::File>) -> Result<Vec<AccountToken>> {
+    fn try_old_format(data: &[u8]) -> Vec<AccountToken> {
         #[derive(Deserialize)]
         struct OldFormat {
             accounts: Vec<AccountToken>,
         }
-        reader.seek(io::SeekFrom::Start(0)).map_err(Error::Read)?;
-        Ok(serde_json::from_reader(reader)
+        serde_json::from_slice(data)
             .map(|old_format: OldFormat| old_format.accounts)
-            .unwrap_or_else(|_| Vec::new()))
+            .unwrap_or_else(|_| Vec::new())
     }
 
     /// Gets account data for a certain account id and bumps it's entry to the top of the list if
@@ -130,7 +154,7 @@ impl AccountHistory {
         if self.accounts.len() > ACCOUNT_HISTORY_LIMIT {
             let _ = self.accounts.pop_back();
         }
-        self.save_to_disk()
+        self.save()
     }
 
     /// Retrieve account history.
@@ -144,24 +168,19 @@ impl AccountHistory {
     /// Remove account data
     pub fn remove_account(&mut self, account: &str) -> Result<()> {
         self.accounts.retain(|entry| entry.account != account);
-        self.save_to_disk()
+        self.save()
     }
 
     /// Remove account history
     #[cfg(not(target_os = "android"))]
     pub fn clear(&mut self) -> Result<()> {
         self.accounts = VecDeque::new();
-        self.save_to_disk()
+        self.save()
     }
 
-    fn save_to_disk(&mut self) -> Result<()> {
-        self.file.get_mut().set_len(0).map_err(Error::Write)?;
-        self.file
-            .seek(io::SeekFrom::Start(0))
-            .map_err(Error::Write)?;
-        serde_json::to_writer_pretty(&mut self.file, &self.accounts).map_err(Error::Serialize)?;
-        self.file.flush().map_err(Error::Write)?;
-        self.file.get_mut().sync_all().map_err(Error::Write)
+    fn save(&mut self) -> Result<()> {
+        let data = serde_json::to_vec(&self.accounts).map_err(Error::Serialize)?;
+        self.storage.write(&data).map_err(Error::Write)
     }
 }
 
@@ -0,0 +1,258 @@
+use chrono::NaiveTime;
+use mullvad_types::{auto_connect::AutoConnectRules, states::TargetState};
+use talpid_types::net::network_info::NetworkInfo;
+
+/// Decides what the target state should be from the auto-connect rules, the networks the device
+/// is connected to and the time of day.
+///
+/// Only changes in the decision are reported, so that the user can still override the target
+/// state manually until the connected networks, the rules or the schedule change again.
+#[derive(Default)]
+pub struct RuleEngine {
+    network_info: NetworkInfo,
+    last_decision: Option<TargetState>,
+}
+
+impl RuleEngine {
+    pub fn network_info(&self) -> &NetworkInfo {
+        &self.network_info
+    }
+
+    /// Stores a new set of connected networks. Returns the target state to apply, if the
+    /// decision changed.
+    pub fn update_network_info(
+        &mut self,
+        network_info: NetworkInfo,
+        rules: &AutoConnectRules,
+        now: NaiveTime,
+    ) -> Option<TargetState> {
+        if network_info != self.network_info {
+            self.network_info = network_info;
+            self.last_decision = None;
+        }
+        self.evaluate(rules, now)
+    }
+
+    /// Forgets the previous decision so that new rules are applied even if the outcome is the
+    /// same as before.
+    pub fn reset(&mut self) {
+        self.last_decision = None;
+    }
+
+    /// Evaluates the rules against the current networks. Returns the target state to apply, if
+    /// the decision changed since the last evaluation.
+    pub fn evaluate(&mut self, rules: &AutoConnectRules, now: NaiveTime) -> Option<TargetState> {
+        let decision = Self::decide(rules, &self.network_info, now);
+        if decision.is_some() && decision != self.last_decision {
+            self.last_decision = decision;
+            decision
+        } else {
+            None
+        }
+    }
+
+    fn decide(
+        rules: &AutoConnectRules,
+        network_info: &NetworkInfo,
+        now: NaiveTime,
+    ) -> Option<TargetState> {
+        if !rules.enabled || network_info.is_empty() {
+            return None;
+        }
+        if let Some(schedule) = rules.schedule {
+            if !schedule.contains(now) {
+                return Some(TargetState::Unsecured);
+            }
+        }
+
+        let all_trusted = network_info
+            .networks
+            .iter()
+            .all(|network| rules.is_trusted(network));
+        if all_trusted {
+            Some(TargetState::Unsecured)
+        } else {
+            Some(TargetState::Secured)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::auto_connect::{NetworkMatcher, Schedule};
+    use talpid_types::net::network_info::{ActiveNetwork, InterfaceKind};
+
+    const OFFICE_GATEWAY: &str = "aa:bb:cc:dd:ee:ff";
+
+    fn noon() -> NaiveTime {
+        NaiveTime::from_hms(12, 0, 0)
+    }
+
+    fn wifi(ssid: &str) -> NetworkInfo {
+        NetworkInfo {
+            networks: vec![ActiveNetwork {
+                interface: "wlan0".to_owned(),
+                kind: InterfaceKind::Wireless,
+                ssid: Some(ssid.to_owned()),
+                gateway_mac: Some("11:22:33:44:55:66".to_owned()),
+            }],
+        }
+    }
+
+    fn office_ethernet() -> NetworkInfo {
+        NetworkInfo {
+            networks: vec![ActiveNetwork {
+                interface: "eth0".to_owned(),
+                kind: InterfaceKind::Wired,
+                ssid: None,
+                gateway_mac: Some(OFFICE_GATEWAY.to_owned()),
+            }],
+        }
+    }
+
+    fn rules() -> AutoConnectRules {
+        AutoConnectRules {
+            enabled: true,
+            trusted_networks: vec![
+                NetworkMatcher::Ssid("Home".to_owned()),
+                NetworkMatcher::GatewayMac(OFFICE_GATEWAY.to_owned()),
+            ],
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn secures_untrusted_network() {
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(wifi("Cafe"), &rules(), noon()),
+            Some(TargetState::Secured)
+        );
+    }
+
+    #[test]
+    fn unsecures_trusted_networks() {
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(wifi("Home"), &rules(), noon()),
+            Some(TargetState::Unsecured)
+        );
+        assert_eq!(
+            engine.update_network_info(office_ethernet(), &rules(), noon()),
+            Some(TargetState::Unsecured)
+        );
+    }
+
+    #[test]
+    fn any_untrusted_network_secures() {
+        let mut network_info = office_ethernet();
+        network_info.networks.extend(wifi("Cafe").networks);
+
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(network_info, &rules(), noon()),
+            Some(TargetState::Secured)
+        );
+    }
+
+    #[test]
+    fn only_reports_changes() {
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(wifi("Cafe"), &rules(), noon()),
+            Some(TargetState::Secured)
+        );
+        assert_eq!(
+            engine.update_network_info(wifi("Cafe"), &rules(), noon()),
+            None
+        );
+        assert_eq!(engine.evaluate(&rules(), noon()), None);
+        assert_eq!(
+            engine.update_network_info(wifi("Home"), &rules(), noon()),
+            Some(TargetState::Unsecured)
+        );
+    }
+
+    #[test]
+    fn reapplies_decision_after_network_change() {
+        let mut engine = RuleEngine::default();
+        engine.update_network_info(wifi("Cafe"), &rules(), noon());
+        assert_eq!(
+            engine.update_network_info(wifi("Airport"), &rules(), noon()),
+            Some(TargetState::Secured)
+        );
+    }
+
+    #[test]
+    fn does_nothing_when_offline_or_disabled() {
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(NetworkInfo::default(), &rules(), noon()),
+            None
+        );
+
+        let disabled = AutoConnectRules {
+            enabled: false,
+            ..rules()
+        };
+        assert_eq!(
+            engine.update_network_info(wifi("Cafe"), &disabled, noon()),
+            None
+        );
+    }
+
+    #[test]
+    fn trusts_interface_kind() {
+        let rules = AutoConnectRules {
+            trusted_networks: vec![NetworkMatcher::InterfaceKind(InterfaceKind::Wired)],
+            ..rules()
+        };
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(office_ethernet(), &rules, noon()),
+            Some(TargetState::Unsecured)
+        );
+        assert_eq!(
+            engine.update_network_info(wifi("Home"), &rules, noon()),
+            Some(TargetState::Secured)
+        );
+    }
+
+    #[test]
+    fn follows_schedule() {
+        let rules = AutoConnectRules {
+            schedule: Some(Schedule {
+                start: NaiveTime::from_hms(8, 0, 0),
+                end: NaiveTime::from_hms(18, 0, 0),
+            }),
+            ..rules()
+        };
+        let mut engine = RuleEngine::default();
+        assert_eq!(
+            engine.update_network_info(wifi("Cafe"), &rules, NaiveTime::from_hms(7, 0, 0)),
+            Some(TargetState::Unsecured)
+        );
+        assert_eq!(
+            engine.evaluate(&rules, NaiveTime::from_hms(8, 0, 0)),
+            Some(TargetState::Secured)
+        );
+        assert_eq!(engine.evaluate(&rules, noon()), None);
+        assert_eq!(
+            engine.evaluate(&rules, NaiveTime::from_hms(18, 0, 0)),
+            Some(TargetState::Unsecured)
+        );
+    }
+
+    #[test]
+    fn reset_reapplies_rules() {
+        let mut engine = RuleEngine::default();
+        engine.update_network_info(wifi("Cafe"), &rules(), noon());
+        assert_eq!(engine.evaluate(&rules(), noon()), None);
+        engine.reset();
+        assert_eq!(
+            engine.evaluate(&rules(), noon()),
+            Some(TargetState::Secured)
+        );
+    }
+}
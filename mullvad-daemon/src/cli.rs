@@ -51,6 +51,8 @@ lazy_static::lazy_static! {
     MULLVAD_SETTINGS_DIR       Directory path for storing settings. [Default: {}]
     MULLVAD_CACHE_DIR          Directory path for storing cache. [Default: {}]
     MULLVAD_LOG_DIR            Directory path for storing logs. [Default: {}]
+    MULLVAD_KEY_DIR            Directory path for storing the key that secrets are encrypted with.
+                               [Default: {}]
     MULLVAD_RPC_SOCKET_PATH    Location of the management interface device.
                                It refers to Unix domain socket on Unix based platforms, and named pipe on Windows.
                                [Default: {}]
@@ -60,6 +62,7 @@ lazy_static::lazy_static! {
         mullvad_paths::get_default_settings_dir().expect("Unable to get settings dir").display(),
         mullvad_paths::get_default_cache_dir().expect("Unable to get cache dir").display(),
         mullvad_paths::get_default_log_dir().expect("Unable to get log dir").display(),
+        mullvad_paths::get_default_key_dir().expect("Unable to get key dir").display(),
         mullvad_paths::get_default_rpc_socket_path().display());
 }
 
@@ -0,0 +1,71 @@
+use futures::{sync::oneshot, Future};
+use mullvad_rpc::encrypted_dns::{self, QuerySender};
+use std::{
+    fmt, io,
+    sync::{mpsc, Arc},
+};
+use talpid_core::dns::{DnsTransport, EncryptedDnsProvider};
+use talpid_types::{net::dns::EncryptedDnsServer, ErrorExt};
+use tokio_core::reactor::Remote;
+
+/// Lets the local resolver send queries to encrypted DNS servers, with the HTTPS and TLS stack
+/// running on the RPC event loop.
+pub struct EncryptedDns {
+    tokio_remote: Remote,
+}
+
+impl EncryptedDns {
+    pub fn new(tokio_remote: Remote) -> Self {
+        EncryptedDns { tokio_remote }
+    }
+}
+
+impl EncryptedDnsProvider for EncryptedDns {
+    fn create_transport(&self, server: &EncryptedDnsServer) -> io::Result<Arc<dyn DnsTransport>> {
+        let (client_tx, client_rx) = mpsc::channel();
+        let client_server = server.clone();
+        self.tokio_remote.spawn(move |handle| {
+            let client = encrypted_dns::create_encrypted_dns_client(client_server, handle)
+                .map_err(|error| error.display_chain());
+            let _ = client_tx.send(client);
+            Ok(())
+        });
+
+        let query_tx = client_rx
+            .recv()
+            .map_err(|_| event_loop_stopped())?
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        Ok(Arc::new(EncryptedDnsTransport {
+            server: server.clone(),
+            query_tx,
+        }))
+    }
+}
+
+struct EncryptedDnsTransport {
+    server: EncryptedDnsServer,
+    query_tx: QuerySender,
+}
+
+impl DnsTransport for EncryptedDnsTransport {
+    fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let (answer_tx, answer_rx) = oneshot::channel();
+        self.query_tx
+            .unbounded_send((query.to_vec(), answer_tx))
+            .map_err(|_| event_loop_stopped())?;
+        answer_rx
+            .wait()
+            .map_err(|_| event_loop_stopped())?
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error.display_chain()))
+    }
+}
+
+impl fmt::Display for EncryptedDnsTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.server.fmt(f)
+    }
+}
+
+fn event_loop_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "The RPC event loop has stopped")
+}
@@ -13,11 +13,15 @@ extern crate serde;
 
 
 mod account_history;
+mod auto_connect;
+mod encrypted_dns;
 mod geoip;
 pub mod logging;
 mod management_interface;
+mod port_forwarding;
 mod relays;
 mod rpc_uniqueness_check;
+mod secret_storage;
 mod settings;
 pub mod version;
 
@@ -25,6 +29,7 @@ pub use crate::management_interface::ManagementCommand;
 use crate::management_interface::{
     BoxFuture, ManagementInterfaceEventBroadcaster, ManagementInterfaceServer,
 };
+use chrono::{offset::Utc, DateTime};
 use futures::{
     future::{self, Executor},
     sync::{mpsc::UnboundedSender, oneshot},
@@ -34,8 +39,12 @@ use log::{debug, error, info, warn};
 use mullvad_rpc::{AccountsProxy, AppVersionProxy, HttpHandle, WireguardKeyProxy};
 use mullvad_types::{
     account::{AccountData, AccountToken},
+    auto_connect::{AutoConnectRules, Schedule},
+    captive_portal::CaptivePortalEvent,
+    diagnostics::Diagnostics,
     endpoint::MullvadEndpoint,
-    location::GeoIpLocation,
+    location::{CityCode, CountryCode, GeoIpLocation},
+    port_forward::PortForward,
     relay_constraints::{
         BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, OpenVpnConstraints,
         RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate, TunnelProtocol,
@@ -43,26 +52,43 @@ use mullvad_types::{
     relay_list::{Relay, RelayList},
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
-    wireguard::KeygenEvent,
+    wg_quick::WgQuickConfig,
+    wireguard::{KeygenEvent, RegisteredKey},
 };
 use settings::Settings;
 #[cfg(not(target_os = "android"))]
 use std::path::Path;
-use std::{io, mem, path::PathBuf, sync::mpsc, thread, time::Duration};
+use std::{
+    cmp, io, mem,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
 use talpid_core::{
+    dns,
     mpsc::IntoSender,
     tunnel::tun_provider::{PlatformTunProvider, TunProvider},
-    tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
+    tunnel_state_machine::{self, NetworkInfoListener, TunnelCommand, TunnelParametersGenerator},
 };
 use talpid_types::{
-    net::{openvpn, TransportProtocol, TunnelParameters},
-    tunnel::{BlockReason, TunnelStateTransition},
+    net::{
+        dns::{DnsUpstream, LocalResolverSettings, SplitDns},
+        lan::LanPolicy,
+        network_info::NetworkInfo,
+        openvpn, TransportProtocol, TunnelParameters, TunnelType,
+    },
+    tunnel::{BlockReason, TunnelStateTransition, TunnelStats},
     ErrorExt,
 };
 
 #[path = "wireguard.rs"]
 mod wireguard;
 
+/// Longest time to wait before re-evaluating a time limited auto-connect schedule. Catches up
+/// with wall clock changes and time spent suspended, which the timer itself doesn't follow.
+const AUTO_CONNECT_SCHEDULE_MAX_WAIT: Duration = Duration::from_secs(15 * 60);
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(err_derive::Error, Debug)]
@@ -147,6 +173,25 @@ pub(crate) enum InternalDaemonEvent {
             ::std::result::Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
         ),
     ),
+    /// The wireguard key of an account is old enough to be replaced.
+    WgKeyRotationDue(AccountToken, wireguard::PublicKey),
+    /// A key on an account was replaced with a new key for this device, either on request from a
+    /// management interface client, or without a response channel when the key was rotated.
+    WgKeyReplaced(
+        AccountToken,
+        wireguard::Result<mullvad_types::wireguard::WireguardData>,
+        Option<wireguard::ResponseTx<KeygenEvent>>,
+    ),
+    /// A port forwarding request to the API finished
+    PortForwardEvent(port_forwarding::PortForwardEvent),
+    /// The networks the device is connected to changed.
+    NetworkInfoChanged(NetworkInfo),
+    /// Periodic trigger for re-evaluating the auto-connect schedule.
+    AutoConnectScheduleTick,
+    /// The time for allowing captive portal login traffic ran out.
+    CaptivePortalExpired,
+    /// Periodic trigger for sending the tunnel statistics to clients.
+    TunnelStatsTick,
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -229,16 +274,33 @@ pub trait EventListener {
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify clients that captive portal login traffic started or stopped being allowed.
+    fn notify_captive_portal_event(&self, event: CaptivePortalEvent);
+
+    /// Notify clients of the traffic counters of the current tunnel.
+    fn notify_tunnel_stats(&self, stats: TunnelStats);
+}
+
+/// The time for allowing captive portal login traffic. Dropping it cancels the timer.
+struct CaptivePortalTimer {
+    expires: Instant,
+    _cancel_tx: mpsc::Sender<()>,
 }
 
 pub struct Daemon<L: EventListener = ManagementInterfaceEventBroadcaster> {
     tunnel_command_tx: SyncUnboundedSender<TunnelCommand>,
     tunnel_state: TunnelState,
+    tunnel_state_entered: Instant,
+    last_tunnel_error: Option<String>,
     target_state: TargetState,
     state: DaemonExecutionState,
     rx: mpsc::Receiver<InternalDaemonEvent>,
     tx: mpsc::Sender<InternalDaemonEvent>,
     reconnection_loop_tx: Option<mpsc::Sender<()>>,
+    captive_portal_timer: Option<CaptivePortalTimer>,
+    /// Sends `TunnelStatsTick`s while set. Dropping it stops the ticks.
+    tunnel_stats_timer: Option<mpsc::Sender<()>>,
     event_listener: L,
     settings: Settings,
     account_history: account_history::AccountHistory,
@@ -247,6 +309,12 @@ pub struct Daemon<L: EventListener = ManagementInterfaceEventBroadcaster> {
     version_proxy: AppVersionProxy<HttpHandle>,
     https_handle: mullvad_rpc::rest::RequestSender,
     wireguard_key_manager: wireguard::KeyManager,
+    /// Replaced wireguard keys that should be removed from their accounts once the tunnel has
+    /// stopped using them.
+    pending_key_removals: Vec<(AccountToken, wireguard::PublicKey)>,
+    port_forward_manager: port_forwarding::PortForwardManager,
+    auto_connect_rule_engine: auto_connect::RuleEngine,
+    auto_connect_schedule_tx: mpsc::Sender<Option<Schedule>>,
     tokio_remote: tokio_core::reactor::Remote,
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
@@ -388,18 +456,31 @@ where
         let tunnel_parameters_generator = MullvadTunnelParametersGenerator {
             tx: internal_event_tx.clone(),
         };
+        let network_info_tx = internal_event_tx.clone();
+        let network_info_listener: NetworkInfoListener = Box::new(move |network_info| {
+            let _ = network_info_tx.send(InternalDaemonEvent::NetworkInfoChanged(network_info));
+        });
         let tunnel_command_tx = tunnel_state_machine::spawn(
-            settings.get_allow_lan(),
+            settings.get_lan_policy().clone(),
+            settings.get_split_dns().clone(),
+            settings.get_local_resolver().clone(),
             settings.get_block_when_disconnected(),
             tunnel_parameters_generator,
             tun_provider,
+            encrypted_dns::EncryptedDns::new(tokio_remote.clone()),
             log_dir,
             resource_dir,
             cache_dir.clone(),
             IntoSender::from(internal_event_tx.clone()),
+            Some(network_info_listener),
         )
         .map_err(Error::TunnelError)?;
 
+        let auto_connect_schedule_tx = Self::spawn_auto_connect_schedule_thread(
+            internal_event_tx.clone(),
+            settings.get_auto_connect_rules().active_schedule(),
+        );
+
 
         let wireguard_key_manager = wireguard::KeyManager::new(
             internal_event_tx.clone(),
@@ -407,17 +488,27 @@ where
             tokio_remote.clone(),
         );
 
+        let port_forward_manager = port_forwarding::PortForwardManager::new(
+            internal_event_tx.clone(),
+            rpc_handle.clone(),
+            tokio_remote.clone(),
+        );
+
         // Attempt to download a fresh relay list
         relay_selector.update();
 
         let mut daemon = Daemon {
             tunnel_command_tx: Sink::wait(tunnel_command_tx),
             tunnel_state: TunnelState::Disconnected,
+            tunnel_state_entered: Instant::now(),
+            last_tunnel_error: None,
             target_state: TargetState::Unsecured,
             state: DaemonExecutionState::Running,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_loop_tx: None,
+            captive_portal_timer: None,
+            tunnel_stats_timer: None,
             event_listener,
             settings,
             account_history,
@@ -431,14 +522,58 @@ where
             last_generated_bridge_relay: None,
             version,
             wireguard_key_manager,
+            pending_key_removals: Vec::new(),
+            port_forward_manager,
+            auto_connect_rule_engine: auto_connect::RuleEngine::default(),
+            auto_connect_schedule_tx,
             shutdown_callbacks: vec![],
         };
 
         daemon.ensure_wireguard_keys_for_current_account();
+        daemon.schedule_wireguard_key_rotation(Utc::now());
+        daemon.schedule_tunnel_stats();
 
         Ok(daemon)
     }
 
+    /// Sends a tick whenever the active auto-connect schedule starts or ends, so that the rules
+    /// are re-evaluated. Sleeps until a new schedule is received when there is nothing to follow.
+    fn spawn_auto_connect_schedule_thread(
+        event_tx: mpsc::Sender<InternalDaemonEvent>,
+        initial_schedule: Option<Schedule>,
+    ) -> mpsc::Sender<Option<Schedule>> {
+        let (schedule_tx, schedule_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut schedule = initial_schedule;
+            loop {
+                let wait = schedule.and_then(|schedule| {
+                    schedule.time_until_next_boundary(chrono::Local::now().time())
+                });
+                let received = match wait {
+                    Some(wait) => {
+                        schedule_rx.recv_timeout(cmp::min(wait, AUTO_CONNECT_SCHEDULE_MAX_WAIT))
+                    }
+                    None => schedule_rx
+                        .recv()
+                        .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(new_schedule) => schedule = new_schedule,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if event_tx
+                            .send(InternalDaemonEvent::AutoConnectScheduleTick)
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        schedule_tx
+    }
+
     /// Retrieve a channel for sending daemon commands.
     pub fn command_sender(&self) -> DaemonCommandSender {
         DaemonCommandSender::new(self.tx.clone())
@@ -496,6 +631,19 @@ where
             }
             TriggerShutdown => self.trigger_shutdown_event(),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event),
+            WgKeyRotationDue(account, public_key) => {
+                self.handle_wireguard_key_rotation_due(account, public_key)
+            }
+            WgKeyReplaced(account, result, tx) => {
+                self.handle_wireguard_key_replaced(account, result, tx)
+            }
+            PortForwardEvent(port_forward_event) => {
+                self.handle_port_forward_event(port_forward_event)
+            }
+            NetworkInfoChanged(network_info) => self.handle_network_info_change(network_info),
+            AutoConnectScheduleTick => self.apply_auto_connect_rules(),
+            CaptivePortalExpired => self.handle_captive_portal_expired(),
+            TunnelStatsTick => self.handle_tunnel_stats_tick(),
         }
         Ok(())
     }
@@ -503,13 +651,16 @@ where
     fn handle_tunnel_state_transition(&mut self, tunnel_state_transition: TunnelStateTransition) {
         let tunnel_state = match tunnel_state_transition {
             TunnelStateTransition::Disconnected => TunnelState::Disconnected,
-            TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
+            TunnelStateTransition::Connecting(endpoint, diagnostics) => TunnelState::Connecting {
                 endpoint,
                 location: self.build_location_from_relay(),
+                diagnostics,
             },
-            TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
+            TunnelStateTransition::Connected(endpoint, diagnostics) => TunnelState::Connected {
                 endpoint,
                 location: self.build_location_from_relay(),
+                forwarded_ports: self.get_forwarded_ports(),
+                diagnostics,
             },
             TunnelStateTransition::Disconnecting(after_disconnect) => {
                 TunnelState::Disconnecting(after_disconnect)
@@ -532,10 +683,73 @@ where
             _ => {}
         }
 
+        match tunnel_state {
+            // Until the old tunnel is torn down it may still be using a replaced key.
+            TunnelState::Disconnecting(_) => (),
+            _ => {
+                for (account, public_key) in self.pending_key_removals.drain(..) {
+                    self.wireguard_key_manager
+                        .remove_key_async(account, public_key);
+                }
+            }
+        }
+
+        self.update_last_tunnel_error(&tunnel_state);
         self.tunnel_state = tunnel_state.clone();
+        self.tunnel_state_entered = Instant::now();
         self.event_listener.notify_new_state(tunnel_state);
     }
 
+    fn update_last_tunnel_error(&mut self, tunnel_state: &TunnelState) {
+        match tunnel_state {
+            TunnelState::Connecting { diagnostics, .. }
+            | TunnelState::Connected { diagnostics, .. } => {
+                if let Some(failure) = &diagnostics.previous_failure {
+                    self.last_tunnel_error = Some(failure.clone());
+                }
+            }
+            TunnelState::Blocked(reason) => self.last_tunnel_error = Some(reason.to_string()),
+            TunnelState::Disconnected | TunnelState::Disconnecting(_) => {}
+        }
+    }
+
+    fn handle_network_info_change(&mut self, network_info: NetworkInfo) {
+        debug!("Connected networks: {:?}", network_info.networks);
+        let new_target_state = self.auto_connect_rule_engine.update_network_info(
+            network_info,
+            self.settings.get_auto_connect_rules(),
+            chrono::Local::now().time(),
+        );
+        self.set_auto_connect_target_state(new_target_state);
+    }
+
+    fn apply_auto_connect_rules(&mut self) {
+        let new_target_state = self.auto_connect_rule_engine.evaluate(
+            self.settings.get_auto_connect_rules(),
+            chrono::Local::now().time(),
+        );
+        self.set_auto_connect_target_state(new_target_state);
+    }
+
+    fn set_auto_connect_target_state(&mut self, new_target_state: Option<TargetState>) {
+        if let Some(new_target_state) = new_target_state {
+            if !self.state.is_running() {
+                return;
+            }
+            if new_target_state == TargetState::Secured
+                && self.settings.get_account_token().is_none()
+            {
+                debug!("Not securing the tunnel from auto-connect rules without an account");
+                return;
+            }
+            info!(
+                "Auto-connect rules changed the target state to {:?}",
+                new_target_state
+            );
+            self.set_target_state(new_target_state);
+        }
+    }
+
     fn handle_generate_tunnel_parameters(
         &mut self,
         tunnel_parameters_tx: &mpsc::Sender<TunnelParameters>,
@@ -660,6 +874,7 @@ where
                 peer,
                 ipv4_gateway,
                 ipv6_gateway,
+                protocol,
             } => {
                 let wg_data = self
                     .account_history
@@ -680,6 +895,7 @@ where
                         peer,
                         ipv4_gateway,
                         ipv6_gateway: Some(ipv6_gateway),
+                        protocol,
                     },
                     options: tunnel_options.wireguard,
                     generic_options: tunnel_options.generic,
@@ -722,6 +938,9 @@ where
         match event {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state),
             GetState(tx) => self.on_get_state(tx),
+            GetDiagnostics(tx) => self.on_get_diagnostics(tx),
+            GetTunnelStats(tx) => self.on_get_tunnel_stats(tx),
+            SetTunnelStatsInterval(tx, seconds) => self.on_set_tunnel_stats_interval(tx, seconds),
             GetCurrentLocation(tx) => self.on_get_current_location(tx),
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token),
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
@@ -732,24 +951,47 @@ where
                 self.on_remove_account_from_history(tx, account_token)
             }
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update),
-            SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan),
+            ImportWireguardConfig(tx, config) => self.on_import_wireguard_config(tx, config),
+            ExportWireguardConfig(tx) => self.on_export_wireguard_config(tx),
+            SetLanPolicy(tx, lan_policy) => self.on_set_lan_policy(tx, lan_policy),
+            SetSplitDns(tx, split_dns) => self.on_set_split_dns(tx, split_dns),
+            SetLocalResolver(tx, local_resolver) => self.on_set_local_resolver(tx, local_resolver),
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
             }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect),
+            SetAutoConnectRules(tx, rules) => self.on_set_auto_connect_rules(tx, rules),
+            GetNetworkInfo(tx) => self.on_get_network_info(tx),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg),
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings)
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6),
+            SetDnsUpstream(tx, dns_upstream) => self.on_set_dns_upstream(tx, dns_upstream),
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu),
+            SetWireguardAutoMtu(tx, auto_mtu) => self.on_set_wireguard_auto_mtu(tx, auto_mtu),
+            SetWireguardBackend(tx, backend) => self.on_set_wireguard_backend(tx, backend),
+            SetWireguardKeepalive(tx, interval) => self.on_set_wireguard_keepalive(tx, interval),
             GetSettings(tx) => self.on_get_settings(tx),
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx),
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx),
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx),
+            SetWireguardRotationInterval(tx, days) => {
+                self.on_set_wireguard_rotation_interval(tx, days)
+            }
+            ListWireguardKeys(tx) => self.on_list_wireguard_keys(tx),
+            RevokeWireguardKey(tx, public_key) => self.on_revoke_wireguard_key(tx, public_key),
+            ReplaceWireguardKey(tx, public_key) => self.on_replace_wireguard_key(tx, public_key),
+            RequestPortForward(tx, country_code, city_code) => {
+                self.on_request_port_forward(tx, country_code, city_code)
+            }
+            ListPortForwards(tx) => self.on_list_port_forwards(tx),
+            ReleasePortForward(tx, port_forward) => self.on_release_port_forward(tx, port_forward),
             GetVersionInfo(tx) => self.on_get_version_info(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
+            AllowCaptivePortal(tx, minutes) => self.on_allow_captive_portal(tx, minutes),
+            EndCaptivePortal(tx) => self.on_end_captive_portal(tx),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx),
             Shutdown => self.trigger_shutdown_event(),
@@ -788,11 +1030,19 @@ where
                         account: account.clone(),
                         wireguard: None,
                     });
-                account_entry.wireguard = Some(data.clone());
+                let old_key = account_entry
+                    .wireguard
+                    .replace(data.clone())
+                    .map(|old_data| old_data.private_key.public_key());
                 match self.account_history.insert(account_entry) {
-                    Ok(_) => self
-                        .event_listener
-                        .notify_key_event(KeygenEvent::NewKey(public_key)),
+                    Ok(_) => {
+                        self.event_listener
+                            .notify_key_event(KeygenEvent::NewKey(public_key));
+                        if let Some(old_key) = old_key {
+                            self.switch_to_new_wireguard_key(account, old_key);
+                        }
+                        self.schedule_wireguard_key_rotation(Utc::now());
+                    }
                     Err(e) => {
                         log::error!(
                             "{}",
@@ -820,6 +1070,192 @@ where
         }
     }
 
+    fn handle_wireguard_key_rotation_due(
+        &mut self,
+        account: AccountToken,
+        public_key: wireguard::PublicKey,
+    ) {
+        // The key may have been replaced by other means since the rotation was scheduled.
+        if !self.is_current_account(&account)
+            || self.get_wireguard_public_key(&account) != Some(public_key.clone())
+        {
+            return;
+        }
+        info!("Rotating wireguard key");
+        if let Err(e) = self
+            .wireguard_key_manager
+            .rotate_key_async(account, public_key)
+        {
+            log::error!(
+                "{}",
+                e.display_chain_with_msg("Failed to start rotating wireguard key")
+            );
+            self.retry_wireguard_key_rotation();
+        }
+    }
+
+    /// Makes the tunnel use the newly stored key of the account, and removes `old_key` from the
+    /// account once the tunnel no longer uses it.
+    fn switch_to_new_wireguard_key(
+        &mut self,
+        account: AccountToken,
+        old_key: wireguard::PublicKey,
+    ) {
+        if self.reconnect_wireguard_tunnel() {
+            self.pending_key_removals.push((account, old_key));
+        } else {
+            self.wireguard_key_manager
+                .remove_key_async(account, old_key);
+        }
+    }
+
+    /// Restarts the tunnel if it is using wireguard, so that it picks up a new key. Returns
+    /// whether a tunnel using the previous key might still be up.
+    fn reconnect_wireguard_tunnel(&mut self) -> bool {
+        match self.tunnel_state {
+            TunnelState::Connecting { ref endpoint, .. }
+            | TunnelState::Connected { ref endpoint, .. }
+                if endpoint.tunnel_type == TunnelType::Wireguard =>
+            {
+                info!("Initiating tunnel restart because the wireguard key was replaced");
+                self.reconnect_tunnel();
+                true
+            }
+            // The tunnel being torn down might still be using the previous key.
+            TunnelState::Disconnecting(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Schedules the next rotation of the key of the current account, if key rotation is
+    /// enabled. The rotation will not happen before `not_before`.
+    fn schedule_wireguard_key_rotation(&mut self, not_before: DateTime<Utc>) {
+        let interval = self.settings.get_wireguard_key_rotation_interval();
+        let account = self.settings.get_account_token();
+        let data = account
+            .as_ref()
+            .and_then(|account| self.account_history.get(account).ok()?)
+            .and_then(|entry| entry.wireguard);
+
+        match (interval, account, data) {
+            (Some(interval), Some(account), Some(data)) => {
+                let due = wireguard::rotation_due(data.created, interval).max(not_before);
+                debug!("Next wireguard key rotation at {}", due);
+                self.wireguard_key_manager.schedule_rotation(
+                    account,
+                    data.private_key.public_key(),
+                    due,
+                );
+            }
+            _ => self.wireguard_key_manager.cancel_rotation(),
+        }
+    }
+
+    fn retry_wireguard_key_rotation(&mut self) {
+        let retry_delay =
+            chrono::Duration::from_std(self.wireguard_key_manager.next_rotation_retry_delay())
+                .expect("Retry delay is out of range");
+        self.schedule_wireguard_key_rotation(Utc::now() + retry_delay);
+    }
+
+    fn get_wireguard_public_key(&self, account: &AccountToken) -> Option<wireguard::PublicKey> {
+        self.account_history
+            .get(account)
+            .ok()?
+            .and_then(|entry| entry.wireguard)
+            .map(|data| data.private_key.public_key())
+    }
+
+    fn handle_port_forward_event(&mut self, event: port_forwarding::PortForwardEvent) {
+        use self::port_forwarding::PortForwardEvent::*;
+        match event {
+            Added(account, result, tx) => {
+                let result = result.and_then(|port_forward| {
+                    if self.is_current_account(&account) {
+                        let changed = self
+                            .settings
+                            .add_port_forward(port_forward.clone())
+                            .map_err(port_forwarding::Error::SettingsError)?;
+                        if changed {
+                            self.on_port_forwards_changed();
+                        }
+                    }
+                    Ok(port_forward)
+                });
+                Self::oneshot_send(tx, result, "request_port_forward response");
+            }
+            Listed(account, result, tx) => {
+                let result = result.and_then(|port_forwards| {
+                    if self.is_current_account(&account) {
+                        let changed = self
+                            .settings
+                            .set_port_forwards(port_forwards.clone())
+                            .map_err(port_forwarding::Error::SettingsError)?;
+                        if changed {
+                            self.on_port_forwards_changed();
+                        }
+                    }
+                    Ok(port_forwards)
+                });
+                Self::oneshot_send(tx, result, "list_port_forwards response");
+            }
+            Removed(account, port_forward, result, tx) => {
+                let result = result.and_then(|()| {
+                    if self.is_current_account(&account) {
+                        let changed = self
+                            .settings
+                            .remove_port_forward(&port_forward)
+                            .map_err(port_forwarding::Error::SettingsError)?;
+                        if changed {
+                            self.on_port_forwards_changed();
+                        }
+                    }
+                    Ok(())
+                });
+                Self::oneshot_send(tx, result, "release_port_forward response");
+            }
+        }
+    }
+
+    fn is_current_account(&self, account: &AccountToken) -> bool {
+        self.settings.get_account_token().as_ref() == Some(account)
+    }
+
+    /// Returns the forwarded ports that are valid for the relay most recently connected to.
+    fn get_forwarded_ports(&self) -> Vec<PortForward> {
+        let location = match self
+            .last_generated_relay
+            .as_ref()
+            .and_then(|relay| relay.location.as_ref())
+        {
+            Some(location) => location,
+            None => return Vec::new(),
+        };
+        self.settings
+            .get_port_forwards()
+            .iter()
+            .filter(|port_forward| port_forward.is_valid_for(location))
+            .cloned()
+            .collect()
+    }
+
+    fn on_port_forwards_changed(&mut self) {
+        self.event_listener.notify_settings(self.settings.clone());
+
+        let new_forwarded_ports = self.get_forwarded_ports();
+        if let TunnelState::Connected {
+            ref mut forwarded_ports,
+            ..
+        } = self.tunnel_state
+        {
+            if *forwarded_ports != new_forwarded_ports {
+                *forwarded_ports = new_forwarded_ports;
+                self.event_listener
+                    .notify_new_state(self.tunnel_state.clone());
+            }
+        }
+    }
+
     fn on_set_target_state(
         &mut self,
         tx: oneshot::Sender<::std::result::Result<(), ()>>,
@@ -837,6 +1273,73 @@ where
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
+    fn on_get_diagnostics(&self, tx: oneshot::Sender<Diagnostics>) {
+        let diagnostics = Diagnostics {
+            target_state: self.target_state,
+            tunnel_state: self.tunnel_state.clone(),
+            time_in_state: self.tunnel_state_entered.elapsed(),
+            last_error: self.last_tunnel_error.clone(),
+            dns_backend: dns::active_backend(),
+        };
+        Self::oneshot_send(tx, diagnostics, "diagnostics");
+    }
+
+    fn on_get_tunnel_stats(&mut self, tx: oneshot::Sender<Option<TunnelStats>>) {
+        self.send_tunnel_command(TunnelCommand::GetStats(tx));
+    }
+
+    fn on_set_tunnel_stats_interval(&mut self, tx: oneshot::Sender<()>, seconds: Option<u32>) {
+        let save_result = self.settings.set_tunnel_stats_interval(seconds);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_tunnel_stats_interval response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    self.schedule_tunnel_stats();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    /// Starts sending `TunnelStatsTick`s at the interval in the settings, replacing any earlier
+    /// timer.
+    fn schedule_tunnel_stats(&mut self) {
+        self.tunnel_stats_timer = None;
+        let interval = match self.settings.get_tunnel_stats_interval() {
+            Some(seconds) => Duration::from_secs(u64::from(seconds.max(1))),
+            None => return,
+        };
+        let event_tx = self.tx.clone();
+        let (cancel_tx, cancel_rx) = mpsc::channel();
+        self.tunnel_stats_timer = Some(cancel_tx);
+        thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = cancel_rx.recv_timeout(interval) {
+                if event_tx.send(InternalDaemonEvent::TunnelStatsTick).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn handle_tunnel_stats_tick(&mut self) {
+        if let TunnelState::Connected { .. } = self.tunnel_state {
+            // The state machine may be waiting for the daemon, so the reply can't be waited for
+            // here.
+            let (tx, rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::GetStats(tx));
+            let event_listener = self.event_listener.clone();
+            self.tokio_remote.spawn(move |_| {
+                rx.map(move |stats| {
+                    if let Some(stats) = stats {
+                        event_listener.notify_tunnel_stats(stats);
+                    }
+                })
+                .map_err(|_| ())
+            });
+        }
+    }
+
     fn on_get_current_location(&self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
         use self::TunnelState::*;
         let get_location: Box<dyn Future<Item = Option<GeoIpLocation>, Error = ()> + Send> =
@@ -925,6 +1428,7 @@ where
                 Self::oneshot_send(tx, (), "set_account response");
                 if account_changed {
                     self.ensure_wireguard_keys_for_current_account();
+                    self.schedule_wireguard_key_rotation(Utc::now());
                     self.event_listener.notify_settings(self.settings.clone());
                     match account_token {
                         Some(token) => {
@@ -985,6 +1489,58 @@ where
         Self::oneshot_send(tx, self.version.clone(), "get_current_version response");
     }
 
+    fn on_allow_captive_portal(&mut self, tx: oneshot::Sender<DateTime<Utc>>, minutes: u32) {
+        let duration = Duration::from_secs(u64::from(minutes) * 60);
+        let event_tx = self.tx.clone();
+        let (cancel_tx, cancel_rx) = mpsc::channel();
+        // Replacing an earlier timer cancels it.
+        self.captive_portal_timer = Some(CaptivePortalTimer {
+            expires: Instant::now() + duration,
+            _cancel_tx: cancel_tx,
+        });
+        thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = cancel_rx.recv_timeout(duration) {
+                let _ = event_tx.send(InternalDaemonEvent::CaptivePortalExpired);
+            }
+        });
+
+        info!(
+            "Allowing captive portal login traffic for {} minutes",
+            minutes
+        );
+        self.send_tunnel_command(TunnelCommand::AllowCaptivePortal(true));
+
+        let expires = Utc::now() + chrono::Duration::minutes(i64::from(minutes));
+        Self::oneshot_send(tx, expires, "allow_captive_portal response");
+        self.event_listener
+            .notify_captive_portal_event(CaptivePortalEvent::Started { expires });
+    }
+
+    fn on_end_captive_portal(&mut self, tx: oneshot::Sender<()>) {
+        if self.captive_portal_timer.is_some() {
+            self.end_captive_portal(CaptivePortalEvent::Ended);
+        }
+        Self::oneshot_send(tx, (), "end_captive_portal response");
+    }
+
+    fn handle_captive_portal_expired(&mut self) {
+        // The timer may have been replaced after the expired one sent its event.
+        let expired = match self.captive_portal_timer {
+            Some(ref timer) => timer.expires <= Instant::now(),
+            None => false,
+        };
+        if expired {
+            self.end_captive_portal(CaptivePortalEvent::Expired);
+        }
+    }
+
+    fn end_captive_portal(&mut self, event: CaptivePortalEvent) {
+        self.captive_portal_timer = None;
+        info!("{}", event);
+        self.send_tunnel_command(TunnelCommand::AllowCaptivePortal(false));
+        self.event_listener.notify_captive_portal_event(event);
+    }
+
     #[cfg(not(target_os = "android"))]
     fn on_factory_reset(&mut self, tx: oneshot::Sender<()>) {
         let mut failed = false;
@@ -1040,14 +1596,91 @@ where
         }
     }
 
-    fn on_set_allow_lan(&mut self, tx: oneshot::Sender<()>, allow_lan: bool) {
-        let save_result = self.settings.set_allow_lan(allow_lan);
+    fn on_import_wireguard_config(&mut self, tx: oneshot::Sender<()>, config: WgQuickConfig) {
+        let update = RelaySettingsUpdate::CustomTunnelEndpoint(config.to_custom_endpoint());
+        let save_result = self
+            .settings
+            .update_relay_settings(update)
+            .and_then(|relay_changed| match config.options.mtu {
+                Some(mtu) => Ok(self.settings.set_wireguard_mtu(Some(mtu))? || relay_changed),
+                None => Ok(relay_changed),
+            });
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "import_wireguard_config response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    info!("Initiating tunnel restart because a WireGuard config was imported");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_export_wireguard_config(&mut self, tx: oneshot::Sender<Option<WgQuickConfig>>) {
+        let config = match self.settings.get_relay_settings() {
+            RelaySettings::CustomTunnelEndpoint(endpoint) => WgQuickConfig::from_custom_endpoint(
+                &endpoint,
+                self.settings.get_tunnel_options().wireguard.clone(),
+            ),
+            RelaySettings::Normal(_) => None,
+        };
+        Self::oneshot_send(tx, config, "export_wireguard_config response");
+    }
+
+    fn on_set_lan_policy(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        lan_policy: LanPolicy,
+    ) {
+        match self.settings.set_lan_policy(lan_policy.clone()) {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lan_policy response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    self.send_tunnel_command(TunnelCommand::LanPolicy(lan_policy));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to set LAN policy"));
+                Self::oneshot_send(tx, Err(e), "set_lan_policy response");
+            }
+        }
+    }
+
+    fn on_set_split_dns(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        split_dns: SplitDns,
+    ) {
+        match self.settings.set_split_dns(split_dns.clone()) {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_dns response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    self.send_tunnel_command(TunnelCommand::SplitDns(split_dns));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to set split DNS"));
+                Self::oneshot_send(tx, Err(e), "set_split_dns response");
+            }
+        }
+    }
+
+    fn on_set_local_resolver(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        local_resolver: LocalResolverSettings,
+    ) {
+        let save_result = self.settings.set_local_resolver(local_resolver.clone());
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_allow_lan response");
+                Self::oneshot_send(tx, (), "set_local_resolver response");
                 if settings_changed {
                     self.event_listener.notify_settings(self.settings.clone());
-                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                    self.send_tunnel_command(TunnelCommand::LocalResolver(local_resolver));
                 }
             }
             Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
@@ -1089,6 +1722,29 @@ where
         }
     }
 
+    fn on_set_auto_connect_rules(&mut self, tx: oneshot::Sender<()>, rules: AutoConnectRules) {
+        let save_result = self.settings.set_auto_connect_rules(rules);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_auto_connect_rules response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    self.auto_connect_rule_engine.reset();
+                    self.apply_auto_connect_rules();
+                    let _ = self
+                        .auto_connect_schedule_tx
+                        .send(self.settings.get_auto_connect_rules().active_schedule());
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_get_network_info(&self, tx: oneshot::Sender<NetworkInfo>) {
+        let network_info = self.auto_connect_rule_engine.network_info().clone();
+        Self::oneshot_send(tx, network_info, "get_network_info response");
+    }
+
     fn on_set_openvpn_mssfix(&mut self, tx: oneshot::Sender<()>, mssfix_arg: Option<u16>) {
         let save_result = self.settings.set_openvpn_mssfix(mssfix_arg);
         match save_result {
@@ -1193,6 +1849,21 @@ where
         }
     }
 
+    fn on_set_dns_upstream(&mut self, tx: oneshot::Sender<()>, dns_upstream: DnsUpstream) {
+        let save_result = self.settings.set_dns_upstream(dns_upstream);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_upstream response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    info!("Initiating tunnel restart because the DNS upstream changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn on_set_wireguard_mtu(&mut self, tx: oneshot::Sender<()>, mtu: Option<u16>) {
         let save_result = self.settings.set_wireguard_mtu(mtu);
         match save_result {
@@ -1208,6 +1879,71 @@ where
         }
     }
 
+    fn on_set_wireguard_auto_mtu(&mut self, tx: oneshot::Sender<()>, auto_mtu: bool) {
+        let save_result = self.settings.set_wireguard_auto_mtu(auto_mtu);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_auto_mtu response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    info!("Initiating tunnel restart because the WireGuard auto MTU changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_wireguard_keepalive(&mut self, tx: oneshot::Sender<()>, interval: Option<u16>) {
+        let save_result = self.settings.set_wireguard_keepalive(interval);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_keepalive response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    info!("Initiating tunnel restart because the WireGuard keepalive changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_wireguard_backend(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        backend: Option<wireguard::Backend>,
+    ) {
+        let save_result = self.settings.set_wireguard_backend(backend);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_backend response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    info!(
+                        "Initiating tunnel restart because the WireGuard backend setting changed"
+                    );
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_wireguard_rotation_interval(&mut self, tx: oneshot::Sender<()>, days: Option<u32>) {
+        let save_result = self.settings.set_wireguard_key_rotation_interval(days);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_rotation_interval response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    self.schedule_wireguard_key_rotation(Utc::now());
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn ensure_wireguard_keys_for_current_account(&mut self) {
         if let Some(account) = self.settings.get_account_token() {
             if self
@@ -1265,6 +2001,7 @@ where
                     })?;
                     let keygen_event = KeygenEvent::NewKey(public_key);
                     self.event_listener.notify_key_event(keygen_event.clone());
+                    self.schedule_wireguard_key_rotation(Utc::now());
                     Ok(keygen_event)
                 }
                 Err(wireguard::Error::TooManyKeys) => Ok(KeygenEvent::TooManyKeys),
@@ -1282,6 +2019,128 @@ where
         }
     }
 
+    fn on_list_wireguard_keys(&mut self, tx: wireguard::ResponseTx<Vec<RegisteredKey>>) {
+        match self.settings.get_account_token() {
+            Some(account) => self.wireguard_key_manager.list_keys(account, tx),
+            None => Self::oneshot_send(
+                tx,
+                Err(wireguard::Error::NoAccountToken),
+                "list_wireguard_keys response",
+            ),
+        }
+    }
+
+    fn on_revoke_wireguard_key(
+        &mut self,
+        tx: wireguard::ResponseTx<()>,
+        public_key: wireguard::PublicKey,
+    ) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::NoAccountToken),
+                    "revoke_wireguard_key response",
+                );
+                return;
+            }
+        };
+        if self.get_wireguard_public_key(&account) == Some(public_key.clone()) {
+            Self::oneshot_send(
+                tx,
+                Err(wireguard::Error::KeyInUse),
+                "revoke_wireguard_key response",
+            );
+            return;
+        }
+        self.wireguard_key_manager
+            .revoke_key(account, public_key, tx);
+    }
+
+    /// Replaces `old_key` on the current account with a new key, which becomes the key of this
+    /// device. Only `old_key` is removed from the account.
+    fn on_replace_wireguard_key(
+        &mut self,
+        tx: wireguard::ResponseTx<KeygenEvent>,
+        old_key: wireguard::PublicKey,
+    ) {
+        match self.settings.get_account_token() {
+            Some(account) => self
+                .wireguard_key_manager
+                .replace_key_async(account, old_key, tx),
+            None => Self::oneshot_send(
+                tx,
+                Err(wireguard::Error::NoAccountToken),
+                "replace_wireguard_key response",
+            ),
+        }
+    }
+
+    fn handle_wireguard_key_replaced(
+        &mut self,
+        account: AccountToken,
+        result: wireguard::Result<mullvad_types::wireguard::WireguardData>,
+        tx: Option<wireguard::ResponseTx<KeygenEvent>>,
+    ) {
+        let result = result.and_then(|data| self.store_replaced_wireguard_key(account, data));
+        match tx {
+            Some(tx) => Self::oneshot_send(tx, result, "replace_wireguard_key response"),
+            None => self.handle_wireguard_key_rotated(result),
+        }
+    }
+
+    /// The old key keeps working until it's replaced, so a failed rotation is retried without
+    /// bothering the user.
+    fn handle_wireguard_key_rotated(&mut self, result: wireguard::Result<KeygenEvent>) {
+        match result {
+            Ok(_) => self.wireguard_key_manager.reset_rotation_retries(),
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to rotate wireguard key")
+                );
+                self.retry_wireguard_key_rotation();
+            }
+        }
+    }
+
+    /// Stores the key that replaced a key on `account` as the key of this device.
+    fn store_replaced_wireguard_key(
+        &mut self,
+        account: AccountToken,
+        data: mullvad_types::wireguard::WireguardData,
+    ) -> wireguard::Result<KeygenEvent> {
+        let public_key = data.private_key.public_key();
+
+        let mut account_entry = self
+            .account_history
+            .get(&account)
+            .ok()
+            .and_then(|entry| entry)
+            .unwrap_or_else(|| account_history::AccountEntry {
+                account: account.clone(),
+                wireguard: None,
+            });
+        let had_key = account_entry.wireguard.replace(data).is_some();
+        self.account_history
+            .insert(account_entry)
+            .map_err(wireguard::Error::AccountHistoryError)?;
+
+        let keygen_event = KeygenEvent::NewKey(public_key);
+        // The account may have been changed while the key was being replaced.
+        if self.is_current_account(&account) {
+            self.event_listener.notify_key_event(keygen_event.clone());
+            // A key previously used by this device is left on the account, but the tunnel has to
+            // stop using it.
+            if had_key {
+                self.reconnect_wireguard_tunnel();
+            }
+            self.schedule_wireguard_key_rotation(Utc::now());
+        }
+        Ok(keygen_event)
+    }
+
     fn on_get_wireguard_key(&mut self, tx: oneshot::Sender<Option<wireguard::PublicKey>>) {
         let key = self
             .settings
@@ -1334,6 +2193,50 @@ where
         }
     }
 
+    fn on_request_port_forward(
+        &mut self,
+        tx: port_forwarding::ResponseTx<PortForward>,
+        country_code: CountryCode,
+        city_code: CityCode,
+    ) {
+        match self.settings.get_account_token() {
+            Some(account) => self
+                .port_forward_manager
+                .add(account, country_code, city_code, tx),
+            None => Self::oneshot_send(
+                tx,
+                Err(port_forwarding::Error::NoAccountToken),
+                "request_port_forward response",
+            ),
+        }
+    }
+
+    fn on_list_port_forwards(&mut self, tx: port_forwarding::ResponseTx<Vec<PortForward>>) {
+        match self.settings.get_account_token() {
+            Some(account) => self.port_forward_manager.list(account, tx),
+            None => Self::oneshot_send(
+                tx,
+                Err(port_forwarding::Error::NoAccountToken),
+                "list_port_forwards response",
+            ),
+        }
+    }
+
+    fn on_release_port_forward(
+        &mut self,
+        tx: port_forwarding::ResponseTx<()>,
+        port_forward: PortForward,
+    ) {
+        match self.settings.get_account_token() {
+            Some(account) => self.port_forward_manager.remove(account, port_forward, tx),
+            None => Self::oneshot_send(
+                tx,
+                Err(port_forwarding::Error::NoAccountToken),
+                "release_port_forward response",
+            ),
+        }
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.clone(), "get_settings response");
     }
@@ -1,4 +1,5 @@
-use crate::EventListener;
+use crate::{port_forwarding, wireguard, EventListener};
+use chrono::{offset::Utc, DateTime};
 use jsonrpc_core::{
     futures::{
         future,
@@ -14,12 +15,19 @@ use mullvad_paths;
 use mullvad_rpc;
 use mullvad_types::{
     account::{AccountData, AccountToken},
-    location::GeoIpLocation,
+    auto_connect::AutoConnectRules,
+    captive_portal::{self, CaptivePortalEvent},
+    diagnostics::Diagnostics,
+    location::{CityCode, CountryCode, GeoIpLocation},
+    port_forward::PortForward,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
     relay_list::RelayList,
     settings::{self, Settings},
     states::{TargetState, TunnelState},
-    version, DaemonEvent,
+    version,
+    wg_quick::WgQuickConfig,
+    wireguard::RegisteredKey,
+    DaemonEvent,
 };
 use parking_lot::{Mutex, RwLock};
 use std::{
@@ -28,7 +36,15 @@ use std::{
 };
 use talpid_core::mpsc::IntoSender;
 use talpid_ipc;
-use talpid_types::{net::wireguard, ErrorExt};
+use talpid_types::{
+    net::{
+        dns::{DnsUpstream, LocalResolverSettings, SplitDns},
+        lan::LanPolicy,
+        network_info::NetworkInfo,
+    },
+    tunnel::TunnelStats,
+    ErrorExt,
+};
 use uuid;
 
 /// FIXME(linus): This is here just because the futures crate has deprecated it and jsonrpc_core
@@ -64,10 +80,33 @@ build_rpc_trait! {
             Self::Metadata, RelaySettingsUpdate
             ) -> BoxFuture<(), Error>;
 
+        /// Use the WireGuard tunnel in a wg-quick configuration file as a custom relay. The MTU is
+        /// also applied if the file sets one.
+        #[rpc(meta, name = "import_wireguard_config")]
+        fn import_wireguard_config(&self, Self::Metadata, String) -> BoxFuture<(), Error>;
+
+        /// Returns the custom WireGuard relay in the format of wg-quick configuration files.
+        #[rpc(meta, name = "export_wireguard_config")]
+        fn export_wireguard_config(&self, Self::Metadata) -> BoxFuture<String, Error>;
+
         /// Set if the client should allow communication with the LAN while in secured state.
+        /// Shorthand for setting a LAN policy that either allows or blocks everything.
         #[rpc(meta, name = "set_allow_lan")]
         fn set_allow_lan(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set what communication with the LAN the client should allow while in secured state.
+        #[rpc(meta, name = "set_lan_policy")]
+        fn set_lan_policy(&self, Self::Metadata, LanPolicy) -> BoxFuture<(), Error>;
+
+        /// Set which domains are resolved by the DNS servers of the physical network instead
+        /// of through the tunnel.
+        #[rpc(meta, name = "set_split_dns")]
+        fn set_split_dns(&self, Self::Metadata, SplitDns) -> BoxFuture<(), Error>;
+
+        /// Set if DNS should be resolved by a local resolver, and which blocklists it applies.
+        #[rpc(meta, name = "set_local_resolver")]
+        fn set_local_resolver(&self, Self::Metadata, LocalResolverSettings) -> BoxFuture<(), Error>;
+
         /// Set if the client should allow network communication when in the disconnected state.
         #[rpc(meta, name = "set_block_when_disconnected")]
         fn set_block_when_disconnected(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -76,6 +115,16 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_auto_connect")]
         fn set_auto_connect(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set the rules that connect or disconnect the tunnel when the connected networks
+        /// change.
+        #[rpc(meta, name = "set_auto_connect_rules")]
+        fn set_auto_connect_rules(&self, Self::Metadata, AutoConnectRules) -> BoxFuture<(), Error>;
+
+        /// Returns the networks the device is currently connected to, as seen by the
+        /// auto-connect rules.
+        #[rpc(meta, name = "get_network_info")]
+        fn get_network_info(&self, Self::Metadata) -> BoxFuture<NetworkInfo, Error>;
+
         /// Try to connect if disconnected, or do nothing if already connecting/connected.
         #[rpc(meta, name = "connect")]
         fn connect(&self, Self::Metadata) -> BoxFuture<(), Error>;
@@ -90,6 +139,21 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_state")]
         fn get_state(&self, Self::Metadata) -> BoxFuture<TunnelState, Error>;
 
+        /// Returns troubleshooting details about the tunnel state machine, such as how long
+        /// the tunnel has been in its current state and the last error.
+        #[rpc(meta, name = "get_diagnostics")]
+        fn get_diagnostics(&self, Self::Metadata) -> BoxFuture<Diagnostics, Error>;
+
+        /// Returns the traffic counters of the current tunnel, or `None` if there is no tunnel
+        /// or its counters can't be read.
+        #[rpc(meta, name = "get_tunnel_stats")]
+        fn get_tunnel_stats(&self, Self::Metadata) -> BoxFuture<Option<TunnelStats>, Error>;
+
+        /// Sets how many seconds apart the `tunnel_stats` events are sent while connected.
+        /// `None` stops sending them.
+        #[rpc(meta, name = "set_tunnel_stats_interval")]
+        fn set_tunnel_stats_interval(&self, Self::Metadata, Option<u32>) -> BoxFuture<(), Error>;
+
         /// Performs a geoIP lookup and returns the current location as perceived by the public
         /// internet.
         #[rpc(meta, name = "get_current_location")]
@@ -123,10 +187,28 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_enable_ipv6")]
         fn set_enable_ipv6(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set where DNS queries are sent while connected, and if they are encrypted
+        #[rpc(meta, name = "set_dns_upstream")]
+        fn set_dns_upstream(&self, Self::Metadata, DnsUpstream) -> BoxFuture<(), Error>;
+
         /// Set MTU for wireguard tunnels
         #[rpc(meta, name = "set_wireguard_mtu")]
         fn set_wireguard_mtu(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
 
+        /// Set if the MTU for wireguard tunnels is picked from the path to the relay, when no MTU
+        /// is set
+        #[rpc(meta, name = "set_wireguard_auto_mtu")]
+        fn set_wireguard_auto_mtu(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
+        /// Set which WireGuard implementation runs the tunnel, or unset it to use the best one
+        #[rpc(meta, name = "set_wireguard_backend")]
+        fn set_wireguard_backend(&self, Self::Metadata, Option<wireguard::Backend>) -> BoxFuture<(), Error>;
+
+        /// Set how many seconds apart keepalive packets are sent to WireGuard peers that don't
+        /// set their own interval. Unset it to not send any.
+        #[rpc(meta, name = "set_wireguard_keepalive")]
+        fn set_wireguard_keepalive(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
+
         /// Returns the current daemon settings
         #[rpc(meta, name = "get_settings")]
         fn get_settings(&self, Self::Metadata) -> BoxFuture<Settings, Error>;
@@ -143,6 +225,42 @@ build_rpc_trait! {
         #[rpc(meta, name = "verify_wireguard_key")]
         fn verify_wireguard_key(&self, Self::Metadata) -> BoxFuture<bool, Error>;
 
+        /// Sets after how many days the wireguard key is replaced. `None` disables key rotation.
+        #[rpc(meta, name = "set_wireguard_rotation_interval")]
+        fn set_wireguard_rotation_interval(&self, Self::Metadata, Option<u32>) -> BoxFuture<(), Error>;
+
+        /// Fetch the wireguard keys registered to the current account.
+        #[rpc(meta, name = "list_wireguard_keys")]
+        fn list_wireguard_keys(&self, Self::Metadata) -> BoxFuture<Vec<RegisteredKey>, Error>;
+
+        /// Remove a wireguard key from the current account. The key in use by this device can
+        /// only be replaced.
+        #[rpc(meta, name = "revoke_wireguard_key")]
+        fn revoke_wireguard_key(&self, Self::Metadata, wireguard::PublicKey) -> BoxFuture<(), Error>;
+
+        /// Replace a wireguard key registered to the current account with a new key for this
+        /// device.
+        #[rpc(meta, name = "replace_wireguard_key")]
+        fn replace_wireguard_key(
+            &self,
+            Self::Metadata, wireguard::PublicKey
+            ) -> BoxFuture<mullvad_types::wireguard::KeygenEvent, Error>;
+
+        /// Request a port to be forwarded through the tunnel on all relays in the given city.
+        #[rpc(meta, name = "request_port_forward")]
+        fn request_port_forward(
+            &self,
+            Self::Metadata, CountryCode, CityCode
+            ) -> BoxFuture<PortForward, Error>;
+
+        /// Fetch the ports currently forwarded for the current account.
+        #[rpc(meta, name = "list_port_forwards")]
+        fn list_port_forwards(&self, Self::Metadata) -> BoxFuture<Vec<PortForward>, Error>;
+
+        /// Release a previously forwarded port.
+        #[rpc(meta, name = "release_port_forward")]
+        fn release_port_forward(&self, Self::Metadata, PortForward) -> BoxFuture<(), Error>;
+
         /// Retreive version of the app
         #[rpc(meta, name = "get_current_version")]
         fn get_current_version(&self, Self::Metadata) -> BoxFuture<String, Error>;
@@ -151,6 +269,16 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_version_info")]
         fn get_version_info(&self, Self::Metadata) -> BoxFuture<version::AppVersionInfo, Error>;
 
+        /// Lets the traffic needed to log in to a captive portal through the firewall while
+        /// blocking, for the given number of minutes. Disconnects any open tunnel. Returns the
+        /// time when traffic is blocked again.
+        #[rpc(meta, name = "allow_captive_portal")]
+        fn allow_captive_portal(&self, Self::Metadata, u32) -> BoxFuture<DateTime<Utc>, Error>;
+
+        /// Stops letting captive portal login traffic through before its time runs out.
+        #[rpc(meta, name = "end_captive_portal")]
+        fn end_captive_portal(&self, Self::Metadata) -> BoxFuture<(), Error>;
+
         /// Remove all configuration and cache files
         #[rpc(meta, name = "factory_reset")]
         fn factory_reset(&self, Self::Metadata) -> BoxFuture<(), Error>;
@@ -178,6 +306,12 @@ pub enum ManagementCommand {
     SetTargetState(OneshotSender<Result<(), ()>>, TargetState),
     /// Request the current state.
     GetState(OneshotSender<TunnelState>),
+    /// Request diagnostics about the tunnel state machine.
+    GetDiagnostics(OneshotSender<Diagnostics>),
+    /// Request the traffic counters of the current tunnel.
+    GetTunnelStats(OneshotSender<Option<TunnelStats>>),
+    /// Set the number of seconds between tunnel statistics events.
+    SetTunnelStatsInterval(OneshotSender<()>, Option<u32>),
     /// Get the current geographical location.
     GetCurrentLocation(OneshotSender<Option<GeoIpLocation>>),
     /// Request the metadata for an account.
@@ -198,12 +332,25 @@ pub enum ManagementCommand {
     SetAccount(OneshotSender<()>, Option<AccountToken>),
     /// Place constraints on the type of tunnel and relay
     UpdateRelaySettings(OneshotSender<()>, RelaySettingsUpdate),
-    /// Set the allow LAN setting.
-    SetAllowLan(OneshotSender<()>, bool),
+    /// Use a WireGuard tunnel from a wg-quick configuration file as a custom relay
+    ImportWireguardConfig(OneshotSender<()>, WgQuickConfig),
+    /// Get the custom WireGuard relay as a wg-quick configuration. `None` if no custom WireGuard
+    /// relay is set.
+    ExportWireguardConfig(OneshotSender<Option<WgQuickConfig>>),
+    /// Set the LAN policy setting.
+    SetLanPolicy(OneshotSender<Result<(), settings::Error>>, LanPolicy),
+    /// Set which domains are resolved outside the tunnel.
+    SetSplitDns(OneshotSender<Result<(), settings::Error>>, SplitDns),
+    /// Set the local DNS resolver settings.
+    SetLocalResolver(OneshotSender<()>, LocalResolverSettings),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(OneshotSender<()>, bool),
     /// Set the auto-connect setting.
     SetAutoConnect(OneshotSender<()>, bool),
+    /// Set the auto-connect rules.
+    SetAutoConnectRules(OneshotSender<()>, AutoConnectRules),
+    /// Get the networks the device is connected to.
+    GetNetworkInfo(OneshotSender<NetworkInfo>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(OneshotSender<()>, Option<u16>),
     /// Set proxy details for OpenVPN
@@ -212,8 +359,16 @@ pub enum ManagementCommand {
     SetBridgeState(OneshotSender<Result<(), settings::Error>>, BridgeState),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(OneshotSender<()>, bool),
+    /// Set where DNS queries are sent while connected
+    SetDnsUpstream(OneshotSender<()>, DnsUpstream),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(OneshotSender<()>, Option<u16>),
+    /// Set if the MTU for wireguard tunnels is picked automatically
+    SetWireguardAutoMtu(OneshotSender<()>, bool),
+    /// Set which WireGuard implementation runs the tunnel
+    SetWireguardBackend(OneshotSender<()>, Option<wireguard::Backend>),
+    /// Set the persistent keepalive interval for wireguard tunnels
+    SetWireguardKeepalive(OneshotSender<()>, Option<u16>),
     /// Get the daemon settings
     GetSettings(OneshotSender<Settings>),
     /// Generate new wireguard key
@@ -222,10 +377,35 @@ pub enum ManagementCommand {
     GetWireguardKey(OneshotSender<Option<wireguard::PublicKey>>),
     /// Verify if the currently set wireguard key is valid.
     VerifyWireguardKey(OneshotSender<bool>),
+    /// Set the number of days after which the wireguard key is rotated
+    SetWireguardRotationInterval(OneshotSender<()>, Option<u32>),
+    /// List the wireguard keys registered to the current account
+    ListWireguardKeys(OneshotSender<wireguard::Result<Vec<RegisteredKey>>>),
+    /// Remove a wireguard key from the current account
+    RevokeWireguardKey(OneshotSender<wireguard::Result<()>>, wireguard::PublicKey),
+    /// Replace a wireguard key on the current account with a new one for this device
+    ReplaceWireguardKey(
+        OneshotSender<wireguard::Result<mullvad_types::wireguard::KeygenEvent>>,
+        wireguard::PublicKey,
+    ),
+    /// Request a new forwarded port in the given city
+    RequestPortForward(
+        OneshotSender<port_forwarding::Result<PortForward>>,
+        CountryCode,
+        CityCode,
+    ),
+    /// List the ports forwarded for the current account
+    ListPortForwards(OneshotSender<port_forwarding::Result<Vec<PortForward>>>),
+    /// Release a forwarded port
+    ReleasePortForward(OneshotSender<port_forwarding::Result<()>>, PortForward),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(OneshotSender<BoxFuture<version::AppVersionInfo, mullvad_rpc::Error>>),
     /// Get current version of the app
     GetCurrentVersion(OneshotSender<version::AppVersion>),
+    /// Allow captive portal login traffic for the given number of minutes
+    AllowCaptivePortal(OneshotSender<DateTime<Utc>>, u32),
+    /// Stop allowing captive portal login traffic
+    EndCaptivePortal(OneshotSender<()>),
     #[cfg(not(target_os = "android"))]
     /// Remove settings and clear the cache
     FactoryReset(OneshotSender<()>),
@@ -309,6 +489,16 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
         log::debug!("Broadcasting new wireguard key event");
         self.notify(DaemonEvent::WireguardKey(key_event));
     }
+
+    fn notify_captive_portal_event(&self, event: CaptivePortalEvent) {
+        log::debug!("Broadcasting captive portal event");
+        self.notify(DaemonEvent::CaptivePortal(event));
+    }
+
+    fn notify_tunnel_stats(&self, stats: TunnelStats) {
+        log::trace!("Broadcasting tunnel statistics");
+        self.notify(DaemonEvent::TunnelStats(stats));
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
@@ -366,6 +556,41 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
             _ => Error::internal_error(),
         }
     }
+
+    /// Converts a port forwarding error to an error that can be given to the caller of the API.
+    fn map_port_forwarding_error(error: port_forwarding::Error) -> Error {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Port forwarding request failed")
+        );
+        match error {
+            port_forwarding::Error::NoAccountToken => Error {
+                code: ErrorCode::ServerError(-900),
+                message: "No account token configured".to_owned(),
+                data: None,
+            },
+            port_forwarding::Error::RpcError(ref rpc_error) => Self::map_rpc_error(rpc_error),
+            _ => Error::internal_error(),
+        }
+    }
+
+    /// Converts a wireguard key error to an error that can be given to the caller of the API.
+    fn map_wireguard_error(error: wireguard::Error) -> Error {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Wireguard key request failed")
+        );
+        match error {
+            wireguard::Error::NoAccountToken => Error {
+                code: ErrorCode::ServerError(-900),
+                message: "No account token configured".to_owned(),
+                data: None,
+            },
+            wireguard::Error::KeyInUse => Error::invalid_params(error.to_string()),
+            wireguard::Error::RpcError(ref rpc_error) => Self::map_rpc_error(rpc_error),
+            _ => Error::internal_error(),
+        }
+    }
 }
 
 impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
@@ -437,11 +662,89 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn set_allow_lan(&self, _: Self::Metadata, allow_lan: bool) -> BoxFuture<(), Error> {
+    fn import_wireguard_config(&self, _: Self::Metadata, config: String) -> BoxFuture<(), Error> {
+        log::debug!("import_wireguard_config");
+        let config = match config.parse::<WgQuickConfig>() {
+            Ok(config) => config,
+            Err(error) => return Box::new(future::err(Error::invalid_params(error.to_string()))),
+        };
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ImportWireguardConfig(tx, config))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn export_wireguard_config(&self, _: Self::Metadata) -> BoxFuture<String, Error> {
+        log::debug!("export_wireguard_config");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ExportWireguardConfig(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|config| match config {
+                Some(config) => Ok(config.to_string()),
+                None => Err(Error {
+                    code: ErrorCode::InvalidRequest,
+                    message: "No custom WireGuard relay is set".to_owned(),
+                    data: None,
+                }),
+            });
+        Box::new(future)
+    }
+
+    fn set_allow_lan(&self, meta: Self::Metadata, allow_lan: bool) -> BoxFuture<(), Error> {
         log::debug!("set_allow_lan({})", allow_lan);
+        let lan_policy = if allow_lan {
+            LanPolicy::allow_all()
+        } else {
+            LanPolicy::block_all()
+        };
+        self.set_lan_policy(meta, lan_policy)
+    }
+
+    fn set_lan_policy(&self, _: Self::Metadata, lan_policy: LanPolicy) -> BoxFuture<(), Error> {
+        log::debug!("set_lan_policy({:?})", lan_policy);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetLanPolicy(tx, lan_policy))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error {
+                    settings::Error::InvalidLanPolicy(reason) => {
+                        Error::invalid_params(reason.to_string())
+                    }
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_split_dns(&self, _: Self::Metadata, split_dns: SplitDns) -> BoxFuture<(), Error> {
+        log::debug!("set_split_dns({:?})", split_dns);
         let (tx, rx) = sync::oneshot::channel();
         let future = self
-            .send_command_to_daemon(ManagementCommand::SetAllowLan(tx, allow_lan))
+            .send_command_to_daemon(ManagementCommand::SetSplitDns(tx, split_dns))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error {
+                    settings::Error::InvalidSplitDns(reason) => {
+                        Error::invalid_params(reason.to_string())
+                    }
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_local_resolver(
+        &self,
+        _: Self::Metadata,
+        local_resolver: LocalResolverSettings,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_local_resolver({:?})", local_resolver);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetLocalResolver(tx, local_resolver))
             .and_then(|_| rx.map_err(|_| Error::internal_error()));
         Box::new(future)
     }
@@ -471,6 +774,28 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn set_auto_connect_rules(
+        &self,
+        _: Self::Metadata,
+        rules: AutoConnectRules,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_auto_connect_rules({:?})", rules);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetAutoConnectRules(tx, rules))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_network_info(&self, _: Self::Metadata) -> BoxFuture<NetworkInfo, Error> {
+        log::debug!("get_network_info");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetNetworkInfo(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn connect(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
         log::debug!("connect");
         let (tx, rx) = sync::oneshot::channel();
@@ -509,6 +834,37 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn get_diagnostics(&self, _: Self::Metadata) -> BoxFuture<Diagnostics, Error> {
+        log::debug!("get_diagnostics");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetDiagnostics(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_tunnel_stats(&self, _: Self::Metadata) -> BoxFuture<Option<TunnelStats>, Error> {
+        log::debug!("get_tunnel_stats");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetTunnelStats(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_tunnel_stats_interval(
+        &self,
+        _: Self::Metadata,
+        seconds: Option<u32>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_tunnel_stats_interval({:?})", seconds);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetTunnelStatsInterval(tx, seconds))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn get_current_location(&self, _: Self::Metadata) -> BoxFuture<Option<GeoIpLocation>, Error> {
         log::debug!("get_current_location");
         let (tx, rx) = sync::oneshot::channel();
@@ -605,6 +961,20 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn set_dns_upstream(
+        &self,
+        _: Self::Metadata,
+        dns_upstream: DnsUpstream,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_dns_upstream({:?})", dns_upstream);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetDnsUpstream(tx, dns_upstream))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+        Box::new(future)
+    }
+
     /// Set MTU for wireguard tunnels
     fn set_wireguard_mtu(&self, _: Self::Metadata, mtu: Option<u16>) -> BoxFuture<(), Error> {
         log::debug!("set_wireguard_mtu({:?})", mtu);
@@ -615,6 +985,46 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn set_wireguard_auto_mtu(&self, _: Self::Metadata, auto_mtu: bool) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_auto_mtu({})", auto_mtu);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetWireguardAutoMtu(tx, auto_mtu))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_wireguard_backend(
+        &self,
+        _: Self::Metadata,
+        backend: Option<wireguard::Backend>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_backend({:?})", backend);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetWireguardBackend(tx, backend))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_wireguard_keepalive(
+        &self,
+        _: Self::Metadata,
+        interval: Option<u16>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_keepalive({:?})", interval);
+        if interval == Some(0) {
+            return Box::new(future::err(Error::invalid_params(
+                "The keepalive interval must be at least one second",
+            )));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetWireguardKeepalive(tx, interval))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn get_settings(&self, _: Self::Metadata) -> BoxFuture<Settings, Error> {
         log::debug!("get_settings");
         let (tx, rx) = sync::oneshot::channel();
@@ -657,6 +1067,105 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn set_wireguard_rotation_interval(
+        &self,
+        _: Self::Metadata,
+        days: Option<u32>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_rotation_interval({:?})", days);
+        if days == Some(0) {
+            return Box::new(future::err(Error::invalid_params(
+                "The rotation interval must be at least one day",
+            )));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetWireguardRotationInterval(tx, days))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn list_wireguard_keys(&self, _: Self::Metadata) -> BoxFuture<Vec<RegisteredKey>, Error> {
+        log::debug!("list_wireguard_keys");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ListWireguardKeys(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_wireguard_error));
+        Box::new(future)
+    }
+
+    fn revoke_wireguard_key(
+        &self,
+        _: Self::Metadata,
+        public_key: wireguard::PublicKey,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("revoke_wireguard_key({})", public_key);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RevokeWireguardKey(tx, public_key))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_wireguard_error));
+        Box::new(future)
+    }
+
+    fn replace_wireguard_key(
+        &self,
+        _: Self::Metadata,
+        public_key: wireguard::PublicKey,
+    ) -> BoxFuture<mullvad_types::wireguard::KeygenEvent, Error> {
+        log::debug!("replace_wireguard_key({})", public_key);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ReplaceWireguardKey(tx, public_key))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_wireguard_error));
+        Box::new(future)
+    }
+
+    fn request_port_forward(
+        &self,
+        _: Self::Metadata,
+        country_code: CountryCode,
+        city_code: CityCode,
+    ) -> BoxFuture<PortForward, Error> {
+        log::debug!("request_port_forward({}, {})", country_code, city_code);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RequestPortForward(
+                tx,
+                country_code,
+                city_code,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_port_forwarding_error));
+        Box::new(future)
+    }
+
+    fn list_port_forwards(&self, _: Self::Metadata) -> BoxFuture<Vec<PortForward>, Error> {
+        log::debug!("list_port_forwards");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ListPortForwards(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_port_forwarding_error));
+        Box::new(future)
+    }
+
+    fn release_port_forward(
+        &self,
+        _: Self::Metadata,
+        port_forward: PortForward,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("release_port_forward({})", port_forward);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ReleasePortForward(tx, port_forward))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_port_forwarding_error));
+        Box::new(future)
+    }
+
     fn get_current_version(&self, _: Self::Metadata) -> BoxFuture<String, Error> {
         log::debug!("get_current_version");
         let (tx, rx) = sync::oneshot::channel();
@@ -686,6 +1195,34 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn allow_captive_portal(
+        &self,
+        _: Self::Metadata,
+        minutes: u32,
+    ) -> BoxFuture<DateTime<Utc>, Error> {
+        log::debug!("allow_captive_portal({})", minutes);
+        if minutes == 0 || minutes > captive_portal::MAX_MINUTES {
+            return Box::new(future::err(Error::invalid_params(format!(
+                "Captive portal login traffic can be allowed for 1 to {} minutes",
+                captive_portal::MAX_MINUTES
+            ))));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::AllowCaptivePortal(tx, minutes))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn end_captive_portal(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
+        log::debug!("end_captive_portal");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::EndCaptivePortal(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn factory_reset(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
         #[cfg(not(target_os = "android"))]
         {
@@ -0,0 +1,108 @@
+use crate::InternalDaemonEvent;
+use futures::{future::Executor, sync::oneshot, Future};
+use mullvad_rpc::{HttpHandle, PortForwardingProxy};
+use mullvad_types::{
+    account::AccountToken,
+    location::{CityCode, CountryCode},
+    port_forward::PortForward,
+};
+use std::sync::mpsc;
+use tokio_core::reactor::Remote;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "No account token configured")]
+    NoAccountToken,
+    #[error(display = "Unexpected RPC error")]
+    RpcError(#[error(cause)] jsonrpc_client_core::Error),
+    #[error(display = "Unable to save port forwards in the settings")]
+    SettingsError(#[error(cause)] mullvad_types::settings::Error),
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Channel used to answer the management interface client that issued a port forwarding command.
+pub type ResponseTx<T> = oneshot::Sender<Result<T>>;
+
+/// The outcome of a port forwarding API call. Sent back to the daemon so that the settings can be
+/// updated before the management interface client gets its response.
+pub enum PortForwardEvent {
+    Added(AccountToken, Result<PortForward>, ResponseTx<PortForward>),
+    Listed(
+        AccountToken,
+        Result<Vec<PortForward>>,
+        ResponseTx<Vec<PortForward>>,
+    ),
+    Removed(AccountToken, PortForward, Result<()>, ResponseTx<()>),
+}
+
+/// Performs port forwarding requests against the API on the tokio event loop.
+pub struct PortForwardManager {
+    daemon_tx: mpsc::Sender<InternalDaemonEvent>,
+    proxy: PortForwardingProxy<HttpHandle>,
+    tokio_remote: Remote,
+}
+
+impl PortForwardManager {
+    pub(crate) fn new(
+        daemon_tx: mpsc::Sender<InternalDaemonEvent>,
+        http_handle: HttpHandle,
+        tokio_remote: Remote,
+    ) -> Self {
+        Self {
+            daemon_tx,
+            proxy: PortForwardingProxy::new(http_handle),
+            tokio_remote,
+        }
+    }
+
+    /// Request a new port to be forwarded on all relays in the given city.
+    pub fn add(
+        &mut self,
+        account: AccountToken,
+        country_code: CountryCode,
+        city_code: CityCode,
+        tx: ResponseTx<PortForward>,
+    ) {
+        let future = self
+            .proxy
+            .add_port_forward(account.clone(), country_code, city_code);
+        self.spawn(future, move |result| {
+            PortForwardEvent::Added(account, result, tx)
+        });
+    }
+
+    /// Fetch all ports currently forwarded for the given account.
+    pub fn list(&mut self, account: AccountToken, tx: ResponseTx<Vec<PortForward>>) {
+        let future = self.proxy.list_port_forwards(account.clone());
+        self.spawn(future, move |result| {
+            PortForwardEvent::Listed(account, result, tx)
+        });
+    }
+
+    /// Release a previously forwarded port.
+    pub fn remove(&mut self, account: AccountToken, port_forward: PortForward, tx: ResponseTx<()>) {
+        let future = self
+            .proxy
+            .remove_port_forward(account.clone(), port_forward.clone());
+        self.spawn(future, move |result| {
+            PortForwardEvent::Removed(account, port_forward, result, tx)
+        });
+    }
+
+    fn spawn<T, F, E>(&self, future: F, into_event: E)
+    where
+        F: Future<Item = T, Error = jsonrpc_client_core::Error> + Send + 'static,
+        E: FnOnce(Result<T>) -> PortForwardEvent + Send + 'static,
+    {
+        let daemon_tx = self.daemon_tx.clone();
+        let future = future.then(move |result| {
+            let event = into_event(result.map_err(Error::RpcError));
+            let _ = daemon_tx.send(InternalDaemonEvent::PortForwardEvent(event));
+            Ok(())
+        });
+        if self.tokio_remote.execute(future).is_err() {
+            log::error!("Failed to spawn a future for a port forwarding request");
+        }
+    }
+}
@@ -567,29 +567,63 @@ impl RelaySelector {
         data: WireguardEndpointData,
         constraints: &WireguardConstraints,
     ) -> Option<MullvadEndpoint> {
-        let port = self.get_port_for_wireguard_relay(&data, &constraints)?;
+        let (port, protocol) = self.get_port_for_wireguard_relay(&data, &constraints)?;
         let peer_config = wireguard::PeerConfig {
             public_key: data.public_key,
             endpoint: SocketAddr::new(host, port),
             allowed_ips: all_of_the_internet(),
+            preshared_key: None,
+            persistent_keepalive: None,
         };
         Some(MullvadEndpoint::Wireguard {
             peer: peer_config,
             ipv4_gateway: data.ipv4_gateway,
             ipv6_gateway: data.ipv6_gateway,
+            protocol,
         })
     }
 
+    /// Picks the port and transport to reach a WireGuard relay on. UDP is used unless TCP is
+    /// required, or the port constraint is only satisfied by TCP. TCP is only used on relays that
+    /// list ports for it.
     fn get_port_for_wireguard_relay(
         &mut self,
         data: &WireguardEndpointData,
         constraints: &WireguardConstraints,
+    ) -> Option<(u16, TransportProtocol)> {
+        match constraints.protocol {
+            Constraint::Only(protocol) => self
+                .get_port_for_wireguard_protocol(data, constraints.port, protocol)
+                .map(|port| (port, protocol)),
+            Constraint::Any => self
+                .get_port_for_wireguard_protocol(data, constraints.port, TransportProtocol::Udp)
+                .map(|port| (port, TransportProtocol::Udp))
+                .or_else(|| {
+                    self.get_port_for_wireguard_protocol(
+                        data,
+                        constraints.port,
+                        TransportProtocol::Tcp,
+                    )
+                    .map(|port| (port, TransportProtocol::Tcp))
+                }),
+        }
+    }
+
+    fn get_port_for_wireguard_protocol(
+        &mut self,
+        data: &WireguardEndpointData,
+        port: Constraint<u16>,
+        protocol: TransportProtocol,
     ) -> Option<u16> {
-        match constraints.port {
+        match port {
             Constraint::Any => {
+                let port_ranges = match protocol {
+                    TransportProtocol::Udp => &data.port_ranges,
+                    TransportProtocol::Tcp => &data.tcp_port_ranges,
+                };
                 let get_port_amount =
                     |range: &(u16, u16)| -> u64 { (1 + range.1 - range.0) as u64 };
-                let port_amount: u64 = data.port_ranges.iter().map(get_port_amount).sum();
+                let port_amount: u64 = port_ranges.iter().map(get_port_amount).sum();
 
                 if port_amount < 1 {
                     return None;
@@ -597,7 +631,7 @@ impl RelaySelector {
 
                 let mut port_index = self.rng.gen_range(0, port_amount);
 
-                for range in data.port_ranges.iter() {
+                for range in port_ranges.iter() {
                     let ports_in_range = get_port_amount(range);
                     if port_index < ports_in_range {
                         return Some(port_index as u16 + range.0);
@@ -607,11 +641,7 @@ impl RelaySelector {
                 panic!("Port selection algorithm is broken")
             }
             Constraint::Only(port) => {
-                if data
-                    .port_ranges
-                    .iter()
-                    .any(|range| (range.0 <= port && port <= range.1))
-                {
+                if data.has_port(port, protocol) {
                     Some(port)
                 } else {
                     None
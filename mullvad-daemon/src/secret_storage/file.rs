@@ -0,0 +1,402 @@
+use super::{Error, Result, SecretStorage};
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    sign::Signer,
+    symm::{self, Cipher},
+};
+use rand::{rngs::OsRng, RngCore};
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The first byte of every file, so the format can be changed later.
+const FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + NONCE_LEN + TAG_LEN;
+const KEY_LEN: usize = 32;
+/// Holds the random key that the keys of the secret files are derived from.
+const KEY_FILE: &str = "secret-storage.key";
+
+/// Keeps a secret in a file encrypted with AES-256-GCM. The key is derived from a random key,
+/// kept in a file of its own that only the user running the daemon can read, and the ID of the
+/// machine. A copy of the files doesn't decrypt on another machine, even with the key file.
+pub struct FileStorage {
+    path: PathBuf,
+    key: [u8; KEY_LEN],
+}
+
+impl FileStorage {
+    /// Opens the secret called `name` in `dir`, encrypted with a key derived from the key in
+    /// `key_dir` and the machine ID. The key file is created if there is none.
+    pub fn open(dir: &Path, key_dir: &Path, name: &str) -> Result<Self> {
+        let machine_id = machine_id().map_err(Error::MachineId)?;
+        Self::open_on_machine(dir, key_dir, name, &machine_id)
+    }
+
+    fn open_on_machine(dir: &Path, key_dir: &Path, name: &str, machine_id: &[u8]) -> Result<Self> {
+        let file_key = read_or_create_key(&key_dir.join(KEY_FILE)).map_err(Error::Key)?;
+        Ok(FileStorage {
+            path: Self::path(dir, name),
+            key: derive_key(&file_key, machine_id).map_err(Error::DeriveKey)?,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn exists(dir: &Path, name: &str) -> bool {
+        Self::path(dir, name).exists()
+    }
+
+    /// Deletes the file.
+    #[cfg(target_os = "linux")]
+    pub fn remove(self) -> Result<()> {
+        fs::remove_file(self.path).map_err(Error::Write)
+    }
+
+    fn path(dir: &Path, name: &str) -> PathBuf {
+        dir.join(format!("{}.enc", name))
+    }
+}
+
+impl SecretStorage for FileStorage {
+    fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Error::Read(error)),
+        };
+        if data.is_empty() {
+            return Ok(None);
+        }
+        decrypt(&self.key, &data).map(Some)
+    }
+
+    fn write(&mut self, secret: &[u8]) -> Result<()> {
+        let data = encrypt(&self.key, secret)?;
+        write_atomically(&self.path, &data).map_err(Error::Write)
+    }
+}
+
+impl fmt::Display for FileStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "encrypted file {}", self.path.display())
+    }
+}
+
+/// Replaces the contents of `path` with `data`. The data is written to a temporary file that is
+/// renamed over `path` once it is on disk, so a crash can't leave a partially written file behind.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = open_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp_path, path)?;
+
+    // The rename itself is only durable once the directory is synced.
+    #[cfg(unix)]
+    {
+        if let Some(dir) = path.parent() {
+            fs::File::open(dir)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+fn open_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+fn encrypt(key: &[u8; KEY_LEN], secret: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[FORMAT_VERSION],
+        secret,
+        &mut tag,
+    )
+    .map_err(Error::Encrypt)?;
+
+    let mut data = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    data.push(FORMAT_VERSION);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&tag);
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+fn decrypt(key: &[u8; KEY_LEN], data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < HEADER_LEN || data[0] != FORMAT_VERSION {
+        return Err(Error::InvalidFormat);
+    }
+    let (nonce, rest) = data[1..].split_at(NONCE_LEN);
+    let (tag, ciphertext) = rest.split_at(TAG_LEN);
+    symm::decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        &data[..1],
+        ciphertext,
+        tag,
+    )
+    .map_err(Error::Decrypt)
+}
+
+/// Binds `file_key` to the machine, as the HMAC-SHA256 of `machine_id` keyed with it.
+fn derive_key(
+    file_key: &[u8; KEY_LEN],
+    machine_id: &[u8],
+) -> std::result::Result<[u8; KEY_LEN], openssl::error::ErrorStack> {
+    let hmac_key = PKey::hmac(file_key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &hmac_key)?;
+    signer.update(machine_id)?;
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&signer.sign_to_vec()?);
+    Ok(key)
+}
+
+/// Returns an ID that is unique to the installation of the OS. It's readable by unprivileged
+/// users too, so it only binds the key to the machine, while the key file keeps it secret.
+#[cfg(target_os = "linux")]
+fn machine_id() -> io::Result<Vec<u8>> {
+    // Older systems without systemd only have the copy kept by D-Bus.
+    fs::read_to_string("/etc/machine-id")
+        .or_else(|_| fs::read_to_string("/var/lib/dbus/machine-id"))
+        .map(|id| id.trim().as_bytes().to_vec())
+}
+
+#[cfg(target_os = "macos")]
+fn machine_id() -> io::Result<Vec<u8>> {
+    const UUID_KEY: &str = "\"IOPlatformUUID\" = \"";
+
+    let output = std::process::Command::new("/usr/sbin/ioreg")
+        .args(&["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    output
+        .lines()
+        .find_map(|line| {
+            let start = line.find(UUID_KEY)? + UUID_KEY.len();
+            let end = start + line[start..].find('"')?;
+            Some(line[start..end].as_bytes().to_vec())
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No IOPlatformUUID"))
+}
+
+#[cfg(windows)]
+fn machine_id() -> io::Result<Vec<u8>> {
+    const CRYPTOGRAPHY_KEY: &str = r"HKLM\SOFTWARE\Microsoft\Cryptography";
+
+    let output = std::process::Command::new("reg")
+        .args(&["query", CRYPTOGRAPHY_KEY, "/v", "MachineGuid"])
+        .output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    output
+        .lines()
+        .find_map(|line| {
+            let mut columns = line.split_whitespace();
+            match (columns.next(), columns.next(), columns.next()) {
+                (Some("MachineGuid"), Some("REG_SZ"), Some(guid)) => Some(guid.as_bytes().to_vec()),
+                _ => None,
+            }
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No MachineGuid"))
+}
+
+/// The data directory of the app is already private to it, and there's no machine ID it can read.
+#[cfg(target_os = "android")]
+fn machine_id() -> io::Result<Vec<u8>> {
+    Ok(Vec::new())
+}
+
+/// Reads the key in `path`, or creates it if there is none.
+fn read_or_create_key(path: &Path) -> io::Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    match fs::read(path) {
+        Ok(data) => {
+            if data.len() == KEY_LEN {
+                key.copy_from_slice(&data);
+                return Ok(key);
+            }
+            log::error!("Replacing secret storage key of invalid length");
+        }
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(error),
+    }
+    OsRng.fill_bytes(&mut key);
+    write_atomically(path, &key)?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    const KEY: [u8; KEY_LEN] = [1; KEY_LEN];
+    const NAME: &str = "test-secret";
+    const MACHINE_ID: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn open(dir: &TempDir, key_dir: &TempDir) -> FileStorage {
+        FileStorage::open_on_machine(dir.path(), key_dir.path(), NAME, MACHINE_ID).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = encrypt(&KEY, b"secret").unwrap();
+        assert_eq!(data[0], FORMAT_VERSION);
+        assert_eq!(data.len(), HEADER_LEN + b"secret".len());
+        assert_eq!(decrypt(&KEY, &data).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_nonce_is_random() {
+        assert_ne!(
+            encrypt(&KEY, b"secret").unwrap(),
+            encrypt(&KEY, b"secret").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let data = encrypt(&KEY, b"secret").unwrap();
+        match decrypt(&[2; KEY_LEN], &data) {
+            Err(Error::Decrypt(_)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_tampered_data() {
+        let mut data = encrypt(&KEY, b"secret").unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&KEY, &data).is_err());
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        let mut data = encrypt(&KEY, b"secret").unwrap();
+        data[0] = FORMAT_VERSION + 1;
+        match decrypt(&KEY, &data) {
+            Err(Error::InvalidFormat) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match decrypt(&KEY, &data[..HEADER_LEN - 1]) {
+            Err(Error::InvalidFormat) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_write_and_reopen() {
+        let dir = TempDir::new().unwrap();
+        let key_dir = TempDir::new().unwrap();
+
+        let mut storage = open(&dir, &key_dir);
+        assert_eq!(storage.read().unwrap(), None);
+        storage.write(b"first").unwrap();
+        storage.write(b"second").unwrap();
+
+        let data = fs::read(FileStorage::path(dir.path(), NAME)).unwrap();
+        assert!(!data
+            .windows(b"second".len())
+            .any(|window| window == b"second"));
+        assert_eq!(
+            open(&dir, &key_dir).read().unwrap(),
+            Some(b"second".to_vec())
+        );
+        // Nothing but the secret is left in the directory.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let key_dir = TempDir::new().unwrap();
+        open(&dir, &key_dir).write(b"secret").unwrap();
+
+        let key_path = key_dir.path().join(KEY_FILE);
+        let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!dir.path().join(KEY_FILE).exists());
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let dir = TempDir::new().unwrap();
+        let key_dir = TempDir::new().unwrap();
+        open(&dir, &key_dir).write(b"secret").unwrap();
+
+        let path = FileStorage::path(dir.path(), NAME);
+        let mut data = fs::read(&path).unwrap();
+        data[HEADER_LEN] ^= 1;
+        fs::write(&path, &data).unwrap();
+        match open(&dir, &key_dir).read() {
+            Err(Error::Decrypt(_)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        fs::write(&path, b"not encrypted").unwrap();
+        match open(&dir, &key_dir).read() {
+            Err(Error::InvalidFormat) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_key_mismatch() {
+        let dir = TempDir::new().unwrap();
+        let key_dir = TempDir::new().unwrap();
+        let other_key_dir = TempDir::new().unwrap();
+        open(&dir, &key_dir).write(b"secret").unwrap();
+
+        match open(&dir, &other_key_dir).read() {
+            Err(Error::Decrypt(_)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_key_is_machine_bound() {
+        let dir = TempDir::new().unwrap();
+        let key_dir = TempDir::new().unwrap();
+        open(&dir, &key_dir).write(b"secret").unwrap();
+
+        let other_machine_id = b"fedcba9876543210fedcba9876543210";
+        let mut storage =
+            FileStorage::open_on_machine(dir.path(), key_dir.path(), NAME, other_machine_id)
+                .unwrap();
+        match storage.read() {
+            Err(Error::Decrypt(_)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // The key file alone doesn't decrypt the secret either.
+        let mut file_key = [0u8; KEY_LEN];
+        file_key.copy_from_slice(&fs::read(key_dir.path().join(KEY_FILE)).unwrap());
+        let data = fs::read(FileStorage::path(dir.path(), NAME)).unwrap();
+        assert!(decrypt(&file_key, &data).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Storage for data that must not be readable by anyone who gets hold of the files of the daemon,
+//! like the private WireGuard keys in the account history.
+//!
+//! Secrets are kept in files encrypted with a key derived from a random key file and the ID of the
+//! machine. The key file is stored in the key directory, which only the user running the daemon
+//! can read, apart from the settings and the cache. Since the machine ID is part of the key, a copy
+//! of the key directory is not enough to decrypt the files on another machine.
+//!
+//! On Linux, the Secret Service can be used instead by setting `MULLVAD_SECRET_STORAGE` to
+//! `secret-service`. It is only reachable on the session bus of a logged in user, so it is not
+//! used by default, since the daemon normally runs as a system service. If it is not available,
+//! the encrypted files are used.
+//!
+//! This protects the secrets from other unprivileged users, and from anyone who gets a copy of the
+//! cache or settings, such as from a backup or a problem report. It does not protect them from
+//! anyone who can act as the user running the daemon, which is root or the system account. Nor does
+//! it protect the encrypted files from anyone who can read the whole disk, unless the disk itself
+//! is encrypted, since the key is stored on the same disk.
+
+use std::{fmt, io, path::Path};
+#[cfg(target_os = "linux")]
+use talpid_types::ErrorExt;
+
+mod file;
+#[cfg(target_os = "linux")]
+mod secret_service;
+
+pub use self::file::FileStorage;
+#[cfg(target_os = "linux")]
+pub use self::secret_service::SecretServiceStorage;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Unable to read secret file")]
+    Read(#[error(cause)] io::Error),
+
+    #[error(display = "Unable to write secret file")]
+    Write(#[error(cause)] io::Error),
+
+    #[error(display = "Unable to read or create the secret storage key")]
+    Key(#[error(cause)] io::Error),
+
+    #[error(display = "Unable to create the key directory")]
+    KeyDir(#[error(cause)] mullvad_paths::Error),
+
+    #[error(display = "Unable to read the machine ID")]
+    MachineId(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to derive the secret storage key")]
+    DeriveKey(#[error(cause)] openssl::error::ErrorStack),
+
+    #[error(display = "Failed to encrypt secret")]
+    Encrypt(#[error(cause)] openssl::error::ErrorStack),
+
+    #[error(display = "Failed to decrypt secret, it is corrupt or the key has changed")]
+    Decrypt(#[error(cause)] openssl::error::ErrorStack),
+
+    #[error(display = "Secret file has an unknown format")]
+    InvalidFormat,
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Secret Service request failed")]
+    SecretService(#[error(cause)] dbus::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Secret Service sent an unexpected reply")]
+    SecretServiceReply(#[error(cause)] dbus::arg::TypeMismatchError),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Secret Service collection is locked")]
+    SecretServiceLocked,
+}
+
+/// A place to keep a single secret.
+pub trait SecretStorage: fmt::Display + Send {
+    /// Returns the stored secret, or `None` if nothing has been stored yet.
+    fn read(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// Replaces the stored secret.
+    fn write(&mut self, secret: &[u8]) -> Result<()>;
+}
+
+/// Opens the storage for the secret called `name`. The file backend keeps it in `dir`.
+pub fn open(dir: &Path, name: &str) -> Result<Box<dyn SecretStorage>> {
+    #[cfg(target_os = "linux")]
+    {
+        let backend = std::env::var_os("MULLVAD_SECRET_STORAGE");
+        if backend.as_ref().and_then(|value| value.to_str()) == Some("secret-service") {
+            match open_secret_service(dir, name) {
+                Ok(storage) => return Ok(storage),
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Secret Service is not available, using an encrypted file"
+                    )
+                ),
+            }
+        }
+    }
+    Ok(Box::new(open_file(dir, name)?))
+}
+
+fn open_file(dir: &Path, name: &str) -> Result<FileStorage> {
+    let key_dir = mullvad_paths::key_dir().map_err(Error::KeyDir)?;
+    FileStorage::open(dir, &key_dir, name)
+}
+
+/// Opens the Secret Service storage, and moves the secret into it from the encrypted file, if one
+/// was written before the Secret Service was chosen.
+#[cfg(target_os = "linux")]
+fn open_secret_service(dir: &Path, name: &str) -> Result<Box<dyn SecretStorage>> {
+    let mut storage = SecretServiceStorage::open(name)?;
+    if storage.read()?.is_none() && FileStorage::exists(dir, name) {
+        let mut file = open_file(dir, name)?;
+        if let Some(secret) = file.read()? {
+            storage.write(&secret)?;
+        }
+        if let Err(error) = file.remove() {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove secret file after moving it")
+            );
+        }
+    }
+    Ok(Box::new(storage))
+}
@@ -0,0 +1,174 @@
+use super::{Error, Result, SecretStorage};
+use dbus::{
+    arg::{RefArg, Variant},
+    BusType, Connection, Message, Path,
+};
+use std::{collections::HashMap, fmt};
+
+const SECRETS_BUS: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+/// The collection secrets are stored in by default, usually the login keyring of the user.
+const DEFAULT_COLLECTION_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Item.Label";
+const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+/// Secrets are passed unencrypted, since only the user can connect to the session bus.
+const PLAIN_ALGORITHM: &str = "plain";
+/// The path returned instead of a prompt when no prompt is needed.
+const NO_PROMPT: &str = "/";
+const APPLICATION: &str = "mullvad-vpn";
+const CONTENT_TYPE: &str = "application/octet-stream";
+const RPC_TIMEOUT_MS: i32 = 3000;
+
+/// A secret as passed over D-Bus: the session, the algorithm parameters, the value and its
+/// content type.
+type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+/// Keeps a secret as an item in the default collection of the Secret Service, like GNOME Keyring
+/// or KWallet. The service is only reachable on the session bus of a logged in user, so this
+/// can't be used when the daemon runs as a system service. The collection has to be unlocked,
+/// since the daemon can't show the prompt for unlocking it.
+pub struct SecretServiceStorage {
+    name: String,
+}
+
+/// A connection to the Secret Service, with a session for passing secrets over it. A new one is
+/// opened for every operation, since the connection can't be moved between threads.
+struct Session {
+    connection: Connection,
+    path: Path<'static>,
+}
+
+impl SecretServiceStorage {
+    /// Opens the storage, failing if the Secret Service is not reachable.
+    pub fn open(name: &str) -> Result<Self> {
+        Session::open()?;
+        Ok(SecretServiceStorage {
+            name: name.to_owned(),
+        })
+    }
+
+    fn attributes(&self) -> HashMap<&str, &str> {
+        let mut attributes = HashMap::new();
+        attributes.insert("application", APPLICATION);
+        attributes.insert("name", self.name.as_str());
+        attributes
+    }
+
+    /// Returns the item holding the secret, unlocking it if that doesn't require a prompt.
+    fn find_item(&self, session: &Session) -> Result<Option<Path<'static>>> {
+        let message =
+            method_call(SERVICE_PATH, SERVICE_INTERFACE, "SearchItems").append1(self.attributes());
+        let (unlocked, locked): (Vec<Path<'static>>, Vec<Path<'static>>) = session
+            .call(message)?
+            .read2()
+            .map_err(Error::SecretServiceReply)?;
+        if let Some(item) = unlocked.into_iter().next() {
+            return Ok(Some(item));
+        }
+        let item = match locked.into_iter().next() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+
+        let message =
+            method_call(SERVICE_PATH, SERVICE_INTERFACE, "Unlock").append1(vec![item.clone()]);
+        let (_unlocked, prompt): (Vec<Path<'static>>, Path<'static>) = session
+            .call(message)?
+            .read2()
+            .map_err(Error::SecretServiceReply)?;
+        check_no_prompt(&prompt)?;
+        Ok(Some(item))
+    }
+}
+
+impl SecretStorage for SecretServiceStorage {
+    fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let session = Session::open()?;
+        let item = match self.find_item(&session)? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let message = method_call(&item, ITEM_INTERFACE, "GetSecret").append1(session.path.clone());
+        let (_session, _parameters, value, _content_type): Secret = session
+            .call(message)?
+            .read1()
+            .map_err(Error::SecretServiceReply)?;
+        Ok(Some(value))
+    }
+
+    fn write(&mut self, secret: &[u8]) -> Result<()> {
+        let session = Session::open()?;
+        let attributes: HashMap<String, String> = self
+            .attributes()
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        let mut properties: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        properties.insert(
+            LABEL_PROPERTY,
+            Variant(Box::new(format!("Mullvad VPN {}", self.name)) as Box<dyn RefArg>),
+        );
+        properties.insert(
+            ATTRIBUTES_PROPERTY,
+            Variant(Box::new(attributes) as Box<dyn RefArg>),
+        );
+        let secret: Secret = (
+            session.path.clone(),
+            Vec::new(),
+            secret.to_vec(),
+            CONTENT_TYPE.to_owned(),
+        );
+
+        // Replaces the item with the same attributes, if there is one.
+        let message = method_call(DEFAULT_COLLECTION_PATH, COLLECTION_INTERFACE, "CreateItem")
+            .append3(properties, secret, true);
+        let (_item, prompt): (Path<'static>, Path<'static>) = session
+            .call(message)?
+            .read2()
+            .map_err(Error::SecretServiceReply)?;
+        check_no_prompt(&prompt)
+    }
+}
+
+impl fmt::Display for SecretServiceStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret Service item {}", self.name)
+    }
+}
+
+impl Session {
+    fn open() -> Result<Self> {
+        let connection = Connection::get_private(BusType::Session).map_err(Error::SecretService)?;
+        let message = method_call(SERVICE_PATH, SERVICE_INTERFACE, "OpenSession")
+            .append2(PLAIN_ALGORITHM, Variant(""));
+        let reply = connection
+            .send_with_reply_and_block(message, RPC_TIMEOUT_MS)
+            .map_err(Error::SecretService)?;
+        let (_output, path): (Variant<Box<dyn RefArg>>, Path<'static>) =
+            reply.read2().map_err(Error::SecretServiceReply)?;
+        Ok(Session { connection, path })
+    }
+
+    fn call(&self, message: Message) -> Result<Message> {
+        self.connection
+            .send_with_reply_and_block(message, RPC_TIMEOUT_MS)
+            .map_err(Error::SecretService)
+    }
+}
+
+fn method_call(path: &str, interface: &str, method: &str) -> Message {
+    Message::new_method_call(SECRETS_BUS, path, interface, method)
+        .expect("failed to construct a new dbus message")
+}
+
+/// Fails if the collection is locked, since unlocking it requires the user to answer a prompt.
+fn check_no_prompt(prompt: &Path<'_>) -> Result<()> {
+    if &**prompt == NO_PROMPT {
+        Ok(())
+    } else {
+        Err(Error::SecretServiceLocked)
+    }
+}
@@ -1,18 +1,36 @@
 use crate::InternalDaemonEvent;
-use futures::{future::Executor, sync::oneshot, Async, Future, Poll};
+use chrono::{offset::Utc, DateTime};
+use futures::{
+    future::{self, Executor},
+    sync::oneshot,
+    Async, Future, Poll,
+};
 use jsonrpc_client_core::Error as JsonRpcError;
-use mullvad_types::{account::AccountToken, wireguard::WireguardData};
-use std::{sync::mpsc, time::Duration};
+use mullvad_types::{
+    account::AccountToken,
+    wireguard::{KeygenEvent, RegisteredKey, WireguardData},
+};
+use std::{cmp, sync::mpsc, thread, time::Duration};
 pub use talpid_types::net::wireguard::*;
 use talpid_types::ErrorExt;
 use tokio_core::reactor::Remote;
 use tokio_retry::{
     strategy::{jitter, ExponentialBackoff},
-    RetryIf,
+    Retry, RetryIf,
 };
 
 const TOO_MANY_KEYS_ERROR_CODE: i64 = -703;
 
+/// How many times removing a replaced key from an account is attempted before giving up.
+const KEY_REMOVAL_ATTEMPTS: usize = 10;
+
+/// Longest time to wait between attempts at uploading or removing a key.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// The rotation timer wakes up at least this often to compare the wall clock against the due
+/// date, since the monotonic clock does not advance while the machine is suspended.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -20,19 +38,32 @@ pub enum Error {
     GenerationError(#[error(cause)] rand::Error),
     #[error(display = "Failed to spawn future")]
     ExectuionError,
+    #[error(display = "Failed to run timer")]
+    TimerError,
     #[error(display = "Unexpected RPC error")]
     RpcError(#[error(cause)] jsonrpc_client_core::Error),
     #[error(display = "Account already has maximum number of keys")]
     TooManyKeys,
+    #[error(display = "No account token configured")]
+    NoAccountToken,
+    #[error(display = "The key is in use by this device")]
+    KeyInUse,
+    #[error(display = "Failed to store the new key in the account history")]
+    AccountHistoryError(#[error(cause)] crate::account_history::Error),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// Channel used to answer the management interface client that issued a key command.
+pub type ResponseTx<T> = oneshot::Sender<Result<T>>;
+
 pub struct KeyManager {
     daemon_tx: mpsc::Sender<InternalDaemonEvent>,
     http_handle: mullvad_rpc::HttpHandle,
     tokio_remote: Remote,
     current_job: Option<CancelHandle>,
+    rotation_timer: Option<mpsc::Sender<()>>,
+    rotation_retry_strategy: Box<dyn Iterator<Item = Duration> + Send>,
 }
 
 impl KeyManager {
@@ -46,6 +77,8 @@ impl KeyManager {
             http_handle,
             tokio_remote,
             current_job: None,
+            rotation_timer: None,
+            rotation_retry_strategy: Box::new(retry_strategy()),
         }
     }
 
@@ -60,8 +93,96 @@ impl KeyManager {
     pub fn generate_key_sync(&mut self, account: AccountToken) -> Result<WireguardData> {
         self.reset();
         let private_key = PrivateKey::new_from_random().map_err(Error::GenerationError)?;
+        let fut = self.push_future_generator(account, private_key)();
+        self.wait_for_key(fut)
+    }
+
+    /// Generate a new private key that atomically replaces `old_key` on the account. The result
+    /// is sent to the daemon channel together with `tx`, so that the daemon can store the new key
+    /// before answering.
+    pub fn replace_key_async(
+        &mut self,
+        account: AccountToken,
+        old_key: PublicKey,
+        tx: ResponseTx<KeygenEvent>,
+    ) {
+        if self
+            .spawn_key_replacement(account, old_key, Some(tx))
+            .is_err()
+        {
+            log::error!("Failed to spawn a future for replacing a wireguard key");
+        }
+    }
+
+    /// Replace `old_key`, the current key of this device, with a new key because it's due for
+    /// rotation. Since the account never holds both keys, this works even when the account has
+    /// as many keys as it's allowed. The result is sent to the daemon channel without a response
+    /// channel.
+    pub fn rotate_key_async(&mut self, account: AccountToken, old_key: PublicKey) -> Result<()> {
+        self.spawn_key_replacement(account, old_key, None)
+    }
+
+    fn spawn_key_replacement(
+        &mut self,
+        account: AccountToken,
+        old_key: PublicKey,
+        tx: Option<ResponseTx<KeygenEvent>>,
+    ) -> Result<()> {
+        self.reset();
+        let (fut, cancel_handle) =
+            Cancellable::new(self.replace_key_future(account.clone(), old_key));
+        let daemon_tx = self.daemon_tx.clone();
+        let fut = fut.then(move |result| {
+            match result {
+                Ok(wireguard_data) => {
+                    let _ = daemon_tx.send(InternalDaemonEvent::WgKeyReplaced(
+                        account,
+                        Ok(wireguard_data),
+                        tx,
+                    ));
+                }
+                Err(CancelErr::Inner(e)) => {
+                    let _ = daemon_tx.send(InternalDaemonEvent::WgKeyReplaced(account, Err(e), tx));
+                }
+                Err(CancelErr::Cancelled) => {
+                    log::error!("Key replacement cancelled");
+                }
+            };
+            Ok(())
+        });
+
+        self.tokio_remote
+            .execute(fut)
+            .map_err(|_| Error::ExectuionError)?;
+        self.current_job = Some(cancel_handle);
+        Ok(())
+    }
+
+    fn replace_key_future(
+        &self,
+        account: AccountToken,
+        old_key: PublicKey,
+    ) -> impl Future<Item = WireguardData, Error = Error> + Send {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        future::result(PrivateKey::new_from_random().map_err(Error::GenerationError)).and_then(
+            move |private_key| {
+                rpc.replace_wg_key(account, old_key, private_key.public_key())
+                    .map(move |addresses| WireguardData {
+                        private_key,
+                        addresses,
+                        created: Utc::now(),
+                    })
+                    .map_err(Self::map_rpc_error)
+            },
+        )
+    }
+
+    fn wait_for_key(
+        &self,
+        fut: impl Future<Item = WireguardData, Error = JsonRpcError> + Send + 'static,
+    ) -> Result<WireguardData> {
         let (tx, rx) = oneshot::channel();
-        let fut = self.push_future_generator(account, private_key)().then(|result| {
+        let fut = fut.then(|result| {
             let _ = tx.send(result);
             Ok(())
         });
@@ -74,6 +195,32 @@ impl KeyManager {
             .map_err(Self::map_rpc_error)
     }
 
+    /// Fetch the public keys registered to an account.
+    pub fn list_keys(&self, account: AccountToken, tx: ResponseTx<Vec<RegisteredKey>>) {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        self.respond(rpc.get_wg_keys(account), tx);
+    }
+
+    /// Remove a key from an account.
+    pub fn revoke_key(&self, account: AccountToken, public_key: PublicKey, tx: ResponseTx<()>) {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        self.respond(rpc.remove_wg_key(account, public_key), tx);
+    }
+
+    fn respond<T, F>(&self, future: F, tx: ResponseTx<T>)
+    where
+        T: Send + 'static,
+        F: Future<Item = T, Error = JsonRpcError> + Send + 'static,
+    {
+        let future = future.then(move |result| {
+            let _ = tx.send(result.map_err(Self::map_rpc_error));
+            Ok(())
+        });
+        if self.tokio_remote.execute(future).is_err() {
+            log::error!("Failed to spawn a future for a wireguard key request");
+        }
+    }
+
 
     /// Generate a new private key asyncronously. The new keys will be sent to the daemon channel.
     pub fn generate_key_async(&mut self, account: AccountToken) -> Result<()> {
@@ -81,9 +228,7 @@ impl KeyManager {
         let private_key = PrivateKey::new_from_random().map_err(Error::GenerationError)?;
         let future_generator = self.push_future_generator(account.clone(), private_key);
 
-        let retry_strategy = ExponentialBackoff::from_millis(300)
-            .max_delay(Duration::from_secs(60 * 60))
-            .map(jitter);
+        let retry_strategy = retry_strategy();
 
         let should_retry = |err: &jsonrpc_client_core::Error| -> bool {
             match err.kind() {
@@ -149,6 +294,94 @@ impl KeyManager {
     }
 
 
+    /// Schedules the key of an account to be replaced at `due`. When the time comes, the daemon
+    /// is notified with a `WgKeyRotationDue` event. Replaces any previously scheduled rotation.
+    pub fn schedule_rotation(
+        &mut self,
+        account: AccountToken,
+        public_key: PublicKey,
+        due: DateTime<Utc>,
+    ) {
+        let (cancel_tx, cancel_rx) = mpsc::channel();
+        // Replacing an earlier timer cancels it.
+        self.rotation_timer = Some(cancel_tx);
+        let daemon_tx = self.daemon_tx.clone();
+        thread::spawn(move || loop {
+            let remaining = match (due - Utc::now()).to_std() {
+                Ok(remaining) if remaining > Duration::from_secs(0) => remaining,
+                _ => {
+                    let _ =
+                        daemon_tx.send(InternalDaemonEvent::WgKeyRotationDue(account, public_key));
+                    return;
+                }
+            };
+            match cancel_rx.recv_timeout(cmp::min(remaining, ROTATION_CHECK_INTERVAL)) {
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                // The timer was cancelled or replaced.
+                _ => return,
+            }
+        });
+    }
+
+    /// Returns how long to wait before retrying a failed key rotation. The delay grows with each
+    /// consecutive failure, until `reset_rotation_retries` is called.
+    pub fn next_rotation_retry_delay(&mut self) -> Duration {
+        self.rotation_retry_strategy
+            .next()
+            .unwrap_or(MAX_RETRY_DELAY)
+    }
+
+    /// Restarts the back-off of failed key rotations, after a key has been replaced.
+    pub fn reset_rotation_retries(&mut self) {
+        self.rotation_retry_strategy = Box::new(retry_strategy());
+    }
+
+    /// Cancels the scheduled key rotation, if any.
+    pub fn cancel_rotation(&mut self) {
+        self.rotation_timer = None;
+    }
+
+    /// Removes a key from an account in the background. The removal is retried with back-off if
+    /// the API can't be reached.
+    pub fn remove_key_async(&self, account: AccountToken, public_key: PublicKey) {
+        let retry_strategy = retry_strategy().take(KEY_REMOVAL_ATTEMPTS);
+
+        let removed_key = public_key.clone();
+        let fut = self
+            .remove_key_future(account, public_key, retry_strategy)
+            .then(move |result| {
+                match result {
+                    Ok(()) => log::info!("Removed old wireguard key {}", removed_key),
+                    Err(e) => log::error!(
+                        "{}",
+                        e.display_chain_with_msg("Failed to remove old wireguard key")
+                    ),
+                }
+                Ok(())
+            });
+        if let Err(e) = self.tokio_remote.execute(fut) {
+            log::error!("Failed to spawn future to remove wireguard key: {:?}", e);
+        }
+    }
+
+    fn remove_key_future(
+        &self,
+        account: AccountToken,
+        public_key: PublicKey,
+        retry_strategy: impl Iterator<Item = Duration> + Send + 'static,
+    ) -> impl Future<Item = (), Error = Error> + Send {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        let remove_future = move || rpc.remove_wg_key(account.clone(), public_key.clone());
+
+        Retry::spawn(retry_strategy, remove_future).map_err(|err| match err {
+            tokio_retry::Error::OperationError(e) => Error::RpcError(e),
+            tokio_retry::Error::TimerError(timer_error) => {
+                log::error!("Tokio timer error {}", timer_error);
+                Error::TimerError
+            }
+        })
+    }
+
     fn push_future_generator(
         &self,
         account: AccountToken,
@@ -165,6 +398,7 @@ impl KeyManager {
                     move |addresses| WireguardData {
                         private_key: key,
                         addresses,
+                        created: Utc::now(),
                     },
                 ))
             };
@@ -182,6 +416,18 @@ impl KeyManager {
     }
 }
 
+/// The back-off between attempts at talking to the API about keys.
+fn retry_strategy() -> impl Iterator<Item = Duration> + Send {
+    ExponentialBackoff::from_millis(300)
+        .max_delay(MAX_RETRY_DELAY)
+        .map(jitter)
+}
+
+/// Returns when a key created at `created` should be replaced, given a rotation interval in days.
+pub fn rotation_due(created: DateTime<Utc>, interval_days: u32) -> DateTime<Utc> {
+    created + chrono::Duration::days(i64::from(interval_days))
+}
+
 pub enum CancelErr<E> {
     Cancelled,
     Inner(E),
@@ -232,3 +478,296 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use mullvad_types::wireguard::AssociatedAddresses;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        iter,
+        net::{TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+    use tokio_core::reactor::Core;
+
+    type Requests = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    /// Starts a JSON-RPC server that answers the first `failures` requests with an error and all
+    /// following requests with `result`. Returns the URI of the server and the requests it has
+    /// received.
+    fn spawn_mock_rpc_server(failures: usize, result: serde_json::Value) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}/rpc/", listener.local_addr().unwrap());
+        let requests = Requests::default();
+        let server_requests = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let requests = server_requests.clone();
+                let result = result.clone();
+                thread::spawn(move || {
+                    serve_connection(stream.unwrap(), failures, &result, &requests)
+                });
+            }
+        });
+        (uri, requests)
+    }
+
+    fn serve_connection(
+        stream: TcpStream,
+        failures: usize,
+        result: &serde_json::Value,
+        requests: &Mutex<Vec<serde_json::Value>>,
+    ) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        loop {
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return;
+                }
+                let line = line.trim().to_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if line.starts_with("content-length:") {
+                    content_length = line["content-length:".len()..].trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            let response = {
+                let mut requests = requests.lock().unwrap();
+                requests.push(request.clone());
+                if requests.len() <= failures {
+                    serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "error": { "code": -32000, "message": "Server error" },
+                    })
+                } else {
+                    serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+                }
+            }
+            .to_string();
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    }
+
+    fn key_manager_with_mock_server(
+        failures: usize,
+        result: serde_json::Value,
+    ) -> (
+        KeyManager,
+        Core,
+        mpsc::Receiver<InternalDaemonEvent>,
+        Requests,
+    ) {
+        let (uri, requests) = spawn_mock_rpc_server(failures, result);
+        let transport = jsonrpc_client_http::HttpTransport::new()
+            .standalone()
+            .unwrap();
+        let http_handle = transport.handle(&uri).unwrap();
+        let core = Core::new().unwrap();
+        let (daemon_tx, daemon_rx) = mpsc::channel();
+        let key_manager = KeyManager::new(daemon_tx, http_handle, core.remote());
+        (key_manager, core, daemon_rx, requests)
+    }
+
+    fn remove_key_with_mock_server(failures: usize, retries: usize) -> (Result<()>, usize) {
+        let (key_manager, mut core, _daemon_rx, requests) =
+            key_manager_with_mock_server(failures, serde_json::Value::Null);
+
+        let public_key = PrivateKey::new_from_random().unwrap().public_key();
+        let retry_strategy = iter::repeat(Duration::from_millis(10)).take(retries);
+        let result =
+            core.run(key_manager.remove_key_future("1234".to_owned(), public_key, retry_strategy));
+        let requests = requests.lock().unwrap();
+        assert!(requests
+            .iter()
+            .all(|request| request["method"] == "remove_wg_key"));
+        (result, requests.len())
+    }
+
+    #[test]
+    fn test_key_removal_retries_until_success() {
+        let (result, requests) = remove_key_with_mock_server(2, 5);
+        assert!(result.is_ok());
+        assert_eq!(requests, 3);
+    }
+
+    #[test]
+    fn test_key_removal_gives_up() {
+        let (result, requests) = remove_key_with_mock_server(10, 2);
+        match result {
+            Err(Error::RpcError(_)) => (),
+            _ => panic!("Key removal should have failed"),
+        }
+        assert_eq!(requests, 3);
+    }
+
+    #[test]
+    fn test_list_keys() {
+        let public_key = PrivateKey::new_from_random().unwrap().public_key();
+        let created = Utc.ymd(2019, 6, 28).and_hms(12, 0, 0);
+        let registered_keys = vec![RegisteredKey {
+            public_key: public_key.clone(),
+            created,
+        }];
+        let (key_manager, mut core, _daemon_rx, requests) =
+            key_manager_with_mock_server(0, serde_json::to_value(&registered_keys).unwrap());
+
+        let (tx, rx) = oneshot::channel();
+        key_manager.list_keys("1234".to_owned(), tx);
+        let keys = core.run(rx).unwrap().unwrap();
+
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].public_key, public_key);
+        assert_eq!(keys[0].created, created);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "get_wg_keys");
+        assert_eq!(requests[0]["params"], serde_json::json!(["1234"]));
+    }
+
+    #[test]
+    fn test_revoke_key() {
+        let (key_manager, mut core, _daemon_rx, requests) =
+            key_manager_with_mock_server(0, serde_json::Value::Null);
+
+        let public_key = PrivateKey::new_from_random().unwrap().public_key();
+        let (tx, rx) = oneshot::channel();
+        key_manager.revoke_key("1234".to_owned(), public_key.clone(), tx);
+        core.run(rx).unwrap().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "remove_wg_key");
+        assert_eq!(
+            requests[0]["params"],
+            serde_json::json!(["1234", public_key])
+        );
+    }
+
+    #[test]
+    fn test_revoke_key_failure() {
+        let (key_manager, mut core, _daemon_rx, _requests) =
+            key_manager_with_mock_server(1, serde_json::Value::Null);
+
+        let public_key = PrivateKey::new_from_random().unwrap().public_key();
+        let (tx, rx) = oneshot::channel();
+        key_manager.revoke_key("1234".to_owned(), public_key, tx);
+        match core.run(rx).unwrap() {
+            Err(Error::RpcError(_)) => (),
+            _ => panic!("Revoking the key should have failed"),
+        }
+    }
+
+    #[test]
+    fn test_replace_key() {
+        let addresses = AssociatedAddresses {
+            ipv4_address: "10.99.0.2/32".parse().unwrap(),
+            ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+        };
+        let (mut key_manager, mut core, daemon_rx, requests) =
+            key_manager_with_mock_server(0, serde_json::to_value(&addresses).unwrap());
+
+        let old_key = PrivateKey::new_from_random().unwrap().public_key();
+        let (tx, rx) = oneshot::channel();
+        key_manager.replace_key_async("1234".to_owned(), old_key.clone(), tx);
+
+        // The result is passed to the daemon together with the response channel.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let event = loop {
+            core.turn(Some(Duration::from_millis(10)));
+            match daemon_rx.try_recv() {
+                Ok(event) => break event,
+                Err(mpsc::TryRecvError::Empty) if Instant::now() < deadline => (),
+                Err(e) => panic!("No key replacement event: {}", e),
+            }
+        };
+        let (account, data, tx) = match event {
+            InternalDaemonEvent::WgKeyReplaced(account, result, tx) => {
+                (account, result.unwrap(), tx)
+            }
+            _ => panic!("Unexpected daemon event"),
+        };
+        assert_eq!(account, "1234");
+        assert_eq!(data.addresses.ipv4_address, addresses.ipv4_address);
+        assert_eq!(data.addresses.ipv6_address, addresses.ipv6_address);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "replace_wg_key");
+        assert_eq!(
+            requests[0]["params"],
+            serde_json::json!(["1234", old_key, data.private_key.public_key()])
+        );
+
+        // The response channel is handed over to the daemon.
+        tx.unwrap().send(Err(Error::KeyInUse)).unwrap();
+        match rx.wait().unwrap() {
+            Err(Error::KeyInUse) => (),
+            _ => panic!("Unexpected response"),
+        }
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let addresses = AssociatedAddresses {
+            ipv4_address: "10.99.0.2/32".parse().unwrap(),
+            ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+        };
+        let (mut key_manager, mut core, daemon_rx, requests) =
+            key_manager_with_mock_server(0, serde_json::to_value(&addresses).unwrap());
+
+        let old_key = PrivateKey::new_from_random().unwrap().public_key();
+        key_manager
+            .rotate_key_async("1234".to_owned(), old_key.clone())
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let event = loop {
+            core.turn(Some(Duration::from_millis(10)));
+            match daemon_rx.try_recv() {
+                Ok(event) => break event,
+                Err(mpsc::TryRecvError::Empty) if Instant::now() < deadline => (),
+                Err(e) => panic!("No key rotation event: {}", e),
+            }
+        };
+        // A rotation has no client waiting for the result.
+        match event {
+            InternalDaemonEvent::WgKeyReplaced(account, Ok(_), None) => {
+                assert_eq!(account, "1234")
+            }
+            _ => panic!("Unexpected daemon event"),
+        }
+
+        // The old key is replaced in place, so the account never holds an extra key.
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "replace_wg_key");
+        assert_eq!(requests[0]["params"][1], serde_json::json!(old_key));
+    }
+
+    #[test]
+    fn test_rotation_due() {
+        let created = Utc.ymd(2019, 6, 28).and_hms(12, 0, 0);
+        assert_eq!(
+            rotation_due(created, 7),
+            Utc.ymd(2019, 7, 5).and_hms(12, 0, 0)
+        );
+    }
+}
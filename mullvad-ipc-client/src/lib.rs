@@ -1,21 +1,34 @@
 #![deny(rust_2018_idioms)]
 
+use chrono::{offset::Utc, DateTime};
 use futures::sync::oneshot;
 use jsonrpc_client_core::{Client, ClientHandle, Future};
 use jsonrpc_client_ipc::IpcTransport;
 use mullvad_types::{
     account::{AccountData, AccountToken},
-    location::GeoIpLocation,
+    auto_connect::AutoConnectRules,
+    diagnostics::Diagnostics,
+    location::{CityCode, CountryCode, GeoIpLocation},
+    port_forward::PortForward,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettings, RelaySettingsUpdate},
     relay_list::RelayList,
     settings::{Settings, TunnelOptions},
     states::TunnelState,
     version::AppVersionInfo,
+    wireguard::{KeygenEvent, RegisteredKey},
     DaemonEvent,
 };
 use serde::{Deserialize, Serialize};
 use std::{io, path::Path, thread};
-use talpid_types::net::wireguard;
+use talpid_types::{
+    net::{
+        dns::{DnsUpstream, LocalResolverSettings, SplitDns},
+        lan::LanPolicy,
+        network_info::NetworkInfo,
+        wireguard,
+    },
+    tunnel::TunnelStats,
+};
 
 static NO_ARGS: [u8; 0] = [];
 
@@ -112,6 +125,18 @@ impl DaemonRpcClient {
         self.call("set_allow_lan", &[allow_lan])
     }
 
+    pub fn set_lan_policy(&mut self, lan_policy: LanPolicy) -> Result<()> {
+        self.call("set_lan_policy", &[lan_policy])
+    }
+
+    pub fn set_split_dns(&mut self, split_dns: SplitDns) -> Result<()> {
+        self.call("set_split_dns", &[split_dns])
+    }
+
+    pub fn set_local_resolver(&mut self, local_resolver: LocalResolverSettings) -> Result<()> {
+        self.call("set_local_resolver", &[local_resolver])
+    }
+
     pub fn set_block_when_disconnected(&mut self, block_when_disconnected: bool) -> Result<()> {
         self.call("set_block_when_disconnected", &[block_when_disconnected])
     }
@@ -128,6 +153,14 @@ impl DaemonRpcClient {
         self.call("get_auto_connect", &NO_ARGS)
     }
 
+    pub fn set_auto_connect_rules(&mut self, rules: AutoConnectRules) -> Result<()> {
+        self.call("set_auto_connect_rules", &[rules])
+    }
+
+    pub fn get_network_info(&mut self) -> Result<NetworkInfo> {
+        self.call("get_network_info", &NO_ARGS)
+    }
+
     pub fn get_current_location(&mut self) -> Result<Option<GeoIpLocation>> {
         self.call("get_current_location", &NO_ARGS)
     }
@@ -152,6 +185,18 @@ impl DaemonRpcClient {
         self.call("get_state", &NO_ARGS)
     }
 
+    pub fn get_diagnostics(&mut self) -> Result<Diagnostics> {
+        self.call("get_diagnostics", &NO_ARGS)
+    }
+
+    pub fn get_tunnel_stats(&mut self) -> Result<Option<TunnelStats>> {
+        self.call("get_tunnel_stats", &NO_ARGS)
+    }
+
+    pub fn set_tunnel_stats_interval(&mut self, seconds: Option<u32>) -> Result<()> {
+        self.call("set_tunnel_stats_interval", &[seconds])
+    }
+
     pub fn get_tunnel_options(&mut self) -> Result<TunnelOptions> {
         self.call("get_tunnel_options", &NO_ARGS)
     }
@@ -160,7 +205,7 @@ impl DaemonRpcClient {
         self.call("get_settings", &NO_ARGS)
     }
 
-    pub fn generate_wireguard_key(&mut self) -> Result<mullvad_types::wireguard::KeygenEvent> {
+    pub fn generate_wireguard_key(&mut self) -> Result<KeygenEvent> {
         self.call("generate_wireguard_key", &NO_ARGS)
     }
 
@@ -172,6 +217,37 @@ impl DaemonRpcClient {
         self.call("verify_wireguard_key", &NO_ARGS)
     }
 
+    pub fn list_wireguard_keys(&mut self) -> Result<Vec<RegisteredKey>> {
+        self.call("list_wireguard_keys", &NO_ARGS)
+    }
+
+    pub fn revoke_wireguard_key(&mut self, public_key: wireguard::PublicKey) -> Result<()> {
+        self.call("revoke_wireguard_key", &[public_key])
+    }
+
+    pub fn replace_wireguard_key(
+        &mut self,
+        public_key: wireguard::PublicKey,
+    ) -> Result<KeygenEvent> {
+        self.call("replace_wireguard_key", &[public_key])
+    }
+
+    pub fn request_port_forward(
+        &mut self,
+        country_code: CountryCode,
+        city_code: CityCode,
+    ) -> Result<PortForward> {
+        self.call("request_port_forward", &(country_code, city_code))
+    }
+
+    pub fn list_port_forwards(&mut self) -> Result<Vec<PortForward>> {
+        self.call("list_port_forwards", &NO_ARGS)
+    }
+
+    pub fn release_port_forward(&mut self, port_forward: PortForward) -> Result<()> {
+        self.call("release_port_forward", &[port_forward])
+    }
+
     pub fn get_version_info(&mut self) -> Result<AppVersionInfo> {
         self.call("get_version_info", &NO_ARGS)
     }
@@ -184,10 +260,30 @@ impl DaemonRpcClient {
         self.call("set_enable_ipv6", &[enabled])
     }
 
+    pub fn set_dns_upstream(&mut self, dns_upstream: DnsUpstream) -> Result<()> {
+        self.call("set_dns_upstream", &[dns_upstream])
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<()> {
         self.call("set_wireguard_mtu", &[mtu])
     }
 
+    pub fn set_wireguard_auto_mtu(&mut self, auto_mtu: bool) -> Result<()> {
+        self.call("set_wireguard_auto_mtu", &[auto_mtu])
+    }
+
+    pub fn set_wireguard_backend(&mut self, backend: Option<wireguard::Backend>) -> Result<()> {
+        self.call("set_wireguard_backend", &[backend])
+    }
+
+    pub fn set_wireguard_keepalive(&mut self, interval: Option<u16>) -> Result<()> {
+        self.call("set_wireguard_keepalive", &[interval])
+    }
+
+    pub fn set_wireguard_rotation_interval(&mut self, days: Option<u32>) -> Result<()> {
+        self.call("set_wireguard_rotation_interval", &[days])
+    }
+
     pub fn set_openvpn_mssfix(&mut self, mssfix: Option<u16>) -> Result<()> {
         self.call("set_openvpn_mssfix", &[mssfix])
     }
@@ -204,6 +300,14 @@ impl DaemonRpcClient {
         self.call("shutdown", &NO_ARGS)
     }
 
+    pub fn allow_captive_portal(&mut self, minutes: u32) -> Result<DateTime<Utc>> {
+        self.call("allow_captive_portal", &[minutes])
+    }
+
+    pub fn end_captive_portal(&mut self) -> Result<()> {
+        self.call("end_captive_portal", &NO_ARGS)
+    }
+
     pub fn factory_reset(&mut self) -> Result<()> {
         self.call("factory_reset", &NO_ARGS)
     }
@@ -212,6 +316,14 @@ impl DaemonRpcClient {
         self.call("update_relay_settings", &[update])
     }
 
+    pub fn import_wireguard_config(&mut self, config: String) -> Result<()> {
+        self.call("import_wireguard_config", &[config])
+    }
+
+    pub fn export_wireguard_config(&mut self) -> Result<String> {
+        self.call("export_wireguard_config", &NO_ARGS)
+    }
+
     pub fn call<A, O>(&mut self, method: &'static str, args: &A) -> Result<O>
     where
         A: Serialize + Send + 'static,
@@ -6,10 +6,11 @@ use jni::{
 };
 use mullvad_daemon::EventListener;
 use mullvad_types::{
-    relay_list::RelayList, settings::Settings, states::TunnelState, wireguard::KeygenEvent,
+    captive_portal::CaptivePortalEvent, relay_list::RelayList, settings::Settings,
+    states::TunnelState, wireguard::KeygenEvent,
 };
 use std::{sync::mpsc, thread};
-use talpid_types::ErrorExt;
+use talpid_types::{tunnel::TunnelStats, ErrorExt};
 
 #[derive(Debug, err_derive::Error)]
 pub enum Error {
@@ -55,6 +56,14 @@ impl EventListener for JniEventListener {
     fn notify_relay_list(&self, relay_list: RelayList) {
         let _ = self.0.send(Event::RelayList(relay_list));
     }
+
+    fn notify_captive_portal_event(&self, _event: CaptivePortalEvent) {
+        // The app doesn't expose captive portal mode.
+    }
+
+    fn notify_tunnel_stats(&self, _stats: TunnelStats) {
+        // The app doesn't show tunnel statistics.
+    }
 }
 
 struct JniEventHandler<'env> {
@@ -0,0 +1,44 @@
+use crate::Result;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::{env, path::PathBuf};
+
+/// Creates and returns the directory for the keys that the daemon encrypts its secrets with,
+/// pointed to by `MULLVAD_KEY_DIR`, or the default one if that variable is unset. It is kept apart
+/// from the settings and the cache, so the keys are not copied along with them.
+pub fn key_dir() -> Result<PathBuf> {
+    #[cfg(unix)]
+    let permissions = Some(PermissionsExt::from_mode(0o700));
+    #[cfg(not(unix))]
+    let permissions = None;
+    crate::create_and_return(get_key_dir, permissions)
+}
+
+fn get_key_dir() -> Result<PathBuf> {
+    match env::var_os("MULLVAD_KEY_DIR") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => get_default_key_dir(),
+    }
+}
+
+pub fn get_default_key_dir() -> Result<PathBuf> {
+    #[cfg(not(target_os = "android"))]
+    {
+        let dir;
+        #[cfg(target_os = "linux")]
+        {
+            dir = Ok(PathBuf::from("/var/lib"));
+        }
+        #[cfg(any(target_os = "macos", windows))]
+        {
+            // Only readable by the service account, which the daemon runs as.
+            dir = dirs::data_dir().ok_or_else(|| crate::Error::FindDirError);
+        }
+        dir.map(|dir| dir.join(crate::PRODUCT_NAME))
+    }
+    #[cfg(target_os = "android")]
+    {
+        Ok(std::path::Path::new(crate::APP_PATH).join("keys"))
+    }
+}
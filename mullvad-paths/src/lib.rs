@@ -54,6 +54,9 @@ fn create_and_return(
 mod cache;
 pub use crate::cache::{cache_dir, get_default_cache_dir};
 
+mod keys;
+pub use crate::keys::{get_default_key_dir, key_dir};
+
 mod logs;
 pub use crate::logs::{get_default_log_dir, get_log_dir, log_dir};
 
@@ -11,9 +11,24 @@ pub fn collect() -> BTreeMap<String, String> {
         PRODUCT_VERSION.to_owned(),
     );
     metadata.insert("os".to_owned(), os::version());
+    metadata.insert("dns-backend".to_owned(), dns_backend());
     metadata
 }
 
+/// Asks the daemon which system service it sets DNS through.
+fn dns_backend() -> String {
+    let diagnostics =
+        mullvad_ipc_client::new_standalone_ipc_client(&mullvad_paths::get_rpc_socket_path())
+            .ok()
+            .and_then(|mut rpc| rpc.get_diagnostics().ok());
+    match diagnostics {
+        Some(diagnostics) => diagnostics
+            .dns_backend
+            .unwrap_or_else(|| String::from("[Not set]")),
+        None => String::from("[Failed to reach the daemon]"),
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod os {
     pub fn version() -> String {
@@ -0,0 +1,324 @@
+//! Clients for DNS-over-HTTPS and DNS-over-TLS servers. They take DNS queries as raw messages and
+//! answer with the raw response from the server.
+
+use crate::HttpsConnectorWithSni;
+use futures::{
+    future::{self, Either},
+    sync::{mpsc, oneshot},
+    Future, Stream,
+};
+use hyper::{client::Client, header::Host, Method, Request, StatusCode, Uri};
+use hyper_openssl::openssl::error::ErrorStack;
+use std::{cell::RefCell, io, net::SocketAddr, rc::Rc, time::Duration};
+use talpid_types::net::dns::{EncryptedDnsProtocol, EncryptedDnsServer};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_service::Service;
+
+/// How long to wait for the server to answer a query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Media type of DNS messages sent over HTTPS.
+const DNS_MESSAGE_MEDIA_TYPE: &str = "application/dns-message";
+/// Path that DNS-over-HTTPS servers conventionally accept queries on.
+const DOH_PATH: &str = "/dns-query";
+
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(derive_more::From, err_derive::Error, Debug)]
+pub enum Error {
+    /// When the http status code of the response is not 200 OK.
+    #[error(display = "Http error. Status code {}", _0)]
+    HttpError(StatusCode),
+
+    /// An error occured in Hyper.
+    #[error(display = "Error in HTTP client")]
+    Hyper(#[error(cause)] hyper::Error),
+
+    /// The address of the server did not form a valid URI.
+    #[error(display = "Not a valid URI")]
+    Uri(#[error(cause)] hyper::error::UriError),
+
+    /// Error in OpenSSL
+    #[error(display = "Error in OpenSSL")]
+    OpenSsl(#[error(cause)] ErrorStack),
+
+    /// Failed to talk to the DNS-over-TLS server.
+    #[error(display = "Failed to communicate with the DNS server")]
+    Io(#[error(cause)] io::Error),
+
+    /// The server did not answer in time.
+    #[error(display = "The DNS server did not answer within {:?}", _0)]
+    TimedOut(Duration),
+}
+
+
+pub type QuerySender = mpsc::UnboundedSender<(Vec<u8>, oneshot::Sender<Result<Vec<u8>>>)>;
+type QueryReceiver = mpsc::UnboundedReceiver<(Vec<u8>, oneshot::Sender<Result<Vec<u8>>>)>;
+
+/// Creates a client that sends the queries it receives to `server`, and sends back the answers.
+/// The certificate of the server is verified against the CA certificates of the system.
+pub fn create_encrypted_dns_client(
+    server: EncryptedDnsServer,
+    handle: &Handle,
+) -> Result<QuerySender> {
+    let mut connector = HttpsConnectorWithSni::with_system_ca(handle)?;
+    connector.set_sni_hostname(Some(server.hostname.clone()));
+    let address = SocketAddr::new(server.address, server.port());
+
+    let (query_tx, query_rx) = mpsc::unbounded();
+    let queries = match server.protocol {
+        EncryptedDnsProtocol::Https => {
+            let client = Client::configure().connector(connector).build(handle);
+            let uri: Uri = format!("https://{}{}", address, DOH_PATH).parse()?;
+            process_queries(query_rx, handle.clone(), move |query| {
+                https_exchange(&client, uri.clone(), &server.hostname, query)
+            })
+        }
+        EncryptedDnsProtocol::Tls => {
+            let uri: Uri = format!("https://{}", address).parse()?;
+            process_stream_queries(query_rx, handle.clone(), move || {
+                connector.call(uri.clone())
+            })
+        }
+    };
+    handle.spawn(queries);
+    Ok(query_tx)
+}
+
+/// Answers each query with the future returned by `exchange`. Queries are processed
+/// concurrently.
+fn process_queries<F>(
+    query_rx: QueryReceiver,
+    handle: Handle,
+    exchange: impl Fn(Vec<u8>) -> F + 'static,
+) -> Box<dyn Future<Item = (), Error = ()>>
+where
+    F: Future<Item = Vec<u8>, Error = Error> + 'static,
+{
+    let f = query_rx.for_each(move |(query, response_tx)| {
+        let answer = with_timeout(exchange(query), &handle).then(move |answer_result| {
+            if response_tx.send(answer_result).is_err() {
+                log::warn!("Unable to send DNS answer back to caller");
+            }
+            Ok(())
+        });
+        handle.spawn(answer);
+        Ok(())
+    });
+    Box::new(f)
+}
+
+/// Sends the query as the body of a POST request, as described in RFC 8484.
+fn https_exchange<CC: hyper::client::Connect>(
+    client: &Client<CC, hyper::Body>,
+    uri: Uri,
+    hostname: &str,
+    query: Vec<u8>,
+) -> impl Future<Item = Vec<u8>, Error = Error> {
+    let mut request = Request::new(Method::Post, uri);
+    request
+        .headers_mut()
+        .set(Host::new(hostname.to_owned(), None));
+    request
+        .headers_mut()
+        .set_raw("Content-Type", DNS_MESSAGE_MEDIA_TYPE);
+    request
+        .headers_mut()
+        .set_raw("Accept", DNS_MESSAGE_MEDIA_TYPE);
+    request.set_body(query);
+
+    client
+        .request(request)
+        .from_err()
+        .and_then(|response: hyper::Response| {
+            if response.status() == StatusCode::Ok {
+                future::ok(response)
+            } else {
+                future::err(Error::HttpError(response.status()))
+            }
+        })
+        .and_then(|response: hyper::Response| response.body().concat2().from_err())
+        .map(|response_chunk| response_chunk.to_vec())
+}
+
+/// Answers each query over a connection returned by `connect`, as described in RFC 7858. The
+/// connection is kept open between queries, so queries are processed one at a time.
+fn process_stream_queries<C, F, S>(
+    query_rx: QueryReceiver,
+    handle: Handle,
+    connect: C,
+) -> Box<dyn Future<Item = (), Error = ()>>
+where
+    C: Fn() -> F + 'static,
+    F: Future<Item = S, Error = io::Error> + 'static,
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let connect = Rc::new(connect);
+    let connection = Rc::new(RefCell::new(None));
+    let f = query_rx.for_each(move |(query, response_tx)| {
+        let exchange = stream_exchange(connect.clone(), connection.clone(), query);
+        with_timeout(exchange, &handle).then(move |answer_result| {
+            if response_tx.send(answer_result).is_err() {
+                log::warn!("Unable to send DNS answer back to caller");
+            }
+            Ok(())
+        })
+    });
+    Box::new(f)
+}
+
+/// Sends the query prefixed with its length over the open connection, or a new one if there is
+/// none. The server may close idle connections at any time, so a query that fails on a reused
+/// connection is sent once more over a new one. The connection is only kept if the exchange
+/// succeeds.
+fn stream_exchange<C, F, S>(
+    connect: Rc<C>,
+    connection: Rc<RefCell<Option<S>>>,
+    query: Vec<u8>,
+) -> Box<dyn Future<Item = Vec<u8>, Error = Error>>
+where
+    C: Fn() -> F + 'static,
+    F: Future<Item = S, Error = io::Error> + 'static,
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(&query);
+
+    let open_stream = connection.borrow_mut().take();
+    let exchange: Box<dyn Future<Item = (S, Vec<u8>), Error = io::Error>> = match open_stream {
+        Some(stream) => {
+            let retry_message = message.clone();
+            Box::new(send_message(stream, message).or_else(move |error| {
+                log::debug!("Reconnecting to DNS server after error: {}", error);
+                connect().and_then(move |stream| send_message(stream, retry_message))
+            }))
+        }
+        None => Box::new(connect().and_then(move |stream| send_message(stream, message))),
+    };
+
+    Box::new(
+        exchange
+            .map(move |(stream, answer)| {
+                *connection.borrow_mut() = Some(stream);
+                answer
+            })
+            .from_err(),
+    )
+}
+
+/// Writes a length prefixed message to the stream and reads back the length prefixed answer.
+fn send_message<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    message: Vec<u8>,
+) -> impl Future<Item = (S, Vec<u8>), Error = io::Error> {
+    tokio_io::io::write_all(stream, message)
+        .and_then(|(stream, _)| tokio_io::io::read_exact(stream, [0u8; 2]))
+        .and_then(|(stream, length)| {
+            let length = u16::from_be_bytes(length) as usize;
+            tokio_io::io::read_exact(stream, vec![0u8; length])
+        })
+}
+
+fn with_timeout<F>(exchange: F, handle: &Handle) -> impl Future<Item = Vec<u8>, Error = Error>
+where
+    F: Future<Item = Vec<u8>, Error = Error>,
+{
+    future::result(Timeout::new(QUERY_TIMEOUT, handle))
+        .from_err()
+        .and_then(move |timeout| {
+            exchange.select2(timeout).then(|result| match result {
+                Ok(Either::A((answer, _))) => Ok(answer),
+                Ok(Either::B(_)) => Err(Error::TimedOut(QUERY_TIMEOUT)),
+                Err(Either::A((error, _))) => Err(error),
+                Err(Either::B((error, _))) => Err(Error::Io(error)),
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+    use tokio_core::{net::TcpStream as AsyncTcpStream, reactor::Core};
+
+    /// Answers `queries` length prefixed queries on the stream, with the query itself.
+    fn echo_queries(stream: &mut TcpStream, queries: usize) {
+        for _ in 0..queries {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut query).unwrap();
+            stream.write_all(&length).unwrap();
+            stream.write_all(&query).unwrap();
+        }
+    }
+
+    /// Starts a server that accepts one connection for each entry in `queries_per_connection`,
+    /// and answers that many queries on it before closing it. Returns the number of connections
+    /// that were accepted once the server is done.
+    fn start_server(queries_per_connection: Vec<usize>) -> (SocketAddr, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for &queries in &queries_per_connection {
+                let (mut stream, _) = listener.accept().unwrap();
+                echo_queries(&mut stream, queries);
+            }
+            listener.set_nonblocking(true).unwrap();
+            queries_per_connection.len()
+                + listener
+                    .incoming()
+                    .take_while(|stream| stream.is_ok())
+                    .count()
+        });
+        (address, server)
+    }
+
+    fn exchange_queries(address: SocketAddr, queries: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (query_tx, query_rx) = mpsc::unbounded();
+        let connect_handle = handle.clone();
+        handle.spawn(process_stream_queries(
+            query_rx,
+            handle.clone(),
+            move || AsyncTcpStream::connect(&address, &connect_handle),
+        ));
+
+        queries
+            .iter()
+            .map(|query| {
+                let (answer_tx, answer_rx) = oneshot::channel();
+                query_tx
+                    .unbounded_send((query.to_vec(), answer_tx))
+                    .unwrap();
+                core.run(answer_rx).unwrap().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reuses_connection() {
+        let (address, server) = start_server(vec![2]);
+
+        let answers = exchange_queries(address, &[b"first", b"second"]);
+
+        assert_eq!(answers, vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn reconnects_when_server_closes_connection() {
+        let (address, server) = start_server(vec![1, 1]);
+
+        let answers = exchange_queries(address, &[b"first", b"second"]);
+
+        assert_eq!(answers, vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(server.join().unwrap(), 2);
+    }
+}
@@ -70,6 +70,16 @@ impl HttpsConnectorWithSni<HttpConnector> {
 
         Ok(HttpsConnectorWithSni::from((http, ssl)))
     }
+
+    /// Construct a new HttpsConnectorWithSni that trusts the CA certificates of the system,
+    /// instead of the ones in a given file.
+    pub fn with_system_ca(handle: &Handle) -> Result<Self, ErrorStack> {
+        let mut http = HttpConnector::new(crate::DNS_THREADS, handle);
+        http.enforce_http(false);
+        let ssl = SslConnector::builder(SslMethod::tls())?.build();
+
+        Ok(HttpsConnectorWithSni::from((http, ssl)))
+    }
 }
 
 impl<T> HttpsConnectorWithSni<T>
@@ -11,7 +11,13 @@
 use chrono::{offset::Utc, DateTime};
 use jsonrpc_client_core::{expand_params, jsonrpc_client};
 use jsonrpc_client_http::{header::Host, HttpTransport, HttpTransportBuilder};
-use mullvad_types::{account::AccountToken, relay_list::RelayList, version};
+use mullvad_types::{
+    account::AccountToken,
+    location::{CityCode, CountryCode},
+    port_forward::PortForward,
+    relay_list::RelayList,
+    version,
+};
 use std::{
     collections::BTreeMap,
     net::{IpAddr, Ipv4Addr},
@@ -24,6 +30,7 @@ use tokio_core::reactor::Handle;
 pub use jsonrpc_client_core::{Error, ErrorKind};
 pub use jsonrpc_client_http::{Error as HttpError, HttpHandle};
 
+pub mod encrypted_dns;
 pub mod event_loop;
 pub mod rest;
 
@@ -136,4 +143,37 @@ jsonrpc_client!(pub struct WireguardKeyProxy {
         account_token: AccountToken,
         public_key: wireguard::PublicKey
     ) -> RpcRequest<bool>;
+    pub fn remove_wg_key(
+        &mut self,
+        account_token: AccountToken,
+        public_key: wireguard::PublicKey
+    ) -> RpcRequest<()>;
+    pub fn get_wg_keys(
+        &mut self,
+        account_token: AccountToken
+    ) -> RpcRequest<Vec<mullvad_types::wireguard::RegisteredKey>>;
+    pub fn replace_wg_key(
+        &mut self,
+        account_token: AccountToken,
+        old: wireguard::PublicKey,
+        new: wireguard::PublicKey
+    ) -> RpcRequest<mullvad_types::wireguard::AssociatedAddresses>;
+});
+
+jsonrpc_client!(pub struct PortForwardingProxy {
+    pub fn add_port_forward(
+        &mut self,
+        account_token: AccountToken,
+        country_code: CountryCode,
+        city_code: CityCode
+    ) -> RpcRequest<PortForward>;
+    pub fn list_port_forwards(
+        &mut self,
+        account_token: AccountToken
+    ) -> RpcRequest<Vec<PortForward>>;
+    pub fn remove_port_forward(
+        &mut self,
+        account_token: AccountToken,
+        port_forward: PortForward
+    ) -> RpcRequest<()>;
 });
@@ -259,6 +259,9 @@ pub struct DaemonRunner {
     process: Option<duct::Handle>,
     mock_openvpn_args_file: PathBuf,
     rpc_socket_path: PathBuf,
+    cache_dir: PathBuf,
+    resource_dir: PathBuf,
+    settings_dir: PathBuf,
     _temp_dir: TempDir,
 }
 
@@ -273,27 +276,52 @@ impl DaemonRunner {
 
         let rpc_socket_path = temp_dir.path().join("rpc_socket");
 
+        let mut runner = DaemonRunner {
+            process: None,
+            mock_openvpn_args_file,
+            rpc_socket_path,
+            cache_dir,
+            resource_dir,
+            settings_dir,
+            _temp_dir: temp_dir,
+        };
+        runner.start_process();
+        runner
+    }
+
+    fn start_process(&mut self) {
         let expression = duct::cmd!(DAEMON_EXECUTABLE_PATH, "-v", "--disable-log-to-file")
             .dir("..")
-            .env("MULLVAD_CACHE_DIR", cache_dir)
-            .env("MULLVAD_RPC_SOCKET_PATH", rpc_socket_path.clone())
-            .env("MULLVAD_RESOURCE_DIR", resource_dir)
-            .env("MULLVAD_SETTINGS_DIR", settings_dir)
-            .env("MOCK_OPENVPN_ARGS_FILE", mock_openvpn_args_file.clone())
+            .env("MULLVAD_CACHE_DIR", &self.cache_dir)
+            .env("MULLVAD_RPC_SOCKET_PATH", &self.rpc_socket_path)
+            .env("MULLVAD_RESOURCE_DIR", &self.resource_dir)
+            .env("MULLVAD_SETTINGS_DIR", &self.settings_dir)
+            .env("MOCK_OPENVPN_ARGS_FILE", &self.mock_openvpn_args_file)
             .stdout_null()
             .stderr_null();
 
+        self.process = Some(expression.start().expect("Failed to start daemon"));
+    }
 
-        let process = expression.start().expect("Failed to start daemon");
-
-        DaemonRunner {
-            process: Some(process),
-            mock_openvpn_args_file,
-            rpc_socket_path,
-            _temp_dir: temp_dir,
+    /// Kills the daemon abruptly, without giving it a chance to clean up after itself.
+    pub fn kill(&mut self) {
+        if let Some(process) = self.process.take() {
+            process.kill().expect("Failed to kill daemon");
         }
     }
 
+    /// Starts the daemon again after it has been killed, with the same directories.
+    pub fn restart(&mut self) {
+        assert!(self.process.is_none(), "The daemon is still running");
+        // The killed daemon didn't get to remove its socket, so wait for a new one to be created.
+        let _ = fs::remove_file(&self.rpc_socket_path);
+        self.start_process();
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
     pub fn mock_openvpn_args_file(&self) -> &Path {
         &self.mock_openvpn_args_file
     }
@@ -18,6 +18,8 @@ const OPENVPN_PLUGIN_NAME: &str = "libtalpid_openvpn_plugin.so";
 #[cfg(windows)]
 const OPENVPN_PLUGIN_NAME: &str = "talpid_openvpn_plugin.dll";
 
+const SYSTEM_STATE_JOURNAL_FILE: &str = "system-state-journal.json";
+
 #[test]
 fn spawns_openvpn() {
     let mut daemon = DaemonRunner::spawn();
@@ -179,6 +181,49 @@ fn disconnects() {
     let _ = assert_state_event(state_events, TunnelStateTransition::Disconnected);
 }
 
+#[test]
+fn restores_system_state_after_being_killed() {
+    let mut daemon = DaemonRunner::spawn();
+    let mut rpc_client = daemon.rpc_client().unwrap();
+    let openvpn_args_file = daemon.mock_openvpn_args_file().to_owned();
+    let mut openvpn_args_file_events = PathWatcher::watch(&openvpn_args_file).unwrap();
+    let state_events = rpc_client.daemon_event_subscribe().wait().unwrap();
+    let journal_file = daemon.cache_dir().join(SYSTEM_STATE_JOURNAL_FILE);
+
+    rpc_client.set_account(Some("123456".to_owned())).unwrap();
+    rpc_client.connect().unwrap();
+
+    let state_events = assert_state_event(
+        state_events,
+        TunnelStateTransition::Connecting(get_default_endpoint()),
+    );
+    openvpn_args_file_events.assert_create_write_close_sequence();
+
+    let mut mock_plugin_client = create_mock_openvpn_plugin_client(&openvpn_args_file);
+
+    mock_plugin_client.up().unwrap();
+
+    let _ = assert_state_event(
+        state_events,
+        TunnelStateTransition::Connected(get_default_endpoint()),
+    );
+    assert!(journal_file.exists());
+
+    daemon.kill();
+    // The mock OpenVPN process exits when the daemon is gone
+    assert_eq!(openvpn_args_file_events.next(), Some(watch_event::REMOVE));
+    assert!(journal_file.exists());
+
+    daemon.restart();
+    let mut rpc_client = daemon.rpc_client().unwrap();
+
+    assert_eq!(
+        rpc_client.get_state().unwrap(),
+        TunnelStateTransition::Disconnected
+    );
+    assert!(!journal_file.exists());
+}
+
 fn get_default_endpoint() -> TunnelEndpoint {
     TunnelEndpoint {
         endpoint: Endpoint {
@@ -0,0 +1,195 @@
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+use talpid_types::net::network_info::{ActiveNetwork, InterfaceKind};
+
+/// Rules deciding whether the tunnel should be secured, based on the networks the device is
+/// connected to and the time of day. Evaluated every time the connected networks change.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AutoConnectRules {
+    /// If the rules should change the target state at all.
+    pub enabled: bool,
+    /// Networks where the tunnel is disconnected. All other networks are untrusted and make the
+    /// tunnel connect.
+    pub trusted_networks: Vec<NetworkMatcher>,
+    /// Only secure untrusted networks during this time of day. Untrusted networks are always
+    /// secured if no schedule is set.
+    pub schedule: Option<Schedule>,
+}
+
+impl AutoConnectRules {
+    /// Returns true if any of the trusted network matchers match the given network.
+    pub fn is_trusted(&self, network: &ActiveNetwork) -> bool {
+        self.trusted_networks
+            .iter()
+            .any(|matcher| matcher.matches(network))
+    }
+
+    /// Returns the schedule, if the rules are enabled and limited to a time of day.
+    pub fn active_schedule(&self) -> Option<Schedule> {
+        if self.enabled {
+            self.schedule
+        } else {
+            None
+        }
+    }
+}
+
+/// Identifies one or more networks.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkMatcher {
+    /// A wireless network with the given name.
+    Ssid(String),
+    /// Any network where the default gateway has the given hardware address.
+    GatewayMac(String),
+    /// All networks reached through a given type of interface.
+    InterfaceKind(InterfaceKind),
+}
+
+impl NetworkMatcher {
+    pub fn matches(&self, network: &ActiveNetwork) -> bool {
+        match self {
+            NetworkMatcher::Ssid(ssid) => network.ssid.as_ref() == Some(ssid),
+            NetworkMatcher::GatewayMac(mac) => network
+                .gateway_mac
+                .as_ref()
+                .map(|gateway_mac| gateway_mac.eq_ignore_ascii_case(mac))
+                .unwrap_or(false),
+            NetworkMatcher::InterfaceKind(kind) => network.kind == *kind,
+        }
+    }
+}
+
+impl fmt::Display for NetworkMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkMatcher::Ssid(ssid) => write!(f, "SSID {}", ssid),
+            NetworkMatcher::GatewayMac(mac) => write!(f, "gateway {}", mac),
+            NetworkMatcher::InterfaceKind(kind) => write!(f, "all {} networks", kind),
+        }
+    }
+}
+
+/// A daily time window, in local time. Windows where `end` is before `start` wrap past midnight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Schedule {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Schedule {
+    /// Returns true if `time` is inside the window. A window that starts and ends at the same
+    /// time covers the whole day.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else if self.start > self.end {
+            self.start <= time || time < self.end
+        } else {
+            true
+        }
+    }
+
+    /// Returns how long it is from `time` until the window next starts or ends, or `None` if the
+    /// window covers the whole day.
+    pub fn time_until_next_boundary(&self, time: NaiveTime) -> Option<Duration> {
+        if self.start == self.end {
+            return None;
+        }
+        [self.start, self.end]
+            .iter()
+            .map(|boundary| {
+                let until = boundary.signed_duration_since(time);
+                if until <= chrono::Duration::zero() {
+                    until + chrono::Duration::days(1)
+                } else {
+                    until
+                }
+            })
+            .min()
+            .and_then(|until| until.to_std().ok())
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn schedule_within_day() {
+        let schedule = Schedule {
+            start: time(8, 0),
+            end: time(18, 0),
+        };
+        assert!(!schedule.contains(time(7, 59)));
+        assert!(schedule.contains(time(8, 0)));
+        assert!(schedule.contains(time(17, 59)));
+        assert!(!schedule.contains(time(18, 0)));
+    }
+
+    #[test]
+    fn schedule_past_midnight() {
+        let schedule = Schedule {
+            start: time(22, 0),
+            end: time(6, 0),
+        };
+        assert!(schedule.contains(time(23, 0)));
+        assert!(schedule.contains(time(5, 59)));
+        assert!(!schedule.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn schedule_next_boundary() {
+        let schedule = Schedule {
+            start: time(22, 0),
+            end: time(6, 0),
+        };
+        assert_eq!(
+            schedule.time_until_next_boundary(time(21, 30)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            schedule.time_until_next_boundary(time(22, 0)),
+            Some(Duration::from_secs(8 * 60 * 60))
+        );
+        assert_eq!(
+            schedule.time_until_next_boundary(time(23, 0)),
+            Some(Duration::from_secs(7 * 60 * 60))
+        );
+
+        let whole_day = Schedule {
+            start: time(8, 0),
+            end: time(8, 0),
+        };
+        assert_eq!(whole_day.time_until_next_boundary(time(12, 0)), None);
+    }
+
+    #[test]
+    fn gateway_mac_is_case_insensitive() {
+        let network = ActiveNetwork {
+            interface: "eth0".to_owned(),
+            kind: InterfaceKind::Wired,
+            ssid: None,
+            gateway_mac: Some("aa:bb:cc:dd:ee:ff".to_owned()),
+        };
+        assert!(NetworkMatcher::GatewayMac("AA:BB:CC:DD:EE:FF".to_owned()).matches(&network));
+        assert!(!NetworkMatcher::Ssid("Office".to_owned()).matches(&network));
+    }
+}
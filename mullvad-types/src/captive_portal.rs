@@ -0,0 +1,37 @@
+use chrono::{offset::Utc, DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The longest time that captive portal login traffic can be allowed for at once, in minutes.
+pub const MAX_MINUTES: u32 = 30;
+
+/// Announces changes to captive portal mode. While in captive portal mode, the firewall lets
+/// HTTP, HTTPS and DNS traffic to the network of the default gateway through, even when
+/// everything else is blocked, so that the user can log in to a captive portal.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptivePortalEvent {
+    /// Captive portal mode was entered, and ends automatically at the given time.
+    Started { expires: DateTime<Utc> },
+    /// Captive portal mode ended because its time ran out.
+    Expired,
+    /// Captive portal mode was ended before its time ran out.
+    Ended,
+}
+
+impl fmt::Display for CaptivePortalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptivePortalEvent::Started { expires } => write!(
+                f,
+                "Allowing captive portal login traffic until {}",
+                expires.with_timezone(&Local).format("%X")
+            ),
+            CaptivePortalEvent::Expired => write!(
+                f,
+                "Stopped allowing captive portal login traffic since its time ran out"
+            ),
+            CaptivePortalEvent::Ended => write!(f, "Stopped allowing captive portal login traffic"),
+        }
+    }
+}
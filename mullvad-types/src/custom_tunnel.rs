@@ -28,6 +28,14 @@ impl CustomTunnelEndpoint {
         Self { host, config }
     }
 
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
     pub fn to_tunnel_parameters(
         &self,
         tunnel_options: TunnelOptions,
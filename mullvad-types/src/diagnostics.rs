@@ -0,0 +1,16 @@
+use crate::states::{TargetState, TunnelState};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The daemon's view of the tunnel, for troubleshooting connection problems.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub target_state: TargetState,
+    pub tunnel_state: TunnelState,
+    /// How long the tunnel has been in its current state.
+    pub time_in_state: Duration,
+    /// The most recent reason a tunnel failed or the daemon started blocking, if any.
+    pub last_error: Option<String>,
+    /// The system service DNS is currently set through, if it's known.
+    pub dns_backend: Option<String>,
+}
@@ -16,6 +16,7 @@ pub enum MullvadEndpoint {
         peer: wireguard::PeerConfig,
         ipv4_gateway: Ipv4Addr,
         ipv6_gateway: Ipv6Addr,
+        protocol: TransportProtocol,
     },
 }
 
@@ -24,11 +25,9 @@ impl MullvadEndpoint {
     pub fn to_endpoint(&self) -> Endpoint {
         match self {
             MullvadEndpoint::OpenVpn(endpoint) => *endpoint,
-            MullvadEndpoint::Wireguard { peer, .. } => Endpoint::new(
-                peer.endpoint.ip(),
-                peer.endpoint.port(),
-                TransportProtocol::Udp,
-            ),
+            MullvadEndpoint::Wireguard { peer, protocol, .. } => {
+                Endpoint::new(peer.endpoint.ip(), peer.endpoint.port(), *protocol)
+            }
         }
     }
 }
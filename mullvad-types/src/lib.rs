@@ -10,13 +10,18 @@
 
 pub mod account;
 pub mod auth_failed;
+pub mod auto_connect;
+pub mod captive_portal;
+pub mod diagnostics;
 pub mod endpoint;
 pub mod location;
+pub mod port_forward;
 pub mod relay_constraints;
 pub mod relay_list;
 pub mod settings;
 pub mod states;
 pub mod version;
+pub mod wg_quick;
 pub mod wireguard;
 
 mod custom_tunnel;
@@ -37,4 +42,11 @@ pub enum DaemonEvent {
 
     /// Key event
     WireguardKey(wireguard::KeygenEvent),
+
+    /// Captive portal mode was entered or ended.
+    CaptivePortal(captive_portal::CaptivePortalEvent),
+
+    /// Traffic counters of the current tunnel. Sent periodically while connected, if an interval
+    /// has been set with `set_tunnel_stats_interval`.
+    TunnelStats(talpid_types::tunnel::TunnelStats),
 }
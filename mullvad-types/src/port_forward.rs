@@ -0,0 +1,30 @@
+use crate::location::{CityCode, CountryCode, Location};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A port that is forwarded from the exit address of all relays in a city, through the tunnel and
+/// to this device.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PortForward {
+    /// The port on the relay that incoming connections should be sent to.
+    pub port: u16,
+    pub country_code: CountryCode,
+    pub city_code: CityCode,
+}
+
+impl PortForward {
+    /// Returns true if the port is forwarded on relays in the given location.
+    pub fn is_valid_for(&self, location: &Location) -> bool {
+        self.country_code == location.country_code && self.city_code == location.city_code
+    }
+}
+
+impl fmt::Display for PortForward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "port {} in {}, {}",
+            self.port, self.city_code, self.country_code
+        )
+    }
+}
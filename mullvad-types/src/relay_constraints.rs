@@ -253,25 +253,39 @@ impl Match<OpenVpnEndpointData> for OpenVpnConstraints {
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct WireguardConstraints {
     pub port: Constraint<u16>,
+    /// Transport used to reach the relay. Over TCP, WireGuard is carried through a local proxy.
+    #[serde(default)]
+    pub protocol: Constraint<TransportProtocol>,
 }
 
 impl fmt::Display for WireguardConstraints {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self.port {
-            Constraint::Any => write!(f, "any port"),
-            Constraint::Only(port) => write!(f, "port {}", port),
+            Constraint::Any => write!(f, "any port")?,
+            Constraint::Only(port) => write!(f, "port {}", port)?,
+        }
+        write!(f, " over ")?;
+        match self.protocol {
+            Constraint::Any => write!(f, "any protocol"),
+            Constraint::Only(protocol) => write!(f, "{}", protocol),
         }
     }
 }
 
 impl Match<WireguardEndpointData> for WireguardConstraints {
     fn matches(&self, endpoint: &WireguardEndpointData) -> bool {
-        match self.port {
-            Constraint::Any => true,
-            Constraint::Only(port) => endpoint
-                .port_ranges
-                .iter()
-                .any(|range| (port >= range.0 && port <= range.1)),
+        match (self.port, self.protocol) {
+            (Constraint::Any, Constraint::Only(TransportProtocol::Tcp)) => {
+                !endpoint.tcp_port_ranges.is_empty()
+            }
+            (Constraint::Any, _) => true,
+            (Constraint::Only(port), Constraint::Only(protocol)) => {
+                endpoint.has_port(port, protocol)
+            }
+            (Constraint::Only(port), Constraint::Any) => {
+                endpoint.has_port(port, TransportProtocol::Udp)
+                    || endpoint.has_port(port, TransportProtocol::Tcp)
+            }
         }
     }
 }
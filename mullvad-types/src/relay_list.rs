@@ -97,6 +97,9 @@ impl fmt::Display for OpenVpnEndpointData {
 pub struct WireguardEndpointData {
     /// Port to connect to
     pub port_ranges: Vec<(u16, u16)>,
+    /// Ports that WireGuard over TCP can be connected to. Empty if the relay doesn't support it.
+    #[serde(default)]
+    pub tcp_port_ranges: Vec<(u16, u16)>,
     /// Gateways to be used with the tunnel
     pub ipv4_gateway: Ipv4Addr,
     pub ipv6_gateway: Ipv6Addr,
@@ -104,6 +107,19 @@ pub struct WireguardEndpointData {
     pub public_key: wireguard::PublicKey,
 }
 
+impl WireguardEndpointData {
+    /// Returns whether the relay accepts WireGuard traffic on `port` over `protocol`.
+    pub fn has_port(&self, port: u16, protocol: TransportProtocol) -> bool {
+        let port_ranges = match protocol {
+            TransportProtocol::Udp => &self.port_ranges,
+            TransportProtocol::Tcp => &self.tcp_port_ranges,
+        };
+        port_ranges
+            .iter()
+            .any(|range| port >= range.0 && port <= range.1)
+    }
+}
+
 impl fmt::Display for WireguardEndpointData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         write!(
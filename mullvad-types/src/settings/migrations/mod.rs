@@ -2,12 +2,13 @@ use super::{Error, Result, Settings};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::io::Read;
 mod v1;
+mod v2;
 
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[repr(u32)]
 pub enum SettingsVersion {
-    V2 = 2,
+    V3 = 3,
 }
 
 impl SettingsVersion {
@@ -16,11 +17,11 @@ impl SettingsVersion {
     }
 
     pub fn max_version() -> Self {
-        SettingsVersion::V2
+        SettingsVersion::V3
     }
 
     pub fn min_version() -> Self {
-        SettingsVersion::V2
+        SettingsVersion::V3
     }
 }
 
@@ -61,14 +62,15 @@ impl Serialize for SettingsVersion {
 #[derive(Debug)]
 enum VersionedSettings {
     V1(v1::Settings),
-    V2(crate::settings::Settings),
+    V2(v2::Settings),
+    V3(crate::settings::Settings),
 }
 
 impl VersionedSettings {
     /// Unrwaps the latest version of settings or panics.
     fn unwrap(self) -> Settings {
         match self {
-            VersionedSettings::V2(settings) => settings,
+            VersionedSettings::V3(settings) => settings,
             lower => {
                 panic!("Unexpected settings version - {:?}", lower);
             }
@@ -83,16 +85,16 @@ trait SettingsMigration {
 }
 
 fn migrations() -> Vec<Box<dyn SettingsMigration>> {
-    vec![Box::new(v1::Migration)]
+    vec![Box::new(v2::Migration), Box::new(v1::Migration)]
 }
 
-pub fn try_migrate_settings(mut settings_file: &[u8]) -> Result<crate::settings::Settings> {
+pub fn try_migrate_settings(settings_file: &[u8]) -> Result<crate::settings::Settings> {
     let mut migrations_to_apply = vec![];
     let mut valid_settings = None;
 
     let migrations = migrations();
     for migration in migrations.iter() {
-        match migration.read(&mut settings_file) {
+        match migration.read(&mut &settings_file[..]) {
             Ok(settings) => {
                 valid_settings = Some(migration.migrate(settings));
                 break;
@@ -122,24 +124,24 @@ mod test {
     #[test]
     #[should_panic]
     fn test_deserialization_failure_version_too_small() {
-        let _version: SettingsVersion = serde_json::from_str("1").expect("Version too small");
+        let _version: SettingsVersion = serde_json::from_str("2").expect("Version too small");
     }
 
     #[test]
     #[should_panic]
     fn test_deserialization_failure_version_too_big() {
-        let _version: SettingsVersion = serde_json::from_str("3").expect("Version too big");
+        let _version: SettingsVersion = serde_json::from_str("4").expect("Version too big");
     }
 
     #[test]
     fn test_deserialization_success() {
-        let _version: SettingsVersion = serde_json::from_str("2").expect("Failed to deserialize valid version");
+        let _version: SettingsVersion = serde_json::from_str("3").expect("Failed to deserialize valid version");
     }
 
     #[test]
     fn test_serialization_success() {
-        let version = SettingsVersion::V2;
+        let version = SettingsVersion::V3;
         let s = serde_json::to_string(&version).expect("Failed to serialize");
-        assert_eq!(s, "2");
+        assert_eq!(s, "3");
     }
 }
@@ -1,4 +1,4 @@
-use super::{Error, Result, VersionedSettings};
+use super::{v2, Error, Result, VersionedSettings};
 use crate::{
     custom_tunnel::CustomTunnelEndpoint,
     relay_constraints::{
@@ -39,7 +39,7 @@ impl super::SettingsMigration for Migration {
     }
     fn migrate(&self, old: VersionedSettings) -> VersionedSettings {
         match old {
-            VersionedSettings::V1(old) => VersionedSettings::V2(crate::settings::Settings {
+            VersionedSettings::V1(old) => VersionedSettings::V2(v2::Settings {
                 account_token: old.account_token,
                 relay_settings: migrate_relay_settings(old.relay_settings),
                 bridge_settings: old.bridge_settings,
@@ -48,9 +48,10 @@ impl super::SettingsMigration for Migration {
                 block_when_disconnected: old.block_when_disconnected,
                 auto_connect: old.auto_connect,
                 tunnel_options: old.tunnel_options,
-                settings_version: super::SettingsVersion::V2,
+                port_forwards: Vec::new(),
+                settings_version: v2::SETTINGS_VERSION,
             }),
-            VersionedSettings::V2(new) => VersionedSettings::V2(new),
+            newer => newer,
         }
     }
 }
@@ -206,9 +207,24 @@ mod test {
         let old_settings = m
             .read(&mut OLD_SETTINGS.as_bytes())
             .expect("Failed to deserialize old format");
-        let new_settings = serde_json::from_str(&NEW_SETTINGS).unwrap();
+        let new_settings: super::super::v2::Settings = serde_json::from_str(&NEW_SETTINGS).unwrap();
 
-        assert_eq!(&m.migrate(old_settings).unwrap(), &new_settings);
+        match m.migrate(old_settings) {
+            super::super::VersionedSettings::V2(migrated_settings) => {
+                assert_eq!(migrated_settings, new_settings)
+            }
+            other => panic!("Unexpected settings version - {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_migration_to_latest_version() {
+        let settings = super::super::try_migrate_settings(OLD_SETTINGS.as_bytes())
+            .expect("Failed to migrate old format");
+        assert_eq!(
+            settings.get_lan_policy(),
+            &talpid_types::net::lan::LanPolicy::allow_all()
+        );
     }
 
     #[test]
@@ -0,0 +1,169 @@
+use super::{Error, Result, VersionedSettings};
+use crate::{
+    port_forward::PortForward,
+    relay_constraints::{BridgeSettings, BridgeState, RelaySettings},
+    settings::TunnelOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use talpid_types::net::lan::LanPolicy;
+
+
+pub(super) const SETTINGS_VERSION: u32 = 2;
+
+/// Mullvad daemon settings.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Settings {
+    pub(super) account_token: Option<String>,
+    pub(super) relay_settings: RelaySettings,
+    pub(super) bridge_settings: BridgeSettings,
+    pub(super) bridge_state: BridgeState,
+    /// If the daemon should allow communication with private (LAN) networks.
+    pub(super) allow_lan: bool,
+    /// Extra level of kill switch. When this setting is on, the disconnected state will block
+    /// the firewall to not allow any traffic in or out.
+    pub(super) block_when_disconnected: bool,
+    /// If the daemon should connect the VPN tunnel directly on start or not.
+    pub(super) auto_connect: bool,
+    /// Options that should be applied to tunnels of a specific type regardless of where the relays
+    /// might be located.
+    pub(super) tunnel_options: TunnelOptions,
+    /// Ports that have been forwarded on the relays for the current account.
+    #[serde(default)]
+    pub(super) port_forwards: Vec<PortForward>,
+    /// Specifies settings schema version
+    pub(super) settings_version: u32,
+}
+
+pub(super) struct Migration;
+impl super::SettingsMigration for Migration {
+    fn read(&self, mut reader: &mut dyn Read) -> Result<VersionedSettings> {
+        let settings: Settings = serde_json::from_reader(&mut reader).map_err(Error::ParseError)?;
+        if settings.settings_version != SETTINGS_VERSION {
+            return Err(Error::NoMatchingVersion);
+        }
+        Ok(VersionedSettings::V2(settings))
+    }
+
+    fn migrate(&self, old: VersionedSettings) -> VersionedSettings {
+        match old {
+            VersionedSettings::V2(old) => VersionedSettings::V3(crate::settings::Settings {
+                account_token: old.account_token,
+                relay_settings: old.relay_settings,
+                bridge_settings: old.bridge_settings,
+                bridge_state: old.bridge_state,
+                lan_policy: if old.allow_lan {
+                    LanPolicy::allow_all()
+                } else {
+                    LanPolicy::block_all()
+                },
+                split_dns: Default::default(),
+                local_resolver: Default::default(),
+                block_when_disconnected: old.block_when_disconnected,
+                auto_connect: old.auto_connect,
+                auto_connect_rules: Default::default(),
+                tunnel_options: old.tunnel_options,
+                port_forwards: old.port_forwards,
+                wireguard_key_rotation_interval: None,
+                tunnel_stats_interval: None,
+                settings_version: super::SettingsVersion::V3,
+            }),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::SettingsMigration;
+    use serde_json;
+    use talpid_types::net::lan::LanPolicy;
+
+    const OLD_SETTINGS: &str = r#"
+{
+  "account_token": "1234",
+  "relay_settings": {
+    "normal": {
+      "location": {
+        "only": {
+          "country": "se"
+        }
+      },
+      "tunnel_protocol": "any",
+      "wireguard_constraints": {
+        "port": "any"
+      },
+      "openvpn_constraints": {
+        "port": "any",
+        "protocol": "any"
+      }
+    }
+  },
+  "bridge_settings": {
+    "normal": {
+      "location": "any"
+    }
+  },
+  "bridge_state": "auto",
+  "allow_lan": true,
+  "block_when_disconnected": false,
+  "auto_connect": false,
+  "tunnel_options": {
+    "openvpn": {
+      "mssfix": null
+    },
+    "wireguard": {
+      "mtu": null
+    },
+    "generic": {
+      "enable_ipv6": false
+    }
+  },
+  "settings_version": 2
+}
+"#;
+
+    #[test]
+    fn test_migration() {
+        let m = super::Migration;
+        let old_settings = m
+            .read(&mut OLD_SETTINGS.as_bytes())
+            .expect("Failed to deserialize old format");
+
+        let new_settings = m.migrate(old_settings).unwrap();
+        assert_eq!(new_settings.get_lan_policy(), &LanPolicy::allow_all());
+        // Exactly the networks that the old setting allowed.
+        let allowed_networks = new_settings
+            .get_lan_policy()
+            .allowed_networks
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            allowed_networks,
+            vec!["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]
+        );
+        assert_eq!(new_settings.get_account_token(), Some("1234".to_owned()));
+    }
+
+    #[test]
+    fn test_migration_blocking_lan() {
+        let m = super::Migration;
+        let old_settings = OLD_SETTINGS.replace(r#""allow_lan": true"#, r#""allow_lan": false"#);
+        let old_settings = m
+            .read(&mut old_settings.as_bytes())
+            .expect("Failed to deserialize old format");
+
+        let new_settings = m.migrate(old_settings).unwrap();
+        assert_eq!(new_settings.get_lan_policy(), &LanPolicy::block_all());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_deserialization_failure() {
+        let m = super::Migration;
+        let new_settings = serde_json::to_string(&crate::settings::Settings::default()).unwrap();
+        m.read(&mut new_settings.as_bytes())
+            .expect("Failed to deserialize old format");
+    }
+}
@@ -1,6 +1,10 @@
-use crate::relay_constraints::{
-    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
-    RelayConstraints, RelaySettings, RelaySettingsUpdate,
+use crate::{
+    auto_connect::AutoConnectRules,
+    port_forward::PortForward,
+    relay_constraints::{
+        BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
+        RelayConstraints, RelaySettings, RelaySettingsUpdate,
+    },
 };
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
@@ -11,7 +15,11 @@ use std::{
     path::PathBuf,
 };
 use talpid_types::{
-    net::{openvpn, wireguard, GenericTunnelOptions},
+    net::{
+        dns::{DnsUpstream, LocalResolverSettings, SplitDns},
+        lan::LanPolicy,
+        openvpn, wireguard, GenericTunnelOptions,
+    },
     ErrorExt,
 };
 
@@ -42,6 +50,12 @@ pub enum Error {
     #[error(display = "Invalid OpenVPN proxy configuration: {}", _0)]
     InvalidProxyData(String),
 
+    #[error(display = "Invalid LAN policy: {}", _0)]
+    InvalidLanPolicy(#[error(cause)] talpid_types::net::lan::InvalidLanNetwork),
+
+    #[error(display = "Invalid split DNS configuration: {}", _0)]
+    InvalidSplitDns(#[error(cause)] talpid_types::net::dns::InvalidSplitDnsDomain),
+
     #[error(display = "Unable to read any version of the settings")]
     NoMatchingVersion,
 }
@@ -57,16 +71,32 @@ pub struct Settings {
     relay_settings: RelaySettings,
     bridge_settings: BridgeSettings,
     bridge_state: BridgeState,
-    /// If the daemon should allow communication with private (LAN) networks.
-    allow_lan: bool,
+    /// What communication with private (LAN) networks the daemon should allow.
+    lan_policy: LanPolicy,
+    /// Domains that should be resolved by the DNS servers of the physical network instead of
+    /// through the tunnel.
+    split_dns: SplitDns,
+    /// Resolve DNS with a local resolver that forwards queries through the tunnel and filters
+    /// them against blocklists.
+    local_resolver: LocalResolverSettings,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     block_when_disconnected: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     auto_connect: bool,
+    /// Rules that connect or disconnect the tunnel when the connected networks change.
+    auto_connect_rules: AutoConnectRules,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     tunnel_options: TunnelOptions,
+    /// Ports that have been forwarded on the relays for the current account.
+    port_forwards: Vec<PortForward>,
+    /// Number of days after which the WireGuard key of the current account is replaced. Keys are
+    /// never rotated if this is unset.
+    wireguard_key_rotation_interval: Option<u32>,
+    /// Number of seconds between the tunnel statistics events sent to subscribers. No events are
+    /// sent if this is unset.
+    tunnel_stats_interval: Option<u32>,
     /// Specifies settings schema version
     settings_version: migrations::SettingsVersion,
 }
@@ -83,11 +113,17 @@ impl Default for Settings {
                 location: Constraint::Any,
             }),
             bridge_state: BridgeState::Auto,
-            allow_lan: false,
+            lan_policy: LanPolicy::block_all(),
+            split_dns: SplitDns::default(),
+            local_resolver: LocalResolverSettings::default(),
             block_when_disconnected: false,
             auto_connect: false,
+            auto_connect_rules: AutoConnectRules::default(),
             tunnel_options: TunnelOptions::default(),
-            settings_version: migrations::SettingsVersion::V2,
+            port_forwards: Vec::new(),
+            wireguard_key_rotation_interval: None,
+            tunnel_stats_interval: None,
+            settings_version: migrations::SettingsVersion::V3,
         }
     }
 }
@@ -176,6 +212,10 @@ impl Settings {
             } else {
                 info!("Changing account token")
             }
+            if !self.port_forwards.is_empty() {
+                info!("Forgetting port forwards belonging to the previous account");
+                self.port_forwards.clear();
+            }
             self.account_token = account_token;
             self.save().map(|_| true)
         } else {
@@ -202,13 +242,41 @@ impl Settings {
         }
     }
 
-    pub fn get_allow_lan(&self) -> bool {
-        self.allow_lan
+    pub fn get_lan_policy(&self) -> &LanPolicy {
+        &self.lan_policy
+    }
+
+    pub fn set_lan_policy(&mut self, lan_policy: LanPolicy) -> Result<bool> {
+        lan_policy.validate().map_err(Error::InvalidLanPolicy)?;
+        if lan_policy != self.lan_policy {
+            self.lan_policy = lan_policy;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_split_dns(&self) -> &SplitDns {
+        &self.split_dns
     }
 
-    pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool> {
-        if allow_lan != self.allow_lan {
-            self.allow_lan = allow_lan;
+    pub fn set_split_dns(&mut self, split_dns: SplitDns) -> Result<bool> {
+        split_dns.validate().map_err(Error::InvalidSplitDns)?;
+        if split_dns != self.split_dns {
+            self.split_dns = split_dns;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_local_resolver(&self) -> &LocalResolverSettings {
+        &self.local_resolver
+    }
+
+    pub fn set_local_resolver(&mut self, local_resolver: LocalResolverSettings) -> Result<bool> {
+        if local_resolver != self.local_resolver {
+            self.local_resolver = local_resolver;
             self.save().map(|_| true)
         } else {
             Ok(false)
@@ -241,6 +309,19 @@ impl Settings {
         }
     }
 
+    pub fn get_auto_connect_rules(&self) -> &AutoConnectRules {
+        &self.auto_connect_rules
+    }
+
+    pub fn set_auto_connect_rules(&mut self, auto_connect_rules: AutoConnectRules) -> Result<bool> {
+        if auto_connect_rules != self.auto_connect_rules {
+            self.auto_connect_rules = auto_connect_rules;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool> {
         if self.tunnel_options.openvpn.mssfix != openvpn_mssfix {
             self.tunnel_options.openvpn.mssfix = openvpn_mssfix;
@@ -259,6 +340,15 @@ impl Settings {
         }
     }
 
+    pub fn set_dns_upstream(&mut self, dns_upstream: DnsUpstream) -> Result<bool> {
+        if self.tunnel_options.generic.dns_upstream != dns_upstream {
+            self.tunnel_options.generic.dns_upstream = dns_upstream;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<bool> {
         if self.tunnel_options.wireguard.mtu != mtu {
             self.tunnel_options.wireguard.mtu = mtu;
@@ -268,6 +358,59 @@ impl Settings {
         }
     }
 
+    pub fn set_wireguard_auto_mtu(&mut self, auto_mtu: bool) -> Result<bool> {
+        if self.tunnel_options.wireguard.auto_mtu != auto_mtu {
+            self.tunnel_options.wireguard.auto_mtu = auto_mtu;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn set_wireguard_backend(&mut self, backend: Option<wireguard::Backend>) -> Result<bool> {
+        if self.tunnel_options.wireguard.backend != backend {
+            self.tunnel_options.wireguard.backend = backend;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn set_wireguard_keepalive(&mut self, interval: Option<u16>) -> Result<bool> {
+        if self.tunnel_options.wireguard.persistent_keepalive != interval {
+            self.tunnel_options.wireguard.persistent_keepalive = interval;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_wireguard_key_rotation_interval(&self) -> Option<u32> {
+        self.wireguard_key_rotation_interval
+    }
+
+    pub fn set_wireguard_key_rotation_interval(&mut self, days: Option<u32>) -> Result<bool> {
+        if self.wireguard_key_rotation_interval != days {
+            self.wireguard_key_rotation_interval = days;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_tunnel_stats_interval(&self) -> Option<u32> {
+        self.tunnel_stats_interval
+    }
+
+    pub fn set_tunnel_stats_interval(&mut self, seconds: Option<u32>) -> Result<bool> {
+        if self.tunnel_stats_interval != seconds {
+            self.tunnel_stats_interval = seconds;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn get_tunnel_options(&self) -> &TunnelOptions {
         &self.tunnel_options
     }
@@ -297,6 +440,41 @@ impl Settings {
             Ok(false)
         }
     }
+
+    pub fn get_port_forwards(&self) -> &[PortForward] {
+        &self.port_forwards
+    }
+
+    pub fn add_port_forward(&mut self, port_forward: PortForward) -> Result<bool> {
+        if !self.port_forwards.contains(&port_forward) {
+            self.port_forwards.push(port_forward);
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn remove_port_forward(&mut self, port_forward: &PortForward) -> Result<bool> {
+        let old_len = self.port_forwards.len();
+        self.port_forwards
+            .retain(|existing| existing != port_forward);
+        if self.port_forwards.len() != old_len {
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Replaces all known port forwards with the given ones, for example after fetching them from
+    /// the API.
+    pub fn set_port_forwards(&mut self, port_forwards: Vec<PortForward>) -> Result<bool> {
+        if self.port_forwards != port_forwards {
+            self.port_forwards = port_forwards;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
 }
 
 /// TunnelOptions holds configuration data that applies to all kinds of tunnels.
@@ -315,8 +493,16 @@ impl Default for TunnelOptions {
     fn default() -> Self {
         TunnelOptions {
             openvpn: openvpn::TunnelOptions::default(),
-            wireguard: wireguard::TunnelOptions { mtu: None },
-            generic: GenericTunnelOptions { enable_ipv6: false },
+            wireguard: wireguard::TunnelOptions {
+                mtu: None,
+                auto_mtu: false,
+                backend: None,
+                persistent_keepalive: None,
+            },
+            generic: GenericTunnelOptions {
+                enable_ipv6: false,
+                dns_upstream: DnsUpstream::Gateway,
+            },
         }
     }
 }
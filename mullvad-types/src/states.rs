@@ -1,8 +1,8 @@
-use crate::location::GeoIpLocation;
+use crate::{location::GeoIpLocation, port_forward::PortForward};
 use serde::{Deserialize, Serialize};
 use talpid_types::{
     net::TunnelEndpoint,
-    tunnel::{ActionAfterDisconnect, BlockReason},
+    tunnel::{ActionAfterDisconnect, BlockReason, ConnectionDiagnostics},
 };
 
 /// Represents the state the client strives towards.
@@ -24,10 +24,15 @@ pub enum TunnelState {
     Connecting {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        diagnostics: ConnectionDiagnostics,
     },
     Connected {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Ports forwarded through the tunnel on the relay currently connected to.
+        #[serde(default)]
+        forwarded_ports: Vec<PortForward>,
+        diagnostics: ConnectionDiagnostics,
     },
     Disconnecting(ActionAfterDisconnect),
     Blocked(BlockReason),
@@ -0,0 +1,502 @@
+//! Conversion between custom WireGuard endpoints and the configuration files read by `wg-quick`.
+
+use crate::{ConnectionConfig, CustomTunnelEndpoint};
+use ipnetwork::IpNetwork;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+};
+use talpid_types::net::{wireguard, TransportProtocol};
+
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error(display = "Line {} is not a section header or a key-value pair", _0)]
+    InvalidLine(usize),
+
+    #[error(display = "Unknown section [{}] on line {}", _1, _0)]
+    UnknownSection(usize, String),
+
+    #[error(display = "Line {} is not inside an [Interface] or [Peer] section", _0)]
+    KeyOutsideSection(usize),
+
+    #[error(display = "Invalid value for {} on line {}", _1, _0)]
+    InvalidValue(usize, String),
+
+    #[error(display = "{} is given more than once", _0)]
+    DuplicateKey(String),
+
+    #[error(display = "Only configurations with exactly one [Peer] section are supported")]
+    PeerCount,
+
+    #[error(display = "Missing {} in the [{}] section", _1, _0)]
+    MissingKey(&'static str, &'static str),
+
+    #[error(display = "DNS must list an IPv4 address, it is used as the tunnel gateway")]
+    NoIpv4Gateway,
+}
+
+
+/// A WireGuard tunnel described in the INI format of `wg-quick` configuration files.
+///
+/// The app sends DNS queries to the gateway of the tunnel, so the first IPv4 and IPv6 addresses
+/// under `DNS` are used as the gateways. All traffic is routed through the tunnel no matter what
+/// `AllowedIPs` says, but the peer still only accepts packets from within `AllowedIPs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgQuickConfig {
+    /// Hostname or IP of the peer.
+    pub host: String,
+    /// Connection parameters. The IP of the peer endpoint is unspecified, since `host` is only
+    /// resolved when connecting.
+    pub connection: wireguard::ConnectionConfig,
+    /// The MTU of the tunnel interface. Other options can't be given in the file.
+    pub options: wireguard::TunnelOptions,
+}
+
+impl WgQuickConfig {
+    /// Builds the configuration of a custom endpoint. Returns `None` for OpenVPN endpoints.
+    pub fn from_custom_endpoint(
+        endpoint: &CustomTunnelEndpoint,
+        options: wireguard::TunnelOptions,
+    ) -> Option<Self> {
+        match endpoint.config() {
+            ConnectionConfig::Wireguard(connection) => Some(WgQuickConfig {
+                host: endpoint.host().to_owned(),
+                connection: connection.clone(),
+                options,
+            }),
+            ConnectionConfig::OpenVpn(_) => None,
+        }
+    }
+
+    pub fn to_custom_endpoint(&self) -> CustomTunnelEndpoint {
+        CustomTunnelEndpoint::new(
+            self.host.clone(),
+            ConnectionConfig::Wireguard(self.connection.clone()),
+        )
+    }
+}
+
+impl FromStr for WgQuickConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut interface: Option<Section> = None;
+        let mut peers: Vec<Section> = Vec::new();
+        // Keys belong to the section that was opened last.
+        let mut current_section = None;
+
+        for (index, line) in s.lines().enumerate() {
+            let line_number = index + 1;
+            let line = match line.find('#') {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                if name.eq_ignore_ascii_case("interface") {
+                    if interface.is_some() {
+                        return Err(Error::DuplicateKey("[Interface]".to_owned()));
+                    }
+                    interface = Some(Section::new(SectionKind::Interface));
+                    current_section = Some(SectionKind::Interface);
+                } else if name.eq_ignore_ascii_case("peer") {
+                    peers.push(Section::new(SectionKind::Peer));
+                    current_section = Some(SectionKind::Peer);
+                } else {
+                    return Err(Error::UnknownSection(line_number, name.to_owned()));
+                }
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().ok_or(Error::InvalidLine(line_number))?.trim();
+            if key.is_empty() {
+                return Err(Error::InvalidLine(line_number));
+            }
+
+            let section = match current_section {
+                Some(SectionKind::Interface) => interface.as_mut(),
+                Some(SectionKind::Peer) => peers.last_mut(),
+                None => None,
+            }
+            .ok_or(Error::KeyOutsideSection(line_number))?;
+            section.add(line_number, key, value)?;
+        }
+
+        let interface = interface.ok_or(Error::MissingKey("Interface", "PrivateKey"))?;
+        if peers.len() != 1 {
+            return Err(Error::PeerCount);
+        }
+        let peer = peers.pop().unwrap();
+
+        let (host, port) = peer.endpoint.ok_or(Error::MissingKey("Peer", "Endpoint"))?;
+        let ipv4_gateway = interface
+            .dns
+            .iter()
+            .filter_map(|address| match address {
+                IpAddr::V4(address) => Some(*address),
+                IpAddr::V6(_) => None,
+            })
+            .next()
+            .ok_or(Error::NoIpv4Gateway)?;
+        let ipv6_gateway = interface
+            .dns
+            .iter()
+            .filter_map(|address| match address {
+                IpAddr::V4(_) => None,
+                IpAddr::V6(address) => Some(*address),
+            })
+            .next();
+        if interface.dns.len() > 1 + ipv6_gateway.iter().count() {
+            log::warn!("Only the first IPv4 and IPv6 DNS servers of the configuration are used");
+        }
+
+        Ok(WgQuickConfig {
+            host,
+            connection: wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: interface
+                        .private_key
+                        .ok_or(Error::MissingKey("Interface", "PrivateKey"))?,
+                    addresses: interface.addresses,
+                },
+                peer: wireguard::PeerConfig {
+                    public_key: peer
+                        .public_key
+                        .ok_or(Error::MissingKey("Peer", "PublicKey"))?,
+                    allowed_ips: peer.allowed_ips,
+                    endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                    preshared_key: peer.preshared_key,
+                    persistent_keepalive: peer.persistent_keepalive,
+                },
+                ipv4_gateway,
+                ipv6_gateway,
+                protocol: TransportProtocol::Udp,
+            },
+            options: wireguard::TunnelOptions {
+                mtu: interface.mtu,
+                auto_mtu: false,
+                backend: None,
+                persistent_keepalive: None,
+            },
+        })
+    }
+}
+
+/// Writes the configuration in the format read by `wg-quick`.
+impl fmt::Display for WgQuickConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tunnel = &self.connection.tunnel;
+        let peer = &self.connection.peer;
+
+        writeln!(f, "[Interface]")?;
+        writeln!(
+            f,
+            "PrivateKey = {}",
+            base64::encode(tunnel.private_key.as_bytes())
+        )?;
+        if !tunnel.addresses.is_empty() {
+            let addresses = tunnel
+                .addresses
+                .iter()
+                .map(|address| IpNetwork::from(*address).to_string())
+                .collect::<Vec<_>>();
+            writeln!(f, "Address = {}", addresses.join(", "))?;
+        }
+        write!(f, "DNS = {}", self.connection.ipv4_gateway)?;
+        if let Some(ipv6_gateway) = self.connection.ipv6_gateway {
+            write!(f, ", {}", ipv6_gateway)?;
+        }
+        writeln!(f)?;
+        if let Some(mtu) = self.options.mtu {
+            writeln!(f, "MTU = {}", mtu)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "[Peer]")?;
+        writeln!(f, "PublicKey = {}", peer.public_key)?;
+        if let Some(preshared_key) = &peer.preshared_key {
+            writeln!(
+                f,
+                "PresharedKey = {}",
+                base64::encode(preshared_key.as_bytes())
+            )?;
+        }
+        if !peer.allowed_ips.is_empty() {
+            let allowed_ips = peer
+                .allowed_ips
+                .iter()
+                .map(|network| network.to_string())
+                .collect::<Vec<_>>();
+            writeln!(f, "AllowedIPs = {}", allowed_ips.join(", "))?;
+        }
+        if self.host.contains(':') {
+            writeln!(f, "Endpoint = [{}]:{}", self.host, peer.endpoint.port())?;
+        } else {
+            writeln!(f, "Endpoint = {}:{}", self.host, peer.endpoint.port())?;
+        }
+        if let Some(interval) = peer
+            .persistent_keepalive
+            .or(self.options.persistent_keepalive)
+        {
+            writeln!(f, "PersistentKeepalive = {}", interval)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SectionKind {
+    Interface,
+    Peer,
+}
+
+/// Values read from an `[Interface]` or `[Peer]` section.
+struct Section {
+    kind: SectionKind,
+    seen_keys: Vec<String>,
+    private_key: Option<wireguard::PrivateKey>,
+    addresses: Vec<IpAddr>,
+    dns: Vec<IpAddr>,
+    mtu: Option<u16>,
+    public_key: Option<wireguard::PublicKey>,
+    preshared_key: Option<wireguard::PresharedKey>,
+    allowed_ips: Vec<IpNetwork>,
+    endpoint: Option<(String, u16)>,
+    persistent_keepalive: Option<u16>,
+}
+
+impl Section {
+    fn new(kind: SectionKind) -> Self {
+        Section {
+            kind,
+            seen_keys: Vec::new(),
+            private_key: None,
+            addresses: Vec::new(),
+            dns: Vec::new(),
+            mtu: None,
+            public_key: None,
+            preshared_key: None,
+            allowed_ips: Vec::new(),
+            endpoint: None,
+            persistent_keepalive: None,
+        }
+    }
+
+    fn add(&mut self, line: usize, key: &str, value: &str) -> Result<(), Error> {
+        let key = key.to_ascii_lowercase();
+        if self.seen_keys.contains(&key) {
+            return Err(Error::DuplicateKey(key));
+        }
+        self.seen_keys.push(key.clone());
+        let invalid_value = || Error::InvalidValue(line, key.clone());
+
+        match (self.kind, key.as_str()) {
+            (SectionKind::Interface, "privatekey") => {
+                self.private_key = Some(parse_key(value).ok_or_else(invalid_value)?.into());
+            }
+            (SectionKind::Interface, "address") => {
+                // The prefix length is ignored, since the tunnel routes all traffic anyway.
+                self.addresses = parse_list::<IpNetwork>(value)
+                    .ok_or_else(invalid_value)?
+                    .into_iter()
+                    .map(|network| network.ip())
+                    .collect();
+            }
+            (SectionKind::Interface, "dns") => {
+                self.dns = parse_list(value).ok_or_else(invalid_value)?;
+            }
+            (SectionKind::Interface, "mtu") => {
+                self.mtu = Some(value.parse().map_err(|_| invalid_value())?);
+            }
+            (SectionKind::Peer, "publickey") => {
+                self.public_key = Some(parse_key(value).ok_or_else(invalid_value)?.into());
+            }
+            (SectionKind::Peer, "presharedkey") => {
+                self.preshared_key = Some(parse_key(value).ok_or_else(invalid_value)?.into());
+            }
+            (SectionKind::Peer, "allowedips") => {
+                self.allowed_ips = parse_list(value).ok_or_else(invalid_value)?;
+            }
+            (SectionKind::Peer, "endpoint") => {
+                self.endpoint = Some(parse_endpoint(value).ok_or_else(invalid_value)?);
+            }
+            (SectionKind::Peer, "persistentkeepalive") => {
+                self.persistent_keepalive = if value.eq_ignore_ascii_case("off") {
+                    None
+                } else {
+                    match value.parse().map_err(|_| invalid_value())? {
+                        0 => None,
+                        interval => Some(interval),
+                    }
+                };
+            }
+            _ => log::warn!("Ignoring unsupported key {} on line {}", key, line),
+        }
+        Ok(())
+    }
+}
+
+fn parse_key(value: &str) -> Option<[u8; 32]> {
+    let bytes = base64::decode(value).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes);
+    Some(key)
+}
+
+fn parse_list<T: FromStr>(value: &str) -> Option<Vec<T>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| item.parse().ok())
+        .collect()
+}
+
+/// Parses `host:port`, where IPv6 addresses are enclosed in brackets.
+fn parse_endpoint(value: &str) -> Option<(String, u16)> {
+    let mut parts = value.rsplitn(2, ':');
+    let port = parts.next()?.parse().ok()?;
+    let host = parts.next()?;
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else if host.contains(':') {
+        return None;
+    } else {
+        host
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Exported from the server
+[Interface]
+PrivateKey = mIRtrzkW9lzzaSCxBzHrLMgamHUNhSf2RZbM5Qd5X2Y=
+Address = 10.66.1.2/32, fc00:bbbb:bbbb:bb01::1:2/128
+DNS = 10.64.0.1, fc00:bbbb:bbbb:bb01::1
+MTU = 1380
+PostUp = iptables -A FORWARD -i %i -j ACCEPT
+
+[Peer]
+PublicKey = xq8DOH3Ffd8sMFZSYTxMgZWk+wHIEfEkBDxwJfWxcTs=
+PresharedKey = FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=
+AllowedIPs = 0.0.0.0/0, ::/0
+Endpoint = vpn.example.com:51820 # relay in Gothenburg
+PersistentKeepalive = 25
+";
+
+    #[test]
+    fn parse_config() {
+        let config: WgQuickConfig = CONFIG.parse().unwrap();
+        assert_eq!(config.host, "vpn.example.com");
+        assert_eq!(config.connection.peer.endpoint.port(), 51820);
+        assert_eq!(
+            config.connection.tunnel.addresses,
+            vec![
+                "10.66.1.2".parse::<IpAddr>().unwrap(),
+                "fc00:bbbb:bbbb:bb01::1:2".parse().unwrap(),
+            ]
+        );
+        assert_eq!(
+            config.connection.ipv4_gateway,
+            "10.64.0.1".parse::<Ipv4Addr>().unwrap()
+        );
+        assert_eq!(
+            config.connection.ipv6_gateway,
+            Some("fc00:bbbb:bbbb:bb01::1".parse().unwrap())
+        );
+        assert_eq!(
+            config.connection.peer.public_key.to_string(),
+            "xq8DOH3Ffd8sMFZSYTxMgZWk+wHIEfEkBDxwJfWxcTs="
+        );
+        assert_eq!(config.connection.peer.allowed_ips.len(), 2);
+        assert_eq!(
+            config
+                .connection
+                .peer
+                .preshared_key
+                .map(|key| base64::encode(key.as_bytes())),
+            Some("FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=".to_owned())
+        );
+        assert_eq!(config.connection.peer.persistent_keepalive, Some(25));
+        assert_eq!(config.options.mtu, Some(1380));
+    }
+
+    #[test]
+    fn round_trip() {
+        let config: WgQuickConfig = CONFIG.parse().unwrap();
+        let exported = config.to_string();
+        assert!(!exported.contains("PostUp"));
+        assert_eq!(exported.parse::<WgQuickConfig>().unwrap(), config);
+
+        let endpoint = config.to_custom_endpoint();
+        let from_endpoint =
+            WgQuickConfig::from_custom_endpoint(&endpoint, config.options.clone()).unwrap();
+        assert_eq!(
+            from_endpoint.to_string().parse::<WgQuickConfig>().unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn round_trip_ipv6_endpoint() {
+        let config: WgQuickConfig = CONFIG
+            .replace("vpn.example.com:51820", "[2001:db8::1]:51820")
+            .parse()
+            .unwrap();
+        assert_eq!(config.host, "2001:db8::1");
+        let exported = config.to_string();
+        assert!(exported.contains("Endpoint = [2001:db8::1]:51820"));
+        assert_eq!(exported.parse::<WgQuickConfig>().unwrap(), config);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert_eq!(
+            CONFIG
+                .replace("MTU = 1380", "MTU = big")
+                .parse::<WgQuickConfig>(),
+            Err(Error::InvalidValue(6, "mtu".to_owned()))
+        );
+        assert_eq!(
+            CONFIG
+                .replace("DNS = 10.64.0.1, ", "DNS = ")
+                .parse::<WgQuickConfig>(),
+            Err(Error::NoIpv4Gateway)
+        );
+        assert_eq!(
+            CONFIG
+                .replace("Endpoint = vpn.example.com:51820", "")
+                .parse::<WgQuickConfig>(),
+            Err(Error::MissingKey("Peer", "Endpoint"))
+        );
+        assert_eq!(
+            format!("{}\n[Peer]\nPublicKey = {}", CONFIG, "a").parse::<WgQuickConfig>(),
+            Err(Error::InvalidValue(17, "publickey".to_owned()))
+        );
+        assert_eq!(
+            "PrivateKey = abc".parse::<WgQuickConfig>(),
+            Err(Error::KeyOutsideSection(1))
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{offset::Utc, DateTime, TimeZone};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use talpid_types::net::wireguard;
@@ -7,6 +8,14 @@ use talpid_types::net::wireguard;
 pub struct WireguardData {
     pub private_key: wireguard::PrivateKey,
     pub addresses: AssociatedAddresses,
+    /// When the key was pushed to the API. Keys stored before this was recorded are considered
+    /// to be as old as possible, so that they are the first to be rotated.
+    #[serde(default = "unknown_creation_time")]
+    pub created: DateTime<Utc>,
+}
+
+fn unknown_creation_time() -> DateTime<Utc> {
+    Utc.timestamp(0, 0)
 }
 
 /// Contains a pair of local link addresses that are paired with a specific wireguard
@@ -17,6 +26,13 @@ pub struct AssociatedAddresses {
     pub ipv6_address: ipnetwork::Ipv6Network,
 }
 
+/// A public key registered to an account, as reported by the API.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisteredKey {
+    pub public_key: wireguard::PublicKey,
+    pub created: DateTime<Utc>,
+}
+
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 /// Event that is emitted when the daemon has finished generating a key.
@@ -1,4 +1,5 @@
 use std::{net::IpAddr, path::Path};
+use talpid_types::net::dns::SplitDns;
 
 /// Stub error type for DNS errors on Android.
 #[derive(Debug, err_derive::Error)]
@@ -10,12 +11,20 @@ pub struct DnsMonitor;
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self, Self::Error> {
+    fn new(
+        _cache_dir: impl AsRef<Path>,
+        _listener: super::ReapplyListener,
+    ) -> Result<Self, Self::Error> {
         Ok(DnsMonitor)
     }
 
-    fn set(&mut self, _interface: &str, _servers: &[IpAddr]) -> Result<(), Self::Error> {
-        Ok(())
+    fn set(
+        &mut self,
+        _interface: &str,
+        _servers: &[IpAddr],
+        _split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>, Self::Error> {
+        Ok(vec![])
     }
 
     fn reset(&mut self) -> Result<(), Self::Error> {
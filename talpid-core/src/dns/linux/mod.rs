@@ -2,15 +2,32 @@ mod network_manager;
 mod resolvconf;
 mod static_resolv_conf;
 mod systemd_resolved;
+mod watcher;
 
 use self::{
-    network_manager::NetworkManager, resolvconf::Resolvconf, static_resolv_conf::StaticResolvConf,
+    network_manager::NetworkManager,
+    resolvconf::Resolvconf,
+    static_resolv_conf::StaticResolvConf,
     systemd_resolved::SystemdResolved,
+    watcher::{EnvironmentChange, EnvironmentWatcher},
 };
-use std::{env, fmt, net::IpAddr, path::Path};
+use super::ReapplyListener;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{
+    env, fmt,
+    net::IpAddr,
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{net::dns::SplitDns, ErrorExt};
 
 
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+/// How long to wait for changes to the DNS environment to settle before re-detecting the backend.
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -33,37 +50,317 @@ pub enum Error {
     #[error(display = "Error in static /etc/resolv.conf DNS monitor")]
     StaticResolvConf(#[error(cause)] static_resolv_conf::Error),
 
-    /// No suitable DNS monitor implementation detected
-    #[error(display = "No suitable DNS monitor implementation detected")]
-    NoDnsMonitor,
+    /// The thread managing DNS has stopped
+    #[error(display = "The DNS manager thread has stopped")]
+    ManagerStopped,
+
+    /// The DNS monitor in use can't resolve some domains outside the tunnel
+    #[error(
+        display = "Split DNS requires systemd-resolved, but DNS is managed via {}",
+        _0
+    )]
+    SplitDnsUnsupported(String),
 }
 
 pub struct DnsMonitor {
-    inner: Option<DnsMonitorHolder>,
+    request_tx: mpsc::Sender<Request>,
+    _watcher: Option<EnvironmentWatcher>,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self> {
-        Ok(DnsMonitor { inner: None })
+    fn new(_cache_dir: impl AsRef<Path>, listener: ReapplyListener) -> Result<Self> {
+        let (request_tx, request_rx) = mpsc::channel();
+        thread::spawn(move || DnsManager::new(listener).run(request_rx));
+
+        let watcher = EnvironmentWatcher::start(request_tx.clone())
+            .map_err(|error| {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Unable to watch for changes in how DNS is managed. DNS will only be \
+                         re-detected when it's set"
+                    )
+                );
+            })
+            .ok();
+
+        Ok(DnsMonitor {
+            request_tx,
+            _watcher: watcher,
+        })
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
-        self.reset()?;
-        // Creating a new DNS monitor for each set, in case the system changed how it manages DNS.
-        let mut inner = DnsMonitorHolder::new()?;
-        inner.set(interface, servers)?;
-        self.inner = Some(inner);
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>> {
+        let (result_tx, result_rx) = mpsc::channel();
+        let desired = DesiredDns {
+            interface: interface.to_owned(),
+            servers: servers.to_vec(),
+            split_dns: split_dns.clone(),
+        };
+        self.send_request(Request::Set(desired, result_tx))?;
+        result_rx.recv().map_err(|_| Error::ManagerStopped)?
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let (result_tx, result_rx) = mpsc::channel();
+        self.send_request(Request::Reset(result_tx))?;
+        result_rx.recv().map_err(|_| Error::ManagerStopped)?
+    }
+}
+
+impl DnsMonitor {
+    fn send_request(&self, request: Request) -> Result<()> {
+        self.request_tx
+            .send(request)
+            .map_err(|_| Error::ManagerStopped)
+    }
+}
+
+/// Returns the backend DNS is currently set through, if any.
+pub fn active_backend() -> Option<DnsBackend> {
+    *ACTIVE_BACKEND.lock()
+}
+
+/// Resets DNS that a previous run set for `interface` through `backend`, the name of a
+/// `DnsBackend`. Settings in systemd-resolved belong to the interface and went away with it, and
+/// a replaced /etc/resolv.conf is restored from its backup when the DNS monitor is created.
+pub fn reset_stale(interface: &str, backend: &str) {
+    log::debug!("Resetting stale DNS set via {}", backend);
+    let result = if backend == DnsBackend::NetworkManager.to_string() {
+        NetworkManager::new()
+            .and_then(|mut network_manager| network_manager.reset())
+            .map_err(Error::NetworkManager)
+    } else if backend == DnsBackend::Resolvconf.to_string() {
+        Resolvconf::new()
+            .and_then(|mut resolvconf| resolvconf.reset_stale(interface))
+            .map_err(Error::Resolvconf)
+    } else {
         Ok(())
+    };
+    if let Err(error) = result {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to reset stale DNS settings")
+        );
+    }
+}
+
+lazy_static! {
+    static ref ACTIVE_BACKEND: Mutex<Option<DnsBackend>> = Mutex::new(None);
+}
+
+/// The DNS config that should be applied, kept so that it can be re-applied if the system changes
+/// how it manages DNS.
+#[derive(Clone)]
+struct DesiredDns {
+    interface: String,
+    servers: Vec<IpAddr>,
+    split_dns: SplitDns,
+}
+
+pub enum Request {
+    Set(DesiredDns, mpsc::Sender<Result<Vec<IpAddr>>>),
+    Reset(mpsc::Sender<Result<()>>),
+    EnvironmentChanged(EnvironmentChange),
+}
+
+/// Owns the DNS monitor backend on a dedicated thread, and replaces it when the environment
+/// changes.
+struct DnsManager {
+    monitor: Option<DnsMonitorHolder>,
+    desired: Option<DesiredDns>,
+    listener: ReapplyListener,
+}
+
+impl DnsManager {
+    fn new(listener: ReapplyListener) -> Self {
+        DnsManager {
+            monitor: None,
+            desired: None,
+            listener,
+        }
+    }
+
+    fn run(mut self, requests: mpsc::Receiver<Request>) {
+        // Changes tend to come in bursts, for example when a service restarts and rewrites
+        // /etc/resolv.conf, so they are only handled once no new change has arrived for a while.
+        let mut pending_change: Option<(Instant, bool)> = None;
+        loop {
+            let request = match pending_change {
+                Some((deadline, service_changed)) => {
+                    let now = Instant::now();
+                    let timeout = if deadline > now {
+                        deadline - now
+                    } else {
+                        Duration::from_secs(0)
+                    };
+                    match requests.recv_timeout(timeout) {
+                        Ok(request) => request,
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            pending_change = None;
+                            self.handle_environment_change(service_changed);
+                            continue;
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                },
+            };
+
+            match request {
+                Request::Set(desired, result_tx) => {
+                    pending_change = None;
+                    let _ = result_tx.send(self.set(desired));
+                }
+                Request::Reset(result_tx) => {
+                    pending_change = None;
+                    let _ = result_tx.send(self.reset());
+                }
+                Request::EnvironmentChanged(change) => {
+                    log::debug!("{}", change);
+                    let service_changed = change.is_service_change()
+                        || pending_change.map(|(_, changed)| changed).unwrap_or(false);
+                    pending_change = Some((Instant::now() + SETTLE_DELAY, service_changed));
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, desired: DesiredDns) -> Result<Vec<IpAddr>> {
+        self.reset()?;
+        // Detecting the backend for each set, in case the system changed how it manages DNS.
+        let split_dns_servers = self.apply(DnsBackend::detect()?, &desired)?;
+        self.desired = Some(desired);
+        Ok(split_dns_servers)
     }
 
     fn reset(&mut self) -> Result<()> {
-        if let Some(mut inner) = self.inner.take() {
-            inner.reset()?;
+        self.desired = None;
+        *ACTIVE_BACKEND.lock() = None;
+        if let Some(mut monitor) = self.monitor.take() {
+            monitor.reset()?;
         }
         Ok(())
     }
+
+    fn apply(&mut self, backend: DnsBackend, desired: &DesiredDns) -> Result<Vec<IpAddr>> {
+        let mut monitor = DnsMonitorHolder::new(backend)?;
+        let split_dns_servers =
+            monitor.set(&desired.interface, &desired.servers, &desired.split_dns)?;
+        log::info!("Managing DNS via {}", backend);
+        self.monitor = Some(monitor);
+        *ACTIVE_BACKEND.lock() = Some(backend);
+        Ok(split_dns_servers)
+    }
+
+    /// Re-applies the desired DNS config if another backend should be used now. If a DNS service
+    /// was started, stopped or restarted, it's re-applied even if the backend stays the same,
+    /// since a restarted service has lost the config. The outcome is reported to the listener,
+    /// since the split DNS servers may have changed, or DNS may no longer be set at all.
+    fn handle_environment_change(&mut self, service_changed: bool) {
+        let desired = match self.desired.clone() {
+            Some(desired) => desired,
+            None => return,
+        };
+        let backend = match DnsBackend::detect() {
+            Ok(backend) => backend,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to detect how DNS is managed")
+                );
+                return;
+            }
+        };
+        let current_backend = self.monitor.as_ref().map(DnsMonitorHolder::backend);
+        if current_backend == Some(backend) && !service_changed {
+            return;
+        }
+
+        if let Some(mut monitor) = self.monitor.take() {
+            // The service behind the old backend might be gone, so this is allowed to fail.
+            if let Err(error) = monitor.reset() {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to reset DNS set via {}",
+                        monitor
+                    ))
+                );
+            }
+        }
+        *ACTIVE_BACKEND.lock() = None;
+
+        log::info!("Re-applying DNS config after the DNS environment changed");
+        let result = self.apply(backend, &desired).map_err(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to re-apply DNS config")
+            );
+        });
+        self.listener.reapplied(&desired.interface, result);
+    }
+}
+
+/// The ways DNS can be managed on Linux.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DnsBackend {
+    SystemdResolved,
+    NetworkManager,
+    Resolvconf,
+    StaticResolvConf,
+}
+
+impl fmt::Display for DnsBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::DnsBackend::*;
+        let name = match self {
+            Resolvconf => "resolvconf",
+            StaticResolvConf => "/etc/resolv.conf",
+            SystemdResolved => "systemd-resolved",
+            NetworkManager => "network manager",
+        };
+        f.write_str(name)
+    }
+}
+
+impl DnsBackend {
+    /// Returns the backend DNS should be managed via, either forced through `TALPID_DNS_MODULE`
+    /// or detected from the system.
+    fn detect() -> Result<Self> {
+        let dns_module = env::var_os("TALPID_DNS_MODULE");
+
+        let backend = match dns_module.as_ref().and_then(|value| value.to_str()) {
+            Some("static-file") => DnsBackend::StaticResolvConf,
+            Some("resolvconf") => DnsBackend::Resolvconf,
+            Some("systemd") => DnsBackend::SystemdResolved,
+            Some("network-manager") => DnsBackend::NetworkManager,
+            Some(_) | None => Self::detect_from_system(),
+        };
+        Ok(backend)
+    }
+
+    fn detect_from_system() -> Self {
+        if SystemdResolved::new().is_ok() {
+            DnsBackend::SystemdResolved
+        } else if NetworkManager::new().is_ok() {
+            DnsBackend::NetworkManager
+        } else if Resolvconf::new().is_ok() {
+            DnsBackend::Resolvconf
+        } else {
+            DnsBackend::StaticResolvConf
+        }
+    }
 }
 
 pub enum DnsMonitorHolder {
@@ -75,54 +372,63 @@ pub enum DnsMonitorHolder {
 
 impl fmt::Display for DnsMonitorHolder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use self::DnsMonitorHolder::*;
-        let name = match self {
-            Resolvconf(..) => "resolvconf",
-            StaticResolvConf(..) => "/etc/resolv.conf",
-            SystemdResolved(..) => "systemd-resolved",
-            NetworkManager(..) => "network manager",
-        };
-        f.write_str(name)
+        self.backend().fmt(f)
     }
 }
 
 impl DnsMonitorHolder {
-    fn new() -> Result<Self> {
-        let dns_module = env::var_os("TALPID_DNS_MODULE");
-
-        let manager = match dns_module.as_ref().and_then(|value| value.to_str()) {
-            Some("static-file") => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
-            Some("resolvconf") => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
-            Some("systemd") => DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?),
-            Some("network-manager") => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
-            Some(_) | None => Self::with_detected_dns_manager()?,
+    fn new(backend: DnsBackend) -> Result<Self> {
+        let manager = match backend {
+            DnsBackend::StaticResolvConf => {
+                DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?)
+            }
+            DnsBackend::Resolvconf => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
+            DnsBackend::SystemdResolved => {
+                DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?)
+            }
+            DnsBackend::NetworkManager => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
         };
-        log::debug!("Managing DNS via {}", manager);
         Ok(manager)
     }
 
-    fn with_detected_dns_manager() -> Result<Self> {
-        SystemdResolved::new()
-            .map(DnsMonitorHolder::SystemdResolved)
-            .or_else(|_| NetworkManager::new().map(DnsMonitorHolder::NetworkManager))
-            .or_else(|_| Resolvconf::new().map(DnsMonitorHolder::Resolvconf))
-            .or_else(|_| StaticResolvConf::new().map(DnsMonitorHolder::StaticResolvConf))
-            .map_err(|_| Error::NoDnsMonitor)
+    fn backend(&self) -> DnsBackend {
+        match self {
+            DnsMonitorHolder::SystemdResolved(..) => DnsBackend::SystemdResolved,
+            DnsMonitorHolder::NetworkManager(..) => DnsBackend::NetworkManager,
+            DnsMonitorHolder::Resolvconf(..) => DnsBackend::Resolvconf,
+            DnsMonitorHolder::StaticResolvConf(..) => DnsBackend::StaticResolvConf,
+        }
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>> {
         use self::DnsMonitorHolder::*;
+        if split_dns.is_enabled() && !self.supports_split_dns() {
+            return Err(Error::SplitDnsUnsupported(self.to_string()));
+        }
         match self {
             Resolvconf(ref mut resolvconf) => resolvconf.set_dns(interface, servers)?,
             StaticResolvConf(ref mut static_resolv_conf) => {
                 static_resolv_conf.set_dns(servers.to_vec())?
             }
             SystemdResolved(ref mut systemd_resolved) => {
-                systemd_resolved.set_dns(interface, &servers)?
+                return Ok(systemd_resolved.set_dns(interface, &servers, split_dns)?);
             }
             NetworkManager(ref mut network_manager) => network_manager.set_dns(servers)?,
         }
-        Ok(())
+        Ok(vec![])
+    }
+
+    /// Returns true if this DNS monitor can resolve some domains outside the tunnel.
+    fn supports_split_dns(&self) -> bool {
+        match self {
+            DnsMonitorHolder::SystemdResolved(..) => true,
+            _ => false,
+        }
     }
 
     fn reset(&mut self) -> Result<()> {
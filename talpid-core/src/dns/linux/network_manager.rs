@@ -31,7 +31,7 @@ pub enum Error {
     SystemdResolved,
 }
 
-const NM_BUS: &str = "org.freedesktop.NetworkManager";
+pub const NM_BUS: &str = "org.freedesktop.NetworkManager";
 const NM_TOP_OBJECT: &str = "org.freedesktop.NetworkManager";
 const NM_DNS_MANAGER: &str = "org.freedesktop.NetworkManager.DnsManager";
 const NM_DNS_MANAGER_PATH: &str = "/org/freedesktop/NetworkManager/DnsManager";
@@ -52,8 +52,12 @@ impl Resolvconf {
             .unwrap_or_else(|_| false)
     }
 
+    fn record_name(interface: &str) -> String {
+        format!("{}.mullvad", interface)
+    }
+
     pub fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
-        let record_name = format!("{}.mullvad", interface);
+        let record_name = Self::record_name(interface);
         let mut record_contents = String::new();
 
         for address in servers {
@@ -101,4 +105,10 @@ impl Resolvconf {
 
         result
     }
+
+    /// Deletes the record for `interface` that was added by an earlier instance.
+    pub fn reset_stale(&mut self, interface: &str) -> Result<()> {
+        self.record_names.insert(Self::record_name(interface));
+        self.reset()
+    }
 }
@@ -1,5 +1,5 @@
 use super::RESOLV_CONF_PATH;
-use crate::linux::iface_index;
+use crate::linux::{iface_index, parse_default_routes, ROUTE_TABLE_PATH};
 use dbus::{
     arg::RefArg, stdintf::*, BusType, Interface, Member, Message, MessageItem, MessageItemArray,
     Signature,
@@ -7,11 +7,11 @@ use dbus::{
 use lazy_static::lazy_static;
 use libc::{AF_INET, AF_INET6};
 use std::{
-    fs, io,
+    fs, io, mem,
     net::{IpAddr, Ipv4Addr},
     path::Path,
 };
-use talpid_types::ErrorExt as _;
+use talpid_types::{net::dns::SplitDns, ErrorExt as _};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -44,6 +44,18 @@ pub enum Error {
     #[error(display = "Failed to configure DNS domains")]
     SetDomainsError(#[error(cause)] dbus::Error),
 
+    #[error(display = "Failed to make the tunnel the default route for DNS queries")]
+    SetDefaultRouteError(#[error(cause)] dbus::Error),
+
+    #[error(display = "Failed to read the routing table")]
+    ReadRoutesError(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to read the DNS configuration of interface: {}", _0)]
+    ReadLinkConfigError(String, #[error(cause)] dbus::Error),
+
+    #[error(display = "Failed to configure split DNS on interface: {}", _0)]
+    SetSplitDnsError(String, #[error(cause)] Box<Error>),
+
     #[error(display = "Failed to revert DNS settings of interface: {}", _0)]
     RevertDnsError(String, #[error(cause)] dbus::Error),
 
@@ -63,7 +75,7 @@ lazy_static! {
 
 const RESOLVED_DNS_SERVER_ADDRESS: [u8; 4] = [127, 0, 0, 53];
 
-const RESOLVED_BUS: &str = "org.freedesktop.resolve1";
+pub const RESOLVED_BUS: &str = "org.freedesktop.resolve1";
 const RPC_TIMEOUT_MS: i32 = 1000;
 
 lazy_static! {
@@ -74,12 +86,20 @@ lazy_static! {
     static ref GET_LINK_METHOD: Member<'static> = Member::from_slice(b"GetLink").unwrap();
     static ref SET_DNS_METHOD: Member<'static> = Member::from_slice(b"SetDNS").unwrap();
     static ref SET_DOMAINS_METHOD: Member<'static> = Member::from_slice(b"SetDomains").unwrap();
+    static ref SET_DEFAULT_ROUTE_METHOD: Member<'static> =
+        Member::from_slice(b"SetDefaultRoute").unwrap();
     static ref REVERT_METHOD: Member<'static> = Member::from_slice(b"Revert").unwrap();
 }
 
+/// DNS domains of a link, as pairs of domain and whether it's a routing-only domain.
+type LinkDomains = Vec<(String, bool)>;
+
 pub struct SystemdResolved {
     dbus_connection: dbus::Connection,
     interface_link: Option<(String, dbus::Path<'static>)>,
+    /// Physical links that have been given the split DNS routing domains, along with the domains
+    /// they had before.
+    split_dns_links: Vec<(String, dbus::Path<'static>, LinkDomains)>,
 }
 
 impl SystemdResolved {
@@ -89,6 +109,7 @@ impl SystemdResolved {
         let systemd_resolved = SystemdResolved {
             dbus_connection,
             interface_link: None,
+            split_dns_links: Vec::new(),
         };
 
         systemd_resolved.ensure_resolved_exists()?;
@@ -169,7 +190,15 @@ impl SystemdResolved {
             .with_path(RESOLVED_BUS, link_object_path, RPC_TIMEOUT_MS)
     }
 
-    pub fn set_dns(&mut self, interface_name: &str, servers: &[IpAddr]) -> Result<()> {
+    /// Makes the tunnel link the default route for DNS queries, except for the split DNS
+    /// domains, which are routed to the DNS servers of the physical links. Returns the DNS
+    /// servers that the split DNS domains will be resolved by.
+    pub fn set_dns(
+        &mut self,
+        interface_name: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>> {
         let link_object_path = self
             .fetch_link(interface_name)
             .map_err(|e| Error::GetLinkError(Box::new(e)))?;
@@ -181,9 +210,87 @@ impl SystemdResolved {
         }
 
         self.set_link_dns(&link_object_path, servers)?;
-        self.interface_link = Some((interface_name.to_string(), link_object_path));
+        self.interface_link = Some((interface_name.to_string(), link_object_path.clone()));
+        self.set_link_default_route(&link_object_path)?;
 
-        Ok(())
+        if !split_dns.is_enabled() {
+            return Ok(vec![]);
+        }
+        let result = self.set_split_dns(interface_name, split_dns);
+        if result.is_err() {
+            self.reset_split_dns();
+        }
+        result
+    }
+
+    /// Adds the split DNS domains as routing domains on every other link with a default route.
+    /// Since they are more specific than the catch-all domain on the tunnel link, resolved sends
+    /// queries for them to the DNS servers of those links instead.
+    fn set_split_dns(
+        &mut self,
+        tunnel_interface: &str,
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>> {
+        let routes = fs::read_to_string(ROUTE_TABLE_PATH).map_err(Error::ReadRoutesError)?;
+        let mut interfaces: Vec<String> = parse_default_routes(&routes)
+            .into_iter()
+            .map(|(interface, _gateway)| interface)
+            .filter(|interface| interface != tunnel_interface)
+            .collect();
+        interfaces.sort();
+        interfaces.dedup();
+
+        let mut split_dns_servers = Vec::new();
+        for interface in interfaces {
+            let servers = self
+                .set_link_split_dns(&interface, split_dns)
+                .map_err(|e| Error::SetSplitDnsError(interface.clone(), Box::new(e)))?;
+            split_dns_servers.extend(servers);
+        }
+        if split_dns_servers.is_empty() {
+            log::warn!(
+                "No DNS servers found outside the tunnel. Split DNS domains will be resolved \
+                 through the tunnel"
+            );
+        }
+        Ok(split_dns_servers)
+    }
+
+    fn set_link_split_dns(
+        &mut self,
+        interface_name: &str,
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>> {
+        let link_object_path = self.fetch_link(interface_name)?;
+        let link = self.as_link_object(link_object_path.clone());
+        let servers: Vec<(i32, Vec<u8>)> = link
+            .get(&LINK_INTERFACE, "DNS")
+            .map_err(|e| Error::ReadLinkConfigError(interface_name.to_owned(), e))?;
+        let original_domains: LinkDomains = link
+            .get(&LINK_INTERFACE, "Domains")
+            .map_err(|e| Error::ReadLinkConfigError(interface_name.to_owned(), e))?;
+
+        let mut domains: Vec<(&str, bool)> = original_domains
+            .iter()
+            .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+            .collect();
+        domains.extend(
+            split_dns
+                .domains
+                .iter()
+                .map(|domain| (domain.as_str(), true)),
+        );
+        self.set_link_domains(&link_object_path, &domains)?;
+        self.split_dns_links.push((
+            interface_name.to_owned(),
+            link_object_path,
+            original_domains,
+        ));
+
+        Ok(servers
+            .into_iter()
+            .filter_map(|(family, address)| parse_address(family, &address))
+            .collect())
     }
 
     fn fetch_link(&self, interface_name: &str) -> Result<dbus::Path<'static>> {
@@ -217,8 +324,14 @@ impl SystemdResolved {
 
         // set the search domain to catch all DNS requests, forces the link to be the prefered
         // resolver, otherwise systemd-resolved will use other interfaces to do DNS lookups
-        let dns_domains: &[_] = &[(&".", true)];
+        self.set_link_domains(link_object_path, &[(".", true)])
+    }
 
+    fn set_link_domains(
+        &self,
+        link_object_path: &dbus::Path<'static>,
+        domains: &[(&str, bool)],
+    ) -> Result<()> {
         let msg = Message::new_method_call(
             RESOLVED_BUS,
             link_object_path as &str,
@@ -226,7 +339,7 @@ impl SystemdResolved {
             &SET_DOMAINS_METHOD as &str,
         )
         .expect("failed to construct a new dbus message")
-        .append1(dns_domains);
+        .append1(domains);
 
         self.dbus_connection
             .send_with_reply_and_block(msg, RPC_TIMEOUT_MS)
@@ -234,7 +347,26 @@ impl SystemdResolved {
             .map_err(Error::SetDomainsError)
     }
 
+    /// Makes resolved prefer the link for queries that don't match any routing domain. Only
+    /// available since systemd 240, older versions rely on the catch-all routing domain alone.
+    fn set_link_default_route(&self, link_object_path: &dbus::Path<'static>) -> Result<()> {
+        let result = self
+            .as_link_object(link_object_path.clone())
+            .method_call_with_args(&LINK_INTERFACE, &SET_DEFAULT_ROUTE_METHOD, |message| {
+                message.append_items(&[MessageItem::Bool(true)]);
+            })
+            .and_then(|mut reply| reply.as_result().map(|_| ()));
+        match result {
+            Err(error) if error.name() == Some("org.freedesktop.DBus.Error.UnknownMethod") => {
+                log::debug!("systemd-resolved does not support setting the default DNS route");
+                Ok(())
+            }
+            result => result.map_err(Error::SetDefaultRouteError),
+        }
+    }
+
     pub fn reset(&mut self) -> Result<()> {
+        self.reset_split_dns();
         if let Some((interface_name, link_object_path)) = self.interface_link.take() {
             self.revert_link(link_object_path, &interface_name)
                 .map_err(|e| Error::RevertDnsError(interface_name.to_owned(), e))
@@ -244,6 +376,29 @@ impl SystemdResolved {
         }
     }
 
+    /// Restores the domains the physical links had before split DNS was configured. The links
+    /// are not reverted, since that would also remove the DNS servers set by their network
+    /// manager.
+    fn reset_split_dns(&mut self) {
+        for (interface_name, link_object_path, original_domains) in
+            mem::replace(&mut self.split_dns_links, Vec::new())
+        {
+            let domains: Vec<(&str, bool)> = original_domains
+                .iter()
+                .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+                .collect();
+            if let Err(error) = self.set_link_domains(&link_object_path, &domains) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to restore DNS domains of interface {}",
+                        interface_name
+                    ))
+                );
+            }
+        }
+    }
+
     fn revert_link(
         &mut self,
         link_object_path: dbus::Path<'static>,
@@ -268,6 +423,22 @@ impl SystemdResolved {
     }
 }
 
+fn parse_address(family: i32, address: &[u8]) -> Option<IpAddr> {
+    match family {
+        AF_INET if address.len() == 4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(address);
+            Some(IpAddr::from(octets))
+        }
+        AF_INET6 if address.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(address);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
 fn build_addresses_argument(addresses: &[IpAddr]) -> MessageItem {
     let addresses = addresses.iter().map(ip_address_to_message_item).collect();
 
@@ -0,0 +1,147 @@
+use super::{network_manager::NM_BUS, systemd_resolved::RESOLVED_BUS, Request, RESOLV_CONF_PATH};
+use dbus::{BusType, ConnectionItem};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    fmt,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
+use talpid_types::ErrorExt;
+
+/// The directory of /etc/resolv.conf. Watched instead of the file itself, since the file is often
+/// a symlink that gets replaced.
+const RESOLV_CONF_DIR: &str = "/etc";
+/// How often the D-Bus listener checks if it should stop.
+const DBUS_POLL_TIMEOUT_MS: i32 = 1000;
+/// The DNS services whose presence decides how DNS is managed.
+const DNS_SERVICES: &[&str] = &[RESOLVED_BUS, NM_BUS];
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to watch /etc/resolv.conf for changes")]
+    WatchResolvConf(#[error(cause)] notify::Error),
+}
+
+/// A change to the environment that might change how DNS should be managed.
+#[derive(Debug)]
+pub enum EnvironmentChange {
+    /// /etc/resolv.conf was modified or replaced.
+    ResolvConf,
+    /// A DNS service was started, stopped or restarted.
+    Service(String),
+}
+
+impl EnvironmentChange {
+    pub fn is_service_change(&self) -> bool {
+        match self {
+            EnvironmentChange::Service(_) => true,
+            EnvironmentChange::ResolvConf => false,
+        }
+    }
+}
+
+impl fmt::Display for EnvironmentChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentChange::ResolvConf => write!(f, "{} changed", RESOLV_CONF_PATH),
+            EnvironmentChange::Service(name) => write!(f, "Owner of D-Bus name {} changed", name),
+        }
+    }
+}
+
+/// Watches /etc/resolv.conf and the D-Bus names of the DNS services, and notifies the DNS manager
+/// when they change. Stops watching when dropped.
+pub struct EnvironmentWatcher {
+    _resolv_conf_watcher: RecommendedWatcher,
+    stop_dbus_listener: Arc<AtomicBool>,
+}
+
+impl EnvironmentWatcher {
+    pub fn start(request_tx: mpsc::Sender<Request>) -> Result<Self> {
+        let (event_tx, event_rx) = mpsc::channel();
+        let mut resolv_conf_watcher =
+            notify::raw_watcher(event_tx).map_err(Error::WatchResolvConf)?;
+        resolv_conf_watcher
+            .watch(RESOLV_CONF_DIR, RecursiveMode::NonRecursive)
+            .map_err(Error::WatchResolvConf)?;
+
+        let resolv_conf_tx = request_tx.clone();
+        thread::spawn(move || {
+            for event in event_rx {
+                if event.path.as_ref().map(|path| path.as_path())
+                    != Some(Path::new(RESOLV_CONF_PATH))
+                {
+                    continue;
+                }
+                let change = Request::EnvironmentChanged(EnvironmentChange::ResolvConf);
+                if resolv_conf_tx.send(change).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stop_dbus_listener = Arc::new(AtomicBool::new(false));
+        let stop = stop_dbus_listener.clone();
+        thread::spawn(move || {
+            if let Err(error) = listen_for_dns_services(&request_tx, &stop) {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to watch DNS services on D-Bus")
+                );
+            }
+        });
+
+        Ok(EnvironmentWatcher {
+            _resolv_conf_watcher: resolv_conf_watcher,
+            stop_dbus_listener,
+        })
+    }
+}
+
+impl Drop for EnvironmentWatcher {
+    fn drop(&mut self) {
+        self.stop_dbus_listener.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Sends a change every time one of the `DNS_SERVICES` gets a new owner on the system bus,
+/// meaning that the service was started, stopped or restarted.
+fn listen_for_dns_services(
+    request_tx: &mpsc::Sender<Request>,
+    stop: &AtomicBool,
+) -> std::result::Result<(), dbus::Error> {
+    let connection = dbus::Connection::get_private(BusType::System)?;
+    for service in DNS_SERVICES {
+        connection.add_match(&format!(
+            "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+             member='NameOwnerChanged',arg0='{}'",
+            service
+        ))?;
+    }
+
+    for item in connection.iter(DBUS_POLL_TIMEOUT_MS) {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        if let ConnectionItem::Signal(message) = item {
+            // The first argument of NameOwnerChanged is the name whose owner changed.
+            if let Some(name) = message.get1::<&str>() {
+                if !DNS_SERVICES.contains(&name) {
+                    continue;
+                }
+                let change =
+                    Request::EnvironmentChanged(EnvironmentChange::Service(name.to_owned()));
+                if request_tx.send(change).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use log::{debug, trace};
 use parking_lot::Mutex;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt,
     net::IpAddr,
     path::Path,
@@ -20,6 +20,7 @@ use system_configuration::{
     dynamic_store::{SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext},
     sys::schema_definitions::kSCPropNetDNSServerAddresses,
 };
+use talpid_types::net::dns::SplitDns;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -33,6 +34,10 @@ pub enum Error {
     /// Failed to initialize dynamic store
     #[error(display = "Failed to initialize dynamic store")]
     DynamicStoreInitError,
+
+    /// Resolving some domains outside the tunnel is not supported
+    #[error(display = "Split DNS is not supported on macOS")]
+    SplitDnsUnsupported,
 }
 
 const STATE_PATH_PATTERN: &str = "State:/Network/Service/.*/DNS";
@@ -140,7 +145,7 @@ impl super::DnsMonitorT for DnsMonitor {
     /// DNS settings for all network interfaces. If any changes occur it will instantly reset
     /// the DNS settings for that interface back to the last server list set to this instance
     /// with `set_dns`.
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self> {
+    fn new(_cache_dir: impl AsRef<Path>, _listener: super::ReapplyListener) -> Result<Self> {
         let state = Arc::new(Mutex::new(None));
         Self::spawn(state.clone())?;
         Ok(DnsMonitor {
@@ -149,7 +154,15 @@ impl super::DnsMonitorT for DnsMonitor {
         })
     }
 
-    fn set(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(
+        &mut self,
+        _interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>> {
+        if split_dns.is_enabled() {
+            return Err(Error::SplitDnsUnsupported);
+        }
         let servers: Vec<DnsServer> = servers.iter().map(|ip| ip.to_string()).collect();
         let settings = DnsSettings::from_server_addresses(&servers);
         let mut state_lock = self.state.lock();
@@ -180,7 +193,7 @@ impl super::DnsMonitorT for DnsMonitor {
                 }
             }
         });
-        Ok(())
+        Ok(vec![])
     }
 
     fn reset(&mut self) -> Result<()> {
@@ -203,6 +216,24 @@ impl super::DnsMonitorT for DnsMonitor {
 }
 
 impl DnsMonitor {
+    /// Returns the DNS servers each network service had before DNS was set, keyed by service
+    /// path, so that they can be restored if the daemon is killed.
+    pub fn service_backup(&self) -> BTreeMap<ServicePath, Option<Vec<DnsServer>>> {
+        match self.state.lock().as_ref() {
+            Some(state) => state
+                .backup
+                .iter()
+                .map(|(path, settings)| {
+                    (
+                        path.clone(),
+                        settings.as_ref().map(DnsSettings::server_addresses),
+                    )
+                })
+                .collect(),
+            None => BTreeMap::new(),
+        }
+    }
+
     /// Spawns the background thread running the CoreFoundation main loop and monitors the system
     /// for DNS changes.
     fn spawn(state: Arc<Mutex<Option<State>>>) -> Result<()> {
@@ -220,6 +251,33 @@ impl DnsMonitor {
     }
 }
 
+/// Restores the DNS servers of each network service from a backup taken by a previous run, which
+/// was shut down before it could restore them.
+pub fn reset_stale(service_backup: &BTreeMap<ServicePath, Option<Vec<DnsServer>>>) {
+    let store = SCDynamicStoreBuilder::new("mullvad-dns-restore").build();
+    for (service_path, servers) in service_backup {
+        let result = match servers {
+            Some(servers) => {
+                DnsSettings::from_server_addresses(servers).save(&store, service_path.as_str())
+            }
+            None => {
+                if store.remove(CFString::new(service_path)) {
+                    Ok(())
+                } else {
+                    Err(Error::SettingDnsFailed)
+                }
+            }
+        };
+        if let Err(error) = result {
+            log::error!(
+                "Failed to restore stale DNS settings for {} - {}",
+                service_path,
+                error
+            );
+        }
+    }
+}
+
 /// Creates a `SCDynamicStore` that watches all network interfaces for changes to the DNS settings.
 fn create_dynamic_store(state: Arc<Mutex<Option<State>>>) -> Result<SCDynamicStore> {
     let callback_context = SCDynamicStoreCallBackContext {
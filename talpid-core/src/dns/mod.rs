@@ -1,4 +1,10 @@
-use std::{net::IpAddr, path::Path};
+use crate::{
+    journal::{JournalDns, SystemStateJournal},
+    tunnel_state_machine::TunnelCommand,
+};
+use futures::sync::mpsc::UnboundedSender;
+use std::{io, net::IpAddr, path::Path, sync::Arc};
+use talpid_types::net::dns::{EncryptedDnsServer, SplitDns};
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -18,21 +24,87 @@ mod imp;
 
 pub use self::imp::Error;
 
+mod resolver;
+pub use self::resolver::{
+    Blocklist, DnsTransport, Error as LocalResolverError, LocalResolver, UdpTransport, DNS_PORT,
+    LOCAL_RESOLVER_ADDRESS,
+};
+
+/// Creates transports to encrypted DNS servers, for the local resolver to send queries with.
+/// Provided by the user of talpid-core, since it has no HTTPS or TLS stack of its own.
+pub trait EncryptedDnsProvider: Send + 'static {
+    /// Returns a transport that sends queries to `server`, encrypted with its protocol.
+    fn create_transport(&self, server: &EncryptedDnsServer) -> io::Result<Arc<dyn DnsTransport>>;
+}
+
+/// Returns the name of the system service DNS is currently set through, if DNS is set. Only known
+/// on Linux, where DNS can be managed in several ways.
+pub fn active_backend() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        imp::active_backend().map(|backend| backend.to_string())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Resets DNS settings that were applied by a previous run, which was shut down before it could
+/// reset them. On Linux, DNS can be set through services that keep the settings after the tunnel
+/// interface is gone, and on macOS the settings stay in the dynamic store. On Windows, the DNS
+/// monitor restores its own backup of the settings when it's created.
+#[cfg_attr(
+    not(any(target_os = "linux", target_os = "macos")),
+    allow(unused_variables)
+)]
+pub(crate) fn reset_stale(dns: &JournalDns) {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(ref backend) = dns.backend {
+            imp::reset_stale(&dns.interface, backend);
+        }
+    }
+    #[cfg(target_os = "macos")]
+    imp::reset_stale(&dns.service_backup);
+}
+
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
 pub struct DnsMonitor {
     inner: imp::DnsMonitor,
+    journal: SystemStateJournal,
 }
 
 impl DnsMonitor {
-    /// Returns a new `DnsMonitor` that can set and monitor the system DNS.
-    pub fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Error> {
+    /// Returns a new `DnsMonitor` that can set and monitor the system DNS. The settings it
+    /// applies are recorded in `journal`, and DNS it re-applies on its own is reported to the
+    /// tunnel state machine through `command_tx`.
+    pub(crate) fn new(
+        cache_dir: impl AsRef<Path>,
+        journal: SystemStateJournal,
+        command_tx: UnboundedSender<TunnelCommand>,
+    ) -> Result<Self, Error> {
+        let listener = ReapplyListener {
+            command_tx,
+            journal: journal.clone(),
+        };
         Ok(DnsMonitor {
-            inner: imp::DnsMonitor::new(cache_dir)?,
+            inner: imp::DnsMonitor::new(cache_dir, listener)?,
+            journal,
         })
     }
 
     /// Set DNS to the given servers. And start monitoring the system for changes.
-    pub fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
+    ///
+    /// Queries for the domains in `split_dns` are instead resolved by the DNS servers of the
+    /// physical network. Those servers are returned, so that they can be reached outside the
+    /// tunnel. Fails if the platform can't resolve domains outside the tunnel.
+    pub fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>, Error> {
         log::info!(
             "Setting DNS servers to {}",
             servers
@@ -41,22 +113,73 @@ impl DnsMonitor {
                 .collect::<Vec<String>>()
                 .join(", ")
         );
-        self.inner.set(interface, servers)
+        if split_dns.is_enabled() {
+            log::info!(
+                "Resolving {} outside the tunnel",
+                split_dns.domains.join(", ")
+            );
+        }
+        let split_dns_servers = self.inner.set(interface, servers, split_dns)?;
+        let dns = JournalDns {
+            interface: interface.to_owned(),
+            backend: active_backend(),
+            #[cfg(target_os = "macos")]
+            service_backup: self.inner.service_backup(),
+            #[cfg(not(target_os = "macos"))]
+            service_backup: Default::default(),
+        };
+        self.journal.update(|journal| journal.dns = Some(dns));
+        Ok(split_dns_servers)
     }
 
     /// Reset system DNS settings to what it was before being set by this instance.
     pub fn reset(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
-        self.inner.reset()
+        self.inner.reset()?;
+        self.journal.update(|journal| journal.dns = None);
+        Ok(())
+    }
+}
+
+/// Reports DNS that a platform monitor re-applied without being asked to, for example after the
+/// system changed how it manages DNS.
+#[derive(Clone)]
+struct ReapplyListener {
+    command_tx: UnboundedSender<TunnelCommand>,
+    journal: SystemStateJournal,
+}
+
+impl ReapplyListener {
+    /// Records the backend DNS for `interface` is now set through, and tells the tunnel state
+    /// machine whether DNS could be re-applied. On success, the result holds the servers that now
+    /// resolve the split DNS domains.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn reapplied(&self, interface: &str, result: Result<Vec<IpAddr>, ()>) {
+        // A stale backend in the journal would make the next run reset DNS through the wrong
+        // service after a crash.
+        let dns = JournalDns {
+            interface: interface.to_owned(),
+            backend: active_backend(),
+            service_backup: Default::default(),
+        };
+        self.journal.update(|journal| journal.dns = Some(dns));
+        let _ = self
+            .command_tx
+            .unbounded_send(TunnelCommand::DnsReapplied(result));
     }
 }
 
 trait DnsMonitorT: Sized {
     type Error: ::std::error::Error;
 
-    fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Self::Error>;
+    fn new(cache_dir: impl AsRef<Path>, listener: ReapplyListener) -> Result<Self, Self::Error>;
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>, Self::Error>;
 
     fn reset(&mut self) -> Result<(), Self::Error>;
 }
@@ -0,0 +1,672 @@
+//! A small stub resolver that listens on the loopback interface, forwards queries to an upstream
+//! server and answers queries for blocked domains itself.
+
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The address the local resolver listens on when it's the system resolver. On macOS, it's added
+/// to the loopback interface while the resolver is running.
+pub const LOCAL_RESOLVER_ADDRESS: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 10);
+/// The port DNS servers listen on.
+pub const DNS_PORT: u16 = 53;
+
+/// How often the listening thread checks if the resolver has been stopped.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long to wait for the upstream server to answer a query.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest DNS message sent over UDP, including EDNS payloads.
+const MAX_MESSAGE_SIZE: usize = 4096;
+/// How many queries are sent upstream at the same time, each by its own worker thread.
+const MAX_QUERIES_IN_FLIGHT: usize = 16;
+/// How many queries can wait for a worker. Queries arriving when the queue is full are dropped,
+/// and left for the client to retry.
+const MAX_QUEUED_QUERIES: usize = 64;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u8 = 0x80;
+const FLAG_RECURSION_AVAILABLE: u8 = 0x80;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Names that hosts file formatted blocklists map to the loopback address without meaning to
+/// block them.
+const HOSTS_FILE_LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+/// Errors that can happen in the local resolver.
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    /// Failed to bind the socket the resolver listens on.
+    #[error(display = "Failed to listen for DNS queries on {}", _0)]
+    BindError(SocketAddr, #[error(cause)] io::Error),
+
+    /// Failed to configure the listening socket.
+    #[error(display = "Failed to configure the DNS listening socket")]
+    ConfigureSocketError(#[error(cause)] io::Error),
+
+    /// Failed to add the address to listen on to the loopback interface.
+    #[cfg(target_os = "macos")]
+    #[error(display = "Failed to add {} to the loopback interface", _0)]
+    AddLoopbackAliasError(Ipv4Addr, #[error(cause)] io::Error),
+
+    /// Failed to read a blocklist.
+    #[error(display = "Failed to read blocklist {}", _0)]
+    ReadBlocklistError(String, #[error(cause)] io::Error),
+}
+
+/// Domains that the local resolver refuses to resolve. A blocked domain also blocks all names
+/// below it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Blocklist {
+    domains: HashSet<String>,
+}
+
+impl Blocklist {
+    /// Reads the domains listed in the given files. Lists that can't be read are skipped, so that
+    /// one missing list doesn't stop the others from being applied.
+    pub fn from_files(paths: &[PathBuf]) -> Self {
+        let mut blocklist = Blocklist::default();
+        for path in paths {
+            if let Err(error) = blocklist.add_file(path) {
+                log::error!("{}", error);
+            }
+        }
+        log::debug!("Blocking {} domains", blocklist.len());
+        blocklist
+    }
+
+    fn add_file(&mut self, path: &Path) -> Result<(), Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| Error::ReadBlocklistError(path.display().to_string(), error))?;
+        self.add_list(&contents);
+        Ok(())
+    }
+
+    /// Adds the domains in a list in hosts file format, or with one domain per line. Comments
+    /// start with `#`.
+    pub fn add_list(&mut self, list: &str) {
+        for line in list.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace().peekable();
+            // Skip the address of hosts file entries.
+            if let Some(first_word) = words.peek() {
+                if first_word.parse::<IpAddr>().is_ok() {
+                    words.next();
+                }
+            }
+            for word in words {
+                let domain = word.trim_end_matches('.').to_lowercase();
+                if !domain.is_empty()
+                    && domain.parse::<IpAddr>().is_err()
+                    && !HOSTS_FILE_LOCAL_NAMES.contains(&domain.as_str())
+                {
+                    self.domains.insert(domain);
+                }
+            }
+        }
+    }
+
+    /// Returns true if the name, or any domain above it, is blocked.
+    pub fn is_blocked(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let mut suffix = name.as_str();
+        loop {
+            if self.domains.contains(suffix) {
+                return true;
+            }
+            match suffix.find('.') {
+                Some(index) => suffix = &suffix[index + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+}
+
+/// Sends DNS queries to an upstream server.
+pub trait DnsTransport: fmt::Display + Send + Sync + 'static {
+    /// Sends the query and blocks until the answer arrives.
+    fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// Sends queries as plain DNS over UDP.
+pub struct UdpTransport {
+    server: SocketAddr,
+}
+
+impl UdpTransport {
+    pub fn new(server: SocketAddr) -> Self {
+        UdpTransport { server }
+    }
+}
+
+impl DnsTransport for UdpTransport {
+    fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let bind_address: SocketAddr = match self.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_address)?;
+        socket.connect(self.server)?;
+        socket.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+        socket.send(query)?;
+
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        loop {
+            let length = socket.recv(&mut buffer)?;
+            // Ignore anything that isn't the answer to this query.
+            if length >= HEADER_LEN && query.len() >= 2 && buffer[..2] == query[..2] {
+                return Ok(buffer[..length].to_vec());
+            }
+        }
+    }
+}
+
+impl fmt::Display for UdpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.server)
+    }
+}
+
+/// A query and the client that sent it.
+type Query = (Vec<u8>, SocketAddr);
+
+/// A running local resolver. Stops when dropped.
+pub struct LocalResolver {
+    listen_address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    listener_thread: Option<thread::JoinHandle<()>>,
+    #[cfg(target_os = "macos")]
+    _loopback_alias: Option<LoopbackAlias>,
+}
+
+impl LocalResolver {
+    /// Starts listening for queries on `listen_address`. Queries for domains in `blocklist` are
+    /// answered with NXDOMAIN, all others are sent upstream with `transport`.
+    pub fn start(
+        listen_address: SocketAddr,
+        transport: Arc<dyn DnsTransport>,
+        blocklist: Blocklist,
+    ) -> Result<Self, Error> {
+        #[cfg(target_os = "macos")]
+        let loopback_alias = match listen_address.ip() {
+            IpAddr::V4(address) if address.is_loopback() && address != Ipv4Addr::LOCALHOST => Some(
+                LoopbackAlias::add(address)
+                    .map_err(|error| Error::AddLoopbackAliasError(address, error))?,
+            ),
+            _ => None,
+        };
+        let socket = UdpSocket::bind(listen_address)
+            .map_err(|error| Error::BindError(listen_address, error))?;
+        socket
+            .set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))
+            .map_err(Error::ConfigureSocketError)?;
+        let listen_address = socket.local_addr().map_err(Error::ConfigureSocketError)?;
+
+        log::info!(
+            "Local DNS resolver listening on {}, forwarding to {}",
+            listen_address,
+            transport
+        );
+        // The workers stop when `query_tx` is dropped, which also happens if this fails.
+        let (query_tx, query_rx) = mpsc::sync_channel(MAX_QUEUED_QUERIES);
+        let query_rx = Arc::new(Mutex::new(query_rx));
+        let mut workers = Vec::with_capacity(MAX_QUERIES_IN_FLIGHT);
+        for _ in 0..MAX_QUERIES_IN_FLIGHT {
+            let reply_socket = socket.try_clone().map_err(Error::ConfigureSocketError)?;
+            let transport = transport.clone();
+            let query_rx = query_rx.clone();
+            workers.push(thread::spawn(move || {
+                Self::forward_queries(reply_socket, transport, query_rx)
+            }));
+        }
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let listener_stopped = stopped.clone();
+        let listener_thread = thread::spawn(move || {
+            Self::listen(socket, query_tx, blocklist, listener_stopped);
+            // The workers hold on to the socket, so they must be done before the address is free.
+            for worker in workers {
+                if worker.join().is_err() {
+                    log::error!("Local DNS resolver worker thread panicked");
+                }
+            }
+        });
+
+        Ok(LocalResolver {
+            listen_address,
+            stopped,
+            listener_thread: Some(listener_thread),
+            #[cfg(target_os = "macos")]
+            _loopback_alias: loopback_alias,
+        })
+    }
+
+    /// The address the resolver is listening on.
+    pub fn listen_address(&self) -> SocketAddr {
+        self.listen_address
+    }
+
+    fn listen(
+        socket: UdpSocket,
+        queries: mpsc::SyncSender<Query>,
+        blocklist: Blocklist,
+        stopped: Arc<AtomicBool>,
+    ) {
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        while !stopped.load(Ordering::SeqCst) {
+            let (length, client) = match socket.recv_from(&mut buffer) {
+                Ok(result) => result,
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue;
+                }
+                Err(error) => {
+                    log::error!("Failed to receive DNS query: {}", error);
+                    continue;
+                }
+            };
+            let query = buffer[..length].to_vec();
+
+            match query_name(&query) {
+                Some(ref name) if blocklist.is_blocked(name) => {
+                    log::trace!("Blocking DNS query for {}", name);
+                    if let Some(response) = blocked_response(&query) {
+                        if let Err(error) = socket.send_to(&response, client) {
+                            log::error!("Failed to answer blocked DNS query: {}", error);
+                        }
+                    }
+                }
+                Some(_) => match queries.try_send((query, client)) {
+                    Ok(()) => (),
+                    Err(mpsc::TrySendError::Full(_)) => {
+                        log::debug!("Dropping DNS query from {}, too many in flight", client)
+                    }
+                    Err(mpsc::TrySendError::Disconnected(_)) => {
+                        log::error!("All local DNS resolver workers have stopped");
+                        return;
+                    }
+                },
+                None => log::debug!("Ignoring malformed DNS query from {}", client),
+            }
+        }
+    }
+
+    /// Sends queries upstream and the answers back to the clients, until `queries` is closed.
+    fn forward_queries(
+        reply_socket: UdpSocket,
+        transport: Arc<dyn DnsTransport>,
+        queries: Arc<Mutex<mpsc::Receiver<Query>>>,
+    ) {
+        loop {
+            let next_query = match queries.lock() {
+                Ok(queries) => queries.recv(),
+                Err(_) => return,
+            };
+            let (query, client) = match next_query {
+                Ok(next_query) => next_query,
+                Err(mpsc::RecvError) => return,
+            };
+            let result = transport
+                .exchange(&query)
+                .and_then(|answer| reply_socket.send_to(&answer, client));
+            if let Err(error) = result {
+                log::debug!("Failed to forward DNS query: {}", error);
+            }
+        }
+    }
+}
+
+impl Drop for LocalResolver {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(listener_thread) = self.listener_thread.take() {
+            if listener_thread.join().is_err() {
+                log::error!("Local DNS resolver thread panicked");
+            }
+        }
+        log::info!("Stopped local DNS resolver");
+    }
+}
+
+/// An address added to the loopback interface. Only 127.0.0.1 is assigned to it on macOS, unlike
+/// on other platforms where the whole 127.0.0.0/8 network can be listened on. Removed again when
+/// dropped.
+#[cfg(target_os = "macos")]
+struct LoopbackAlias(Ipv4Addr);
+
+#[cfg(target_os = "macos")]
+impl LoopbackAlias {
+    fn add(address: Ipv4Addr) -> io::Result<Self> {
+        duct::cmd!("ifconfig", "lo0", "alias", address.to_string(), "up")
+            .stdout_null()
+            .run()?;
+        Ok(LoopbackAlias(address))
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for LoopbackAlias {
+    fn drop(&mut self) {
+        if let Err(error) = duct::cmd!("ifconfig", "lo0", "-alias", self.0.to_string())
+            .stdout_null()
+            .run()
+        {
+            log::error!(
+                "Failed to remove {} from the loopback interface: {}",
+                self.0,
+                error
+            );
+        }
+    }
+}
+
+/// Returns the name asked for in the first question of a query, or `None` if the message isn't a
+/// well formed query.
+fn query_name(message: &[u8]) -> Option<String> {
+    if message.len() < HEADER_LEN || message[2] & FLAG_RESPONSE != 0 {
+        return None;
+    }
+    let question_count = u16::from_be_bytes([message[4], message[5]]);
+    if question_count == 0 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut offset = HEADER_LEN;
+    loop {
+        let length = *message.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        // Compression pointers and extended label types are not expected in questions.
+        if length > 63 {
+            return None;
+        }
+        let label = message.get(offset..offset + length)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += length;
+    }
+    // The type and class must follow the name.
+    message.get(offset..offset + 4)?;
+    Some(labels.join("."))
+}
+
+/// Builds an NXDOMAIN response to the query, containing only the first question.
+fn blocked_response(query: &[u8]) -> Option<Vec<u8>> {
+    let mut offset = HEADER_LEN;
+    while *query.get(offset)? != 0 {
+        offset += 1 + query[offset] as usize;
+    }
+    let question_end = offset + 1 + 4;
+    let mut response = query.get(..question_end)?.to_vec();
+
+    // Keep the opcode and recursion desired bit of the query.
+    response[2] = FLAG_RESPONSE | (query[2] & 0x79);
+    response[3] = FLAG_RECURSION_AVAILABLE | RCODE_NXDOMAIN;
+    // One question, no answer, authority or additional records.
+    response[4..HEADER_LEN].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    Some(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{atomic::AtomicUsize, Condvar};
+
+    fn build_query(id: u16, name: &str) -> Vec<u8> {
+        let mut query = Vec::new();
+        query.extend_from_slice(&id.to_be_bytes());
+        // Recursion desired, one question.
+        query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        // Type A, class IN.
+        query.extend_from_slice(&[0, 0, 1, 0, 1]);
+        query
+    }
+
+    fn start_fake_upstream() -> (UdpSocket, SocketAddr) {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        upstream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = upstream.local_addr().unwrap();
+        (upstream, address)
+    }
+
+    fn start_resolver(upstream: SocketAddr, blocklist: &str) -> LocalResolver {
+        let mut list = Blocklist::default();
+        list.add_list(blocklist);
+        let transport = Arc::new(UdpTransport::new(upstream));
+        LocalResolver::start("127.0.0.1:0".parse().unwrap(), transport, list).unwrap()
+    }
+
+    fn create_client(resolver: &LocalResolver) -> UdpSocket {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.connect(resolver.listen_address()).unwrap();
+        client
+    }
+
+    #[test]
+    fn parses_blocklists() {
+        let mut blocklist = Blocklist::default();
+        blocklist.add_list(
+            "# Ads\n\
+             0.0.0.0 ads.example tracker.example # inline comment\n\
+             127.0.0.1 localhost\n\
+             ::1 ip6-localhost\n\
+             Malware.Example.\n\
+             \n",
+        );
+
+        assert_eq!(blocklist.len(), 3);
+        assert!(blocklist.is_blocked("ads.example"));
+        assert!(blocklist.is_blocked("tracker.example."));
+        assert!(blocklist.is_blocked("malware.example"));
+        assert!(!blocklist.is_blocked("localhost"));
+        assert!(!blocklist.is_blocked("example"));
+    }
+
+    #[test]
+    fn blocks_subdomains() {
+        let mut blocklist = Blocklist::default();
+        blocklist.add_list("ads.example");
+
+        assert!(blocklist.is_blocked("cdn.ADS.example"));
+        assert!(!blocklist.is_blocked("notads.example"));
+        assert!(!blocklist.is_blocked("example"));
+    }
+
+    #[test]
+    fn forwards_queries_to_upstream() {
+        let (upstream, upstream_address) = start_fake_upstream();
+        let resolver = start_resolver(upstream_address, "ads.example");
+        let client = create_client(&resolver);
+
+        let query = build_query(0x1234, "mullvad.net");
+        client.send(&query).unwrap();
+
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let (length, source) = upstream.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], &query[..]);
+
+        let mut answer = query.clone();
+        answer[2] |= FLAG_RESPONSE;
+        upstream.send_to(&answer, source).unwrap();
+
+        let length = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], &answer[..]);
+    }
+
+    /// Answers every query itself, like a transport to an encrypted DNS server would.
+    struct FakeTransport;
+
+    impl DnsTransport for FakeTransport {
+        fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+            let mut answer = query.to_vec();
+            answer[2] |= FLAG_RESPONSE;
+            Ok(answer)
+        }
+    }
+
+    impl fmt::Display for FakeTransport {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "fake transport")
+        }
+    }
+
+    #[test]
+    fn forwards_queries_with_transport() {
+        let resolver = LocalResolver::start(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(FakeTransport),
+            Blocklist::default(),
+        )
+        .unwrap();
+        let client = create_client(&resolver);
+
+        let query = build_query(0x4321, "mullvad.net");
+        client.send(&query).unwrap();
+
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let length = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..2], &[0x43, 0x21]);
+        assert_ne!(buffer[2] & FLAG_RESPONSE, 0);
+        assert_eq!(&buffer[HEADER_LEN..length], &query[HEADER_LEN..]);
+    }
+
+    /// Holds on to every query until released.
+    #[derive(Default)]
+    struct StalledTransport {
+        in_flight: AtomicUsize,
+        released: Mutex<bool>,
+        release: Condvar,
+    }
+
+    impl StalledTransport {
+        fn release(&self) {
+            *self.released.lock().unwrap() = true;
+            self.release.notify_all();
+        }
+    }
+
+    impl DnsTransport for StalledTransport {
+        fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+            let mut released = self.released.lock().unwrap();
+            while !*released {
+                released = self.release.wait(released).unwrap();
+            }
+            FakeTransport.exchange(query)
+        }
+    }
+
+    impl fmt::Display for StalledTransport {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "stalled transport")
+        }
+    }
+
+    #[test]
+    fn drops_queries_past_the_limit() {
+        let transport = Arc::new(StalledTransport::default());
+        let resolver = LocalResolver::start(
+            "127.0.0.1:0".parse().unwrap(),
+            transport.clone(),
+            Blocklist::default(),
+        )
+        .unwrap();
+        let client = create_client(&resolver);
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        let accepted = MAX_QUERIES_IN_FLIGHT + MAX_QUEUED_QUERIES;
+        for id in 0..accepted + 10 {
+            client.send(&build_query(id as u16, "mullvad.net")).unwrap();
+            // Don't overflow the receive buffer of the resolver's socket.
+            thread::sleep(Duration::from_millis(1));
+        }
+        // Let the listener go through the remaining queries.
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            transport.in_flight.load(Ordering::SeqCst),
+            MAX_QUERIES_IN_FLIGHT
+        );
+        transport.release();
+
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let mut answers = 0;
+        while client.recv(&mut buffer).is_ok() {
+            answers += 1;
+        }
+        assert_eq!(answers, accepted);
+    }
+
+    #[test]
+    fn answers_blocked_queries_locally() {
+        let (upstream, upstream_address) = start_fake_upstream();
+        upstream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let resolver = start_resolver(upstream_address, "0.0.0.0 ads.example");
+        let client = create_client(&resolver);
+
+        client
+            .send(&build_query(0xabcd, "www.ads.example"))
+            .unwrap();
+
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let length = client.recv(&mut buffer).unwrap();
+        let response = &buffer[..length];
+        assert_eq!(&response[..2], &[0xab, 0xcd]);
+        assert_ne!(response[2] & FLAG_RESPONSE, 0);
+        assert_eq!(response[3] & 0x0f, RCODE_NXDOMAIN);
+
+        assert!(upstream.recv_from(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn ignores_malformed_queries() {
+        assert_eq!(query_name(&[0u8; 4]), None);
+
+        let mut response = build_query(1, "mullvad.net");
+        response[2] |= FLAG_RESPONSE;
+        assert_eq!(query_name(&response), None);
+
+        let mut truncated = build_query(1, "mullvad.net");
+        truncated.truncate(truncated.len() - 2);
+        assert_eq!(query_name(&truncated), None);
+    }
+}
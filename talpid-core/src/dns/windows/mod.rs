@@ -6,6 +6,7 @@ use std::{
     path::Path,
     ptr, slice,
 };
+use talpid_types::net::dns::SplitDns;
 use widestring::WideCString;
 
 mod system_state;
@@ -36,6 +37,10 @@ pub enum Error {
     /// Failure to reset DNS settings from backup.
     #[error(display = "Failed to recover to backed up system state")]
     Recovery,
+
+    /// Resolving some domains outside the tunnel is not supported.
+    #[error(display = "Split DNS is not supported on Windows")]
+    SplitDnsUnsupported,
 }
 
 pub struct DnsMonitor {
@@ -45,7 +50,7 @@ pub struct DnsMonitor {
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Error> {
+    fn new(cache_dir: impl AsRef<Path>, _listener: super::ReapplyListener) -> Result<Self, Error> {
         unsafe { WinDns_Initialize(Some(log_sink), ptr::null_mut()).into_result()? };
 
         let backup_writer = SystemStateWriter::new(
@@ -59,7 +64,15 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(dns)
     }
 
-    fn set(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
+    fn set(
+        &mut self,
+        _interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<Vec<IpAddr>, Error> {
+        if split_dns.is_enabled() {
+            return Err(Error::SplitDnsUnsupported);
+        }
         let ipv4 = servers
             .iter()
             .filter(|ip| ip.is_ipv4())
@@ -93,8 +106,9 @@ impl super::DnsMonitorT for DnsMonitor {
                 Some(write_system_state_backup_cb),
                 &self.backup_writer as *const _ as *const c_void,
             )
-            .into_result()
-        }
+            .into_result()?
+        };
+        Ok(vec![])
     }
 
     fn reset(&mut self) -> Result<(), Error> {
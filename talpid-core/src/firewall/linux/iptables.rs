@@ -0,0 +1,1060 @@
+use crate::{
+    firewall::{self, FirewallArguments, FirewallPolicy, FirewallT},
+    tunnel,
+};
+use ipnetwork::IpNetwork;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    process::Output,
+};
+use talpid_types::net::{lan::LanPolicy, Endpoint, TransportProtocol};
+use which::which;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can happen when interacting with iptables and ip6tables.
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    /// A required program could not be found in PATH.
+    #[error(display = "Failed to detect '{}' program", _0)]
+    MissingProgram(&'static str),
+
+    /// Failed to start one of the iptables programs.
+    #[error(display = "Failed to execute '{}' program", _0)]
+    RunProgram(&'static str, #[error(cause)] io::Error),
+
+    /// Loading the generated rule set failed.
+    #[error(display = "Applying firewall rules with '{}' failed: {}", _0, _1)]
+    RestoreError(&'static str, String),
+
+    /// Adding or removing the jump to one of our chains failed.
+    #[error(display = "Failed to update chain {} with '{}': {}", _0, _1, _2)]
+    ChainError(&'static str, &'static str, String),
+}
+
+/// TODO(linus): This crate is not supposed to be Mullvad-aware. So at some point this should be
+/// replaced by allowing the chain names to be configured from the public API of this crate.
+const IN_CHAIN_NAME: &str = "mullvad-in";
+const OUT_CHAIN_NAME: &str = "mullvad-out";
+const PREROUTING_CHAIN_NAME: &str = "mullvad-prerouting";
+
+/// The built-in chains our own chains are hooked into, with their table and the chain they jump
+/// to.
+const HOOKS: [(&str, &str, &str); 3] = [
+    ("filter", "INPUT", IN_CHAIN_NAME),
+    ("filter", "OUTPUT", OUT_CHAIN_NAME),
+    ("mangle", "PREROUTING", PREROUTING_CHAIN_NAME),
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables",
+            Family::V6 => "ip6tables",
+        }
+    }
+
+    fn restore_program(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables-restore",
+            Family::V6 => "ip6tables-restore",
+        }
+    }
+
+    fn icmp_protocol(self) -> &'static str {
+        match self {
+            Family::V4 => "icmp",
+            Family::V6 => "ipv6-icmp",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    fn chain(self) -> &'static str {
+        match self {
+            Direction::In => IN_CHAIN_NAME,
+            Direction::Out => OUT_CHAIN_NAME,
+        }
+    }
+
+    fn iface_flag(self) -> &'static str {
+        match self {
+            Direction::In => "-i",
+            Direction::Out => "-o",
+        }
+    }
+}
+
+/// Paths to the programs used to manage the rules of one IP family.
+struct Programs {
+    iptables: PathBuf,
+    restore: PathBuf,
+}
+
+impl Programs {
+    fn find(family: Family) -> Result<Self> {
+        let find_program = |name| which(name).map_err(|_| Error::MissingProgram(name));
+        Ok(Programs {
+            iptables: find_program(family.program())?,
+            restore: find_program(family.restore_program())?,
+        })
+    }
+}
+
+/// Firewall backend that enforces policies through iptables and ip6tables, for hosts where the
+/// kernel lacks nftables support.
+pub struct Firewall {
+    ipv4: Programs,
+    ipv6: Programs,
+}
+
+impl FirewallT for Firewall {
+    type Error = Error;
+
+    fn new(_args: FirewallArguments) -> Result<Self> {
+        Ok(Firewall {
+            ipv4: Programs::find(Family::V4)?,
+            ipv6: Programs::find(Family::V6)?,
+        })
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        let rules = PolicyRules::new(&policy);
+        for &family in &[Family::V4, Family::V6] {
+            self.restore(family, rules.restore_input(family))?;
+            self.hook_chains(family)?;
+        }
+        Ok(())
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        log::debug!("Removing chains from iptables and ip6tables");
+        let mut result = Ok(());
+        for &family in &[Family::V4, Family::V6] {
+            if let Err(error) = self.remove_chains(family) {
+                result = Err(error);
+            }
+        }
+        result
+    }
+}
+
+impl Firewall {
+    fn programs(&self, family: Family) -> &Programs {
+        match family {
+            Family::V4 => &self.ipv4,
+            Family::V6 => &self.ipv6,
+        }
+    }
+
+    /// Atomically replaces the contents of our chains with the given rule set.
+    fn restore(&self, family: Family, input: String) -> Result<()> {
+        let output = duct::cmd!(&self.programs(family).restore, "--noflush")
+            .input(input)
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(|e| Error::RunProgram(family.restore_program(), e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            return Err(Error::RestoreError(family.restore_program(), stderr));
+        }
+        Ok(())
+    }
+
+    /// Makes sure the built-in chains jump to our chains before any other rule is evaluated.
+    fn hook_chains(&self, family: Family) -> Result<()> {
+        for &(table, builtin_chain, chain) in &HOOKS {
+            if self
+                .iptables(family, &["-t", table, "-C", builtin_chain, "-j", chain])?
+                .status
+                .success()
+            {
+                continue;
+            }
+            let output = self.iptables(
+                family,
+                &["-t", table, "-I", builtin_chain, "1", "-j", chain],
+            )?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+                return Err(Error::ChainError(chain, family.program(), stderr));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_chains(&self, family: Family) -> Result<()> {
+        for &(table, builtin_chain, chain) in &HOOKS {
+            while self
+                .iptables(family, &["-t", table, "-C", builtin_chain, "-j", chain])?
+                .status
+                .success()
+            {
+                let output =
+                    self.iptables(family, &["-t", table, "-D", builtin_chain, "-j", chain])?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+                    return Err(Error::ChainError(chain, family.program(), stderr));
+                }
+            }
+
+            // The chain only has to be removed if it exists.
+            if !self
+                .iptables(family, &["-t", table, "-S", chain])?
+                .status
+                .success()
+            {
+                continue;
+            }
+            for &operation in &["-F", "-X"] {
+                let output = self.iptables(family, &["-t", table, operation, chain])?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+                    return Err(Error::ChainError(chain, family.program(), stderr));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn iptables(&self, family: Family, args: &[&str]) -> Result<Output> {
+        duct::cmd(
+            &self.programs(family).iptables,
+            std::iter::once("-w").chain(args.iter().cloned()),
+        )
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| Error::RunProgram(family.program(), e))
+    }
+}
+
+/// The rules needed to enforce a policy, for both IP families. Each rule is expressed in the
+/// syntax accepted by `iptables-restore`.
+#[derive(Debug, Default)]
+struct PolicyRules {
+    ipv4: Vec<String>,
+    ipv6: Vec<String>,
+    /// Rules in the mangle table, that mark packets before they are routed.
+    ipv4_mangle: Vec<String>,
+    ipv6_mangle: Vec<String>,
+}
+
+impl PolicyRules {
+    /// Generates every rule needed to satisfy the given policy. Mirrors the nftables backend.
+    fn new(policy: &FirewallPolicy) -> Self {
+        let mut rules = PolicyRules::default();
+        rules.add_loopback_rules();
+        rules.add_dhcp_client_rules();
+        rules.add_policy_specific_rules(policy);
+        rules.add_drop_rules();
+        rules
+    }
+
+    /// Returns the input to feed `iptables-restore --noflush` for the given family. Declaring
+    /// our chains creates them if missing and flushes them otherwise, so the whole rule set is
+    /// replaced atomically.
+    fn restore_input(&self, family: Family) -> String {
+        let (rules, mangle_rules) = match family {
+            Family::V4 => (&self.ipv4, &self.ipv4_mangle),
+            Family::V6 => (&self.ipv6, &self.ipv6_mangle),
+        };
+        let mut input = String::from("*filter\n");
+        input.push_str(&format!(":{} - [0:0]\n", IN_CHAIN_NAME));
+        input.push_str(&format!(":{} - [0:0]\n", OUT_CHAIN_NAME));
+        for rule in rules {
+            input.push_str(rule);
+            input.push('\n');
+        }
+        input.push_str("COMMIT\n");
+
+        input.push_str("*mangle\n");
+        input.push_str(&format!(":{} - [0:0]\n", PREROUTING_CHAIN_NAME));
+        for rule in mangle_rules {
+            input.push_str(rule);
+            input.push('\n');
+        }
+        input.push_str("COMMIT\n");
+        input
+    }
+
+    fn add(&mut self, family: Family, direction: Direction, matches: &str, target: &str) {
+        let rule = if matches.is_empty() {
+            format!("-A {} -j {}", direction.chain(), target)
+        } else {
+            format!("-A {} {} -j {}", direction.chain(), matches, target)
+        };
+        match family {
+            Family::V4 => self.ipv4.push(rule),
+            Family::V6 => self.ipv6.push(rule),
+        }
+    }
+
+    fn add_both(&mut self, direction: Direction, matches: &str, target: &str) {
+        self.add(Family::V4, direction, matches, target);
+        self.add(Family::V6, direction, matches, target);
+    }
+
+    fn add_loopback_rules(&mut self) {
+        const LOOPBACK_IFACE_NAME: &str = "lo";
+        self.add_allow_interface_rules(LOOPBACK_IFACE_NAME);
+    }
+
+    fn add_dhcp_client_rules(&mut self) {
+        // Outgoing DHCPv4 request
+        self.add(
+            Family::V4,
+            Direction::Out,
+            &format!(
+                "-d {} -p udp --sport {} --dport {}",
+                Ipv4Addr::BROADCAST,
+                firewall::DHCPV4_CLIENT_PORT,
+                firewall::DHCPV4_SERVER_PORT
+            ),
+            "ACCEPT",
+        );
+        // Incoming DHCPv4 response
+        self.add(
+            Family::V4,
+            Direction::In,
+            &format!(
+                "-p udp --sport {} --dport {}",
+                firewall::DHCPV4_SERVER_PORT,
+                firewall::DHCPV4_CLIENT_PORT
+            ),
+            "ACCEPT",
+        );
+
+        for dhcpv6_server in &*firewall::DHCPV6_SERVER_ADDRS {
+            self.add(
+                Family::V6,
+                Direction::Out,
+                &format!(
+                    "-s {} -d {} -p udp --sport {} --dport {}",
+                    *firewall::IPV6_LINK_LOCAL,
+                    dhcpv6_server,
+                    firewall::DHCPV6_CLIENT_PORT,
+                    firewall::DHCPV6_SERVER_PORT
+                ),
+                "ACCEPT",
+            );
+        }
+        self.add(
+            Family::V6,
+            Direction::In,
+            &format!(
+                "-s {link_local} -d {link_local} -p udp --sport {} --dport {}",
+                firewall::DHCPV6_SERVER_PORT,
+                firewall::DHCPV6_CLIENT_PORT,
+                link_local = *firewall::IPV6_LINK_LOCAL,
+            ),
+            "ACCEPT",
+        );
+        // Outgoing Router solicitation (part of NDP)
+        self.add(
+            Family::V6,
+            Direction::Out,
+            &format!(
+                "-d {} -p ipv6-icmp --icmpv6-type 133/0",
+                *firewall::ROUTER_SOLICITATION_OUT_DST_ADDR
+            ),
+            "ACCEPT",
+        );
+        // Incoming Router advertisement and Redirect (part of NDP)
+        for icmpv6_type in &[134, 137] {
+            self.add(
+                Family::V6,
+                Direction::In,
+                &format!(
+                    "-s {} -p ipv6-icmp --icmpv6-type {}/0",
+                    *firewall::IPV6_LINK_LOCAL,
+                    icmpv6_type
+                ),
+                "ACCEPT",
+            );
+        }
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
+        match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                pingable_hosts,
+                allow_ipv6,
+                ..
+            } => {
+                let pingable_hosts: Vec<IpAddr> =
+                    firewall::allowed_ips(pingable_hosts, *allow_ipv6).collect();
+                self.add_allow_icmp_pingable_hosts(&pingable_hosts);
+                self.add_allow_endpoint_rules(peer_endpoint);
+                self.add_allow_captive_portal_probe_rules();
+            }
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                split_dns_servers,
+                allow_gateway_dns,
+                local_resolver_upstream,
+                allow_ipv6,
+                ..
+            } => {
+                self.add_allow_endpoint_rules(peer_endpoint);
+                for &protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    self.add_dns_rules(
+                        tunnel,
+                        *allow_gateway_dns,
+                        split_dns_servers,
+                        *allow_ipv6,
+                        protocol,
+                    );
+                }
+                self.add_allow_tunnel_rules(&tunnel.interface, *allow_ipv6);
+                if let Some(upstream) = local_resolver_upstream {
+                    self.add_block_local_resolver_upstream_rules(*upstream);
+                }
+                self.add_allow_captive_portal_probe_rules();
+            }
+            FirewallPolicy::Blocked { .. } => (),
+            FirewallPolicy::CaptivePortal {
+                gateway_networks, ..
+            } => {
+                self.add_captive_portal_rules(gateway_networks);
+                self.add_allow_captive_portal_probe_rules();
+            }
+        }
+
+        self.add_lan_rules(policy.lan_policy());
+    }
+
+    fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        self.add_endpoint_reply_rules(endpoint);
+        self.add(
+            Family::of(endpoint.address.ip()),
+            Direction::Out,
+            &format!(
+                "-d {} -p {} --dport {}",
+                endpoint.address.ip(),
+                protocol_name(endpoint.protocol),
+                endpoint.address.port()
+            ),
+            "ACCEPT",
+        );
+    }
+
+    /// Allows the captive portal probe to reach its endpoint outside the tunnel. Only packets
+    /// marked with `TUNNEL_FWMARK` are let through, like the probe marks its socket. Not allowed
+    /// by the blocked policy, where the result of the probe isn't acted on.
+    fn add_allow_captive_portal_probe_rules(&mut self) {
+        let endpoint = crate::offline::captive_portal::probe_endpoint();
+        self.add_endpoint_reply_rules(&endpoint);
+        self.add(
+            Family::of(endpoint.address.ip()),
+            Direction::Out,
+            &format!(
+                "-d {} -p {} --dport {} -m mark --mark {:#x}",
+                endpoint.address.ip(),
+                protocol_name(endpoint.protocol),
+                endpoint.address.port(),
+                crate::linux::TUNNEL_FWMARK
+            ),
+            "ACCEPT",
+        );
+    }
+
+    fn add_captive_portal_rules(&mut self, gateway_networks: &[IpNetwork]) {
+        for network in gateway_networks {
+            let family = Family::of(network.ip());
+            for &(protocol, port) in &firewall::CAPTIVE_PORTAL_PORTS {
+                let protocol = protocol_name(protocol);
+                self.add(
+                    family,
+                    Direction::Out,
+                    &format!("-d {} -p {} --dport {}", network, protocol, port),
+                    "ACCEPT",
+                );
+                self.add(
+                    family,
+                    Direction::In,
+                    &format!(
+                        "-s {} -p {} --sport {} -m conntrack --ctstate ESTABLISHED",
+                        network, protocol, port
+                    ),
+                    "ACCEPT",
+                );
+            }
+        }
+    }
+
+    fn add_endpoint_reply_rules(&mut self, endpoint: &Endpoint) {
+        let family = Family::of(endpoint.address.ip());
+        let protocol = protocol_name(endpoint.protocol);
+        // Mark the packets from the endpoint like the packets the tunnel sends to it, so that the
+        // reverse path filter looks them up in the main routing table.
+        let mark_rule = format!(
+            "-A {} -s {} -p {} --sport {} -j MARK --set-mark {:#x}",
+            PREROUTING_CHAIN_NAME,
+            endpoint.address.ip(),
+            protocol,
+            endpoint.address.port(),
+            crate::linux::TUNNEL_FWMARK
+        );
+        match family {
+            Family::V4 => self.ipv4_mangle.push(mark_rule),
+            Family::V6 => self.ipv6_mangle.push(mark_rule),
+        }
+        self.add(
+            family,
+            Direction::In,
+            &format!(
+                "-s {} -p {} --sport {} -m conntrack --ctstate ESTABLISHED",
+                endpoint.address.ip(),
+                protocol,
+                endpoint.address.port()
+            ),
+            "ACCEPT",
+        );
+    }
+
+    fn add_allow_icmp_pingable_hosts(&mut self, pingable_hosts: &[IpAddr]) {
+        for host in pingable_hosts {
+            let family = Family::of(*host);
+            let icmp_protocol = family.icmp_protocol();
+            self.add(
+                family,
+                Direction::Out,
+                &format!("-d {} -p {}", host, icmp_protocol),
+                "ACCEPT",
+            );
+            self.add(
+                family,
+                Direction::In,
+                &format!("-s {} -p {}", host, icmp_protocol),
+                "ACCEPT",
+            );
+        }
+    }
+
+    fn add_dns_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        allow_gateway_dns: bool,
+        split_dns_servers: &[IpAddr],
+        allow_ipv6: bool,
+        protocol: TransportProtocol,
+    ) {
+        // allow DNS traffic to the tunnel gateway, unless DNS is encrypted
+        if allow_gateway_dns {
+            self.add_allow_dns_rule(
+                Some(&tunnel.interface),
+                protocol,
+                tunnel.ipv4_gateway.into(),
+            );
+            if let (Some(ipv6_gateway), true) = (tunnel.ipv6_gateway, allow_ipv6) {
+                self.add_allow_dns_rule(Some(&tunnel.interface), protocol, ipv6_gateway.into());
+            };
+        }
+        // allow DNS traffic to the servers resolving split DNS domains, outside the tunnel
+        for server in firewall::allowed_ips(split_dns_servers, allow_ipv6) {
+            self.add_allow_dns_rule(None, protocol, server);
+        }
+        self.add_both(
+            Direction::Out,
+            &format!("-p {} --dport 53", protocol_name(protocol)),
+            "DROP",
+        );
+    }
+
+    fn add_allow_dns_rule(
+        &mut self,
+        interface: Option<&str>,
+        protocol: TransportProtocol,
+        host: IpAddr,
+    ) {
+        let mut rule = String::new();
+        if let Some(interface) = interface {
+            rule.push_str(&format!("-o {} ", interface));
+        }
+        rule.push_str(&format!(
+            "-d {} -p {} --dport 53",
+            host,
+            protocol_name(protocol)
+        ));
+        self.add(Family::of(host), Direction::Out, &rule, "ACCEPT");
+    }
+
+    fn add_allow_interface_rules(&mut self, interface: &str) {
+        for &direction in &[Direction::Out, Direction::In] {
+            self.add_both(
+                direction,
+                &format!("{} {}", direction.iface_flag(), interface),
+                "ACCEPT",
+            );
+        }
+    }
+
+    fn add_allow_tunnel_rules(&mut self, interface: &str, allow_ipv6: bool) {
+        if allow_ipv6 {
+            return self.add_allow_interface_rules(interface);
+        }
+        for &direction in &[Direction::Out, Direction::In] {
+            self.add(
+                Family::V4,
+                direction,
+                &format!("{} {}", direction.iface_flag(), interface),
+                "ACCEPT",
+            );
+        }
+    }
+
+    /// Drops the queries of the local DNS resolver that would not go through the tunnel. Has to
+    /// come after the tunnel rules, and before the LAN rules that could otherwise let them out.
+    fn add_block_local_resolver_upstream_rules(&mut self, upstream: SocketAddr) {
+        for &protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+            self.add(
+                Family::of(upstream.ip()),
+                Direction::Out,
+                &format!(
+                    "-d {} -p {} --dport {}",
+                    upstream.ip(),
+                    protocol_name(protocol),
+                    upstream.port()
+                ),
+                "DROP",
+            );
+        }
+    }
+
+    fn add_lan_rules(&mut self, lan_policy: &LanPolicy) {
+        // LAN -> LAN
+        for net in firewall::allowed_lan_nets(lan_policy) {
+            let family = Family::of(net.ip());
+            self.add(family, Direction::Out, &format!("-d {}", net), "ACCEPT");
+            self.add(family, Direction::In, &format!("-s {}", net), "ACCEPT");
+        }
+        // LAN -> Multicast
+        if lan_policy.allow_multicast {
+            for net in &*firewall::ALLOWED_LAN_MULTICAST_NETS {
+                self.add(
+                    Family::of(net.ip()),
+                    Direction::Out,
+                    &format!("-d {}", net),
+                    "ACCEPT",
+                );
+            }
+        }
+        if lan_policy.allow_dhcp_server {
+            self.add_dhcp_server_rules();
+        }
+    }
+
+    fn add_dhcp_server_rules(&mut self) {
+        // Outgoing DHCPv4 response
+        self.add(
+            Family::V4,
+            Direction::Out,
+            &format!(
+                "-p udp --sport {} --dport {}",
+                firewall::DHCPV4_SERVER_PORT,
+                firewall::DHCPV4_CLIENT_PORT
+            ),
+            "ACCEPT",
+        );
+        // Incoming DHCPv4 request
+        self.add(
+            Family::V4,
+            Direction::In,
+            &format!(
+                "-d {} -p udp --sport {} --dport {}",
+                Ipv4Addr::BROADCAST,
+                firewall::DHCPV4_CLIENT_PORT,
+                firewall::DHCPV4_SERVER_PORT
+            ),
+            "ACCEPT",
+        );
+    }
+
+    /// Everything not explicitly allowed above is dropped.
+    fn add_drop_rules(&mut self) {
+        self.add_both(Direction::In, "", "DROP");
+        self.add_both(Direction::Out, "", "DROP");
+    }
+}
+
+fn protocol_name(protocol: TransportProtocol) -> &'static str {
+    match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn connected_policy(lan_policy: LanPolicy) -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 1194, TransportProtocol::Udp),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "tun0".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 8, 0, 1),
+                ipv6_gateway: Some(Ipv6Addr::new(0xfdda, 0xd0d0, 0xcafe, 0x1194, 0, 0, 0, 1)),
+                wireguard_backend: None,
+            },
+            lan_policy,
+            split_dns_servers: vec![],
+            allow_gateway_dns: true,
+            local_resolver_upstream: None,
+            allow_ipv6: true,
+        }
+    }
+
+    #[test]
+    fn test_restore_input_layout() {
+        let rules = PolicyRules::new(&FirewallPolicy::Blocked {
+            lan_policy: LanPolicy::block_all(),
+        });
+        let input = rules.restore_input(Family::V4);
+        let lines: Vec<&str> = input.lines().collect();
+
+        assert_eq!(
+            &lines[..3],
+            &["*filter", ":mullvad-in - [0:0]", ":mullvad-out - [0:0]"]
+        );
+        assert_eq!(lines[3], "-A mullvad-out -o lo -j ACCEPT");
+        assert_eq!(lines[4], "-A mullvad-in -i lo -j ACCEPT");
+        let filter_end = lines.iter().position(|line| *line == "COMMIT").unwrap();
+        assert_eq!(
+            &lines[filter_end - 2..=filter_end],
+            &["-A mullvad-in -j DROP", "-A mullvad-out -j DROP", "COMMIT"]
+        );
+        assert_eq!(
+            &lines[filter_end + 1..],
+            &["*mangle", ":mullvad-prerouting - [0:0]", "COMMIT"]
+        );
+    }
+
+    #[test]
+    fn test_blocked_policy_without_lan() {
+        let rules = PolicyRules::new(&FirewallPolicy::Blocked {
+            lan_policy: LanPolicy::block_all(),
+        });
+
+        assert!(rules.ipv4.contains(
+            &"-A mullvad-out -d 255.255.255.255 -p udp --sport 68 --dport 67 -j ACCEPT".to_owned()
+        ));
+        assert!(rules.ipv6.contains(
+            &"-A mullvad-out -d ff02::2 -p ipv6-icmp --icmpv6-type 133/0 -j ACCEPT".to_owned()
+        ));
+        assert!(!rules.ipv4.iter().any(|rule| rule.contains("10.0.0.0/8")));
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("-p icmp ")));
+    }
+
+    #[test]
+    fn test_blocked_policy_with_lan() {
+        let rules = PolicyRules::new(&FirewallPolicy::Blocked {
+            lan_policy: LanPolicy::allow_all(),
+        });
+
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -d 10.0.0.0/8 -j ACCEPT".to_owned()));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-in -s 192.168.0.0/16 -j ACCEPT".to_owned()));
+        assert!(rules
+            .ipv6
+            .contains(&"-A mullvad-out -d ff02::/16 -j ACCEPT".to_owned()));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -p udp --sport 67 --dport 68 -j ACCEPT".to_owned()));
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("10.0.0.0/8")));
+    }
+
+    #[test]
+    fn test_partial_lan_policy() {
+        let lan_policy = LanPolicy {
+            allowed_networks: vec!["192.168.1.0/24".parse().unwrap()],
+            ..LanPolicy::block_all()
+        };
+        let rules = PolicyRules::new(&connected_policy(lan_policy));
+
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -d 192.168.1.0/24 -j ACCEPT".to_owned()));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-in -s 192.168.1.0/24 -j ACCEPT".to_owned()));
+        assert!(!rules.ipv4.iter().any(|rule| rule.contains("10.0.0.0/8")));
+        assert!(!rules
+            .ipv4
+            .iter()
+            .any(|rule| rule.contains("169.254.0.0/16")));
+        assert!(!rules.ipv4.iter().any(|rule| rule.contains("224.0.0.0/24")));
+        assert!(!rules
+            .ipv4
+            .contains(&"-A mullvad-out -p udp --sport 67 --dport 68 -j ACCEPT".to_owned()));
+    }
+
+    #[test]
+    fn test_connecting_policy() {
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            pingable_hosts: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            lan_policy: LanPolicy::block_all(),
+            allow_ipv6: true,
+        };
+        let rules = PolicyRules::new(&policy);
+
+        assert!(rules.ipv4.contains(
+            &"-A mullvad-in -s 1.2.3.4 -p tcp --sport 443 -m conntrack --ctstate ESTABLISHED -j ACCEPT"
+                .to_owned()
+        ));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -d 1.2.3.4 -p tcp --dport 443 -j ACCEPT".to_owned()));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -d 10.64.0.1 -p icmp -j ACCEPT".to_owned()));
+        assert_eq!(
+            rules.ipv4_mangle,
+            vec![
+                "-A mullvad-prerouting -s 1.2.3.4 -p tcp --sport 443 -j MARK --set-mark 0x6d6f6c65"
+                    .to_owned(),
+                "-A mullvad-prerouting -s 192.0.2.1 -p tcp --sport 80 -j MARK --set-mark 0x6d6f6c65"
+                    .to_owned(),
+            ]
+        );
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("1.2.3.4")));
+        assert!(rules.ipv6_mangle.is_empty());
+    }
+
+    #[test]
+    fn test_captive_portal_policy() {
+        let policy = FirewallPolicy::CaptivePortal {
+            gateway_networks: vec![
+                "192.168.1.0/24".parse().unwrap(),
+                "2001:db8::/64".parse().unwrap(),
+            ],
+            lan_policy: LanPolicy::block_all(),
+        };
+        let rules = PolicyRules::new(&policy);
+
+        for port in &[80, 443] {
+            assert!(rules.ipv4.contains(&format!(
+                "-A mullvad-out -d 192.168.1.0/24 -p tcp --dport {} -j ACCEPT",
+                port
+            )));
+        }
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -d 192.168.1.0/24 -p udp --dport 53 -j ACCEPT".to_owned()));
+        assert!(rules.ipv4.contains(
+            &"-A mullvad-in -s 192.168.1.0/24 -p tcp --sport 443 -m conntrack --ctstate ESTABLISHED -j ACCEPT"
+                .to_owned()
+        ));
+        assert!(rules
+            .ipv6
+            .contains(&"-A mullvad-out -d 2001:db8::/64 -p tcp --dport 80 -j ACCEPT".to_owned()));
+        assert!(!rules.ipv4.iter().any(|rule| rule.contains("--dport 22")));
+    }
+
+    #[test]
+    fn test_connected_policy_allows_captive_portal_probe() {
+        let rules = PolicyRules::new(&connected_policy(LanPolicy::block_all()));
+
+        assert!(rules.ipv4.contains(
+            &"-A mullvad-out -d 192.0.2.1 -p tcp --dport 80 -m mark --mark 0x6d6f6c65 -j ACCEPT"
+                .to_owned()
+        ));
+        assert!(rules.ipv4.contains(
+            &"-A mullvad-in -s 192.0.2.1 -p tcp --sport 80 -m conntrack --ctstate ESTABLISHED -j ACCEPT"
+                .to_owned()
+        ));
+    }
+
+    #[test]
+    fn test_blocked_policy_blocks_captive_portal_probe() {
+        let rules = PolicyRules::new(&FirewallPolicy::Blocked {
+            lan_policy: LanPolicy::block_all(),
+        });
+
+        assert!(!rules.ipv4.iter().any(|rule| rule.contains("192.0.2.1")));
+        assert!(rules.ipv4_mangle.is_empty());
+    }
+
+    #[test]
+    fn test_connected_policy_dns_rules_precede_tunnel_rules() {
+        let rules = PolicyRules::new(&connected_policy(LanPolicy::block_all()));
+
+        let position = |rules: &[String], rule: &str| {
+            rules
+                .iter()
+                .position(|r| r == rule)
+                .unwrap_or_else(|| panic!("Missing rule: {}", rule))
+        };
+
+        let allow_dns = position(
+            &rules.ipv4,
+            "-A mullvad-out -o tun0 -d 10.8.0.1 -p udp --dport 53 -j ACCEPT",
+        );
+        let block_dns = position(&rules.ipv4, "-A mullvad-out -p udp --dport 53 -j DROP");
+        let allow_tunnel = position(&rules.ipv4, "-A mullvad-out -o tun0 -j ACCEPT");
+        assert!(allow_dns < block_dns);
+        assert!(block_dns < allow_tunnel);
+
+        position(
+            &rules.ipv6,
+            "-A mullvad-out -o tun0 -d fdda:d0d0:cafe:1194::1 -p tcp --dport 53 -j ACCEPT",
+        );
+        position(&rules.ipv6, "-A mullvad-in -i tun0 -j ACCEPT");
+    }
+
+    #[test]
+    fn test_connected_policy_allows_split_dns_servers() {
+        let policy = match connected_policy(LanPolicy::block_all()) {
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                lan_policy,
+                ..
+            } => FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                lan_policy,
+                split_dns_servers: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))],
+                allow_gateway_dns: true,
+                local_resolver_upstream: None,
+                allow_ipv6: true,
+            },
+            _ => unreachable!(),
+        };
+        let rules = PolicyRules::new(&policy);
+
+        let allow_split_dns = rules
+            .ipv4
+            .iter()
+            .position(|rule| rule == "-A mullvad-out -d 192.168.1.1 -p udp --dport 53 -j ACCEPT")
+            .expect("Missing split DNS rule");
+        let block_dns = rules
+            .ipv4
+            .iter()
+            .position(|rule| rule == "-A mullvad-out -p udp --dport 53 -j DROP")
+            .unwrap();
+        assert!(allow_split_dns < block_dns);
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("192.168.1.1")));
+    }
+
+    #[test]
+    fn test_connected_policy_blocks_gateway_dns_when_encrypted() {
+        let mut policy = connected_policy(LanPolicy::block_all());
+        if let FirewallPolicy::Connected {
+            ref mut allow_gateway_dns,
+            ..
+        } = policy
+        {
+            *allow_gateway_dns = false;
+        }
+        let rules = PolicyRules::new(&policy);
+
+        assert!(!rules.ipv4.iter().any(|rule| rule.contains("-d 10.8.0.1")));
+        assert!(!rules
+            .ipv6
+            .iter()
+            .any(|rule| rule.contains("-d fdda:d0d0:cafe:1194::1")));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -p udp --dport 53 -j DROP".to_owned()));
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -o tun0 -j ACCEPT".to_owned()));
+    }
+
+    #[test]
+    fn test_connected_policy_blocks_local_resolver_upstream_outside_tunnel() {
+        let lan_policy = LanPolicy {
+            allowed_networks: vec!["192.168.1.0/24".parse().unwrap()],
+            ..LanPolicy::block_all()
+        };
+        let mut policy = connected_policy(lan_policy);
+        if let FirewallPolicy::Connected {
+            ref mut local_resolver_upstream,
+            ..
+        } = policy
+        {
+            *local_resolver_upstream = Some("192.168.1.53:853".parse().unwrap());
+        }
+        let rules = PolicyRules::new(&policy);
+
+        let position = |rule: &str| {
+            rules
+                .ipv4
+                .iter()
+                .position(|r| r == rule)
+                .unwrap_or_else(|| panic!("Missing rule: {}", rule))
+        };
+        let allow_tunnel = position("-A mullvad-out -o tun0 -j ACCEPT");
+        let allow_lan = position("-A mullvad-out -d 192.168.1.0/24 -j ACCEPT");
+        for protocol in &["udp", "tcp"] {
+            let block_upstream = position(&format!(
+                "-A mullvad-out -d 192.168.1.53 -p {} --dport 853 -j DROP",
+                protocol
+            ));
+            assert!(allow_tunnel < block_upstream);
+            assert!(block_upstream < allow_lan);
+        }
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("192.168.1.53")));
+    }
+
+    #[test]
+    fn test_connected_policy_without_ipv6() {
+        let mut policy = connected_policy(LanPolicy::block_all());
+        if let FirewallPolicy::Connected {
+            ref mut split_dns_servers,
+            ref mut allow_ipv6,
+            ..
+        } = policy
+        {
+            split_dns_servers.push("2001:db8::53".parse().unwrap());
+            *allow_ipv6 = false;
+        }
+        let rules = PolicyRules::new(&policy);
+
+        assert!(rules
+            .ipv4
+            .contains(&"-A mullvad-out -o tun0 -j ACCEPT".to_owned()));
+        assert!(rules.ipv4.contains(
+            &"-A mullvad-out -o tun0 -d 10.8.0.1 -p udp --dport 53 -j ACCEPT".to_owned()
+        ));
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("tun0")));
+        assert!(!rules.ipv6.iter().any(|rule| rule.contains("2001:db8::53")));
+        assert!(rules
+            .ipv6
+            .contains(&"-A mullvad-out -p udp --dport 53 -j DROP".to_owned()));
+    }
+}
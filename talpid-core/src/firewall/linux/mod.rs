@@ -0,0 +1,103 @@
+mod iptables;
+mod nftables;
+
+use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use std::{env, fmt};
+use talpid_types::ErrorExt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can happen in the Linux firewall.
+#[derive(err_derive::Error, derive_more::From, Debug)]
+pub enum Error {
+    /// Error in the nftables firewall backend.
+    #[error(display = "Error in the nftables firewall backend")]
+    Nftables(#[error(cause)] nftables::Error),
+
+    /// Error in the iptables firewall backend.
+    #[error(display = "Error in the iptables firewall backend")]
+    Iptables(#[error(cause)] iptables::Error),
+
+    /// No usable firewall backend was detected on the host.
+    #[error(display = "No suitable firewall backend detected")]
+    NoFirewallBackend,
+}
+
+/// The Linux implementation for the firewall. Delegates to nftables when the host supports it and
+/// falls back to iptables/ip6tables otherwise.
+pub struct Firewall {
+    inner: FirewallBackend,
+}
+
+impl FirewallT for Firewall {
+    type Error = Error;
+
+    fn new(args: FirewallArguments) -> Result<Self> {
+        let backend = env::var_os("TALPID_FIREWALL_BACKEND");
+
+        let inner = match backend.as_ref().and_then(|value| value.to_str()) {
+            Some("nftables") => FirewallBackend::Nftables(nftables::Firewall::new(args)?),
+            Some("iptables") => FirewallBackend::Iptables(iptables::Firewall::new(args)?),
+            Some(_) | None => FirewallBackend::detect(args)?,
+        };
+        log::debug!("Managing firewall via {}", inner);
+        Ok(Firewall { inner })
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        match self.inner {
+            FirewallBackend::Nftables(ref mut nftables) => nftables.apply_policy(policy)?,
+            FirewallBackend::Iptables(ref mut iptables) => iptables.apply_policy(policy)?,
+        }
+        Ok(())
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        match self.inner {
+            FirewallBackend::Nftables(ref mut nftables) => nftables.reset_policy()?,
+            FirewallBackend::Iptables(ref mut iptables) => iptables.reset_policy()?,
+        }
+        Ok(())
+    }
+}
+
+enum FirewallBackend {
+    Nftables(nftables::Firewall),
+    Iptables(iptables::Firewall),
+}
+
+impl fmt::Display for FirewallBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FirewallBackend::Nftables(..) => "nftables",
+            FirewallBackend::Iptables(..) => "iptables",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FirewallBackend {
+    fn detect(args: FirewallArguments) -> Result<Self> {
+        let nftables_error = match nftables::Firewall::new(args.clone()) {
+            Ok(nftables) => match nftables.probe() {
+                Ok(()) => return Ok(FirewallBackend::Nftables(nftables)),
+                Err(error) => error,
+            },
+            Err(error) => error,
+        };
+        log::warn!(
+            "{}",
+            nftables_error.display_chain_with_msg("nftables is unavailable, trying iptables")
+        );
+
+        iptables::Firewall::new(args)
+            .map(FirewallBackend::Iptables)
+            .map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("iptables is unavailable")
+                );
+                Error::NoFirewallBackend
+            })
+    }
+}
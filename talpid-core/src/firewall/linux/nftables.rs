@@ -1,4 +1,4 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use crate::firewall::{self, FirewallArguments, FirewallPolicy, FirewallT};
 use crate::tunnel;
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
@@ -12,9 +12,12 @@ use std::{
     env,
     ffi::{CStr, CString},
     io,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use talpid_types::{
+    net::{lan::LanPolicy, Endpoint, TransportProtocol},
+    ErrorExt,
 };
-use talpid_types::net::{Endpoint, TransportProtocol};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -54,8 +57,11 @@ lazy_static! {
     /// TODO(linus): This crate is not supposed to be Mullvad-aware. So at some point this should be
     /// replaced by allowing the table name to be configured from the public API of this crate.
     static ref TABLE_NAME: CString = CString::new("mullvad").unwrap();
+    /// Table that is created and removed again to check that nftables works.
+    static ref PROBE_TABLE_NAME: CString = CString::new("mullvad-probe").unwrap();
     static ref IN_CHAIN_NAME: CString = CString::new("in").unwrap();
     static ref OUT_CHAIN_NAME: CString = CString::new("out").unwrap();
+    static ref PREROUTING_CHAIN_NAME: CString = CString::new("prerouting").unwrap();
 
     /// Allows controlling whether firewall rules should have packet counters or not from an env
     /// variable. Useful for debugging the rules.
@@ -76,7 +82,7 @@ enum End {
     Dst,
 }
 
-/// The Linux implementation for the firewall and DNS.
+/// Firewall backend that enforces policies through nftables.
 pub struct Firewall {
     table_name: CString,
 }
@@ -114,6 +120,32 @@ impl FirewallT for Firewall {
 }
 
 impl Firewall {
+    /// Checks that the host supports nftables by making sure a table can be created. A separate
+    /// table is used and removed afterwards, so our own table and its rules are left untouched.
+    pub fn probe(&self) -> Result<()> {
+        let table = Table::new(&*PROBE_TABLE_NAME, ProtoFamily::Inet);
+        let batch = {
+            let mut batch = Batch::new();
+            batch.add(&table, nftnl::MsgType::Add);
+            batch.finalize()
+        };
+        self.send_and_process(&batch)?;
+        let result = self.verify_tables(&[&PROBE_TABLE_NAME]);
+
+        let batch = {
+            let mut batch = Batch::new();
+            batch.add(&table, nftnl::MsgType::Del);
+            batch.finalize()
+        };
+        if let Err(error) = self.send_and_process(&batch) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove the nftables probe table")
+            );
+        }
+        result
+    }
+
     fn send_and_process(&self, batch: &FinalizedBatch) -> Result<()> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         socket.send_all(batch).map_err(Error::NetlinkSendError)?;
@@ -186,6 +218,7 @@ struct PolicyBatch<'a> {
     batch: Batch,
     in_chain: Chain<'a>,
     out_chain: Chain<'a>,
+    prerouting_chain: Chain<'a>,
 }
 
 impl<'a> PolicyBatch<'a> {
@@ -199,6 +232,9 @@ impl<'a> PolicyBatch<'a> {
         in_chain.set_hook(nftnl::Hook::In, 0);
         out_chain.set_policy(nftnl::Policy::Drop);
         in_chain.set_policy(nftnl::Policy::Drop);
+        // Runs at the priority of the mangle table, before packets are routed.
+        let mut prerouting_chain = Chain::new(&*PREROUTING_CHAIN_NAME, table);
+        prerouting_chain.set_hook(nftnl::Hook::PreRouting, -150);
 
         // A little dance that will make sure the table exists, but is cleared.
         batch.add(table, nftnl::MsgType::Add);
@@ -206,11 +242,13 @@ impl<'a> PolicyBatch<'a> {
         batch.add(table, nftnl::MsgType::Add);
         batch.add(&out_chain, nftnl::MsgType::Add);
         batch.add(&in_chain, nftnl::MsgType::Add);
+        batch.add(&prerouting_chain, nftnl::MsgType::Add);
 
         PolicyBatch {
             batch,
             in_chain,
             out_chain,
+            prerouting_chain,
         }
     }
 
@@ -242,36 +280,36 @@ impl<'a> PolicyBatch<'a> {
         // Outgoing DHCPv4 request
         {
             let mut out_v4 = Rule::new(&self.out_chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut out_v4, Udp, End::Src, firewall::DHCPV4_CLIENT_PORT);
             check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
+            check_port(&mut out_v4, Udp, End::Dst, firewall::DHCPV4_SERVER_PORT);
             add_verdict(&mut out_v4, &Verdict::Accept);
             self.batch.add(&out_v4, nftnl::MsgType::Add);
         }
         // Incoming DHCPv4 response
         {
             let mut in_v4 = Rule::new(&self.in_chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut in_v4, Udp, End::Src, firewall::DHCPV4_SERVER_PORT);
+            check_port(&mut in_v4, Udp, End::Dst, firewall::DHCPV4_CLIENT_PORT);
             add_verdict(&mut in_v4, &Verdict::Accept);
             self.batch.add(&in_v4, nftnl::MsgType::Add);
         }
 
-        for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
+        for dhcpv6_server in &*firewall::DHCPV6_SERVER_ADDRS {
             let mut out_v6 = Rule::new(&self.out_chain);
-            check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
+            check_net(&mut out_v6, End::Src, *firewall::IPV6_LINK_LOCAL);
+            check_port(&mut out_v6, Udp, End::Src, firewall::DHCPV6_CLIENT_PORT);
             check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
-            check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
+            check_port(&mut out_v6, Udp, End::Dst, firewall::DHCPV6_SERVER_PORT);
             add_verdict(&mut out_v6, &Verdict::Accept);
             self.batch.add(&out_v6, nftnl::MsgType::Add);
         }
         {
             let mut in_v6 = Rule::new(&self.in_chain);
-            check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
-            check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
+            check_net(&mut in_v6, End::Src, *firewall::IPV6_LINK_LOCAL);
+            check_port(&mut in_v6, Udp, End::Src, firewall::DHCPV6_SERVER_PORT);
+            check_net(&mut in_v6, End::Dst, *firewall::IPV6_LINK_LOCAL);
+            check_port(&mut in_v6, Udp, End::Dst, firewall::DHCPV6_CLIENT_PORT);
             add_verdict(&mut in_v6, &Verdict::Accept);
             self.batch.add(&in_v6, nftnl::MsgType::Add);
         }
@@ -281,7 +319,7 @@ impl<'a> PolicyBatch<'a> {
             check_ip(
                 &mut rule,
                 End::Dst,
-                *super::ROUTER_SOLICITATION_OUT_DST_ADDR,
+                *firewall::ROUTER_SOLICITATION_OUT_DST_ADDR,
             );
 
             rule.add_expr(&nft_expr!(meta l4proto));
@@ -302,7 +340,7 @@ impl<'a> PolicyBatch<'a> {
         // Incoming Router advertisement (part of NDP)
         {
             let mut rule = Rule::new(&self.in_chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Src, *firewall::IPV6_LINK_LOCAL);
 
             rule.add_expr(&nft_expr!(meta l4proto));
             rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
@@ -322,7 +360,7 @@ impl<'a> PolicyBatch<'a> {
         // Incoming Redirect (part of NDP)
         {
             let mut rule = Rule::new(&self.in_chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Src, *firewall::IPV6_LINK_LOCAL);
 
             rule.add_expr(&nft_expr!(meta l4proto));
             rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
@@ -342,37 +380,114 @@ impl<'a> PolicyBatch<'a> {
     }
 
     fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
-        let allow_lan = match policy {
+        match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 pingable_hosts,
-                allow_lan,
+                allow_ipv6,
+                ..
             } => {
+                let pingable_hosts: Vec<IpAddr> =
+                    firewall::allowed_ips(pingable_hosts, *allow_ipv6).collect();
                 self.add_allow_icmp_pingable_hosts(&pingable_hosts);
                 self.add_allow_endpoint_rules(peer_endpoint);
-                *allow_lan
+                self.add_allow_captive_portal_probe_rules();
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
-                allow_lan,
+                split_dns_servers,
+                allow_gateway_dns,
+                local_resolver_upstream,
+                allow_ipv6,
+                ..
             } => {
                 self.add_allow_endpoint_rules(peer_endpoint);
-                self.add_dns_rule(tunnel, TransportProtocol::Udp)?;
-                self.add_dns_rule(tunnel, TransportProtocol::Tcp)?;
-                self.add_allow_tunnel_rules(tunnel)?;
-                *allow_lan
+                for &protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    self.add_dns_rule(
+                        tunnel,
+                        *allow_gateway_dns,
+                        split_dns_servers,
+                        *allow_ipv6,
+                        protocol,
+                    )?;
+                }
+                self.add_allow_tunnel_rules(tunnel, *allow_ipv6)?;
+                if let Some(upstream) = local_resolver_upstream {
+                    self.add_block_local_resolver_upstream_rules(*upstream);
+                }
+                self.add_allow_captive_portal_probe_rules();
+            }
+            FirewallPolicy::Blocked { .. } => (),
+            FirewallPolicy::CaptivePortal {
+                gateway_networks, ..
+            } => {
+                self.add_captive_portal_rules(gateway_networks);
+                self.add_allow_captive_portal_probe_rules();
             }
-            FirewallPolicy::Blocked { allow_lan } => *allow_lan,
-        };
-
-        if allow_lan {
-            self.add_allow_lan_rules();
         }
+
+        self.add_lan_rules(policy.lan_policy());
         Ok(())
     }
 
     fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        self.add_endpoint_reply_rules(endpoint);
+
+        let mut out_rule = Rule::new(&self.out_chain);
+        check_endpoint(&mut out_rule, End::Dst, endpoint);
+        add_verdict(&mut out_rule, &Verdict::Accept);
+
+        self.batch.add(&out_rule, nftnl::MsgType::Add);
+    }
+
+    /// Allows the captive portal probe to reach its endpoint outside the tunnel. Only packets
+    /// marked with `TUNNEL_FWMARK` are let through, like the probe marks its socket. Not allowed
+    /// by the blocked policy, where the result of the probe isn't acted on.
+    fn add_allow_captive_portal_probe_rules(&mut self) {
+        let endpoint = crate::offline::captive_portal::probe_endpoint();
+        self.add_endpoint_reply_rules(&endpoint);
+
+        let mut out_rule = Rule::new(&self.out_chain);
+        check_endpoint(&mut out_rule, End::Dst, &endpoint);
+        out_rule.add_expr(&nft_expr!(meta mark));
+        out_rule.add_expr(&nft_expr!(cmp == crate::linux::TUNNEL_FWMARK));
+        add_verdict(&mut out_rule, &Verdict::Accept);
+
+        self.batch.add(&out_rule, nftnl::MsgType::Add);
+    }
+
+    fn add_captive_portal_rules(&mut self, gateway_networks: &[IpNetwork]) {
+        for network in gateway_networks {
+            for &(protocol, port) in &firewall::CAPTIVE_PORTAL_PORTS {
+                let mut out_rule = Rule::new(&self.out_chain);
+                check_net(&mut out_rule, End::Dst, *network);
+                check_port(&mut out_rule, protocol, End::Dst, port);
+                add_verdict(&mut out_rule, &Verdict::Accept);
+                self.batch.add(&out_rule, nftnl::MsgType::Add);
+
+                let mut in_rule = Rule::new(&self.in_chain);
+                check_net(&mut in_rule, End::Src, *network);
+                check_port(&mut in_rule, protocol, End::Src, port);
+                in_rule.add_expr(&nft_expr!(ct state));
+                let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
+                in_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+                in_rule.add_expr(&nft_expr!(cmp != 0u32));
+                add_verdict(&mut in_rule, &Verdict::Accept);
+                self.batch.add(&in_rule, nftnl::MsgType::Add);
+            }
+        }
+    }
+
+    fn add_endpoint_reply_rules(&mut self, endpoint: &Endpoint) {
+        // Mark the packets from the endpoint like the packets the tunnel sends to it, so that the
+        // reverse path filter looks them up in the main routing table.
+        let mut mark_rule = Rule::new(&self.prerouting_chain);
+        check_endpoint(&mut mark_rule, End::Src, endpoint);
+        mark_rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FWMARK));
+        mark_rule.add_expr(&nft_expr!(meta mark set));
+        self.batch.add(&mark_rule, nftnl::MsgType::Add);
+
         let mut in_rule = Rule::new(&self.in_chain);
         check_endpoint(&mut in_rule, End::Src, endpoint);
 
@@ -383,13 +498,6 @@ impl<'a> PolicyBatch<'a> {
         add_verdict(&mut in_rule, &Verdict::Accept);
 
         self.batch.add(&in_rule, nftnl::MsgType::Add);
-
-
-        let mut out_rule = Rule::new(&self.out_chain);
-        check_endpoint(&mut out_rule, End::Dst, endpoint);
-        add_verdict(&mut out_rule, &Verdict::Accept);
-
-        self.batch.add(&out_rule, nftnl::MsgType::Add);
     }
 
     fn add_allow_icmp_pingable_hosts(&mut self, pingable_hosts: &[IpAddr]) {
@@ -418,13 +526,26 @@ impl<'a> PolicyBatch<'a> {
     fn add_dns_rule(
         &mut self,
         tunnel: &tunnel::TunnelMetadata,
+        allow_gateway_dns: bool,
+        split_dns_servers: &[IpAddr],
+        allow_ipv6: bool,
         protocol: TransportProtocol,
     ) -> Result<()> {
-        // allow DNS traffic to the tunnel gateway
-        self.add_allow_dns_rule(&tunnel.interface, protocol, tunnel.ipv4_gateway.into())?;
-        if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
-            self.add_allow_dns_rule(&tunnel.interface, protocol, ipv6_gateway.into())?;
-        };
+        // allow DNS traffic to the tunnel gateway, unless DNS is encrypted
+        if allow_gateway_dns {
+            self.add_allow_dns_rule(
+                Some(&tunnel.interface),
+                protocol,
+                tunnel.ipv4_gateway.into(),
+            )?;
+            if let (Some(ipv6_gateway), true) = (tunnel.ipv6_gateway, allow_ipv6) {
+                self.add_allow_dns_rule(Some(&tunnel.interface), protocol, ipv6_gateway.into())?;
+            };
+        }
+        // allow DNS traffic to the servers resolving split DNS domains, outside the tunnel
+        for server in firewall::allowed_ips(split_dns_servers, allow_ipv6) {
+            self.add_allow_dns_rule(None, protocol, server)?;
+        }
         let mut block_rule = Rule::new(&self.out_chain);
         check_port(&mut block_rule, protocol, End::Dst, 53);
         add_verdict(&mut block_rule, &Verdict::Drop);
@@ -435,7 +556,7 @@ impl<'a> PolicyBatch<'a> {
 
     fn add_allow_dns_rule(
         &mut self,
-        interface: &str,
+        interface: Option<&str>,
         protocol: TransportProtocol,
         host: IpAddr,
     ) -> Result<()> {
@@ -445,7 +566,9 @@ impl<'a> PolicyBatch<'a> {
             IpAddr::V6(_) => nft_expr!(payload ipv6 daddr),
         };
 
-        check_iface(&mut allow_rule, Direction::Out, interface)?;
+        if let Some(interface) = interface {
+            check_iface(&mut allow_rule, Direction::Out, interface)?;
+        }
         check_port(&mut allow_rule, protocol, End::Dst, 53);
         check_l3proto(&mut allow_rule, host);
 
@@ -457,39 +580,67 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
-    fn add_allow_tunnel_rules(&mut self, tunnel: &tunnel::TunnelMetadata) -> Result<()> {
-        self.batch.add(
-            &allow_interface_rule(&self.out_chain, Direction::Out, &tunnel.interface[..])?,
-            nftnl::MsgType::Add,
-        );
-        self.batch.add(
-            &allow_interface_rule(&self.in_chain, Direction::In, &tunnel.interface[..])?,
-            nftnl::MsgType::Add,
-        );
+    fn add_allow_tunnel_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        allow_ipv6: bool,
+    ) -> Result<()> {
+        for &(chain, direction) in &[
+            (&self.out_chain, Direction::Out),
+            (&self.in_chain, Direction::In),
+        ] {
+            let mut rule = Rule::new(chain);
+            check_iface(&mut rule, direction, &tunnel.interface[..])?;
+            if !allow_ipv6 {
+                rule.add_expr(&nft_expr!(meta nfproto));
+                rule.add_expr(&nft_expr!(cmp == libc::NFPROTO_IPV4 as u8));
+            }
+            add_verdict(&mut rule, &Verdict::Accept);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
         Ok(())
     }
 
-    fn add_allow_lan_rules(&mut self) {
+    /// Drops the queries of the local DNS resolver that would not go through the tunnel. Has to
+    /// come after the tunnel rules, and before the LAN rules that could otherwise let them out.
+    fn add_block_local_resolver_upstream_rules(&mut self, upstream: SocketAddr) {
+        for &protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+            let mut rule = Rule::new(&self.out_chain);
+            check_endpoint(
+                &mut rule,
+                End::Dst,
+                &Endpoint::new(upstream.ip(), upstream.port(), protocol),
+            );
+            add_verdict(&mut rule, &Verdict::Drop);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+    }
+
+    fn add_lan_rules(&mut self, lan_policy: &LanPolicy) {
         // LAN -> LAN
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in firewall::allowed_lan_nets(lan_policy) {
             let mut out_rule = Rule::new(&self.out_chain);
-            check_net(&mut out_rule, End::Dst, *net);
+            check_net(&mut out_rule, End::Dst, net);
             add_verdict(&mut out_rule, &Verdict::Accept);
             self.batch.add(&out_rule, nftnl::MsgType::Add);
 
             let mut in_rule = Rule::new(&self.in_chain);
-            check_net(&mut in_rule, End::Src, *net);
+            check_net(&mut in_rule, End::Src, net);
             add_verdict(&mut in_rule, &Verdict::Accept);
             self.batch.add(&in_rule, nftnl::MsgType::Add);
         }
         // LAN -> Multicast
-        for net in &*super::ALLOWED_LAN_MULTICAST_NETS {
-            let mut rule = Rule::new(&self.out_chain);
-            check_net(&mut rule, End::Dst, *net);
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+        if lan_policy.allow_multicast {
+            for net in &*firewall::ALLOWED_LAN_MULTICAST_NETS {
+                let mut rule = Rule::new(&self.out_chain);
+                check_net(&mut rule, End::Dst, *net);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+        if lan_policy.allow_dhcp_server {
+            self.add_dhcp_server_rules();
         }
-        self.add_dhcp_server_rules();
     }
 
     fn add_dhcp_server_rules(&mut self) {
@@ -497,19 +648,19 @@ impl<'a> PolicyBatch<'a> {
         // Outgoing DHCPv4 response
         {
             let mut out_v4 = Rule::new(&self.out_chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut out_v4, Udp, End::Src, firewall::DHCPV4_SERVER_PORT);
+            check_port(&mut out_v4, Udp, End::Dst, firewall::DHCPV4_CLIENT_PORT);
             add_verdict(&mut out_v4, &Verdict::Accept);
             self.batch.add(&out_v4, nftnl::MsgType::Add);
         }
         // Incoming DHCPv4 request
         {
             let mut in_v4 = Rule::new(&self.in_chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut in_v4, Udp, End::Src, firewall::DHCPV4_CLIENT_PORT);
             check_endpoint(
                 &mut in_v4,
                 End::Dst,
-                &Endpoint::new(Ipv4Addr::BROADCAST, super::DHCPV4_SERVER_PORT, Udp),
+                &Endpoint::new(Ipv4Addr::BROADCAST, firewall::DHCPV4_SERVER_PORT, Udp),
             );
             add_verdict(&mut in_v4, &Verdict::Accept);
             self.batch.add(&in_v4, nftnl::MsgType::Add);
@@ -623,3 +774,104 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    const IPV6_GATEWAY: Ipv6Addr = Ipv6Addr::new(0xfdda, 0xd0d0, 0xcafe, 0x1194, 0, 0, 0, 1);
+    const IPV6_DNS_SERVER: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+
+    fn connected_policy(allow_ipv6: bool) -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 1194, TransportProtocol::Udp),
+            tunnel: tunnel::TunnelMetadata {
+                // The interface index is looked up, so the interface has to exist.
+                interface: "lo".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 8, 0, 1),
+                ipv6_gateway: Some(IPV6_GATEWAY),
+                wireguard_backend: None,
+            },
+            lan_policy: LanPolicy::block_all(),
+            split_dns_servers: vec![IpAddr::V6(IPV6_DNS_SERVER)],
+            allow_gateway_dns: true,
+            local_resolver_upstream: None,
+            allow_ipv6,
+        }
+    }
+
+    /// Returns all netlink messages in the batch that finalizes `policy`, concatenated.
+    fn policy_batch_bytes(policy: &FirewallPolicy) -> Vec<u8> {
+        let table = Table::new(&*TABLE_NAME, ProtoFamily::Inet);
+        let batch = PolicyBatch::new(&table).finalize(policy).unwrap();
+        (&batch).into_iter().flatten().cloned().collect()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_connected_policy_with_ipv6() {
+        let bytes = policy_batch_bytes(&connected_policy(true));
+
+        assert!(contains(&bytes, &IPV6_GATEWAY.octets()));
+        assert!(contains(&bytes, &IPV6_DNS_SERVER.octets()));
+    }
+
+    #[test]
+    fn test_connected_policy_without_ipv6() {
+        let bytes = policy_batch_bytes(&connected_policy(false));
+
+        assert!(contains(&bytes, &Ipv4Addr::new(10, 8, 0, 1).octets()));
+        assert!(!contains(&bytes, &IPV6_GATEWAY.octets()));
+        assert!(!contains(&bytes, &IPV6_DNS_SERVER.octets()));
+    }
+
+    #[test]
+    fn test_connected_policy_blocks_local_resolver_upstream_outside_tunnel() {
+        let upstream = Ipv4Addr::new(192, 168, 1, 53);
+        let lan_network = Ipv4Addr::new(192, 168, 1, 0);
+        let mut policy = connected_policy(true);
+        if let FirewallPolicy::Connected {
+            ref mut lan_policy,
+            ref mut local_resolver_upstream,
+            ..
+        } = policy
+        {
+            lan_policy.allowed_networks = vec![IpNetwork::new(lan_network.into(), 24).unwrap()];
+            *local_resolver_upstream = Some(SocketAddr::new(upstream.into(), 853));
+        }
+        let bytes = policy_batch_bytes(&policy);
+
+        let position = |needle: &[u8]| {
+            bytes
+                .windows(needle.len())
+                .position(|window| window == needle)
+                .unwrap()
+        };
+        assert!(contains(&bytes, &853u16.to_be_bytes()));
+        assert!(position(&upstream.octets()) < position(&lan_network.octets()));
+    }
+
+    #[test]
+    fn test_connecting_policy_without_ipv6() {
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 1194, TransportProtocol::Udp),
+            pingable_hosts: vec![
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+                IpAddr::V6(IPV6_GATEWAY),
+            ],
+            lan_policy: LanPolicy::block_all(),
+            allow_ipv6: false,
+        };
+        let bytes = policy_batch_bytes(&policy);
+
+        assert!(contains(&bytes, &Ipv4Addr::new(10, 64, 0, 1).octets()));
+        assert!(!contains(&bytes, &IPV6_GATEWAY.octets()));
+    }
+}
@@ -3,9 +3,9 @@ use ipnetwork::IpNetwork;
 use pfctl::FilterRuleAction;
 use std::{
     env,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
 };
-use talpid_types::net;
+use talpid_types::net::{self, lan::LanPolicy};
 
 pub use pfctl::Error;
 
@@ -19,7 +19,7 @@ const ANCHOR_NAME: &'static str = "mullvad";
 pub struct Firewall {
     pf: pfctl::PfCtl,
     pf_was_enabled: Option<bool>,
-    rule_logging: RuleLogging,
+    policy_rules: PolicyRules,
 }
 
 impl FirewallT for Firewall {
@@ -40,7 +40,7 @@ impl FirewallT for Firewall {
         Ok(Firewall {
             pf: pfctl::PfCtl::new()?,
             pf_was_enabled: None,
-            rule_logging,
+            policy_rules: PolicyRules { rule_logging },
         })
     }
 
@@ -64,84 +64,140 @@ impl FirewallT for Firewall {
 
 impl Firewall {
     fn set_rules(&mut self, policy: FirewallPolicy) -> Result<()> {
-        let mut new_filter_rules = vec![];
+        let new_filter_rules = self.policy_rules.get_rules(policy)?;
+
+        let mut anchor_change = pfctl::AnchorChange::new();
+        anchor_change.set_filter_rules(new_filter_rules);
+        Ok(self.pf.set_rules(ANCHOR_NAME, anchor_change)?)
+    }
 
-        new_filter_rules.append(&mut self.get_allow_loopback_rules()?);
-        new_filter_rules.append(&mut self.get_allow_dhcp_client_rules()?);
-        new_filter_rules.append(&mut self.get_policy_specific_rules(policy)?);
+    fn remove_rules(&mut self) -> Result<()> {
+        // remove_anchor() does not deactivate active rules
+        self.pf
+            .flush_rules(ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
+        Ok(())
+    }
+
+    fn enable(&mut self) -> Result<()> {
+        if self.pf_was_enabled.is_none() {
+            self.pf_was_enabled = Some(self.pf.is_enabled()?);
+        }
+        Ok(self.pf.try_enable()?)
+    }
+
+    fn restore_state(&mut self) -> Result<()> {
+        match self.pf_was_enabled.take() {
+            Some(true) => Ok(self.pf.try_enable()?),
+            Some(false) => Ok(self.pf.try_disable()?),
+            None => Ok(()),
+        }
+    }
+
+    fn add_anchor(&mut self) -> Result<()> {
+        self.pf
+            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
+        self.pf
+            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
+        Ok(())
+    }
+
+    fn remove_anchor(&mut self) -> Result<()> {
+        self.pf
+            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
+        self.pf
+            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
+        Ok(())
+    }
+}
+
+/// Creates the filter rules that enforce a policy.
+struct PolicyRules {
+    rule_logging: RuleLogging,
+}
+
+impl PolicyRules {
+    fn get_rules(&self, policy: FirewallPolicy) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+
+        rules.append(&mut self.get_allow_loopback_rules()?);
+        rules.append(&mut self.get_allow_dhcp_client_rules()?);
+        rules.append(&mut self.get_policy_specific_rules(policy)?);
 
         let drop_all_rule = self
             .create_rule_builder(FilterRuleAction::Drop)
             .quick(true)
             .build()?;
-        new_filter_rules.push(drop_all_rule);
+        rules.push(drop_all_rule);
 
-        let mut anchor_change = pfctl::AnchorChange::new();
-        anchor_change.set_filter_rules(new_filter_rules);
-        Ok(self.pf.set_rules(ANCHOR_NAME, anchor_change)?)
+        Ok(rules)
     }
 
-    fn get_policy_specific_rules(
-        &mut self,
-        policy: FirewallPolicy,
-    ) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_policy_specific_rules(&self, policy: FirewallPolicy) -> Result<Vec<pfctl::FilterRule>> {
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
-                allow_lan,
+                lan_policy,
                 pingable_hosts,
+                allow_ipv6,
             } => {
+                let pingable_hosts: Vec<IpAddr> =
+                    super::allowed_ips(&pingable_hosts, allow_ipv6).collect();
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
                 rules.extend(self.get_allow_pingable_hosts(&pingable_hosts)?);
-                if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
-                }
+                rules.append(&mut self.get_lan_rules(&lan_policy)?);
                 Ok(rules)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
-                allow_lan,
+                lan_policy,
+                allow_gateway_dns,
+                local_resolver_upstream,
+                allow_ipv6,
+                ..
             } => {
                 let mut rules = vec![];
-                let allow_tcp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Tcp)
-                    .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
-                    .build()?;
-                rules.push(allow_tcp_dns_to_relay_rule);
-                let allow_udp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Udp)
-                    .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
-                    .build()?;
-                rules.push(allow_udp_dns_to_relay_rule);
-
-                if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
-                    let v6_dns_rule_tcp = self
+                // Plain DNS to the relay is blocked when DNS is encrypted
+                if allow_gateway_dns {
+                    let allow_tcp_dns_to_relay_rule = self
                         .create_rule_builder(FilterRuleAction::Pass)
                         .direction(pfctl::Direction::Out)
                         .quick(true)
                         .interface(&tunnel.interface)
                         .proto(pfctl::Proto::Tcp)
-                        .to(pfctl::Endpoint::new(ipv6_gateway, 53))
+                        .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
                         .build()?;
-                    rules.push(v6_dns_rule_tcp);
-                    let v6_dns_rule_udp = self
+                    rules.push(allow_tcp_dns_to_relay_rule);
+                    let allow_udp_dns_to_relay_rule = self
                         .create_rule_builder(FilterRuleAction::Pass)
                         .direction(pfctl::Direction::Out)
                         .quick(true)
                         .interface(&tunnel.interface)
                         .proto(pfctl::Proto::Udp)
-                        .to(pfctl::Endpoint::new(ipv6_gateway, 53))
+                        .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
                         .build()?;
-                    rules.push(v6_dns_rule_udp);
+                    rules.push(allow_udp_dns_to_relay_rule);
+
+                    if let (Some(ipv6_gateway), true) = (tunnel.ipv6_gateway, allow_ipv6) {
+                        let v6_dns_rule_tcp = self
+                            .create_rule_builder(FilterRuleAction::Pass)
+                            .direction(pfctl::Direction::Out)
+                            .quick(true)
+                            .interface(&tunnel.interface)
+                            .proto(pfctl::Proto::Tcp)
+                            .to(pfctl::Endpoint::new(ipv6_gateway, 53))
+                            .build()?;
+                        rules.push(v6_dns_rule_tcp);
+                        let v6_dns_rule_udp = self
+                            .create_rule_builder(FilterRuleAction::Pass)
+                            .direction(pfctl::Direction::Out)
+                            .quick(true)
+                            .interface(&tunnel.interface)
+                            .proto(pfctl::Proto::Udp)
+                            .to(pfctl::Endpoint::new(ipv6_gateway, 53))
+                            .build()?;
+                        rules.push(v6_dns_rule_udp);
+                    }
                 }
 
                 let block_tcp_dns_rule = self
@@ -162,24 +218,47 @@ impl Firewall {
 
                 rules.push(block_udp_dns_rule);
                 rules.push(self.get_allow_relay_rule(peer_endpoint)?);
-                rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
-
-                if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str(), allow_ipv6)?);
+                if let Some(upstream) = local_resolver_upstream {
+                    rules.append(&mut self.get_block_local_resolver_upstream_rules(upstream)?);
                 }
+                rules.append(&mut self.get_lan_rules(&lan_policy)?);
 
                 Ok(rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
-                let mut rules = Vec::new();
-                if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
-                }
+            FirewallPolicy::Blocked { lan_policy } => self.get_lan_rules(&lan_policy),
+            FirewallPolicy::CaptivePortal {
+                gateway_networks,
+                lan_policy,
+            } => {
+                let mut rules = self.get_captive_portal_rules(&gateway_networks)?;
+                rules.append(&mut self.get_lan_rules(&lan_policy)?);
                 Ok(rules)
             }
         }
     }
 
+    fn get_captive_portal_rules(
+        &self,
+        gateway_networks: &[IpNetwork],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for network in gateway_networks {
+            for &(protocol, port) in &super::CAPTIVE_PORTAL_PORTS {
+                let rule = self
+                    .create_rule_builder(FilterRuleAction::Pass)
+                    .quick(true)
+                    .direction(pfctl::Direction::Out)
+                    .proto(as_pfctl_proto(protocol))
+                    .to(pfctl::Endpoint::new(pfctl::Ip::from(*network), port))
+                    .keep_state(pfctl::StatePolicy::Keep)
+                    .build()?;
+                rules.push(rule);
+            }
+        }
+        Ok(rules)
+    }
+
     fn get_allow_relay_rule(&self, relay_endpoint: net::Endpoint) -> Result<pfctl::FilterRule> {
         let pfctl_proto = as_pfctl_proto(relay_endpoint.protocol);
 
@@ -228,14 +307,41 @@ impl Firewall {
         Ok(rules)
     }
 
-    fn get_allow_tunnel_rule(&self, tunnel_interface: &str) -> Result<pfctl::FilterRule> {
-        Ok(self
-            .create_rule_builder(FilterRuleAction::Pass)
+    fn get_allow_tunnel_rule(
+        &self,
+        tunnel_interface: &str,
+        allow_ipv6: bool,
+    ) -> Result<pfctl::FilterRule> {
+        let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+        rule_builder
             .quick(true)
             .interface(tunnel_interface)
             .keep_state(pfctl::StatePolicy::Keep)
-            .tcp_flags(Self::get_tcp_flags())
-            .build()?)
+            .tcp_flags(Self::get_tcp_flags());
+        if !allow_ipv6 {
+            rule_builder.af(pfctl::AddrFamily::Ipv4);
+        }
+        Ok(rule_builder.build()?)
+    }
+
+    /// Drops the queries of the local DNS resolver that would not go through the tunnel. Has to
+    /// come after the tunnel rule, and before the LAN rules that could otherwise let them out.
+    fn get_block_local_resolver_upstream_rules(
+        &self,
+        upstream: SocketAddr,
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for &protocol in &[pfctl::Proto::Tcp, pfctl::Proto::Udp] {
+            let rule = self
+                .create_rule_builder(FilterRuleAction::Drop)
+                .direction(pfctl::Direction::Out)
+                .quick(true)
+                .proto(protocol)
+                .to(upstream)
+                .build()?;
+            rules.push(rule);
+        }
+        Ok(rules)
     }
 
     fn get_allow_loopback_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
@@ -248,34 +354,43 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
-    fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_lan_rules(&self, lan_policy: &LanPolicy) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in super::allowed_lan_nets(lan_policy) {
             let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
             rule_builder.quick(true);
             let allow_out = rule_builder
                 .direction(pfctl::Direction::Out)
                 .from(pfctl::Ip::Any)
-                .to(pfctl::Ip::from(*net))
+                .to(pfctl::Ip::from(net))
                 .build()?;
             let allow_in = rule_builder
                 .direction(pfctl::Direction::In)
-                .from(pfctl::Ip::from(*net))
+                .from(pfctl::Ip::from(net))
                 .to(pfctl::Ip::Any)
                 .build()?;
             rules.push(allow_out);
             rules.push(allow_in);
         }
-        for multicast_net in &*super::ALLOWED_LAN_MULTICAST_NETS {
-            let allow_multicast_out = self
-                .create_rule_builder(FilterRuleAction::Pass)
-                .quick(true)
-                .direction(pfctl::Direction::Out)
-                .to(pfctl::Ip::from(*multicast_net))
-                .build()?;
-            rules.push(allow_multicast_out);
+        if lan_policy.allow_multicast {
+            for multicast_net in &*super::ALLOWED_LAN_MULTICAST_NETS {
+                let allow_multicast_out = self
+                    .create_rule_builder(FilterRuleAction::Pass)
+                    .quick(true)
+                    .direction(pfctl::Direction::Out)
+                    .to(pfctl::Ip::from(*multicast_net))
+                    .build()?;
+                rules.push(allow_multicast_out);
+            }
+        }
+        if lan_policy.allow_dhcp_server {
+            rules.append(&mut self.get_dhcp_server_rules()?);
         }
 
+        Ok(rules)
+    }
+
+    fn get_dhcp_server_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let dhcpv4_out = self
             .create_rule_builder(FilterRuleAction::Pass)
             .quick(true)
@@ -294,10 +409,7 @@ impl Firewall {
                 pfctl::Port::from(super::DHCPV4_SERVER_PORT),
             ))
             .build()?;
-        rules.push(dhcpv4_out);
-        rules.push(dhcpv4_in);
-
-        Ok(rules)
+        Ok(vec![dhcpv4_out, dhcpv4_in])
     }
 
     fn get_allow_dhcp_client_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
@@ -393,44 +505,6 @@ impl Firewall {
             &[pfctl::TcpFlag::Syn, pfctl::TcpFlag::Ack],
         )
     }
-
-    fn remove_rules(&mut self) -> Result<()> {
-        // remove_anchor() does not deactivate active rules
-        self.pf
-            .flush_rules(ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
-        Ok(())
-    }
-
-    fn enable(&mut self) -> Result<()> {
-        if self.pf_was_enabled.is_none() {
-            self.pf_was_enabled = Some(self.pf.is_enabled()?);
-        }
-        Ok(self.pf.try_enable()?)
-    }
-
-    fn restore_state(&mut self) -> Result<()> {
-        match self.pf_was_enabled.take() {
-            Some(true) => Ok(self.pf.try_enable()?),
-            Some(false) => Ok(self.pf.try_disable()?),
-            None => Ok(()),
-        }
-    }
-
-    fn add_anchor(&mut self) -> Result<()> {
-        self.pf
-            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
-        self.pf
-            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
-        Ok(())
-    }
-
-    fn remove_anchor(&mut self) -> Result<()> {
-        self.pf
-            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
-        self.pf
-            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
-        Ok(())
-    }
 }
 
 fn as_pfctl_proto(protocol: net::TransportProtocol) -> pfctl::Proto {
@@ -447,3 +521,125 @@ enum RuleLogging {
     Drop,
     All,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tunnel;
+    use std::net::Ipv6Addr;
+
+    const IPV6_GATEWAY: &str = "fdda:d0d0:cafe:1194::1";
+
+    fn policy_rules() -> PolicyRules {
+        PolicyRules {
+            rule_logging: RuleLogging::None,
+        }
+    }
+
+    fn connected_policy(allow_ipv6: bool) -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: net::Endpoint::new(
+                Ipv4Addr::new(1, 2, 3, 4),
+                1194,
+                net::TransportProtocol::Udp,
+            ),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "utun3".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 8, 0, 1),
+                ipv6_gateway: Some(IPV6_GATEWAY.parse::<Ipv6Addr>().unwrap()),
+                wireguard_backend: None,
+            },
+            lan_policy: LanPolicy::block_all(),
+            split_dns_servers: vec![],
+            allow_gateway_dns: true,
+            local_resolver_upstream: None,
+            allow_ipv6,
+        }
+    }
+
+    /// The rules are compared through their debug representation, since pfctl does not expose
+    /// their fields.
+    fn describe(rules: &[pfctl::FilterRule]) -> Vec<String> {
+        rules.iter().map(|rule| format!("{:?}", rule)).collect()
+    }
+
+    /// Returns the rule that passes everything on the tunnel interface, as opposed to the rules
+    /// that pass DNS to the gateways on it.
+    fn tunnel_rule(rules: &[String]) -> &String {
+        let mut tunnel_rules = rules
+            .iter()
+            .filter(|rule| rule.contains("\"utun3\"") && rule.contains("proto: Any"));
+        let rule = tunnel_rules.next().expect("No tunnel rule");
+        assert!(tunnel_rules.next().is_none());
+        rule
+    }
+
+    #[test]
+    fn test_connected_policy_with_ipv6() {
+        let rules = describe(&policy_rules().get_rules(connected_policy(true)).unwrap());
+
+        assert!(tunnel_rule(&rules).contains("af: Any"));
+        assert!(rules.iter().any(|rule| rule.contains(IPV6_GATEWAY)));
+    }
+
+    #[test]
+    fn test_connected_policy_without_ipv6() {
+        let rules = describe(&policy_rules().get_rules(connected_policy(false)).unwrap());
+
+        assert!(tunnel_rule(&rules).contains("af: Ipv4"));
+        assert!(rules.iter().any(|rule| rule.contains("10.8.0.1")));
+        assert!(!rules.iter().any(|rule| rule.contains(IPV6_GATEWAY)));
+    }
+
+    #[test]
+    fn test_connected_policy_blocks_local_resolver_upstream_outside_tunnel() {
+        let mut policy = connected_policy(true);
+        if let FirewallPolicy::Connected {
+            ref mut lan_policy,
+            ref mut local_resolver_upstream,
+            ..
+        } = policy
+        {
+            lan_policy.allowed_networks = vec!["192.168.1.0/24".parse().unwrap()];
+            *local_resolver_upstream = Some("192.168.1.53:853".parse().unwrap());
+        }
+        let rules = describe(&policy_rules().get_rules(policy).unwrap());
+
+        let block_upstream = rules
+            .iter()
+            .position(|rule| rule.contains("192.168.1.53") && rule.contains("Drop"))
+            .expect("No rule for the upstream");
+        let tunnel = rules
+            .iter()
+            .position(|rule| rule == tunnel_rule(&rules))
+            .unwrap();
+        let allow_lan = rules
+            .iter()
+            .position(|rule| rule.contains("192.168.1.0"))
+            .unwrap();
+        assert!(tunnel < block_upstream);
+        assert!(block_upstream < allow_lan);
+    }
+
+    #[test]
+    fn test_connecting_policy_without_ipv6() {
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint: net::Endpoint::new(
+                Ipv4Addr::new(1, 2, 3, 4),
+                1194,
+                net::TransportProtocol::Udp,
+            ),
+            pingable_hosts: vec![
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+                IpAddr::V6(IPV6_GATEWAY.parse().unwrap()),
+            ],
+            lan_policy: LanPolicy::block_all(),
+            allow_ipv6: false,
+        };
+        let rules = describe(&policy_rules().get_rules(policy).unwrap());
+
+        assert!(rules.iter().any(|rule| rule.contains("10.64.0.1")));
+        assert!(!rules.iter().any(|rule| rule.contains(IPV6_GATEWAY)));
+    }
+}
@@ -1,13 +1,17 @@
+use crate::journal::{JournalFirewallPolicy, SystemStateJournal};
+use ipnetwork::IpNetwork;
 #[cfg(unix)]
-use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use ipnetwork::{Ipv4Network, Ipv6Network};
 #[cfg(unix)]
 use lazy_static::lazy_static;
 use std::fmt;
-#[cfg(windows)]
-use std::net::IpAddr;
 #[cfg(unix)]
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use talpid_types::net::Endpoint;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(windows)]
+use std::net::{IpAddr, SocketAddr};
+#[cfg(all(unix, not(target_os = "android")))]
+use talpid_types::net::TransportProtocol;
+use talpid_types::net::{lan::LanPolicy, Endpoint};
 
 
 #[cfg(target_os = "macos")]
@@ -15,7 +19,7 @@ use talpid_types::net::Endpoint;
 mod imp;
 
 #[cfg(target_os = "linux")]
-#[path = "linux.rs"]
+#[path = "linux/mod.rs"]
 mod imp;
 
 #[cfg(windows)]
@@ -30,15 +34,7 @@ pub use self::imp::Error;
 
 #[cfg(unix)]
 lazy_static! {
-    /// When "allow local network" is enabled the app will allow traffic to and from these networks.
-    static ref ALLOWED_LAN_NETS: [IpNetwork; 5] = [
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()),
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(172, 16, 0, 0), 12).unwrap()),
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 0, 0), 16).unwrap()),
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(169, 254, 0, 0), 16).unwrap()),
-        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
-    ];
-    /// When "allow local network" is enabled the app will allow traffic to these networks.
+    /// When the LAN policy allows multicast the app will allow traffic to these networks.
     static ref ALLOWED_LAN_MULTICAST_NETS: [IpNetwork; 5] = [
         // Local subnetwork multicast. Not routable
         IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(224, 0, 0, 0), 24).unwrap()),
@@ -67,6 +63,34 @@ const DHCPV4_CLIENT_PORT: u16 = 68;
 const DHCPV6_SERVER_PORT: u16 = 547;
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV6_CLIENT_PORT: u16 = 546;
+/// The destinations that the `CaptivePortal` policy allows traffic to on the gateway networks.
+/// HTTP and HTTPS for the login page, and DNS to resolve its address.
+#[cfg(all(unix, not(target_os = "android")))]
+const CAPTIVE_PORTAL_PORTS: [(TransportProtocol, u16); 4] = [
+    (TransportProtocol::Tcp, 80),
+    (TransportProtocol::Tcp, 443),
+    (TransportProtocol::Udp, 53),
+    (TransportProtocol::Tcp, 53),
+];
+
+/// Returns all networks that traffic should be allowed to, and from, under the given LAN policy.
+#[cfg(not(target_os = "android"))]
+fn allowed_lan_nets(lan_policy: &LanPolicy) -> Vec<IpNetwork> {
+    let mut nets = lan_policy.allowed_networks.clone();
+    if lan_policy.allow_link_local {
+        nets.extend(talpid_types::net::lan::link_local_networks().iter());
+    }
+    nets
+}
+
+/// Returns the IPs among `ips` that traffic may be allowed to. Those are only the IPv4 addresses,
+/// unless `allow_ipv6` is set.
+#[cfg(all(unix, not(target_os = "android")))]
+fn allowed_ips<'a>(ips: &'a [IpAddr], allow_ipv6: bool) -> impl Iterator<Item = IpAddr> + 'a {
+    ips.iter()
+        .cloned()
+        .filter(move |ip| allow_ipv6 || ip.is_ipv4())
+}
 
 
 /// A enum that describes network security strategy
@@ -86,25 +110,46 @@ const DHCPV6_CLIENT_PORT: u16 = 546;
 /// 3. Router solicitation, advertisement and redirects (subset of NDP):
 ///    * Outgoing to ROUTER_SOLICITATION_OUT_DST_ADDR, but only ICMPv6 with type 133 and code 0.
 ///    * Incoming from IPV6_LINK_LOCAL, but only ICMPv6 type 134 or 137 and code 0.
-/// 4. If `allow_lan` is enabled, all policies should allow the following traffic:
-///    * Outgoing to, and incoming from, any IP in the networks listed in ALLOWED_LAN_NETS
-///    * Outgoing to any IP in the networks listed in ALLOWED_LAN_MULTICAST_NETS
-///    * Incoming DHCPv4 requests and outgoing responses (be a DHCPv4 server):
+/// 4. Depending on `lan_policy`, all policies should allow the following traffic:
+///    * Outgoing to, and incoming from, any IP in `lan_policy.allowed_networks`
+///    * If `allow_link_local` is set, outgoing to, and incoming from, any IP in the link-local
+///      networks
+///    * If `allow_multicast` is set, outgoing to any IP in the networks listed in
+///      ALLOWED_LAN_MULTICAST_NETS
+///    * If `allow_dhcp_server` is set, incoming DHCPv4 requests and outgoing responses (be a DHCPv4
+///      server):
 ///      * Incoming from *:DHCPV4_CLIENT_PORT to 255.255.255.255:DHCPV4_SERVER_PORT
 ///      * Outgoing from *:DHCPV4_SERVER_PORT to *:DHCPV4_CLIENT_PORT
 ///
 /// ## Policy specific rules
 ///
 /// 1. In the `Connecting` and `Connected` policies traffic should be allowed to and from the IP and
-///    port in `peer_endpoint`
+///    port in `peer_endpoint`. On Linux, incoming packets from `peer_endpoint` should also be given
+///    the firewall mark of the tunnel before they are routed, so that policy based routing treats
+///    them like the packets sent to the endpoint.
 /// 2. In the `Connecting` policy, ICMP packets should be allowed to and from all IPs in
 ///    `pingable_hosts`.
 /// 3. In the `Connected` policy, DNS requests (destination port 53 on both UDP and TCP) should be
 ///    allowed over the tunnel interface in `tunnel.interface` and to the IPs `tunnel.ipv4_gateway`
-///    and `tunnel.ipv6_gateway`. But blocked to all other destinations and over all other
-///    interfaces.
+///    and `tunnel.ipv6_gateway`, as well as to the IPs in `split_dns_servers` over any interface.
+///    But blocked to all other destinations and over all other interfaces. This keeps the queries
+///    that the local DNS resolver forwards to the gateway inside the tunnel. The tunnel gateways
+///    are only allowed if `allow_gateway_dns` is set, which it isn't when DNS is encrypted.
+///    Encrypted DNS doesn't use port 53 and is covered by the next rule. DNS requests to the local
+///    DNS resolver on the loopback interface must still be allowed, when `local_resolver_upstream`
+///    is set.
 /// 4. In the `Connected` policy, all traffic should be allowed over the tunnel interface in
 ///    `tunnel.interface`, minus the DNS packets described above.
+/// 5. In the `CaptivePortal` policy, HTTP and HTTPS (destination port 80 and 443 on TCP) and DNS
+///    (destination port 53 on both UDP and TCP) should be allowed to any IP in `gateway_networks`,
+///    as well as the responses to them. Everything else is blocked like in the `Blocked` policy.
+/// 6. If `allow_ipv6` is not set in the `Connecting` and `Connected` policies, the rules above
+///    should not allow any IPv6 traffic, except to and from `peer_endpoint`. This includes IPv6
+///    over the tunnel interface, to the IPv6 tunnel gateway and to IPv6 split DNS servers. IPv6 is
+///    then only allowed by the rules that all policies share.
+/// 7. In the `Connected` policy, if `local_resolver_upstream` is set, UDP and TCP traffic to that
+///    IP and port should only be allowed over the tunnel interface. It should be blocked over all
+///    other interfaces, even if the address is on a LAN network that `lan_policy` allows.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum FirewallPolicy {
     /// Allow traffic only to server
@@ -113,8 +158,10 @@ pub enum FirewallPolicy {
         peer_endpoint: Endpoint,
         /// Hosts that should be pingable whilst connecting.
         pingable_hosts: Vec<IpAddr>,
-        /// Flag setting if communication with LAN networks should be possible.
-        allow_lan: bool,
+        /// Which communication with LAN networks should be possible.
+        lan_policy: LanPolicy,
+        /// If IPv6 should be allowed, or only IPv4 besides the peer endpoint.
+        allow_ipv6: bool,
     },
 
     /// Allow traffic only to server and over tunnel interface
@@ -123,14 +170,30 @@ pub enum FirewallPolicy {
         peer_endpoint: Endpoint,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: crate::tunnel::TunnelMetadata,
-        /// Flag setting if communication with LAN networks should be possible.
-        allow_lan: bool,
+        /// Which communication with LAN networks should be possible.
+        lan_policy: LanPolicy,
+        /// DNS servers outside the tunnel that resolve the split DNS domains.
+        split_dns_servers: Vec<IpAddr>,
+        /// If plain DNS to the tunnel gateways should be allowed.
+        allow_gateway_dns: bool,
+        /// The server the local DNS resolver forwards queries to, if the resolver is running.
+        local_resolver_upstream: Option<SocketAddr>,
+        /// If IPv6 should be allowed, or only IPv4 besides the peer endpoint.
+        allow_ipv6: bool,
     },
 
     /// Block all network traffic in and out from the computer.
     Blocked {
-        /// Flag setting if communication with LAN networks should be possible.
-        allow_lan: bool,
+        /// Which communication with LAN networks should be possible.
+        lan_policy: LanPolicy,
+    },
+
+    /// Block all network traffic, except what is needed to log in through a captive portal
+    CaptivePortal {
+        /// The networks of the default gateways, where the captive portal is served from.
+        gateway_networks: Vec<IpNetwork>,
+        /// Which communication with LAN networks should be possible.
+        lan_policy: LanPolicy,
     },
 }
 
@@ -140,25 +203,32 @@ impl fmt::Display for FirewallPolicy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 pingable_hosts,
-                allow_lan,
+                lan_policy,
+                allow_ipv6,
             } => write!(
                 f,
-                "Connecting to {} with gateways {}, {} LAN",
+                "Connecting to {} with gateways {} (IPv6: {}), {}",
                 peer_endpoint,
                 pingable_hosts
                     .iter()
                     .map(ToString::to_string)
                     .collect::<Vec<String>>()
                     .join(","),
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                allow_ipv6,
+                lan_policy
             ),
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
-                allow_lan,
+                lan_policy,
+                split_dns_servers,
+                allow_gateway_dns,
+                local_resolver_upstream,
+                allow_ipv6,
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}, gw DNS: {}, split DNS: \
+                 {}, resolver upstream: {:?}, IPv6: {}), {}",
                 peer_endpoint,
                 tunnel.interface,
                 tunnel
@@ -169,51 +239,101 @@ impl fmt::Display for FirewallPolicy {
                     .join(","),
                 tunnel.ipv4_gateway,
                 tunnel.ipv6_gateway,
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                allow_gateway_dns,
+                split_dns_servers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(","),
+                local_resolver_upstream,
+                allow_ipv6,
+                lan_policy
             ),
-            FirewallPolicy::Blocked { allow_lan } => write!(
+            FirewallPolicy::Blocked { lan_policy } => write!(f, "Blocked, {}", lan_policy),
+            FirewallPolicy::CaptivePortal {
+                gateway_networks,
+                lan_policy,
+            } => write!(
                 f,
-                "Blocked, {} LAN",
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                "Captive portal on {}, {}",
+                gateway_networks
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(","),
+                lan_policy
             ),
         }
     }
 }
 
+impl FirewallPolicy {
+    /// Returns the LAN policy that is part of this firewall policy.
+    pub fn lan_policy(&self) -> &LanPolicy {
+        match self {
+            FirewallPolicy::Connecting { lan_policy, .. }
+            | FirewallPolicy::Connected { lan_policy, .. }
+            | FirewallPolicy::Blocked { lan_policy }
+            | FirewallPolicy::CaptivePortal { lan_policy, .. } => lan_policy,
+        }
+    }
+}
+
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
     inner: imp::Firewall,
+    journal: SystemStateJournal,
 }
 
 /// Arguments required when first initializing the firewall.
+#[derive(Debug, Clone)]
 pub struct FirewallArguments {
     /// Determines whether the firewall should atomically enter the blocked state during init.
     pub initialize_blocked: bool,
     /// This argument is required for the blocked state to configure the firewall correctly.
-    pub allow_lan: Option<bool>,
+    pub lan_policy: Option<LanPolicy>,
 }
 
 impl Firewall {
-    /// Returns a new `Firewall`, ready to apply policies.
-    pub fn new(args: FirewallArguments) -> Result<Self, Error> {
-        Ok(Firewall {
+    /// Returns a new `Firewall`, ready to apply policies. The policies in effect are recorded in
+    /// `journal`.
+    pub(crate) fn new(args: FirewallArguments, journal: SystemStateJournal) -> Result<Self, Error> {
+        let initialize_blocked = args.initialize_blocked;
+        let firewall = Firewall {
             inner: imp::Firewall::new(args)?,
-        })
+            journal,
+        };
+        if initialize_blocked {
+            firewall
+                .journal
+                .update(|journal| journal.firewall_policy = Some(JournalFirewallPolicy::Blocked));
+        }
+        Ok(firewall)
     }
 
     /// Applies and starts enforcing the given `FirewallPolicy` Makes sure it is being kept in place
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        let journal_policy = match policy {
+            FirewallPolicy::Blocked { .. } => JournalFirewallPolicy::Blocked,
+            _ => JournalFirewallPolicy::Permissive,
+        };
+        self.inner.apply_policy(policy)?;
+        self.journal
+            .update(|journal| journal.firewall_policy = Some(journal_policy));
+        Ok(())
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
-        self.inner.reset_policy()
+        self.inner.reset_policy()?;
+        self.journal
+            .update(|journal| journal.firewall_policy = None);
+        Ok(())
     }
 }
 
@@ -1,4 +1,8 @@
-use std::{net::IpAddr, ptr};
+use ipnetwork::IpNetwork;
+use std::{
+    net::{IpAddr, SocketAddr},
+    ptr,
+};
 
 use self::winfw::*;
 use super::{FirewallArguments, FirewallPolicy, FirewallT};
@@ -31,6 +35,10 @@ pub enum Error {
     #[error(display = "Failed to apply blocked firewall policy")]
     ApplyingBlockedPolicy,
 
+    /// Failure to apply firewall _captive portal_ policy
+    #[error(display = "Failed to apply captive portal firewall policy")]
+    ApplyingCaptivePortalPolicy,
+
     /// Failure to reset firewall policies
     #[error(display = "Failed to reset firewall policies")]
     ResettingPolicy,
@@ -50,7 +58,10 @@ impl FirewallT for Firewall {
 
     fn new(args: FirewallArguments) -> Result<Self, Self::Error> {
         if args.initialize_blocked {
-            let cfg = &WinFwSettings::new(args.allow_lan.unwrap());
+            let lan_policy = args.lan_policy.unwrap();
+            // lan_networks has to outlive cfg
+            let lan_networks = IpNetworks::allowed_lan(&lan_policy);
+            let cfg = &WinFwSettings::new(&lan_policy, &lan_networks);
             unsafe {
                 WinFw_InitializeBlocked(
                     WINFW_TIMEOUT_SECONDS,
@@ -76,28 +87,39 @@ impl FirewallT for Firewall {
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Self::Error> {
+        let lan_policy = policy.lan_policy();
+        // lan_networks has to outlive cfg
+        let lan_networks = IpNetworks::allowed_lan(lan_policy);
+        let cfg = &WinFwSettings::new(lan_policy, &lan_networks);
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 // TODO: Allow ICMP traffic to a list of hosts for wireguard
                 pingable_hosts: _,
-                allow_lan,
-            } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connecting_state(&peer_endpoint, &cfg)
-            }
+                lan_policy: _,
+                allow_ipv6: _,
+            } => self.set_connecting_state(&peer_endpoint, &cfg),
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
-                allow_lan,
-            } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel)
-            }
-            FirewallPolicy::Blocked { allow_lan } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_blocked_state(&cfg)
-            }
+                lan_policy: _,
+                split_dns_servers: _,
+                allow_gateway_dns,
+                local_resolver_upstream,
+                allow_ipv6,
+            } => self.set_connected_state(
+                &peer_endpoint,
+                &cfg,
+                &tunnel,
+                allow_gateway_dns,
+                local_resolver_upstream,
+                allow_ipv6,
+            ),
+            FirewallPolicy::Blocked { lan_policy: _ } => self.set_blocked_state(&cfg),
+            FirewallPolicy::CaptivePortal {
+                gateway_networks,
+                lan_policy: _,
+            } => self.set_captive_portal_state(&cfg, &gateway_networks),
         }
     }
 
@@ -146,12 +168,19 @@ impl Firewall {
         endpoint: &Endpoint,
         winfw_settings: &WinFwSettings,
         tunnel_metadata: &crate::tunnel::TunnelMetadata,
+        allow_gateway_dns: bool,
+        local_resolver_upstream: Option<SocketAddr>,
+        allow_ipv6: bool,
     ) -> Result<(), Error> {
         trace!("Applying 'connected' firewall policy");
         let ip_str = Self::widestring_ip(endpoint.address.ip());
-        let v4_gateway = Self::widestring_ip(tunnel_metadata.ipv4_gateway.into());
+        // DNS to the gateways is blocked when they are not passed to the firewall module
+        let v4_gateway = Some(tunnel_metadata.ipv4_gateway)
+            .filter(|_| allow_gateway_dns)
+            .map(|v4_ip| Self::widestring_ip(v4_ip.into()));
         let v6_gateway = tunnel_metadata
             .ipv6_gateway
+            .filter(|_| allow_gateway_dns && allow_ipv6)
             .map(|v6_ip| Self::widestring_ip(v6_ip.into()));
 
         let tunnel_alias =
@@ -173,18 +202,44 @@ impl Firewall {
             debug!("Network interface metrics were not changed");
         }
 
+        let v4_gateway_ptr = match &v4_gateway {
+            Some(v4_ip) => v4_ip.as_ptr(),
+            None => ptr::null(),
+        };
         let v6_gateway_ptr = match &v6_gateway {
             Some(v6_ip) => v6_ip.as_ptr(),
             None => ptr::null(),
         };
 
+        // DNS to the local resolver is blocked unless it's passed to the firewall module
+        let resolver_host = local_resolver_upstream
+            .map(|_| Self::widestring_ip(crate::dns::LOCAL_RESOLVER_ADDRESS.into()));
+        let resolver_host_ptr = match &resolver_host {
+            Some(resolver_host) => resolver_host.as_ptr(),
+            None => ptr::null(),
+        };
+        // upstream has to outlive winfw_upstream
+        let upstream = local_resolver_upstream
+            .map(|upstream| (Self::widestring_ip(upstream.ip()), upstream.port()));
+        let winfw_upstream = upstream.as_ref().map(|(ip, port)| WinFwEndpoint {
+            ip: ip.as_ptr(),
+            port: *port,
+        });
+        let upstream_ptr = match &winfw_upstream {
+            Some(upstream) => upstream as *const WinFwEndpoint,
+            None => ptr::null(),
+        };
+
         unsafe {
             WinFw_ApplyPolicyConnected(
                 winfw_settings,
                 &winfw_relay,
                 tunnel_alias.as_ptr(),
-                v4_gateway.as_ptr(),
+                v4_gateway_ptr,
                 v6_gateway_ptr,
+                resolver_host_ptr,
+                upstream_ptr,
+                allow_ipv6,
             )
             .into_result()
         }
@@ -194,15 +249,35 @@ impl Firewall {
         trace!("Applying 'blocked' firewall policy");
         unsafe { WinFw_ApplyPolicyBlocked(winfw_settings).into_result() }
     }
+
+    fn set_captive_portal_state(
+        &mut self,
+        winfw_settings: &WinFwSettings,
+        gateway_networks: &[IpNetwork],
+    ) -> Result<(), Error> {
+        trace!("Applying 'captive portal' firewall policy");
+        // gateway_networks has to outlive the call
+        let gateway_networks = IpNetworks::new(gateway_networks);
+        unsafe {
+            WinFw_ApplyPolicyCaptivePortal(
+                winfw_settings,
+                gateway_networks.as_ptr(),
+                gateway_networks.len(),
+            )
+            .into_result()
+        }
+    }
 }
 
 
 #[allow(non_snake_case)]
 mod winfw {
     use super::Error;
-    use crate::winnet;
+    use crate::{firewall::allowed_lan_nets, winnet};
+    use ipnetwork::IpNetwork;
     use libc;
-    use talpid_types::net::TransportProtocol;
+    use talpid_types::net::{lan::LanPolicy, TransportProtocol};
+    use widestring::WideCString;
 
     #[repr(C)]
     pub struct WinFwRelay {
@@ -211,6 +286,12 @@ mod winfw {
         pub protocol: WinFwProt,
     }
 
+    #[repr(C)]
+    pub struct WinFwEndpoint {
+        pub ip: *const libc::wchar_t,
+        pub port: u16,
+    }
+
     #[repr(u8)]
     #[derive(Clone, Copy)]
     pub enum WinFwProt {
@@ -227,17 +308,72 @@ mod winfw {
         }
     }
 
-    #[repr(C)]
+    #[repr(C, packed)]
+    pub struct WinFwIpNetwork {
+        address: *const libc::wchar_t,
+        prefix: u8,
+    }
+
+    /// Owns the networks, and their address strings, that are passed to the firewall module.
+    pub struct IpNetworks {
+        _addresses: Vec<WideCString>,
+        networks: Vec<WinFwIpNetwork>,
+    }
+
+    impl IpNetworks {
+        pub fn new(nets: &[IpNetwork]) -> IpNetworks {
+            let addresses = nets
+                .iter()
+                .map(|net| {
+                    let buf = net.ip().to_string().encode_utf16().collect::<Vec<_>>();
+                    WideCString::new(buf).unwrap()
+                })
+                .collect::<Vec<_>>();
+            let networks = nets
+                .iter()
+                .zip(addresses.iter())
+                .map(|(net, address)| WinFwIpNetwork {
+                    address: address.as_ptr(),
+                    prefix: net.prefix(),
+                })
+                .collect();
+            IpNetworks {
+                _addresses: addresses,
+                networks,
+            }
+        }
+
+        /// The LAN networks that `lan_policy` allows communication with.
+        pub fn allowed_lan(lan_policy: &LanPolicy) -> IpNetworks {
+            Self::new(&allowed_lan_nets(lan_policy))
+        }
+
+        pub fn as_ptr(&self) -> *const WinFwIpNetwork {
+            self.networks.as_ptr()
+        }
+
+        pub fn len(&self) -> u32 {
+            self.networks.len() as u32
+        }
+    }
+
+    #[repr(C, packed)]
     pub struct WinFwSettings {
         permitDhcp: bool,
-        permitLan: bool,
+        lanNetworks: *const WinFwIpNetwork,
+        numLanNetworks: u32,
+        permitLanMulticast: bool,
+        permitDhcpServer: bool,
     }
 
     impl WinFwSettings {
-        pub fn new(permit_lan: bool) -> WinFwSettings {
+        pub fn new(lan_policy: &LanPolicy, lan_networks: &IpNetworks) -> WinFwSettings {
             WinFwSettings {
                 permitDhcp: true,
-                permitLan: permit_lan,
+                lanNetworks: lan_networks.as_ptr(),
+                numLanNetworks: lan_networks.len(),
+                permitLanMulticast: lan_policy.allow_multicast,
+                permitDhcpServer: lan_policy.allow_dhcp_server,
             }
         }
     }
@@ -247,6 +383,7 @@ mod winfw {
     ffi_error!(ApplyConnectingResult, Error::ApplyingConnectingPolicy);
     ffi_error!(ApplyConnectedResult, Error::ApplyingConnectedPolicy);
     ffi_error!(ApplyBlockedResult, Error::ApplyingBlockedPolicy);
+    ffi_error!(ApplyCaptivePortalResult, Error::ApplyingCaptivePortalPolicy);
     ffi_error!(ResettingPolicyResult, Error::ResettingPolicy);
 
     extern "system" {
@@ -281,11 +418,21 @@ mod winfw {
             tunnelIfaceAlias: *const libc::wchar_t,
             v4Gateway: *const libc::wchar_t,
             v6Gateway: *const libc::wchar_t,
+            resolverHost: *const libc::wchar_t,
+            resolverUpstream: *const WinFwEndpoint,
+            permitIpv6: bool,
         ) -> ApplyConnectedResult;
 
         #[link_name = "WinFw_ApplyPolicyBlocked"]
         pub fn WinFw_ApplyPolicyBlocked(settings: &WinFwSettings) -> ApplyBlockedResult;
 
+        #[link_name = "WinFw_ApplyPolicyCaptivePortal"]
+        pub fn WinFw_ApplyPolicyCaptivePortal(
+            settings: &WinFwSettings,
+            gatewayNetworks: *const WinFwIpNetwork,
+            numGatewayNetworks: u32,
+        ) -> ApplyCaptivePortalResult;
+
         #[link_name = "WinFw_Reset"]
         pub fn WinFw_Reset() -> ResettingPolicyResult;
     }
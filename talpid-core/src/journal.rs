@@ -0,0 +1,372 @@
+//! A journal of the changes made to the routing table, firewall and DNS settings, kept in the
+//! cache directory. Changes are added to the journal as they are applied and removed as they are
+//! undone, so a journal that is left when starting up means that the previous run was killed before
+//! it could clean up after itself.
+//!
+//! The journal is owned by the tunnel state machine, which hands a handle to it to the firewall,
+//! DNS and routing modules.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use talpid_types::ErrorExt;
+
+const JOURNAL_FILENAME: &str = "system-state-journal.json";
+
+/// The changes to the system that have been applied and not undone yet.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Journal {
+    /// Routes added to a routing table.
+    pub routes: Vec<JournalRoute>,
+    /// The routing rules added for policy based routing.
+    pub policy_routing: Option<JournalPolicyRouting>,
+    /// The kind of firewall policy that is applied, if any.
+    pub firewall_policy: Option<JournalFirewallPolicy>,
+    /// The DNS settings applied for a tunnel.
+    pub dns: Option<JournalDns>,
+}
+
+/// A route added to a routing table.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JournalRoute {
+    /// The destination of the route.
+    pub destination: String,
+    /// The device the route goes through, if any.
+    pub device: Option<String>,
+    /// The gateway the route goes through, if any.
+    pub gateway: Option<IpAddr>,
+    /// The metric of the route, if any.
+    pub metric: Option<u32>,
+    /// The routing table the route is in, if not the main table.
+    pub table_id: Option<u32>,
+}
+
+/// The parameters of the routing rules added for policy based routing.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JournalPolicyRouting {
+    /// The firewall mark of packets that bypass the table.
+    pub fwmark: u32,
+    /// The routing table used for packets without the mark.
+    pub table_id: u32,
+}
+
+/// The kind of firewall policy that is applied.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalFirewallPolicy {
+    /// All traffic is blocked. Deliberately left in place on a clean shutdown when traffic should
+    /// be blocked while disconnected.
+    Blocked,
+    /// Traffic to a relay, a tunnel or a captive portal is let through. Never left in place on a
+    /// clean shutdown.
+    Permissive,
+}
+
+/// DNS settings applied for a tunnel.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JournalDns {
+    /// The tunnel interface DNS was set for.
+    pub interface: String,
+    /// The system service DNS was set through, if known.
+    pub backend: Option<String>,
+    /// The DNS servers each network service had before DNS was set, keyed by the path of its
+    /// settings in the dynamic store. `None` if the service had no DNS settings. Only recorded on
+    /// macOS, where the settings outlive the daemon.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub service_backup: BTreeMap<String, Option<Vec<String>>>,
+}
+
+impl Journal {
+    /// Returns true if the journal holds changes that a clean shutdown would have undone. A
+    /// blocking firewall policy is deliberately left in place when shutting down, so it alone
+    /// doesn't mean that the shutdown was unclean.
+    pub fn is_unclean(&self) -> bool {
+        !self.routes.is_empty()
+            || self.policy_routing.is_some()
+            || self.firewall_policy == Some(JournalFirewallPolicy::Permissive)
+            || self.dns.is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Journal::default()
+    }
+}
+
+/// Undoes the changes recorded in a journal left by an unclean shutdown.
+pub trait Restore {
+    /// Removes routes and routing rules that are still in place.
+    fn remove_routes(&mut self, routes: &[JournalRoute], policy: Option<JournalPolicyRouting>);
+
+    /// Resets DNS settings that are still in place.
+    fn reset_dns(&mut self, dns: &JournalDns);
+
+    /// Removes a firewall policy that lets traffic through.
+    fn reset_firewall(&mut self);
+}
+
+struct JournalFile {
+    path: PathBuf,
+    journal: Journal,
+}
+
+impl JournalFile {
+    fn new(path: PathBuf) -> Self {
+        JournalFile {
+            path,
+            journal: Journal::default(),
+        }
+    }
+
+    /// Reads the journal from the file. A missing or unreadable file is an empty journal.
+    fn read(&self) -> Journal {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Journal::default(),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read the system state journal")
+                );
+                return Journal::default();
+            }
+        };
+        serde_json::from_slice(&contents).unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to parse the system state journal")
+            );
+            Journal::default()
+        })
+    }
+
+    /// Writes the journal to the file, or removes the file if the journal is empty. The file is
+    /// replaced atomically, so that it's never left half written.
+    fn write(&self) -> io::Result<()> {
+        if self.journal.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+        let contents = serde_json::to_vec_pretty(&self.journal)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+/// A handle to the journal file. Clones refer to the same journal.
+#[derive(Clone)]
+pub struct SystemStateJournal {
+    file: Arc<Mutex<JournalFile>>,
+}
+
+impl SystemStateJournal {
+    /// Starts keeping the journal in `cache_dir`. Also returns the journal left by the previous
+    /// run if it was shut down uncleanly. The changes in it are kept in the journal until they
+    /// have been undone by `restore`, so that they are found again if this run is killed before
+    /// that.
+    pub fn open(cache_dir: &Path) -> (Self, Option<Journal>) {
+        let mut journal_file = JournalFile::new(cache_dir.join(JOURNAL_FILENAME));
+        journal_file.journal = journal_file.read();
+        let previous = journal_file.journal.clone();
+        let journal = SystemStateJournal {
+            file: Arc::new(Mutex::new(journal_file)),
+        };
+        if previous.is_unclean() {
+            (journal, Some(previous))
+        } else {
+            (journal, None)
+        }
+    }
+
+    /// Undoes the changes in a journal left by an unclean shutdown with `restorer`, and removes
+    /// them from the journal. A blocking firewall policy is left alone, since it's what the
+    /// previous run wanted in place after shutting down.
+    pub fn restore(&self, journal: &Journal, restorer: &mut impl Restore) {
+        log::warn!("The previous run was not shut down cleanly, restoring the system state");
+        if !journal.routes.is_empty() || journal.policy_routing.is_some() {
+            restorer.remove_routes(&journal.routes, journal.policy_routing);
+        }
+        if let Some(ref dns) = journal.dns {
+            restorer.reset_dns(dns);
+        }
+        if journal.firewall_policy == Some(JournalFirewallPolicy::Permissive) {
+            restorer.reset_firewall();
+        }
+        self.update(|current| {
+            current.routes.clear();
+            current.policy_routing = None;
+            current.dns = None;
+            if current.firewall_policy == Some(JournalFirewallPolicy::Permissive) {
+                current.firewall_policy = None;
+            }
+        });
+    }
+
+    /// Records a change in the journal and writes it to disk.
+    pub fn update(&self, change: impl FnOnce(&mut Journal)) {
+        let mut journal_file = self.file.lock();
+        change(&mut journal_file.journal);
+        if let Err(error) = journal_file.write() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to write the system state journal")
+            );
+        }
+    }
+
+    /// Returns the changes currently recorded in the journal.
+    #[cfg(test)]
+    pub fn current(&self) -> Journal {
+        self.file.lock().journal.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tunnel_route() -> JournalRoute {
+        JournalRoute {
+            destination: "0.0.0.0/0".to_owned(),
+            device: Some("wg0-mullvad".to_owned()),
+            gateway: None,
+            metric: None,
+            table_id: Some(0x6d6f_6c65),
+        }
+    }
+
+    fn tunnel_dns() -> JournalDns {
+        JournalDns {
+            interface: "wg0-mullvad".to_owned(),
+            backend: Some("systemd-resolved".to_owned()),
+            service_backup: BTreeMap::new(),
+        }
+    }
+
+    /// Records what it's asked to undo.
+    #[derive(Default)]
+    struct FakeRestorer {
+        routes: Vec<JournalRoute>,
+        policy: Option<JournalPolicyRouting>,
+        dns: Option<JournalDns>,
+        firewall_reset: bool,
+    }
+
+    impl Restore for FakeRestorer {
+        fn remove_routes(&mut self, routes: &[JournalRoute], policy: Option<JournalPolicyRouting>) {
+            self.routes.extend_from_slice(routes);
+            self.policy = policy;
+        }
+
+        fn reset_dns(&mut self, dns: &JournalDns) {
+            self.dns = Some(dns.clone());
+        }
+
+        fn reset_firewall(&mut self) {
+            self.firewall_reset = true;
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal_file = JournalFile::new(dir.path().join(JOURNAL_FILENAME));
+        journal_file.journal.routes.push(tunnel_route());
+        journal_file.journal.dns = Some(tunnel_dns());
+        journal_file.write().unwrap();
+
+        let journal = journal_file.read();
+        assert!(journal.is_unclean());
+        assert_eq!(journal, journal_file.journal);
+    }
+
+    #[test]
+    fn test_empty_journal_removes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(JOURNAL_FILENAME);
+        let mut journal_file = JournalFile::new(path.clone());
+        journal_file.journal.firewall_policy = Some(JournalFirewallPolicy::Blocked);
+        journal_file.write().unwrap();
+        assert!(path.exists());
+        assert!(!journal_file.read().is_unclean());
+
+        journal_file.journal.firewall_policy = None;
+        journal_file.write().unwrap();
+        assert!(!path.exists());
+        assert_eq!(journal_file.read(), Journal::default());
+    }
+
+    #[test]
+    fn test_read_corrupt_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(JOURNAL_FILENAME);
+        fs::write(&path, b"{\"routes\": [").unwrap();
+        assert_eq!(JournalFile::new(path).read(), Journal::default());
+    }
+
+    #[test]
+    fn test_restore_undoes_unclean_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = JournalPolicyRouting {
+            fwmark: 0x6d6f_6c65,
+            table_id: 0x6d6f_6c65,
+        };
+        {
+            let (journal, unclean) = SystemStateJournal::open(dir.path());
+            assert_eq!(unclean, None);
+            journal.update(|journal| {
+                journal.routes.push(tunnel_route());
+                journal.policy_routing = Some(policy);
+                journal.firewall_policy = Some(JournalFirewallPolicy::Permissive);
+                journal.dns = Some(tunnel_dns());
+            });
+            // Killed here, before anything was undone.
+        }
+
+        let (journal, unclean) = SystemStateJournal::open(dir.path());
+        let unclean = unclean.expect("The unclean shutdown was not detected");
+        let mut restorer = FakeRestorer::default();
+        journal.restore(&unclean, &mut restorer);
+
+        assert_eq!(restorer.routes, vec![tunnel_route()]);
+        assert_eq!(restorer.policy, Some(policy));
+        assert_eq!(restorer.dns, Some(tunnel_dns()));
+        assert!(restorer.firewall_reset);
+        assert_eq!(journal.current(), Journal::default());
+        assert!(!dir.path().join(JOURNAL_FILENAME).exists());
+    }
+
+    #[test]
+    fn test_restore_keeps_blocking_firewall_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let (journal, _) = SystemStateJournal::open(dir.path());
+            journal.update(|journal| {
+                journal.firewall_policy = Some(JournalFirewallPolicy::Blocked);
+                journal.dns = Some(tunnel_dns());
+            });
+        }
+
+        let (journal, unclean) = SystemStateJournal::open(dir.path());
+        let mut restorer = FakeRestorer::default();
+        journal.restore(&unclean.unwrap(), &mut restorer);
+
+        assert!(restorer.routes.is_empty());
+        assert!(!restorer.firewall_reset);
+        assert_eq!(restorer.dns, Some(tunnel_dns()));
+        assert_eq!(
+            journal.current().firewall_policy,
+            Some(JournalFirewallPolicy::Blocked)
+        );
+    }
+}
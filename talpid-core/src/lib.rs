@@ -47,6 +47,9 @@ pub mod firewall;
 /// Abstractions over operating system DNS settings.
 pub mod dns;
 
+/// Journal of changes made to the system, used to undo them after an unclean shutdown.
+mod journal;
+
 /// State machine to handle tunnel configuration.
 pub mod tunnel_state_machine;
 
@@ -60,3 +63,7 @@ mod mktemp;
 /// Misc utilities for the Linux platform.
 #[cfg(target_os = "linux")]
 mod linux;
+
+/// Raw netlink requests for the Linux platform.
+#[cfg(target_os = "linux")]
+mod netlink;
@@ -1,8 +1,18 @@
 use std::{
     ffi::{self, CString},
     io,
+    net::Ipv4Addr,
 };
 
+/// The kernel's IPv4 routing table, in text form.
+pub const ROUTE_TABLE_PATH: &str = "/proc/net/route";
+
+/// Firewall mark of the packets sent by the tunnel itself. Marked packets bypass the routing
+/// table of the tunnel.
+pub const TUNNEL_FWMARK: u32 = 0x6d6f_6c65;
+/// ID of the routing table that routes everything that isn't marked with `TUNNEL_FWMARK`.
+pub const TUNNEL_TABLE_ID: u32 = 0x6d6f_6c65;
+
 /// Converts an interface name into the corresponding index.
 pub fn iface_index(name: &str) -> Result<libc::c_uint, IfaceIndexLookupError> {
     let c_name = CString::new(name)
@@ -25,3 +35,21 @@ pub enum IfaceIndexLookupError {
     #[error(display = "Failed to get index for interface {}", _0)]
     InterfaceLookupError(String, #[error(cause)] io::Error),
 }
+
+/// Returns the interface and gateway of every IPv4 default route in the main routing table, given
+/// the contents of `ROUTE_TABLE_PATH`.
+pub fn parse_default_routes(routes: &str) -> Vec<(String, Ipv4Addr)> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 8 || columns[1] != "00000000" || columns[7] != "00000000" {
+                return None;
+            }
+            // The kernel prints the gateway as a hex integer in host byte order.
+            let gateway = u32::from_str_radix(columns[2], 16).ok()?;
+            Some((columns[0].to_owned(), Ipv4Addr::from(u32::from_be(gateway))))
+        })
+        .collect()
+}
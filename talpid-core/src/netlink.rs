@@ -0,0 +1,251 @@
+//! Requests to the kernel over raw netlink sockets. Used where only a few messages are needed,
+//! and a full netlink library would be overkill. The messages themselves are built by the callers.
+
+use std::{io, os::unix::io::RawFd, ptr};
+
+// Netlink constants from the kernel headers.
+pub const NLMSG_ERROR: u16 = 2;
+pub const NLMSG_DONE: u16 = 3;
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_DUMP: u16 = 0x300;
+pub const NLM_F_EXCL: u16 = 0x200;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+/// Size of `struct nlmsghdr`.
+pub const NETLINK_HEADER_LEN: usize = 16;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to open netlink socket")]
+    OpenSocket(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to send netlink message")]
+    Send(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to receive netlink message")]
+    Receive(#[error(cause)] io::Error),
+
+    #[error(display = "Received a truncated netlink message")]
+    TruncatedMessage,
+
+    #[error(display = "Netlink request was rejected by the kernel")]
+    Rejected(#[error(cause)] io::Error),
+}
+
+pub struct NetlinkSocket {
+    fd: RawFd,
+    sequence: u32,
+}
+
+impl NetlinkSocket {
+    /// Opens a socket for the given netlink protocol, such as `NETLINK_ROUTE`.
+    pub fn open(protocol: libc::c_int) -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::OpenSocket(io::Error::last_os_error()));
+        }
+        Ok(NetlinkSocket { fd, sequence: 1 })
+    }
+
+    /// Sends a request and waits for the kernel to acknowledge it, or to finish the dump. Returns
+    /// the payloads of the replies that were received before that. The request must start with a
+    /// complete netlink header, except for the sequence number, which is set here.
+    pub fn request(&mut self, mut message: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        message[8..12].copy_from_slice(&sequence.to_ne_bytes());
+
+        let sent = unsafe {
+            libc::send(
+                self.fd,
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(Error::Send(io::Error::last_os_error()));
+        }
+
+        let mut replies = Vec::new();
+        let mut buffer = Vec::new();
+        loop {
+            let received = self.receive(&mut buffer)?;
+            match parse_replies(&buffer[..received], sequence, &mut replies)? {
+                Some(Ok(())) => return Ok(replies),
+                Some(Err(error)) => return Err(Error::Rejected(error)),
+                None => (),
+            }
+        }
+    }
+
+    /// Receives the next datagram into `buffer`, which is grown to fit it. A dump is split into
+    /// datagrams of a size picked by the kernel, so no fixed buffer size is sure to be enough.
+    fn receive(&self, buffer: &mut Vec<u8>) -> Result<usize> {
+        let length = unsafe {
+            libc::recv(
+                self.fd,
+                ptr::null_mut(),
+                0,
+                libc::MSG_PEEK | libc::MSG_TRUNC,
+            )
+        };
+        if length < 0 {
+            return Err(Error::Receive(io::Error::last_os_error()));
+        }
+        if buffer.len() < length as usize {
+            buffer.resize(length as usize, 0);
+        }
+
+        let received = unsafe {
+            libc::recv(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if received < 0 {
+            return Err(Error::Receive(io::Error::last_os_error()));
+        }
+        Ok(received as usize)
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Collects the payloads of the replies to the request with the given sequence number. Returns
+/// the outcome of the request once its acknowledgement, or the end of its dump, is found. Returns
+/// `None` if neither is there, and more messages have to be received.
+fn parse_replies(
+    mut messages: &[u8],
+    sequence: u32,
+    replies: &mut Vec<Vec<u8>>,
+) -> Result<Option<io::Result<()>>> {
+    while messages.len() >= NETLINK_HEADER_LEN {
+        let length = read_u32(&messages[0..4]) as usize;
+        if length < NETLINK_HEADER_LEN || length > messages.len() {
+            return Err(Error::TruncatedMessage);
+        }
+        let message_type = u16::from_ne_bytes([messages[4], messages[5]]);
+        let message_sequence = read_u32(&messages[8..12]);
+
+        if message_sequence == sequence {
+            let payload = &messages[NETLINK_HEADER_LEN..length];
+            // Dumps end with `NLMSG_DONE` rather than an acknowledgement.
+            if message_type == NLMSG_ERROR || message_type == NLMSG_DONE {
+                if payload.len() < 4 {
+                    return Err(Error::TruncatedMessage);
+                }
+                let code = read_u32(&payload[0..4]) as i32;
+                return Ok(Some(if code == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::from_raw_os_error(-code))
+                }));
+            }
+            replies.push(payload.to_vec());
+        }
+
+        // Messages are aligned to four bytes.
+        let aligned_length = (length + 3) & !3;
+        messages = &messages[aligned_length.min(messages.len())..];
+    }
+    Ok(None)
+}
+
+pub fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RTM_GETLINK: u16 = 18;
+
+    fn message(message_type: u16, flags: u16, sequence: u32, payload: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&((NETLINK_HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&flags.to_ne_bytes());
+        message.extend_from_slice(&sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+        message
+    }
+
+    #[test]
+    fn test_parse_replies_until_done() {
+        let mut messages = message(21, 0, 4, &[0; 4]);
+        messages.extend_from_slice(&message(21, 0, 3, &[0; 4]));
+        messages.extend_from_slice(&message(NLMSG_DONE, 0, 4, &[0; 4]));
+
+        let mut replies = Vec::new();
+        let result = parse_replies(&messages, 4, &mut replies).unwrap();
+        assert!(match result {
+            Some(Ok(())) => true,
+            _ => false,
+        });
+        assert_eq!(replies.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_replies_across_datagrams() {
+        let mut replies = Vec::new();
+        let first = message(21, 0, 4, &[0; 4]);
+        assert!(parse_replies(&first, 4, &mut replies).unwrap().is_none());
+
+        let mut error = 0i32.to_ne_bytes().to_vec();
+        error.extend_from_slice(&first);
+        let ack = message(NLMSG_ERROR, 0, 4, &error);
+        assert!(parse_replies(&ack, 4, &mut replies).unwrap().is_some());
+        assert_eq!(replies.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_rejected_request() {
+        let ack = message(NLMSG_ERROR, 0, 3, &(-libc::ENOENT).to_ne_bytes());
+
+        let mut replies = Vec::new();
+        assert!(parse_replies(&ack, 2, &mut replies).unwrap().is_none());
+        match parse_replies(&ack, 3, &mut replies).unwrap() {
+            Some(Err(error)) => assert_eq!(error.raw_os_error(), Some(libc::ENOENT)),
+            _ => panic!("Expected the request to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let mut ack = message(NLMSG_ERROR, 0, 3, &0i32.to_ne_bytes());
+        ack.truncate(ack.len() - 1);
+
+        match parse_replies(&ack, 3, &mut Vec::new()) {
+            Err(Error::TruncatedMessage) => (),
+            _ => panic!("Expected the message to be truncated"),
+        }
+    }
+
+    #[test]
+    fn test_dump_links() {
+        let mut socket = NetlinkSocket::open(libc::NETLINK_ROUTE).unwrap();
+        // A `struct ifinfomsg` that matches all links.
+        let request = message(RTM_GETLINK, NLM_F_REQUEST | NLM_F_DUMP, 0, &[0; 16]);
+        let replies = socket.request(request).unwrap();
+        // There is at least a loopback interface.
+        assert!(!replies.is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+//! Detects captive portals, which intercept the traffic of a network until the user has logged in
+//! through a web page. The probe requests a page from an address reserved for documentation
+//! (TEST-NET-1), which is never routed on the internet. Any response has to come from something
+//! on the local network that intercepts HTTP, which is what captive portals do.
+//!
+//! Without a captive portal, the probe waits until it times out, so it runs on a thread of its
+//! own instead of holding up the link monitor.
+
+use crate::tunnel_state_machine::TunnelCommand;
+use futures::sync::mpsc::UnboundedSender;
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::{Ipv4Addr, SocketAddrV4, TcpStream},
+    os::unix::io::FromRawFd,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use talpid_types::net::{Endpoint, TransportProtocol};
+
+const PROBE_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const PROBE_PORT: u16 = 80;
+/// How long to wait for the connection and the response. Without a captive portal, the probe
+/// always waits this long, since the address doesn't answer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often to check if a captive portal has let the device through.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Returns the endpoint the probe connects to. The firewall lets packets marked with
+/// `TUNNEL_FWMARK` reach it while connecting, connected and logging in through a captive portal.
+/// The blocked policy stops the probe, so it can't tell that the portal is gone.
+pub fn probe_endpoint() -> Endpoint {
+    Endpoint::new(PROBE_ADDRESS, PROBE_PORT, TransportProtocol::Tcp)
+}
+
+/// Checks for captive portals on a separate thread, and sends `TunnelCommand::IsCaptivePortal`
+/// when the result changes. While behind a captive portal, it checks again periodically to see if
+/// the user has logged in. The last result is withdrawn when the monitor is dropped, so that
+/// it fails open.
+pub struct CaptivePortalMonitor {
+    check_tx: mpsc::Sender<bool>,
+}
+
+impl CaptivePortalMonitor {
+    pub fn spawn(sender: UnboundedSender<TunnelCommand>) -> Self {
+        Self::spawn_with_probe(sender, is_captive_portal)
+    }
+
+    fn spawn_with_probe(
+        sender: UnboundedSender<TunnelCommand>,
+        probe: impl Fn() -> bool + Send + 'static,
+    ) -> Self {
+        let (check_tx, check_rx) = mpsc::channel();
+        thread::spawn(move || run_monitor(check_rx, sender, probe));
+        CaptivePortalMonitor { check_tx }
+    }
+
+    /// Checks for a captive portal again, such as after the network changed. Nothing is probed
+    /// while the computer is offline.
+    pub fn check(&self, is_offline: bool) {
+        let _ = self.check_tx.send(is_offline);
+    }
+}
+
+fn run_monitor(
+    checks: mpsc::Receiver<bool>,
+    sender: UnboundedSender<TunnelCommand>,
+    probe: impl Fn() -> bool,
+) {
+    let mut is_offline = false;
+    let mut is_captive_portal = false;
+    loop {
+        let check = if is_captive_portal {
+            checks.recv_timeout(RECHECK_INTERVAL)
+        } else {
+            checks
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+        };
+        match check {
+            Ok(offline) => {
+                // Checks that were requested while probing are handled by a single probe.
+                is_offline = checks.try_iter().last().unwrap_or(offline);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        let new_is_captive_portal = !is_offline && probe();
+        if new_is_captive_portal != is_captive_portal {
+            is_captive_portal = new_is_captive_portal;
+            let _ = sender.unbounded_send(TunnelCommand::IsCaptivePortal(is_captive_portal));
+        }
+    }
+    if is_captive_portal {
+        let _ = sender.unbounded_send(TunnelCommand::IsCaptivePortal(false));
+    }
+}
+
+/// Returns true if the network is behind a captive portal. Returns false if the probe could not
+/// tell.
+fn is_captive_portal() -> bool {
+    let result = connect(SocketAddrV4::new(PROBE_ADDRESS, PROBE_PORT)).and_then(probe);
+    match result {
+        Ok(is_captive_portal) => is_captive_portal,
+        Err(error) => {
+            log::debug!("No response to the captive portal probe: {}", error);
+            false
+        }
+    }
+}
+
+/// Requests a page over `stream`, and returns true if something answered with HTTP.
+fn probe(mut stream: TcpStream) -> io::Result<bool> {
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        PROBE_ADDRESS
+    )?;
+
+    let mut response = Vec::new();
+    stream.take(16).read_to_end(&mut response)?;
+    Ok(is_http_response(&response))
+}
+
+/// Connects to `address` with a socket marked with `TUNNEL_FWMARK`, so that the connection is
+/// routed outside the tunnel and allowed by the firewall.
+fn connect(address: SocketAddrV4) -> io::Result<TcpStream> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owning the socket from the start closes it on every error.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let mark = crate::linux::TUNNEL_FWMARK;
+    set_socket_option(fd, libc::SO_MARK, &mark)?;
+    // Connecting times out after the send timeout on Linux.
+    let timeout = libc::timeval {
+        tv_sec: PROBE_TIMEOUT.as_secs() as libc::time_t,
+        tv_usec: 0,
+    };
+    set_socket_option(fd, libc::SO_SNDTIMEO, &timeout)?;
+
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: address.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*address.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    let result = unsafe {
+        libc::connect(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stream)
+}
+
+fn set_socket_option<T>(fd: libc::c_int, option: libc::c_int, value: &T) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn is_http_response(response: &[u8]) -> bool {
+    response.starts_with(b"HTTP/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{sync::mpsc::UnboundedReceiver, Stream};
+    use std::{
+        net::{Shutdown, TcpListener},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    /// Connects to a local server that answers the request with `response`.
+    fn probe_server(response: &'static [u8]) -> io::Result<bool> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 128];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(response).unwrap();
+            stream.shutdown(Shutdown::Both).unwrap();
+        });
+        let result = probe(TcpStream::connect(address).unwrap());
+        server.join().unwrap();
+        result
+    }
+
+    fn next_captive_portal_command(commands: &mut UnboundedReceiver<TunnelCommand>) -> bool {
+        match commands.by_ref().wait().next() {
+            Some(Ok(TunnelCommand::IsCaptivePortal(is_captive_portal))) => is_captive_portal,
+            _ => panic!("Expected a captive portal command"),
+        }
+    }
+
+    #[test]
+    fn test_probe_detects_captive_portal() {
+        assert!(probe_server(b"HTTP/1.1 302 Found\r\nLocation: http://portal/\r\n\r\n").unwrap());
+    }
+
+    #[test]
+    fn test_probe_ignores_other_responses() {
+        assert!(!probe_server(b"SSH-2.0-OpenSSH\r\n").unwrap());
+        assert!(!probe_server(b"").unwrap());
+    }
+
+    #[test]
+    fn test_monitor_reports_changes() {
+        let (sender, mut commands) = futures::sync::mpsc::unbounded();
+        let behind_portal = Arc::new(AtomicBool::new(true));
+        let probe_behind_portal = behind_portal.clone();
+        let monitor = CaptivePortalMonitor::spawn_with_probe(sender, move || {
+            probe_behind_portal.load(Ordering::SeqCst)
+        });
+
+        monitor.check(false);
+        assert!(next_captive_portal_command(&mut commands));
+
+        behind_portal.store(false, Ordering::SeqCst);
+        monitor.check(false);
+        assert!(!next_captive_portal_command(&mut commands));
+
+        // Nothing is probed while offline, and nothing changes.
+        behind_portal.store(true, Ordering::SeqCst);
+        monitor.check(true);
+        monitor.check(false);
+        assert!(next_captive_portal_command(&mut commands));
+
+        // The last result is withdrawn when the monitor stops.
+        drop(monitor);
+        assert!(!next_captive_portal_command(&mut commands));
+        assert!(commands.wait().next().is_none());
+    }
+
+    #[test]
+    fn test_is_http_response() {
+        assert!(is_http_response(b"HTTP/1.1 302 Found\r\n"));
+        assert!(is_http_response(b"HTTP/1.0 200"));
+        assert!(!is_http_response(b""));
+        assert!(!is_http_response(b"SSH-2.0-OpenSSH"));
+    }
+}
@@ -1,5 +1,7 @@
+use super::NetworkInfoListener;
 use crate::tunnel_state_machine::TunnelCommand;
 use futures::sync::mpsc::UnboundedSender;
+use talpid_types::net::network_info::NetworkInfo;
 
 #[derive(err_derive::Error, Debug)]
 #[error(display = "Dummy offline check error")]
@@ -7,10 +9,17 @@ pub struct Error;
 
 pub struct MonitorHandle;
 
-pub fn spawn_monitor(_sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHandle, Error> {
+pub fn spawn_monitor(
+    _sender: UnboundedSender<TunnelCommand>,
+    _network_info_listener: Option<NetworkInfoListener>,
+) -> Result<MonitorHandle, Error> {
     Ok(MonitorHandle)
 }
 
 pub fn is_offline() -> bool {
     false
 }
+
+pub fn network_info() -> NetworkInfo {
+    NetworkInfo::default()
+}
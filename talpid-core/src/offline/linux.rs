@@ -1,16 +1,53 @@
-use crate::tunnel_state_machine::TunnelCommand;
+use super::{captive_portal::CaptivePortalMonitor, NetworkInfoListener};
+use crate::{
+    linux::{parse_default_routes, ROUTE_TABLE_PATH, TUNNEL_TABLE_ID},
+    tunnel_state_machine::TunnelCommand,
+};
 use futures::{future::Either, sync::mpsc::UnboundedSender, Future, Stream};
-use log::{error, warn};
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use log::{debug, error, warn};
 use netlink_packet::{
-    AddressMessage, LinkInfo, LinkInfoKind, LinkLayerType, LinkMessage, LinkNla, NetlinkMessage,
+    AddressMessage, LinkInfo, LinkInfoKind, LinkLayerType, LinkMessage, LinkNla, NetlinkFlags,
+    NetlinkMessage, NetlinkPayload, RouteMessage, RouteNla, RtnlMessage,
 };
 use netlink_sys::SocketAddr;
 use rtnetlink::{
-    constants::{RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR, RTMGRP_LINK, RTMGRP_NOTIFY},
+    constants::{
+        AF_INET, AF_INET6, NLM_F_DUMP, NLM_F_REQUEST, RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE,
+        RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK, RTMGRP_NEIGH, RTMGRP_NOTIFY,
+        RTN_UNICAST, RT_SCOPE_UNIVERSE,
+    },
     Connection, Handle,
 };
-use std::{collections::BTreeSet, io, thread};
-use talpid_types::ErrorExt;
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{
+    net::network_info::{ActiveNetwork, InterfaceKind, NetworkInfo},
+    ErrorExt,
+};
+
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+const SYS_NET_PATH: &str = "/sys/class/net";
+/// The kernel's IPv6 routing table, in text form. Missing if IPv6 is disabled.
+const IPV6_ROUTE_TABLE_PATH: &str = "/proc/net/ipv6_route";
+
+/// How long links and routes have to be left alone before connectivity is checked again, so that
+/// a flapping link isn't reported as going offline and online over and over.
+const SETTLE_DELAY: Duration = Duration::from_secs(1);
+/// The longest connectivity goes unchecked while links keep changing.
+const MAX_SETTLE_TIME: Duration = Duration::from_secs(10);
+
+// Message types of neighbour table notifications, from the kernel headers.
+const RTM_NEWNEIGH: u16 = 28;
+const RTM_DELNEIGH: u16 = 29;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -33,14 +70,29 @@ pub enum Error {
 
     #[error(display = "Netlink connection has unexpectedly disconnected")]
     NetlinkDisconnected,
+
+    #[error(display = "Failed to read {}", _0)]
+    ReadNetworkInfoError(&'static str, #[error(cause)] io::Error),
+
+    #[error(display = "Failed to read the routing table from {}", _0)]
+    ReadRoutesError(&'static str, #[error(cause)] io::Error),
 }
 
 pub struct MonitorHandle;
 
-pub fn spawn_monitor(sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHandle> {
+pub fn spawn_monitor(
+    sender: UnboundedSender<TunnelCommand>,
+    network_info_listener: Option<NetworkInfoListener>,
+) -> Result<MonitorHandle> {
     let socket = SocketAddr::new(
         0,
-        RTMGRP_NOTIFY | RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR,
+        RTMGRP_NOTIFY
+            | RTMGRP_LINK
+            | RTMGRP_IPV4_IFADDR
+            | RTMGRP_IPV6_IFADDR
+            | RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_ROUTE
+            | RTMGRP_NEIGH,
     );
 
     let (mut connection, _, messages) = rtnetlink::new_connection_with_messages().unwrap();
@@ -49,10 +101,12 @@ pub fn spawn_monitor(sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHa
         .bind(&socket)
         .map_err(Error::NetlinkBindError)?;
 
-    let link_monitor = LinkMonitor::new(sender);
+    let link_monitor = LinkMonitor::new(sender, network_info_listener);
+    let (change_tx, change_rx) = mpsc::channel();
 
+    thread::spawn(move || link_monitor.run(change_rx));
     thread::spawn(|| {
-        if let Err(error) = monitor_event_loop(connection, messages, link_monitor) {
+        if let Err(error) = monitor_event_loop(connection, messages, change_tx) {
             error!(
                 "{}",
                 error.display_chain_with_msg("Error running link monitor event loop")
@@ -73,21 +127,286 @@ pub fn is_offline() -> bool {
     })
 }
 
-/// Checks if there are no running links or that none of the running links have IP addresses
-/// assigned to them.
+/// Checks that no running link has both a global IP address and a default route of the same IP
+/// family. Links without a default route, like the bridges of container runtimes, can't reach the
+/// internet. The default route can be in any routing table but the tunnel's, since policy routing
+/// may leave the main table without one.
 fn check_if_offline() -> Result<bool> {
     let mut connection = NetlinkConnection::new()?;
     let interfaces = connection.running_interfaces()?;
-
     if interfaces.is_empty() {
-        Ok(true)
-    } else {
-        // Check if the current IP addresses are not assigned to any one of the running interfaces
-        Ok(connection
-            .addresses()?
-            .into_iter()
-            .all(|address| !interfaces.contains(&address.header.index)))
+        return Ok(true);
+    }
+
+    let ipv4_default_links = connection.default_route_links(AF_INET)?;
+    let ipv6_default_links = connection.default_route_links(AF_INET6)?;
+
+    let has_connectivity = connection.addresses()?.into_iter().any(|address| {
+        let index = address.header.index;
+        if !interfaces.contains_key(&index) || address.header.scope != RT_SCOPE_UNIVERSE {
+            return false;
+        }
+        match address.header.family as u16 {
+            AF_INET => ipv4_default_links.contains(&index),
+            AF_INET6 => ipv6_default_links.contains(&index),
+            _ => false,
+        }
+    });
+    Ok(!has_connectivity)
+}
+
+/// Reads the IPv6 routing table. The table is missing if IPv6 is disabled, which is treated like an
+/// empty table.
+fn read_ipv6_routes() -> Result<String> {
+    match fs::read_to_string(IPV6_ROUTE_TABLE_PATH) {
+        Ok(routes) => Ok(routes),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(error) => Err(Error::ReadRoutesError(IPV6_ROUTE_TABLE_PATH, error)),
+    }
+}
+
+/// Returns the interfaces of every usable IPv6 default route, given the contents of
+/// `IPV6_ROUTE_TABLE_PATH`.
+fn parse_ipv6_default_routes(routes: &str) -> HashSet<String> {
+    const DEFAULT_DESTINATION: &str = "00000000000000000000000000000000";
+    const RTF_UP: u32 = 0x1;
+    const RTF_REJECT: u32 = 0x200;
+
+    routes
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 10 || columns[0] != DEFAULT_DESTINATION || columns[1] != "00" {
+                return None;
+            }
+            let flags = u32::from_str_radix(columns[8], 16).ok()?;
+            if flags & RTF_UP == 0 || flags & RTF_REJECT != 0 {
+                return None;
+            }
+            Some(columns[9].to_owned())
+        })
+        .collect()
+}
+
+pub fn gateway_networks() -> Vec<IpNetwork> {
+    get_gateway_networks().unwrap_or_else(|error| {
+        warn!(
+            "{}",
+            error.display_chain_with_msg("Failed to find the networks of the default gateways")
+        );
+        Vec::new()
+    })
+}
+
+/// Returns the networks that are directly reachable through the interfaces of the default
+/// routes. The default gateways are on these networks.
+fn get_gateway_networks() -> Result<Vec<IpNetwork>> {
+    let ipv4_routes = fs::read_to_string(ROUTE_TABLE_PATH)
+        .map_err(|e| Error::ReadRoutesError(ROUTE_TABLE_PATH, e))?;
+    let ipv4_default_interfaces: HashSet<String> = parse_default_routes(&ipv4_routes)
+        .into_iter()
+        .map(|(interface, _gateway)| interface)
+        .collect();
+    let ipv6_routes = read_ipv6_routes()?;
+    let ipv6_default_interfaces = parse_ipv6_default_routes(&ipv6_routes);
+
+    let mut networks = parse_on_link_networks(&ipv4_routes, &ipv4_default_interfaces);
+    for network in parse_ipv6_on_link_networks(&ipv6_routes, &ipv6_default_interfaces) {
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+    Ok(networks)
+}
+
+/// Returns the destinations of the IPv4 routes without a gateway through any of `interfaces`,
+/// given the contents of `ROUTE_TABLE_PATH`.
+fn parse_on_link_networks(routes: &str, interfaces: &HashSet<String>) -> Vec<IpNetwork> {
+    const RTF_UP: u32 = 0x1;
+
+    let mut networks = Vec::new();
+    for line in routes.lines().skip(1) {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 8
+            || !interfaces.contains(columns[0])
+            || columns[2] != "00000000"
+            || columns[7] == "00000000"
+        {
+            continue;
+        }
+        let parse_hex = |column: &str| u32::from_str_radix(column, 16).ok();
+        let (destination, flags, mask) = match (
+            parse_hex(columns[1]),
+            parse_hex(columns[3]),
+            parse_hex(columns[7]),
+        ) {
+            (Some(destination), Some(flags), Some(mask)) => (destination, flags, mask),
+            _ => continue,
+        };
+        if flags & RTF_UP == 0 {
+            continue;
+        }
+        // Like the gateway, the destination and mask are printed in host byte order.
+        let destination = Ipv4Addr::from(u32::from_be(destination));
+        let prefix = mask.count_ones() as u8;
+        if let Ok(network) = Ipv4Network::new(destination, prefix) {
+            let network = IpNetwork::V4(network);
+            if !networks.contains(&network) {
+                networks.push(network);
+            }
+        }
     }
+    networks
+}
+
+/// Returns the destinations of the unicast IPv6 routes without a next hop through any of
+/// `interfaces`, given the contents of `IPV6_ROUTE_TABLE_PATH`.
+fn parse_ipv6_on_link_networks(routes: &str, interfaces: &HashSet<String>) -> Vec<IpNetwork> {
+    const NO_NEXT_HOP: &str = "00000000000000000000000000000000";
+    const RTF_UP: u32 = 0x1;
+    const RTF_REJECT: u32 = 0x200;
+    const RTF_LOCAL: u32 = 0x8000_0000;
+
+    routes
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 10
+                || !interfaces.contains(columns[9])
+                || columns[4] != NO_NEXT_HOP
+                || columns[0].starts_with("ff")
+            {
+                return None;
+            }
+            let prefix = u8::from_str_radix(columns[1], 16).ok()?;
+            let flags = u32::from_str_radix(columns[8], 16).ok()?;
+            if prefix == 0 || flags & RTF_UP == 0 || flags & (RTF_REJECT | RTF_LOCAL) != 0 {
+                return None;
+            }
+            let destination = Ipv6Addr::from(u128::from_str_radix(columns[0], 16).ok()?);
+            Ipv6Network::new(destination, prefix)
+                .ok()
+                .map(IpNetwork::V6)
+        })
+        .collect()
+}
+
+pub fn network_info() -> NetworkInfo {
+    get_network_info().unwrap_or_else(|error| {
+        warn!(
+            "{}",
+            error.display_chain_with_msg("Failed to identify the connected networks")
+        );
+        NetworkInfo::default()
+    })
+}
+
+/// Identifies the networks behind every physical interface that has an IPv4 default route.
+fn get_network_info() -> Result<NetworkInfo> {
+    let routes = fs::read_to_string(ROUTE_TABLE_PATH)
+        .map_err(|e| Error::ReadNetworkInfoError(ROUTE_TABLE_PATH, e))?;
+    let arp_table = fs::read_to_string(ARP_TABLE_PATH)
+        .map_err(|e| Error::ReadNetworkInfoError(ARP_TABLE_PATH, e))?;
+    let neighbours = parse_arp_table(&arp_table);
+
+    let mut networks: Vec<ActiveNetwork> = Vec::new();
+    for (interface, gateway) in parse_default_routes(&routes) {
+        if networks
+            .iter()
+            .any(|network| network.interface == interface)
+        {
+            continue;
+        }
+        let kind = match interface_kind(&interface) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let ssid = match kind {
+            InterfaceKind::Wireless => wireless_ssid(&interface),
+            _ => None,
+        };
+        let gateway_mac = neighbours.get(&(interface.clone(), gateway)).cloned();
+        networks.push(ActiveNetwork {
+            interface,
+            kind,
+            ssid,
+            gateway_mac,
+        });
+    }
+    Ok(NetworkInfo { networks })
+}
+
+/// Maps interface and IP address pairs to the hardware address of the neighbour.
+fn parse_arp_table(arp_table: &str) -> HashMap<(String, Ipv4Addr), String> {
+    const ATF_COM: u32 = 0x2;
+
+    arp_table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 6 {
+                return None;
+            }
+            let flags = u32::from_str_radix(columns[2].trim_start_matches("0x"), 16).ok()?;
+            if flags & ATF_COM == 0 {
+                return None;
+            }
+            let address = columns[0].parse().ok()?;
+            Some(((columns[5].to_owned(), address), columns[3].to_lowercase()))
+        })
+        .collect()
+}
+
+/// Classifies an interface. Returns `None` for loopback and tunnel interfaces, since those never
+/// identify the network the device is connected to.
+fn interface_kind(interface: &str) -> Option<InterfaceKind> {
+    const ARPHRD_ETHER: &str = "1";
+    const ARPHRD_LOOPBACK: &str = "772";
+    const ARPHRD_NONE: &str = "65534";
+
+    let interface_path = Path::new(SYS_NET_PATH).join(interface);
+    if interface_path.join("tun_flags").exists() {
+        return None;
+    }
+    if interface_path.join("wireless").exists() || interface_path.join("phy80211").exists() {
+        return Some(InterfaceKind::Wireless);
+    }
+    match fs::read_to_string(interface_path.join("type")) {
+        Ok(ref link_type) if link_type.trim() == ARPHRD_ETHER => Some(InterfaceKind::Wired),
+        Ok(ref link_type)
+            if link_type.trim() == ARPHRD_LOOPBACK || link_type.trim() == ARPHRD_NONE =>
+        {
+            None
+        }
+        _ => Some(InterfaceKind::Other),
+    }
+}
+
+fn wireless_ssid(interface: &str) -> Option<String> {
+    match duct::cmd!("iw", "dev", interface, "link")
+        .stderr_null()
+        .read()
+    {
+        Ok(output) => parse_iw_ssid(&output),
+        Err(error) => {
+            debug!(
+                "{}",
+                error.display_chain_with_msg(&format!("Failed to get SSID of {}", interface))
+            );
+            None
+        }
+    }
+}
+
+fn parse_iw_ssid(output: &str) -> Option<String> {
+    const SSID_PREFIX: &str = "SSID: ";
+
+    output
+        .lines()
+        .map(str::trim_start)
+        .find(|line| line.starts_with(SSID_PREFIX))
+        .map(|line| line[SSID_PREFIX.len()..].to_owned())
 }
 
 struct NetlinkConnection {
@@ -117,14 +436,43 @@ impl NetlinkConnection {
         self.execute_request(self.handle.link().get().execute().collect())
     }
 
-    /// List all unique interface indices that have a running link.
-    pub fn running_interfaces(&mut self) -> Result<BTreeSet<u32>> {
+    /// Maps the index of every running link that can provide connectivity to its name.
+    pub fn running_interfaces(&mut self) -> Result<BTreeMap<u32, String>> {
         let links = self.links()?;
 
         Ok(links
             .into_iter()
             .filter(link_provides_connectivity)
-            .map(|link| link.header.index)
+            .filter_map(|link| {
+                let index = link.header.index;
+                link.nlas.into_iter().find_map(|nla| match nla {
+                    LinkNla::IfName(name) => Some((index, name)),
+                    _ => None,
+                })
+            })
+            .collect())
+    }
+
+    /// Returns the indices of the links that have a default route of `address_family`, in any
+    /// routing table except the tunnel's.
+    pub fn default_route_links(&mut self, address_family: u16) -> Result<HashSet<u32>> {
+        let mut message = RouteMessage::default();
+        message.header.address_family = address_family as u8;
+        let mut request = NetlinkMessage::from(RtnlMessage::GetRoute(message));
+        request.header.flags = NetlinkFlags::from(NLM_F_REQUEST | NLM_F_DUMP);
+
+        let routes = self
+            .handle
+            .request(request)
+            .filter_map(|response| match response.payload {
+                NetlinkPayload::Rtnl(RtnlMessage::NewRoute(route)) => Some(route),
+                _ => None,
+            })
+            .collect();
+        Ok(self
+            .execute_request(routes)?
+            .iter()
+            .filter_map(default_route_link)
             .collect())
     }
 
@@ -150,6 +498,28 @@ impl NetlinkConnection {
     }
 }
 
+/// Returns the index of the link `route` goes through, if it's a unicast default route outside
+/// the tunnel's routing table.
+fn default_route_link(route: &RouteMessage) -> Option<u32> {
+    if route.header.destination_length != 0 || route.header.kind != RTN_UNICAST {
+        return None;
+    }
+    // Table IDs that don't fit in the header are only given as an attribute.
+    let mut table = u32::from(route.header.table);
+    let mut link = None;
+    for nla in route.nlas.iter() {
+        match nla {
+            RouteNla::Table(id) => table = *id,
+            RouteNla::Oif(index) => link = Some(*index),
+            _ => (),
+        }
+    }
+    if table == TUNNEL_TABLE_ID {
+        return None;
+    }
+    link
+}
+
 fn link_provides_connectivity(link: &LinkMessage) -> bool {
     // Some tunnels have the link layer type set to None
     link.header.link_layer_type != LinkLayerType::Loopback
@@ -167,7 +537,7 @@ fn is_virtual_interface(link: &LinkMessage) -> bool {
                 if let LinkInfo::Kind(ref kind) = info {
                     use LinkInfoKind::*;
                     return match kind {
-                        Dummy | Bridge | Tun | Nlmon | IpTun => true,
+                        Dummy | Tun | Nlmon | IpTun => true,
                         _ => false,
                     };
                 }
@@ -177,43 +547,146 @@ fn is_virtual_interface(link: &LinkMessage) -> bool {
     false
 }
 
+/// What a netlink notification was about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// Links, addresses or routes changed, which can affect connectivity.
+    Links,
+    /// The neighbour table changed, like when the hardware address of a gateway is resolved.
+    Neighbours,
+}
+
+impl Change {
+    fn from_message_type(message_type: u16) -> Self {
+        match message_type {
+            RTM_NEWNEIGH | RTM_DELNEIGH => Change::Neighbours,
+            _ => Change::Links,
+        }
+    }
+}
+
+/// Notifies the link monitor of every change to the links, addresses, routes and neighbours. When
+/// this returns, `change_tx` is dropped, which stops the link monitor.
 fn monitor_event_loop(
     connection: Connection,
     channel: impl Stream<Item = NetlinkMessage, Error = ()>,
-    mut link_monitor: LinkMonitor,
+    change_tx: mpsc::Sender<Change>,
 ) -> Result<()> {
     let monitor = channel
-        .for_each(|_message| {
-            link_monitor.update();
+        .for_each(|message| {
+            let _ = change_tx.send(Change::from_message_type(message.header.message_type));
             Ok(())
         })
         .map_err(|_| Error::MonitorNetlinkError);
 
     // Under normal circumstances, this runs forever.
-    let result = connection
+    connection
         .map_err(Error::NetlinkError)
         .join(monitor)
         .wait()
-        .map(|_| ());
-    // But if it fails, it should fail open.
-    link_monitor.reset();
-    result
+        .map(|_| ())
 }
 
 struct LinkMonitor {
     is_offline: bool,
+    captive_portal: CaptivePortalMonitor,
     sender: UnboundedSender<TunnelCommand>,
+    network_info: NetworkInfo,
+    network_info_listener: Option<NetworkInfoListener>,
 }
 
 impl LinkMonitor {
-    pub fn new(sender: UnboundedSender<TunnelCommand>) -> Self {
+    pub fn new(
+        sender: UnboundedSender<TunnelCommand>,
+        mut network_info_listener: Option<NetworkInfoListener>,
+    ) -> Self {
         let is_offline = is_offline();
+        let network_info = match network_info_listener {
+            Some(ref mut listener) => {
+                let network_info = network_info();
+                listener(network_info.clone());
+                network_info
+            }
+            None => NetworkInfo::default(),
+        };
+
+        LinkMonitor {
+            is_offline,
+            captive_portal: CaptivePortalMonitor::spawn(sender.clone()),
+            sender,
+            network_info,
+            network_info_listener,
+        }
+    }
 
-        LinkMonitor { is_offline, sender }
+    /// Checks connectivity once changes to the links have settled. Fails open when `changes` is
+    /// closed.
+    fn run(mut self, changes: mpsc::Receiver<Change>) {
+        self.captive_portal.check(self.is_offline);
+        while let Ok(change) = changes.recv() {
+            let links_changed = match Self::wait_until_settled(&changes, change) {
+                Some(links_changed) => links_changed,
+                None => break,
+            };
+            if links_changed {
+                self.update();
+            } else if self.has_unresolved_gateway() {
+                // The hardware address of a gateway is often resolved after its route is added.
+                self.update_network_info();
+            }
+        }
+        self.reset();
+    }
+
+    /// Waits until no change has arrived for `SETTLE_DELAY`, or for at most `MAX_SETTLE_TIME`.
+    /// Once links have changed, neighbour changes no longer delay the check. Returns whether any
+    /// links changed, or `None` if `changes` was closed.
+    fn wait_until_settled(changes: &mpsc::Receiver<Change>, first_change: Change) -> Option<bool> {
+        let give_up = Instant::now() + MAX_SETTLE_TIME;
+        let mut links_changed = first_change == Change::Links;
+        let mut settled = Instant::now() + SETTLE_DELAY;
+        loop {
+            let now = Instant::now();
+            let deadline = cmp::min(settled, give_up);
+            if now >= deadline {
+                return Some(links_changed);
+            }
+            match changes.recv_timeout(deadline - now) {
+                Ok(change) => {
+                    if change == Change::Links || !links_changed {
+                        settled = Instant::now() + SETTLE_DELAY;
+                    }
+                    links_changed |= change == Change::Links;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => return Some(links_changed),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    fn has_unresolved_gateway(&self) -> bool {
+        self.network_info_listener.is_some()
+            && self
+                .network_info
+                .networks
+                .iter()
+                .any(|network| network.gateway_mac.is_none())
     }
 
     pub fn update(&mut self) {
         self.set_is_offline(is_offline());
+        self.captive_portal.check(self.is_offline);
+        self.update_network_info();
+    }
+
+    fn update_network_info(&mut self) {
+        if let Some(ref mut listener) = self.network_info_listener {
+            let network_info = network_info();
+            if network_info != self.network_info {
+                self.network_info = network_info.clone();
+                listener(network_info);
+            }
+        }
     }
 
     fn set_is_offline(&mut self, is_offline: bool) {
@@ -225,8 +698,165 @@ impl LinkMonitor {
         }
     }
 
-    /// Allow the offline check to fail open.
+    /// Allow the offline check to fail open. The captive portal monitor does the same when it's
+    /// dropped along with the link monitor.
     fn reset(&mut self) {
         let _ = self.sender.unbounded_send(TunnelCommand::IsOffline(false));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROUTES: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlp2s0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlp2s0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+enp0s31f6\t00000000\t01000A0A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+";
+
+    const ARP_TABLE: &str = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         AA:BB:CC:DD:EE:FF     *        wlp2s0
+10.10.0.1        0x1         0x0         00:00:00:00:00:00     *        enp0s31f6
+";
+
+    #[test]
+    fn parses_default_routes() {
+        assert_eq!(
+            parse_default_routes(ROUTES),
+            vec![
+                ("wlp2s0".to_owned(), Ipv4Addr::new(192, 168, 1, 1)),
+                ("enp0s31f6".to_owned(), Ipv4Addr::new(10, 10, 0, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_ipv6_default_routes() {
+        let routes = "\
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+fe800000000000000000000000000001 00000400 00000001 00000000 00000003 wlp2s0
+20010db8000000000000000000000000 40 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 00000100 00000001 00000000 00000001 docker0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 ffffffff 00000001 00000000 00200200 lo
+";
+        let interfaces = parse_ipv6_default_routes(routes);
+        assert_eq!(interfaces.len(), 1);
+        assert!(interfaces.contains("wlp2s0"));
+    }
+
+    #[test]
+    fn parses_gateway_networks() {
+        let interfaces = ["wlp2s0".to_owned()].iter().cloned().collect();
+        assert_eq!(
+            parse_on_link_networks(ROUTES, &interfaces),
+            vec!["192.168.1.0/24".parse::<IpNetwork>().unwrap()]
+        );
+
+        let ipv6_routes = "\
+20010db8000000000000000000000000 40 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 00000100 00000001 00000000 00000001 wlp2s0
+20010db8000000000000000000000005 80 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 00000000 00000002 00000000 80200001 wlp2s0
+ff000000000000000000000000000000 08 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 00000100 00000004 00000000 00000001 wlp2s0
+20010db8000100000000000000000000 40 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 00000100 00000001 00000000 00000001 docker0
+";
+        assert_eq!(
+            parse_ipv6_on_link_networks(ipv6_routes, &interfaces),
+            vec!["2001:db8::/64".parse::<IpNetwork>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn finds_default_route_links_outside_tunnel_table() {
+        const MAIN_TABLE: u32 = 254;
+        const VPN_TABLE: u32 = 1000;
+        // The kernel puts this in the header of routes in tables with larger IDs.
+        const RT_TABLE_COMPAT: u8 = 252;
+
+        let default_route = |table: u32, link: u32| {
+            let mut route = RouteMessage::default();
+            route.header.kind = RTN_UNICAST;
+            route.header.table = if table < 256 {
+                table as u8
+            } else {
+                RT_TABLE_COMPAT
+            };
+            route.nlas.push(RouteNla::Table(table));
+            route.nlas.push(RouteNla::Oif(link));
+            route
+        };
+
+        assert_eq!(default_route_link(&default_route(MAIN_TABLE, 2)), Some(2));
+        assert_eq!(default_route_link(&default_route(VPN_TABLE, 3)), Some(3));
+        assert_eq!(default_route_link(&default_route(TUNNEL_TABLE_ID, 4)), None);
+
+        let mut subnet_route = default_route(MAIN_TABLE, 2);
+        subnet_route.header.destination_length = 24;
+        assert_eq!(default_route_link(&subnet_route), None);
+    }
+
+    #[test]
+    fn parses_complete_arp_entries() {
+        let neighbours = parse_arp_table(ARP_TABLE);
+        assert_eq!(neighbours.len(), 1);
+        assert_eq!(
+            neighbours.get(&("wlp2s0".to_owned(), Ipv4Addr::new(192, 168, 1, 1))),
+            Some(&"aa:bb:cc:dd:ee:ff".to_owned())
+        );
+    }
+
+    #[test]
+    fn classifies_changes() {
+        const RTM_NEWLINK: u16 = 16;
+        const RTM_NEWROUTE: u16 = 24;
+        assert_eq!(Change::from_message_type(RTM_NEWLINK), Change::Links);
+        assert_eq!(Change::from_message_type(RTM_NEWROUTE), Change::Links);
+        assert_eq!(Change::from_message_type(RTM_NEWNEIGH), Change::Neighbours);
+        assert_eq!(Change::from_message_type(RTM_DELNEIGH), Change::Neighbours);
+    }
+
+    #[test]
+    fn settles_changes() {
+        let (change_tx, changes) = mpsc::channel();
+        change_tx.send(Change::Links).unwrap();
+        assert_eq!(
+            LinkMonitor::wait_until_settled(&changes, Change::Neighbours),
+            Some(true)
+        );
+        assert_eq!(
+            LinkMonitor::wait_until_settled(&changes, Change::Neighbours),
+            Some(false)
+        );
+
+        // Neighbours changing over and over don't hold back a check of the links.
+        let start = Instant::now();
+        let neighbour_tx = change_tx.clone();
+        thread::spawn(move || {
+            while neighbour_tx.send(Change::Neighbours).is_ok() {
+                thread::sleep(SETTLE_DELAY / 4);
+            }
+        });
+        assert_eq!(
+            LinkMonitor::wait_until_settled(&changes, Change::Links),
+            Some(true)
+        );
+        assert!(start.elapsed() < SETTLE_DELAY * 2);
+
+        drop(change_tx);
+        drop(changes);
+    }
+
+    #[test]
+    fn parses_ssid() {
+        let output = "Connected to aa:bb:cc:dd:ee:ff (on wlp2s0)\n\tSSID: Office Wi-Fi\n\tfreq: \
+                      5180\n";
+        assert_eq!(parse_iw_ssid(output), Some("Office Wi-Fi".to_owned()));
+        assert_eq!(parse_iw_ssid("Not connected.\n"), None);
+    }
+}
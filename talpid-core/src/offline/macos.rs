@@ -1,3 +1,4 @@
+use super::NetworkInfoListener;
 use crate::tunnel_state_machine::TunnelCommand;
 use futures::sync::mpsc::UnboundedSender;
 use log::{debug, trace};
@@ -10,6 +11,7 @@ use system_configuration::{
     },
     dynamic_store::{SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext},
 };
+use talpid_types::net::network_info::NetworkInfo;
 
 
 const PRIMARY_INTERFACE_KEY: &str = "State:/Network/Global/IPv4";
@@ -23,7 +25,10 @@ pub enum Error {
 
 pub struct MonitorHandle;
 
-pub fn spawn_monitor(sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHandle, Error> {
+pub fn spawn_monitor(
+    sender: UnboundedSender<TunnelCommand>,
+    _network_info_listener: Option<NetworkInfoListener>,
+) -> Result<MonitorHandle, Error> {
     let (result_tx, result_rx) = mpsc::channel();
     thread::spawn(move || match create_dynamic_store(sender) {
         Ok(store) => {
@@ -42,6 +47,11 @@ pub fn is_offline() -> bool {
     is_offline
 }
 
+/// Identifying the connected networks is not supported on macOS yet.
+pub fn network_info() -> NetworkInfo {
+    NetworkInfo::default()
+}
+
 fn create_dynamic_store(sender: UnboundedSender<TunnelCommand>) -> Result<SCDynamicStore, Error> {
     let callback_context = SCDynamicStoreCallBackContext {
         callout: primary_interface_change_callback,
@@ -1,5 +1,7 @@
 use crate::tunnel_state_machine::TunnelCommand;
 use futures::sync::mpsc::UnboundedSender;
+use ipnetwork::IpNetwork;
+use talpid_types::net::network_info::NetworkInfo;
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -17,10 +19,36 @@ mod imp;
 #[path = "dummy.rs"]
 mod imp;
 
-pub use self::imp::{is_offline, Error};
+#[cfg(target_os = "linux")]
+pub mod captive_portal;
+
+pub use self::imp::{is_offline, network_info, Error};
+
+/// Returns the networks that the default gateways are on. Only detected on Linux for now, other
+/// platforms return all private networks, since that is where gateways are found.
+pub fn gateway_networks() -> Vec<IpNetwork> {
+    #[cfg(target_os = "linux")]
+    {
+        imp::gateway_networks()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        talpid_types::net::lan::private_networks().to_vec()
+    }
+}
+
+/// Callback that receives a new `NetworkInfo` every time the set of connected networks changes.
+/// Only invoked on Linux for now.
+pub type NetworkInfoListener = Box<dyn FnMut(NetworkInfo) + Send>;
 
 pub struct MonitorHandle(imp::MonitorHandle);
 
-pub fn spawn_monitor(sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHandle, Error> {
-    Ok(MonitorHandle(imp::spawn_monitor(sender)?))
+pub fn spawn_monitor(
+    sender: UnboundedSender<TunnelCommand>,
+    network_info_listener: Option<NetworkInfoListener>,
+) -> Result<MonitorHandle, Error> {
+    Ok(MonitorHandle(imp::spawn_monitor(
+        sender,
+        network_info_listener,
+    )?))
 }
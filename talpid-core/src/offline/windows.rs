@@ -6,6 +6,7 @@
 //! GNU General Public License as published by the Free Software Foundation, either version 3 of
 //! the License, or (at your option) any later version.
 
+use super::NetworkInfoListener;
 use crate::{tunnel_state_machine::TunnelCommand, winnet};
 use futures::sync::mpsc::UnboundedSender;
 use parking_lot::Mutex;
@@ -19,7 +20,7 @@ use std::{
     thread,
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::network_info::NetworkInfo, ErrorExt};
 use winapi::{
     shared::{
         basetsd::LONG_PTR,
@@ -273,7 +274,10 @@ impl SystemState {
 
 pub type MonitorHandle = BroadcastListener;
 
-pub fn spawn_monitor(sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHandle, Error> {
+pub fn spawn_monitor(
+    sender: UnboundedSender<TunnelCommand>,
+    _network_info_listener: Option<NetworkInfoListener>,
+) -> Result<MonitorHandle, Error> {
     BroadcastListener::start(sender)
 }
 
@@ -294,3 +298,8 @@ pub fn is_offline() -> bool {
         }
     }
 }
+
+/// Identifying the connected networks is not supported on Windows yet.
+pub fn network_info() -> NetworkInfo {
+    NetworkInfo::default()
+}
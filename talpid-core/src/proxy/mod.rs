@@ -1,8 +1,10 @@
 mod shadowsocks;
+mod udp2tcp;
 
 pub use std::io::Result;
 
 use self::shadowsocks::ShadowsocksProxyMonitor;
+pub use self::udp2tcp::Udp2TcpProxyMonitor;
 use std::{fmt, path::PathBuf, sync::mpsc};
 use talpid_types::net::openvpn;
 
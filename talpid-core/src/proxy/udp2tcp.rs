@@ -0,0 +1,409 @@
+//! Carries WireGuard over TCP, for networks that block UDP. WireGuard is pointed at a local UDP
+//! socket, and every datagram sent to it is written to a TCP stream to the relay, prefixed with
+//! its length as a big-endian `u16`. Datagrams are read back from the stream the same way and
+//! sent to WireGuard. The counterpart on the relay does the reverse.
+
+use super::{ProxyMonitor, ProxyMonitorCloseHandle, Result, WaitResult};
+use parking_lot::Mutex;
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long to wait for the relay to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the thread reading from the UDP socket checks if the proxy has been closed.
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Largest datagram whose length fits in the two bytes before it.
+const MAX_DATAGRAM_LEN: usize = 0xffff;
+
+pub struct Udp2TcpProxyMonitor {
+    port: u16,
+    stream: TcpStream,
+    closed: Arc<AtomicBool>,
+    exit_rx: mpsc::Receiver<String>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Udp2TcpProxyMonitor {
+    /// Connects to the counterpart at `endpoint`, and starts forwarding datagrams sent to the
+    /// local port.
+    pub fn start(endpoint: SocketAddr) -> Result<Self> {
+        let stream = connect(endpoint)?;
+        stream.set_nodelay(true)?;
+        let udp_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        udp_socket.set_read_timeout(Some(CLOSE_CHECK_INTERVAL))?;
+        let port = udp_socket.local_addr()?.port();
+
+        let closed = Arc::new(AtomicBool::new(false));
+        // Replies go to the first sender, which is WireGuard. Any other local process that sends to
+        // the port is ignored, so it can't take over the tunnel traffic.
+        let client = Arc::new(Mutex::new(None));
+        let (exit_tx, exit_rx) = mpsc::channel();
+
+        let udp_to_tcp = {
+            let udp_socket = udp_socket.try_clone()?;
+            let stream = stream.try_clone()?;
+            let closed = closed.clone();
+            let client = client.clone();
+            let exit_tx = exit_tx.clone();
+            thread::spawn(move || {
+                let reason = forward_udp_to_tcp(udp_socket, stream, &closed, &client);
+                let _ = exit_tx.send(reason);
+            })
+        };
+        let tcp_to_udp = {
+            let stream = stream.try_clone()?;
+            thread::spawn(move || {
+                let reason = forward_tcp_to_udp(stream, udp_socket, &client);
+                let _ = exit_tx.send(reason);
+            })
+        };
+
+        log::debug!(
+            "Forwarding WireGuard from local port {} over TCP to {}",
+            port,
+            endpoint
+        );
+        Ok(Udp2TcpProxyMonitor {
+            port,
+            stream,
+            closed,
+            exit_rx,
+            threads: vec![udp_to_tcp, tcp_to_udp],
+        })
+    }
+}
+
+impl ProxyMonitor for Udp2TcpProxyMonitor {
+    fn close_handle(&mut self) -> Box<dyn ProxyMonitorCloseHandle> {
+        Box::new(Udp2TcpProxyMonitorCloseHandle {
+            stream: self.stream.try_clone().ok(),
+            closed: self.closed.clone(),
+        })
+    }
+
+    fn wait(self: Box<Self>) -> Result<WaitResult> {
+        // Either thread stopping means that the proxy is broken.
+        let reason = self
+            .exit_rx
+            .recv()
+            .unwrap_or_else(|_| "Forwarding threads disappeared".to_owned());
+        let result = if self.closed.load(Ordering::SeqCst) {
+            WaitResult::ProperShutdown
+        } else {
+            WaitResult::UnexpectedExit(reason)
+        };
+
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        Ok(result)
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+struct Udp2TcpProxyMonitorCloseHandle {
+    stream: Option<TcpStream>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ProxyMonitorCloseHandle for Udp2TcpProxyMonitorCloseHandle {
+    fn close(self: Box<Self>) -> Result<()> {
+        self.closed.store(true, Ordering::SeqCst);
+        // Unblocks the thread reading from the stream.
+        match self.stream {
+            Some(stream) => match stream.shutdown(Shutdown::Both) {
+                Err(ref error) if error.kind() == io::ErrorKind::NotConnected => Ok(()),
+                result => result,
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the reason for stopping.
+fn forward_udp_to_tcp(
+    udp_socket: UdpSocket,
+    mut stream: TcpStream,
+    closed: &AtomicBool,
+    client: &Mutex<Option<SocketAddr>>,
+) -> String {
+    let mut frame = vec![0u8; 2 + MAX_DATAGRAM_LEN];
+    while !closed.load(Ordering::SeqCst) {
+        let (len, source) = match udp_socket.recv_from(&mut frame[2..]) {
+            Ok(received) => received,
+            Err(ref error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue;
+            }
+            Err(error) => return format!("Failed to receive from WireGuard: {}", error),
+        };
+        if !accept_source(&udp_socket, client, source) {
+            log::warn!("Dropping datagram from {}, which is not WireGuard", source);
+            continue;
+        }
+        frame[..2].copy_from_slice(&(len as u16).to_be_bytes());
+        if let Err(error) = stream.write_all(&frame[..2 + len]) {
+            return format!("Failed to send to the relay: {}", error);
+        }
+    }
+    "Proxy closed".to_owned()
+}
+
+/// Returns whether a datagram from `source` should be forwarded. The first source is pinned as
+/// the client, and the socket is connected to it, so the kernel drops datagrams from anyone else.
+fn accept_source(
+    udp_socket: &UdpSocket,
+    client: &Mutex<Option<SocketAddr>>,
+    source: SocketAddr,
+) -> bool {
+    let mut client = client.lock();
+    match *client {
+        Some(client) => client == source,
+        None => {
+            if let Err(error) = udp_socket.connect(source) {
+                log::warn!("Failed to connect the proxy socket to WireGuard: {}", error);
+            }
+            *client = Some(source);
+            true
+        }
+    }
+}
+
+/// Returns the reason for stopping.
+fn forward_tcp_to_udp(
+    mut stream: TcpStream,
+    udp_socket: UdpSocket,
+    client: &Mutex<Option<SocketAddr>>,
+) -> String {
+    let mut datagram = vec![0u8; MAX_DATAGRAM_LEN];
+    loop {
+        let mut header = [0u8; 2];
+        if let Err(error) = stream.read_exact(&mut header) {
+            return if error.kind() == io::ErrorKind::UnexpectedEof {
+                "Connection closed by the relay".to_owned()
+            } else {
+                format!("Failed to receive from the relay: {}", error)
+            };
+        }
+        let len = u16::from_be_bytes(header) as usize;
+        if let Err(error) = stream.read_exact(&mut datagram[..len]) {
+            return format!("Failed to receive from the relay: {}", error);
+        }
+
+        let destination = *client.lock();
+        match destination {
+            Some(destination) => {
+                if let Err(error) = udp_socket.send_to(&datagram[..len], destination) {
+                    log::debug!("Failed to send datagram to WireGuard: {}", error);
+                }
+            }
+            None => log::debug!("Dropping datagram from the relay, WireGuard hasn't sent any"),
+        }
+    }
+}
+
+/// Connects to the relay outside of the tunnel, like WireGuard's own packets are sent.
+#[cfg(target_os = "linux")]
+fn connect(endpoint: SocketAddr) -> io::Result<TcpStream> {
+    use std::{mem, os::unix::io::FromRawFd};
+
+    let family = match endpoint {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owning the socket from the start closes it on every error.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let mark = crate::linux::TUNNEL_FWMARK;
+    set_socket_option(fd, libc::SO_MARK, &mark)?;
+    // Connecting times out after the send timeout on Linux.
+    let timeout = libc::timeval {
+        tv_sec: CONNECT_TIMEOUT.as_secs() as libc::time_t,
+        tv_usec: 0,
+    };
+    set_socket_option(fd, libc::SO_SNDTIMEO, &timeout)?;
+
+    let result = match endpoint {
+        SocketAddr::V4(address) => {
+            let sockaddr = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: address.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(*address.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                libc::connect(
+                    fd,
+                    &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(address) => {
+            let sockaddr = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: address.port().to_be(),
+                sin6_flowinfo: address.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: address.ip().octets(),
+                },
+                sin6_scope_id: address.scope_id(),
+            };
+            unsafe {
+                libc::connect(
+                    fd,
+                    &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Only connecting should time out.
+    let no_timeout = libc::timeval {
+        tv_sec: 0,
+        tv_usec: 0,
+    };
+    set_socket_option(fd, libc::SO_SNDTIMEO, &no_timeout)?;
+    Ok(stream)
+}
+
+#[cfg(target_os = "linux")]
+fn set_socket_option<T>(fd: libc::c_int, option: libc::c_int, value: &T) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Connects to the relay. The route to the relay already leads outside of the tunnel.
+#[cfg(not(target_os = "linux"))]
+fn connect(endpoint: SocketAddr) -> io::Result<TcpStream> {
+    TcpStream::connect_timeout(&endpoint, CONNECT_TIMEOUT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Starts a counterpart that sends every frame it receives straight back. Returns its
+    /// address.
+    fn start_echo_counterpart() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let _ = io::copy(&mut &stream, &mut writer);
+        });
+        address
+    }
+
+    fn start_client(proxy: &Udp2TcpProxyMonitor) -> UdpSocket {
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        client.connect((Ipv4Addr::LOCALHOST, proxy.port())).unwrap();
+        client
+    }
+
+    #[test]
+    fn test_datagrams_are_echoed() {
+        let proxy = Udp2TcpProxyMonitor::start(start_echo_counterpart()).unwrap();
+        let client = start_client(&proxy);
+
+        let mut buffer = [0u8; 2048];
+        for datagram in &[&b"handshake"[..], &[0u8; 1420][..], &b""[..]] {
+            client.send(datagram).unwrap();
+            let len = client.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], *datagram);
+        }
+    }
+
+    #[test]
+    fn test_other_senders_are_ignored() {
+        let proxy = Udp2TcpProxyMonitor::start(start_echo_counterpart()).unwrap();
+        let client = start_client(&proxy);
+        let intruder = start_client(&proxy);
+        intruder
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+
+        let mut buffer = [0u8; 2048];
+        client.send(b"first").unwrap();
+        let len = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"first");
+
+        let _ = intruder.send(b"intruder");
+        client.send(b"second").unwrap();
+        let len = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"second");
+        assert!(intruder.recv(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_close() {
+        let mut proxy = Box::new(Udp2TcpProxyMonitor::start(start_echo_counterpart()).unwrap());
+        proxy.close_handle().close().unwrap();
+        match proxy.wait().unwrap() {
+            WaitResult::ProperShutdown => (),
+            WaitResult::UnexpectedExit(reason) => panic!("Unexpected exit: {}", reason),
+        }
+    }
+
+    #[test]
+    fn test_counterpart_disconnects() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || drop(listener.accept().unwrap()));
+
+        let proxy = Box::new(Udp2TcpProxyMonitor::start(address).unwrap());
+        match proxy.wait().unwrap() {
+            WaitResult::UnexpectedExit(_) => (),
+            WaitResult::ProperShutdown => panic!("Disconnect was not detected"),
+        }
+    }
+
+    #[test]
+    fn test_no_counterpart() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(Udp2TcpProxyMonitor::start(address).is_err());
+    }
+}
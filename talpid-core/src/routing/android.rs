@@ -13,6 +13,7 @@ pub struct RouteManagerImpl;
 impl RouteManagerImpl {
     pub fn new(
         _required_routes: HashMap<IpNetwork, super::NetNode>,
+        _journal: crate::journal::SystemStateJournal,
         _shutdown_rx: oneshot::Receiver<oneshot::Sender<()>>,
     ) -> Result<Self, Error> {
         Ok(Self {})
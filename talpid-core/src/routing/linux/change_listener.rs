@@ -1,16 +1,27 @@
 use super::{
     super::{Node, Route},
-    RouteChange,
+    Error as RoutingError, RouteChange, RouteRequest, RoutingTable, MAIN_TABLE_ID,
+};
+use futures::{
+    future::{self, Either},
+    sync::mpsc,
+    Async, Future, Poll, Stream,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io,
+    net::IpAddr,
 };
-use futures::{future::Either, sync::mpsc, Async, Future, Stream};
-use std::{collections::BTreeMap, io, net::IpAddr};
 
 use netlink_packet::{
-    LinkMessage, LinkNla, NetlinkMessage, NetlinkPayload, RouteMessage, RouteNla, RtnlMessage,
+    LinkMessage, LinkNla, NetlinkFlags, NetlinkMessage, NetlinkPayload, RouteMessage, RouteNla,
+    RtnlMessage,
 };
 use netlink_sys::SocketAddr;
 use rtnetlink::constants::{
-    AF_INET, AF_INET6, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_ROUTE, RTMGRP_LINK, RTMGRP_NOTIFY,
+    AF_INET, AF_INET6, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_REPLACE, NLM_F_REQUEST,
+    RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_ROUTE, RTMGRP_LINK, RTMGRP_NOTIFY, RTN_UNICAST, RTPROT_BOOT,
+    RT_SCOPE_LINK, RT_SCOPE_UNIVERSE, RT_TABLE_MAIN, RT_TABLE_UNSPEC,
 };
 
 #[derive(err_derive::Error, Debug)]
@@ -33,12 +44,19 @@ pub enum Error {
     BindError(#[error(cause)] io::Error),
     #[error(display = "Netlink connection stopped sending messages")]
     NetlinkConnectionClosed,
+    #[error(display = "The kernel rejected the netlink request")]
+    RequestRejected(#[error(cause)] io::Error),
+    #[error(display = "Failed to find the index of the route's device")]
+    DeviceIndexError(#[error(cause)] crate::linux::IfaceIndexLookupError),
 }
 
 type Result<T> = ::std::result::Result<T, Error>;
 
-pub(super) struct RouteChangeListener {
+/// Listens for changes to the main routing table and makes requests to it, over one netlink
+/// connection.
+pub(crate) struct RouteChangeListener {
     connection: rtnetlink::Connection,
+    handle: rtnetlink::Handle,
     messages: mpsc::UnboundedReceiver<NetlinkMessage>,
     iface_map: BTreeMap<u32, String>,
 }
@@ -55,10 +73,11 @@ impl RouteChangeListener {
             .bind(&addr)
             .map_err(Error::BindError)?;
 
-        let (iface_map, connection) = Self::initialize_link_map(connection, handle)?;
+        let (iface_map, connection) = Self::initialize_link_map(connection, handle.clone())?;
 
         Ok(Self {
             connection,
+            handle,
             messages,
             iface_map,
         })
@@ -79,18 +98,29 @@ impl RouteChangeListener {
                 Ok(None)
             }
 
+            // Routes in other tables don't affect where traffic is routed by default.
+            NetlinkPayload::Rtnl(RtnlMessage::NewRoute(ref route))
+            | NetlinkPayload::Rtnl(RtnlMessage::DelRoute(ref route))
+                if route.header.table != RT_TABLE_MAIN =>
+            {
+                Ok(None)
+            }
             NetlinkPayload::Rtnl(RtnlMessage::NewRoute(new_route)) => {
-                self.get_route(new_route).map(RouteChange::Add).map(Some)
+                Self::get_route(new_route, &self.iface_map)
+                    .map(RouteChange::Add)
+                    .map(Some)
             }
             NetlinkPayload::Rtnl(RtnlMessage::DelRoute(old_route)) => {
-                self.get_route(old_route).map(RouteChange::Remove).map(Some)
+                Self::get_route(old_route, &self.iface_map)
+                    .map(RouteChange::Remove)
+                    .map(Some)
             }
             _ => Ok(None),
         }
     }
 
     // Tries to coax a Route out of a RouteMessage
-    fn get_route(&self, msg: RouteMessage) -> Result<Route> {
+    fn get_route(msg: RouteMessage, iface_map: &BTreeMap<u32, String>) -> Result<Route> {
         let mut prefix = None;
         let mut node_addr = None;
         let mut device = None;
@@ -103,7 +133,7 @@ impl RouteChangeListener {
         for nla in msg.nlas.iter() {
             match nla {
                 RouteNla::Oif(device_idx) => {
-                    match self.iface_map.get(&device_idx) {
+                    match iface_map.get(&device_idx) {
                         Some(device_name) => device = Some(device_name.to_string()),
                         None => {
                             return Err(Error::UnknownDeviceIndex(*device_idx));
@@ -124,7 +154,7 @@ impl RouteChangeListener {
                         .map(Some)?;
                 }
 
-                // gateway NLAs indicate that this is actually a default route
+                // gateway NLAs hold the next hop of the route
                 RouteNla::Gateway(gateway_ip) => {
                     gateway = Self::parse_ip(&gateway_ip).map(Some)?;
                 }
@@ -136,8 +166,8 @@ impl RouteChangeListener {
             }
         }
 
-        // when a gateway is specified but prefix is none, then this is a default route
-        if prefix.is_none() && gateway.is_some() {
+        // when no destination is specified, then this is a default route
+        if prefix.is_none() && destination_length == 0 {
             prefix = match af_spec as u16 {
                 AF_INET => Some("0.0.0.0/0".parse().expect("failed to parse ipnetwork")),
                 AF_INET6 => Some("::/0".parse().expect("failed to parse ipnetwork")),
@@ -151,7 +181,7 @@ impl RouteChangeListener {
 
 
         let node = Node {
-            ip: node_addr,
+            ip: node_addr.or(gateway),
             device,
         };
 
@@ -159,6 +189,7 @@ impl RouteChangeListener {
             node,
             prefix: prefix.unwrap(),
             metric,
+            table_id: MAIN_TABLE_ID,
         })
     }
 
@@ -210,6 +241,117 @@ impl RouteChangeListener {
     }
 }
 
+impl RouteChangeListener {
+    /// Sends a request that only expects an acknowledgement back.
+    fn request(
+        &self,
+        message: RtnlMessage,
+        flags: u16,
+    ) -> impl Future<Item = (), Error = Error> + Send {
+        let mut request = NetlinkMessage::from(message);
+        request.header.flags = NetlinkFlags::from(flags | NLM_F_REQUEST | NLM_F_ACK);
+
+        let mut handle = self.handle.clone();
+        handle
+            .request(request)
+            .map_err(|error| Error::NetlinkError(failure::Fail::compat(error)))
+            .for_each(|response| match response.payload {
+                NetlinkPayload::Error(ref error) if error.code != 0 => Err(Error::RequestRejected(
+                    io::Error::from_raw_os_error(-error.code),
+                )),
+                _ => Ok(()),
+            })
+    }
+
+    fn dump_default_routes(
+        &self,
+        address_family: u16,
+    ) -> impl Future<Item = Vec<Route>, Error = Error> + Send {
+        let mut message = RouteMessage::default();
+        message.header.address_family = address_family as u8;
+        let mut request = NetlinkMessage::from(RtnlMessage::GetRoute(message));
+        request.header.flags = NetlinkFlags::from(NLM_F_REQUEST | NLM_F_DUMP);
+
+        let iface_map = self.iface_map.clone();
+        let mut handle = self.handle.clone();
+        handle
+            .request(request)
+            .map_err(|error| Error::NetlinkError(failure::Fail::compat(error)))
+            .filter_map(move |response| match response.payload {
+                NetlinkPayload::Rtnl(RtnlMessage::NewRoute(route)) => {
+                    if route.header.table == RT_TABLE_MAIN && route.header.destination_length == 0 {
+                        Self::get_route(route, &iface_map).ok()
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl RoutingTable for RouteChangeListener {
+    fn add_route(&mut self, route: &Route) -> RouteRequest<()> {
+        let message = match route_message(route) {
+            Ok(message) => message,
+            Err(error) => return Box::new(future::err(RoutingError::FailedToAddRoute(error))),
+        };
+        Box::new(
+            self.request(RtnlMessage::NewRoute(message), NLM_F_CREATE | NLM_F_REPLACE)
+                .map_err(RoutingError::FailedToAddRoute),
+        )
+    }
+
+    fn delete_route(&mut self, route: &Route) -> RouteRequest<()> {
+        let message = match route_message(route) {
+            Ok(message) => message,
+            Err(error) => return Box::new(future::err(RoutingError::FailedToRemoveRoute(error))),
+        };
+        Box::new(
+            self.request(RtnlMessage::DelRoute(message), 0)
+                .or_else(|error| match error {
+                    // The route is already gone, for example because its device was removed.
+                    Error::RequestRejected(ref io_error)
+                        if io_error.raw_os_error() == Some(libc::ESRCH) =>
+                    {
+                        Ok(())
+                    }
+                    error => Err(error),
+                })
+                .map_err(RoutingError::FailedToRemoveRoute),
+        )
+    }
+
+    fn get_default_routes(&mut self) -> RouteRequest<HashSet<Route>> {
+        Box::new(
+            self.dump_default_routes(AF_INET)
+                .join(self.dump_default_routes(AF_INET6))
+                .map(|(v4_routes, v6_routes)| {
+                    v4_routes.into_iter().chain(v6_routes.into_iter()).collect()
+                })
+                .map_err(RoutingError::FailedToGetDefaultRoutes),
+        )
+    }
+
+    fn wait_for<T>(&mut self, request: RouteRequest<T>) -> Result<T, RoutingError> {
+        match (&mut self.connection).select2(request).wait() {
+            Ok(Either::A(_)) => Err(RoutingError::ChangeListenerError(
+                Error::NetlinkConnectionClosed,
+            )),
+            Err(Either::A((error, _))) => Err(RoutingError::ChangeListenerError(
+                Error::NetlinkProtocolError(failure::Fail::compat(error)),
+            )),
+            Ok(Either::B((result, _))) => Ok(result),
+            Err(Either::B((error, _))) => Err(error),
+        }
+    }
+
+    fn poll_changes(&mut self) -> Poll<Option<RouteChange>, RoutingError> {
+        self.poll().map_err(RoutingError::ChangeListenerError)
+    }
+}
+
 impl Stream for RouteChangeListener {
     type Item = RouteChange;
     type Error = Error;
@@ -239,3 +381,53 @@ impl Stream for RouteChangeListener {
         }
     }
 }
+
+/// Creates a message describing `route`, for adding or deleting it.
+fn route_message(route: &Route) -> Result<RouteMessage> {
+    let mut message = RouteMessage::default();
+    message.header.address_family = if route.prefix.is_ipv4() {
+        AF_INET as u8
+    } else {
+        AF_INET6 as u8
+    };
+    message.header.destination_length = route.prefix.prefix();
+    // Table IDs that don't fit in the header are only given as an attribute.
+    message.header.table = if route.table_id < 256 {
+        route.table_id as u8
+    } else {
+        RT_TABLE_UNSPEC
+    };
+    message.nlas.push(RouteNla::Table(route.table_id));
+    message.header.protocol = RTPROT_BOOT;
+    message.header.kind = RTN_UNICAST;
+    // Routes without a gateway are only valid on the link, the same as `ip route` assumes.
+    message.header.scope = if route.node.get_address().is_some() {
+        RT_SCOPE_UNIVERSE
+    } else {
+        RT_SCOPE_LINK
+    };
+
+    if route.prefix.prefix() > 0 {
+        message
+            .nlas
+            .push(RouteNla::Destination(ip_to_bytes(route.prefix.network())));
+    }
+    if let Some(address) = route.node.get_address() {
+        message.nlas.push(RouteNla::Gateway(ip_to_bytes(address)));
+    }
+    if let Some(device) = route.node.get_device() {
+        let index = crate::linux::iface_index(device).map_err(Error::DeviceIndexError)?;
+        message.nlas.push(RouteNla::Oif(index));
+    }
+    if let Some(metric) = route.metric {
+        message.nlas.push(RouteNla::Priority(metric));
+    }
+    Ok(message)
+}
+
+fn ip_to_bytes(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
@@ -1,17 +1,29 @@
-use super::{NetNode, Node, Route};
+use super::{NetNode, Node, PolicyRouting, Route};
+use crate::journal::{JournalPolicyRouting, JournalRoute, SystemStateJournal};
 
 use ipnetwork::IpNetwork;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
-    io,
-    process::{Command, Stdio},
+    fs, mem,
+    path::Path,
 };
 
 mod change_listener;
 use change_listener::{Error as RouteChangeListenerError, RouteChangeListener};
 
-use futures::{sync::oneshot, Async, Future, IntoFuture, Stream};
-use tokio_process::CommandExt;
+mod rules;
+use rules::Rule;
+
+use futures::{sync::oneshot, Async, Future, Poll};
+
+/// The ID of the main routing table.
+pub const MAIN_TABLE_ID: u32 = 254;
+
+/// Makes the reverse path filter take firewall marks into account, so that the replies to marked
+/// packets aren't dropped when policy based routing is used.
+const SRC_VALID_MARK_PATH: &str = "/proc/sys/net/ipv4/conf/all/src_valid_mark";
+/// Contains a directory for each network device.
+const NETWORK_DEVICES_PATH: &str = "/sys/class/net";
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -20,27 +32,15 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     /// Failed to add route.
     #[error(display = "Failed to add route")]
-    FailedToAddRoute,
+    FailedToAddRoute(#[error(cause)] RouteChangeListenerError),
 
     /// Failed to remove route.
     #[error(display = "Failed to remove route")]
-    FailedToRemoveRoute,
-
-    /// Error while running "ip route".
-    #[error(display = "Error while running \"ip route\"")]
-    FailedToRunIp(#[error(cause)] io::Error),
-
-    /// Invocation of `ip route` ended with a non-zero exit code
-    #[error(display = "ip returend a non-zero exit code")]
-    ErrorIpFailed,
+    FailedToRemoveRoute(#[error(cause)] RouteChangeListenerError),
 
-    /// Received unexpected output from `ip route`
-    #[error(display = "Received unexpected output from \"ip\"")]
-    UnexpectedOutput,
-
-    /// No default route exists
-    #[error(display = "No default route in \"ip route\" output")]
-    NoDefaultRoute,
+    /// Failed to read the default routes from the routing table.
+    #[error(display = "Failed to get the default routes")]
+    FailedToGetDefaultRoutes(#[error(cause)] RouteChangeListenerError),
 
     /// Route table change stream failed.
     #[error(display = "Route change listener failed")]
@@ -49,10 +49,38 @@ pub enum Error {
     /// Route table change stream failed.
     #[error(display = "Route change listener closed unexpectedly")]
     ChangeListenerClosed,
+
+    /// Failed to add the routing rules used for policy based routing.
+    #[error(display = "Failed to add routing rules")]
+    FailedToAddRoutingRules(#[error(cause)] rules::Error),
+}
+
+/// A request to the routing table that has not completed yet.
+pub(crate) type RouteRequest<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
+
+/// The operations the route manager performs on the routing table. Implemented with rtnetlink by
+/// `RouteChangeListener`, which is what's used outside of tests.
+pub(crate) trait RoutingTable: Send {
+    /// Adds a route to the main table, replacing any route to the same destination.
+    fn add_route(&mut self, route: &Route) -> RouteRequest<()>;
+
+    /// Removes a route from the main table.
+    fn delete_route(&mut self, route: &Route) -> RouteRequest<()>;
+
+    /// Returns all default routes in the main table.
+    fn get_default_routes(&mut self) -> RouteRequest<HashSet<Route>>;
+
+    /// Blocks until the given request has completed. Only to be used before the route manager is
+    /// polled as a future.
+    fn wait_for<T>(&mut self, request: RouteRequest<T>) -> Result<T>;
+
+    /// Polls for changes to the main table. Also drives the requests made to the table, so it has
+    /// to be polled until all requests have completed.
+    fn poll_changes(&mut self) -> Poll<Option<RouteChange>, Error>;
 }
 
-pub struct RouteManagerImpl {
-    changes: RouteChangeListener,
+pub(crate) struct RouteManagerImpl<T: RoutingTable = RouteChangeListener> {
+    table: T,
 
     // currently added routes
     added_routes: HashSet<Route>,
@@ -67,6 +95,12 @@ pub struct RouteManagerImpl {
     needed_changes: VecDeque<RouteChange>,
     pending_change: Option<PendingChange>,
 
+    // routing rules for policy based routing, removed when shutting down
+    rules: Vec<Rule>,
+
+    // records the added routes and rules, so that they can be removed after a crash
+    journal: SystemStateJournal,
+
     // if the stop channel is set, the future should wind down - remove added routes and send a
     // signal.
     shutdown_finished_tx: Option<oneshot::Sender<()>>,
@@ -74,14 +108,68 @@ pub struct RouteManagerImpl {
     should_shut_down: bool,
 }
 
-impl RouteManagerImpl {
-    /// Creates a new RouteManager.
+impl RouteManagerImpl<RouteChangeListener> {
+    /// Creates a new RouteManager. If `policy` is set, the routes through real nodes are added to
+    /// the table of the policy instead of the main table.
     pub fn new(
         required_routes: HashMap<IpNetwork, NetNode>,
+        policy: Option<PolicyRouting>,
+        journal: SystemStateJournal,
         shutdown_rx: oneshot::Receiver<oneshot::Sender<()>>,
     ) -> Result<Self> {
-        let changes = RouteChangeListener::new().map_err(Error::ChangeListenerError)?;
+        let table = RouteChangeListener::new().map_err(Error::ChangeListenerError)?;
+        let (table_id, rules) = match policy {
+            Some(policy) => {
+                if let Err(error) = fs::write(SRC_VALID_MARK_PATH, b"1") {
+                    log::warn!("Failed to enable src_valid_mark: {}", error);
+                }
+                journal.update(|journal| {
+                    journal.policy_routing = Some(JournalPolicyRouting {
+                        fwmark: policy.fwmark,
+                        table_id: policy.table_id,
+                    })
+                });
+                (
+                    policy.table_id,
+                    Rule::policy_rules(policy.fwmark, policy.table_id),
+                )
+            }
+            None => (MAIN_TABLE_ID, vec![]),
+        };
+
+        if !rules.is_empty() {
+            if let Err(error) = rules::add_rules(&rules) {
+                delete_rules(&rules, &journal);
+                return Err(Error::FailedToAddRoutingRules(error));
+            }
+        }
+        match Self::with_table(
+            table,
+            required_routes,
+            table_id,
+            journal.clone(),
+            shutdown_rx,
+        ) {
+            Ok(mut manager) => {
+                manager.rules = rules;
+                Ok(manager)
+            }
+            Err(error) => {
+                delete_rules(&rules, &journal);
+                Err(error)
+            }
+        }
+    }
+}
 
+impl<T: RoutingTable> RouteManagerImpl<T> {
+    fn with_table(
+        mut table: T,
+        required_routes: HashMap<IpNetwork, NetNode>,
+        table_id: u32,
+        journal: SystemStateJournal,
+        shutdown_rx: oneshot::Receiver<oneshot::Sender<()>>,
+    ) -> Result<Self> {
         let mut required_normal_routes = HashSet::new();
         let mut required_default_routes = HashSet::new();
         let mut added_routes = HashSet::new();
@@ -89,7 +177,7 @@ impl RouteManagerImpl {
         for (destination, node) in required_routes {
             match node {
                 NetNode::RealNode(node) => {
-                    required_normal_routes.insert(Route::new(node, destination));
+                    required_normal_routes.insert(Route::new(node, destination).table(table_id));
                 }
                 NetNode::DefaultNode => {
                     required_default_routes.insert(destination);
@@ -97,14 +185,17 @@ impl RouteManagerImpl {
             }
         }
 
-        let default_routes = Self::get_default_routes().wait()?;
+        let default_routes_request = table.get_default_routes();
+        let default_routes = table.wait_for(default_routes_request)?;
 
         let best_default_node_v4 = Self::pick_best_default_node(&default_routes, true);
         let best_default_node_v6 = Self::pick_best_default_node(&default_routes, false);
 
         let mut establish_baseline_fn = || -> Result<()> {
             for normal_route in required_normal_routes.iter() {
-                Self::add_route(&normal_route).wait()?;
+                let request = table.add_route(&normal_route);
+                table.wait_for(request)?;
+                journal_added_route(&journal, &normal_route);
                 added_routes.insert(normal_route.clone());
             }
 
@@ -117,7 +208,9 @@ impl RouteManagerImpl {
                     (false, _, Some(default_node)) | (true, Some(default_node), _) => {
                         // best to pick a single node identifier rather than device + ip
                         let route = Route::new(default_node.clone(), *prefix);
-                        Self::add_route(&route).wait()?;
+                        let request = table.add_route(&route);
+                        table.wait_for(request)?;
+                        journal_added_route(&journal, &route);
                         added_routes.insert(route);
                     }
                     // at this point in time, there exists no default route for the given IP version
@@ -131,12 +224,14 @@ impl RouteManagerImpl {
 
         if let Err(e) = establish_baseline_fn() {
             for setup_route in added_routes {
-                if let Err(removal_err) = Self::delete_route(&setup_route).wait() {
-                    log::error!(
+                let request = table.delete_route(&setup_route);
+                match table.wait_for(request) {
+                    Ok(()) => journal_removed_route(&journal, &setup_route),
+                    Err(removal_err) => log::error!(
                         "Failed to remove route whilst cleaning up failed initialization
 of route monitor -{}",
                         removal_err
-                    );
+                    ),
                 }
             }
             return Err(e);
@@ -144,7 +239,7 @@ of route monitor -{}",
 
 
         Ok(Self {
-            changes,
+            table,
 
             required_default_routes,
             added_routes,
@@ -156,6 +251,10 @@ of route monitor -{}",
             needed_changes: VecDeque::new(),
             pending_change: None,
 
+            rules: Vec::new(),
+
+            journal,
+
             shutdown_finished_tx: None,
             shutdown_rx,
             should_shut_down: false,
@@ -164,9 +263,11 @@ of route monitor -{}",
 
     fn process_route_table_change(&mut self) -> Result<()> {
         loop {
-            let change = self.changes.poll().map_err(Error::ChangeListenerError)?;
+            let change = self.table.poll_changes()?;
             match change {
                 Async::NotReady => return Ok(()),
+                // The routes are being removed, so they should no longer follow the default route.
+                Async::Ready(Some(_)) if self.should_shut_down => continue,
                 Async::Ready(Some(RouteChange::Add(route))) => self.process_new_route(route),
                 Async::Ready(Some(RouteChange::Remove(route))) => self.process_deleted_route(route),
                 Async::Ready(None) => return Err(Error::ChangeListenerClosed),
@@ -197,6 +298,7 @@ of route monitor -{}",
             }
         });
         if route.prefix.prefix() == 0 {
+            self.default_routes.remove(&route);
             self.update_default_rotues();
         }
     }
@@ -301,8 +403,8 @@ of route monitor -{}",
             if self.pending_change.is_none() {
                 if let Some(change) = self.needed_changes.pop_front() {
                     let process = match &change {
-                        RouteChange::Add(route) => Self::add_route(route),
-                        RouteChange::Remove(route) => Self::delete_route(route),
+                        RouteChange::Add(route) => self.table.add_route(route),
+                        RouteChange::Remove(route) => self.table.delete_route(route),
                     };
                     self.pending_change = Some(PendingChange { change, process });
                 }
@@ -317,9 +419,11 @@ of route monitor -{}",
                     Async::Ready(_) => {
                         match change.change {
                             RouteChange::Add(route) => {
+                                journal_added_route(&self.journal, &route);
                                 self.added_routes.insert(route);
                             }
                             RouteChange::Remove(route) => {
+                                journal_removed_route(&self.journal, &route);
                                 self.added_routes.remove(&route);
                             }
                         };
@@ -332,115 +436,9 @@ of route monitor -{}",
 
         Ok(self.pending_change.is_none() && self.needed_changes.is_empty())
     }
-
-    fn route_cmd(action: &str, route: &Route) -> Command {
-        let mut cmd = Command::new("ip");
-
-        cmd.arg(ip_vers(&route))
-            .arg("route")
-            .arg(action)
-            .arg(route.prefix.to_string());
-
-        if let Some(addr) = route.node.get_address() {
-            cmd.arg("via").arg(addr.to_string());
-        };
-        if let Some(device) = route.node.get_device() {
-            cmd.arg("dev").arg(device);
-        };
-        if let Some(metric) = route.metric {
-            cmd.arg("metric").arg(metric.to_string());
-        };
-
-        cmd
-    }
-
-    fn run_cmd(mut cmd: Command, err: Error) -> Box<dyn Future<Item = (), Error = Error> + Send> {
-        log::trace!("running cmd - {:?}", &cmd);
-        Box::new(
-            cmd.spawn_async()
-                .into_future()
-                .flatten()
-                .map_err(Error::FailedToRunIp)
-                .and_then(|exit_status| {
-                    if exit_status.success() {
-                        Ok(())
-                    } else {
-                        Err(err)
-                    }
-                }),
-        )
-    }
-
-    fn get_default_routes_inner(
-        ip_version: IpVersion,
-    ) -> impl Future<Item = Vec<Route>, Error = Error> {
-        let mut cmd = Command::new("ip");
-        cmd.arg(ip_version.to_route_arg()).arg("route").arg("show");
-
-        Box::new(
-            cmd.stdout(Stdio::piped())
-                .spawn_async()
-                .map_err(Error::FailedToRunIp)
-                .into_future()
-                .and_then(|proc| proc.wait_with_output().map_err(Error::FailedToRunIp))
-                .and_then(move |output| {
-                    let output_lines = String::from_utf8(output.stdout.clone())
-                        .map_err(|_| Error::UnexpectedOutput)?;
-                    Ok(output_lines
-                        .lines()
-                        .filter_map(|line| {
-                            if line.starts_with("default") {
-                                parse_ip_route_show_line(line, ip_version)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect())
-                }),
-        )
-    }
-
-    /// Adds routes to the system routing table.
-    fn add_route(route: &Route) -> Box<dyn Future<Item = (), Error = Error> + Send> {
-        let cmd = Self::route_cmd("replace", route);
-        Self::run_cmd(cmd, Error::FailedToAddRoute)
-    }
-
-    /// Removes previously set routes. If routes were set for specific tables, the whole tables
-    /// will be removed.
-    fn delete_route(route: &Route) -> Box<dyn Future<Item = (), Error = Error> + Send> {
-        let cmd = Self::route_cmd("delete", route);
-        Self::run_cmd(cmd, Error::FailedToRemoveRoute)
-    }
-
-    /// Retrieves the gateway for the default route
-    fn get_default_routes() -> Box<dyn Future<Item = HashSet<Route>, Error = Error> + Send> {
-        Box::new(
-            Self::get_default_routes_inner(IpVersion::V4)
-                .join(Self::get_default_routes_inner(IpVersion::V6))
-                .map(|(v4_routes, v6_routes)| {
-                    v4_routes.into_iter().chain(v6_routes.into_iter()).collect()
-                }),
-        )
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
-enum IpVersion {
-    V4,
-    V6,
-}
-
-impl IpVersion {
-    fn to_route_arg(self) -> &'static str {
-        match self {
-            IpVersion::V4 => "-4",
-            IpVersion::V6 => "-6",
-        }
-    }
 }
 
-impl Future for RouteManagerImpl {
+impl<T: RoutingTable> Future for RouteManagerImpl<T> {
     type Item = ();
     type Error = Error;
     fn poll(&mut self) -> Result<Async<()>> {
@@ -450,15 +448,26 @@ impl Future for RouteManagerImpl {
                 Ok(Async::Ready(tx)) => {
                     self.should_shut_down = true;
                     self.shutdown_finished_tx = Some(tx);
+                    self.needed_changes.clear();
                 }
                 Err(_) => {
                     self.should_shut_down = true;
+                    self.needed_changes.clear();
                 }
             };
-            self.process_route_table_change()?;
         }
-        let all_changes_applied = self.apply_route_table_changes()?;
+        // Polling for changes also drives the requests made to the routing table, so it has to be
+        // done while shutting down as well.
+        self.process_route_table_change()?;
+        let mut all_changes_applied = self.apply_route_table_changes()?;
+        if all_changes_applied && self.should_shut_down && !self.added_routes.is_empty() {
+            for route in self.added_routes.clone() {
+                self.enque_route_change(RouteChange::Remove(route));
+            }
+            all_changes_applied = self.apply_route_table_changes()?;
+        }
         if all_changes_applied && self.should_shut_down {
+            delete_rules(&mem::replace(&mut self.rules, Vec::new()), &self.journal);
             if let Some(tx) = self.shutdown_finished_tx.take() {
                 if tx.send(()).is_err() {
                     log::error!("RouteManagerHandle already stopped");
@@ -471,71 +480,333 @@ impl Future for RouteManagerImpl {
     }
 }
 
-// intended to parse lines sucha as the following:
-//      default via 192.168.1.1 dev wlp61s0 proto dhcp metric 600
-fn parse_ip_route_show_line(line: &str, ip_version: IpVersion) -> Option<Route> {
-    let mut node_ip = None;
-    let mut device = None;
-    let mut metric = None;
-
-    let mut tokens = line.split_whitespace();
-    let prefix_str = tokens.next()?;
-    let prefix = match prefix_str {
-        "default" => match ip_version {
-            IpVersion::V4 => "0.0.0.0/0".parse().unwrap(),
-            IpVersion::V6 => "::/0".parse().unwrap(),
-        },
-        prefix_str => prefix_str.parse().ok()?,
-    };
-
-    let tokens: Vec<&str> = tokens.collect();
-    for pair in tokens.chunks(2) {
-        if pair.len() != 2 {
-            log::error!("unexpected output from ip");
-            break;
+/// Removes routes and routing rules that were added by a previous run, which was shut down
+/// before it could remove them. Routes through devices that no longer exist were removed along
+/// with the device.
+pub fn remove_stale_routes(routes: &[JournalRoute], policy: Option<JournalPolicyRouting>) {
+    if let Some(policy) = policy {
+        if let Err(error) = rules::delete_rules(&Rule::policy_rules(policy.fwmark, policy.table_id))
+        {
+            log::error!("Failed to remove stale routing rules - {}", error);
         }
-        let kind = pair[0];
-        let value = pair[1];
-
-        match kind {
-            "via" => node_ip = value.parse().ok(),
-            "dev" => device = Some(value.to_string()),
-            "metric" => metric = value.parse().ok(),
-            _ => continue,
-        };
     }
 
-    if node_ip.is_none() && device.is_none() {
-        None
-    } else {
-        let node = super::Node {
-            ip: node_ip,
-            device,
-        };
-
-        Some(super::Route {
-            node,
-            prefix,
-            metric,
+    let stale_routes: Vec<Route> = routes
+        .iter()
+        .filter_map(route_from_journal)
+        .filter(|route| match route.node.get_device() {
+            Some(device) => Path::new(NETWORK_DEVICES_PATH).join(device).exists(),
+            None => true,
         })
+        .collect();
+    if stale_routes.is_empty() {
+        return;
+    }
+    let mut table = match RouteChangeListener::new() {
+        Ok(table) => table,
+        Err(error) => {
+            log::error!("Failed to remove stale routes - {}", error);
+            return;
+        }
+    };
+    for route in stale_routes {
+        log::debug!("Removing stale route to {}", route.prefix);
+        let request = table.delete_route(&route);
+        if let Err(error) = table.wait_for(request) {
+            log::warn!(
+                "Failed to remove stale route to {} - {}",
+                route.prefix,
+                error
+            );
+        }
     }
 }
 
-fn ip_vers(route: &Route) -> &'static str {
-    if route.prefix.is_ipv4() {
-        "-4"
-    } else {
-        "-6"
+fn delete_rules(rules: &[Rule], journal: &SystemStateJournal) {
+    if rules.is_empty() {
+        return;
     }
+    match rules::delete_rules(rules) {
+        Ok(()) => journal.update(|journal| journal.policy_routing = None),
+        Err(error) => log::error!("Failed to remove routing rules - {}", error),
+    }
+}
+
+fn journal_added_route(journal: &SystemStateJournal, route: &Route) {
+    let route = journal_route(route);
+    journal.update(|journal| journal.routes.push(route));
+}
+
+fn journal_removed_route(journal: &SystemStateJournal, route: &Route) {
+    let route = journal_route(route);
+    journal.update(|journal| {
+        journal
+            .routes
+            .retain(|journal_route| *journal_route != route)
+    });
+}
+
+fn journal_route(route: &Route) -> JournalRoute {
+    JournalRoute {
+        destination: route.prefix.to_string(),
+        device: route.node.device.clone(),
+        gateway: route.node.ip,
+        metric: route.metric,
+        table_id: Some(route.table_id).filter(|table_id| *table_id != MAIN_TABLE_ID),
+    }
+}
+
+fn route_from_journal(route: &JournalRoute) -> Option<Route> {
+    let prefix = match route.destination.parse() {
+        Ok(prefix) => prefix,
+        Err(_) => {
+            log::warn!("Invalid destination of stale route: {}", route.destination);
+            return None;
+        }
+    };
+    Some(Route {
+        node: Node {
+            ip: route.gateway,
+            device: route.device.clone(),
+        },
+        prefix,
+        metric: route.metric,
+        table_id: route.table_id.unwrap_or(MAIN_TABLE_ID),
+    })
 }
 
 #[derive(Debug, PartialEq)]
-enum RouteChange {
+pub(crate) enum RouteChange {
     Add(Route),
     Remove(Route),
 }
 
 struct PendingChange {
     change: RouteChange,
-    process: Box<dyn Future<Item = (), Error = Error> + Send>,
+    process: RouteRequest<()>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{future, sync::mpsc, Stream};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// A routing table kept in memory. Changes to it are only reported when sent on `changes`,
+    /// like the kernel reporting changes made by someone else.
+    struct FakeRoutingTable {
+        routes: Arc<Mutex<HashSet<Route>>>,
+        changes: mpsc::UnboundedReceiver<RouteChange>,
+    }
+
+    impl RoutingTable for FakeRoutingTable {
+        fn add_route(&mut self, route: &Route) -> RouteRequest<()> {
+            self.routes.lock().insert(route.clone());
+            Box::new(future::ok(()))
+        }
+
+        fn delete_route(&mut self, route: &Route) -> RouteRequest<()> {
+            self.routes.lock().remove(route);
+            Box::new(future::ok(()))
+        }
+
+        fn get_default_routes(&mut self) -> RouteRequest<HashSet<Route>> {
+            let default_routes = self
+                .routes
+                .lock()
+                .iter()
+                .filter(|route| route.prefix.prefix() == 0)
+                .cloned()
+                .collect();
+            Box::new(future::ok(default_routes))
+        }
+
+        fn wait_for<T>(&mut self, request: RouteRequest<T>) -> Result<T> {
+            request.wait()
+        }
+
+        fn poll_changes(&mut self) -> Poll<Option<RouteChange>, Error> {
+            self.changes.poll().map_err(|_| Error::ChangeListenerClosed)
+        }
+    }
+
+    fn default_route(gateway: &str, device: &str, metric: u32) -> Route {
+        Route {
+            node: Node::new(gateway.parse().unwrap(), device.to_owned()),
+            prefix: "0.0.0.0/0".parse().unwrap(),
+            metric: Some(metric),
+            table_id: MAIN_TABLE_ID,
+        }
+    }
+
+    struct TestManager {
+        manager: RouteManagerImpl<FakeRoutingTable>,
+        routes: Arc<Mutex<HashSet<Route>>>,
+        changes_tx: mpsc::UnboundedSender<RouteChange>,
+        shutdown_tx: Option<oneshot::Sender<oneshot::Sender<()>>>,
+        journal: SystemStateJournal,
+        _journal_dir: tempfile::TempDir,
+    }
+
+    impl TestManager {
+        /// Starts a route manager that routes `0.0.0.0/1` through `wg0` and `10.0.0.1` through
+        /// the default route, on a table that already has `initial_routes`.
+        fn start(initial_routes: Vec<Route>) -> Self {
+            let routes = Arc::new(Mutex::new(initial_routes.into_iter().collect()));
+            let (changes_tx, changes_rx) = mpsc::unbounded();
+            let table = FakeRoutingTable {
+                routes: routes.clone(),
+                changes: changes_rx,
+            };
+
+            let mut required_routes = HashMap::new();
+            required_routes.insert(
+                "0.0.0.0/1".parse().unwrap(),
+                NetNode::RealNode(Node::device("wg0".to_owned())),
+            );
+            required_routes.insert("10.0.0.1/32".parse().unwrap(), NetNode::DefaultNode);
+            let (shutdown_tx, shutdown_rx) = oneshot::channel();
+            let journal_dir = tempfile::tempdir().unwrap();
+            let (journal, _) = SystemStateJournal::open(journal_dir.path());
+
+            let manager = RouteManagerImpl::with_table(
+                table,
+                required_routes,
+                MAIN_TABLE_ID,
+                journal.clone(),
+                shutdown_rx,
+            )
+            .expect("Failed to start route manager");
+            TestManager {
+                manager,
+                routes,
+                changes_tx,
+                shutdown_tx: Some(shutdown_tx),
+                journal,
+                _journal_dir: journal_dir,
+            }
+        }
+
+        /// Reports a route that was added to the table by someone else.
+        fn add_external_route(&self, route: Route) {
+            self.routes.lock().insert(route.clone());
+            self.changes_tx
+                .unbounded_send(RouteChange::Add(route))
+                .unwrap();
+        }
+
+        /// Reports a route that was removed from the table by someone else.
+        fn remove_external_route(&self, route: Route) {
+            self.routes.lock().remove(&route);
+            self.changes_tx
+                .unbounded_send(RouteChange::Remove(route))
+                .unwrap();
+        }
+
+        fn poll(&mut self) -> Async<()> {
+            let manager = &mut self.manager;
+            future::lazy(|| manager.poll())
+                .wait()
+                .expect("Route manager failed")
+        }
+
+        fn contains(&self, route: &Route) -> bool {
+            self.routes.lock().contains(route)
+        }
+
+        fn relay_route_via(&self, default_route: &Route) -> Route {
+            Route::new(default_route.node.clone(), "10.0.0.1/32".parse().unwrap())
+        }
+
+        fn journaled_routes(&self) -> Vec<JournalRoute> {
+            self.journal.current().routes
+        }
+    }
+
+    #[test]
+    fn adds_required_routes() {
+        let default_route = default_route("192.168.1.1", "eth0", 100);
+        let test = TestManager::start(vec![default_route.clone()]);
+
+        assert!(test.contains(&Route::new(
+            Node::device("wg0".to_owned()),
+            "0.0.0.0/1".parse().unwrap()
+        )));
+        assert!(test.contains(&test.relay_route_via(&default_route)));
+        assert_eq!(test.routes.lock().len(), 3);
+    }
+
+    #[test]
+    fn journals_added_routes() {
+        let old_default_route = default_route("192.168.1.1", "eth0", 100);
+        let mut test = TestManager::start(vec![old_default_route.clone()]);
+
+        let journaled_routes = test.journaled_routes();
+        assert_eq!(journaled_routes.len(), 2);
+        assert!(
+            journaled_routes.contains(&journal_route(&test.relay_route_via(&old_default_route)))
+        );
+
+        let new_default_route = default_route("192.168.2.1", "wlan0", 50);
+        test.add_external_route(new_default_route.clone());
+        assert_eq!(test.poll(), Async::NotReady);
+
+        let journaled_routes = test.journaled_routes();
+        assert_eq!(journaled_routes.len(), 2);
+        assert!(
+            journaled_routes.contains(&journal_route(&test.relay_route_via(&new_default_route)))
+        );
+        assert!(
+            !journaled_routes.contains(&journal_route(&test.relay_route_via(&old_default_route)))
+        );
+    }
+
+    #[test]
+    fn follows_best_default_route() {
+        let old_default_route = default_route("192.168.1.1", "eth0", 100);
+        let mut test = TestManager::start(vec![old_default_route.clone()]);
+
+        let new_default_route = default_route("192.168.2.1", "wlan0", 50);
+        test.add_external_route(new_default_route.clone());
+        assert_eq!(test.poll(), Async::NotReady);
+
+        assert!(test.contains(&test.relay_route_via(&new_default_route)));
+        assert!(!test.contains(&test.relay_route_via(&old_default_route)));
+
+        test.remove_external_route(new_default_route.clone());
+        assert_eq!(test.poll(), Async::NotReady);
+
+        assert!(test.contains(&test.relay_route_via(&old_default_route)));
+        assert!(!test.contains(&test.relay_route_via(&new_default_route)));
+    }
+
+    #[test]
+    fn ignores_worse_default_route() {
+        let best_default_route = default_route("192.168.1.1", "eth0", 100);
+        let mut test = TestManager::start(vec![best_default_route.clone()]);
+
+        let worse_default_route = default_route("192.168.2.1", "wlan0", 600);
+        test.add_external_route(worse_default_route.clone());
+        assert_eq!(test.poll(), Async::NotReady);
+
+        assert!(test.contains(&test.relay_route_via(&best_default_route)));
+        assert!(!test.contains(&test.relay_route_via(&worse_default_route)));
+    }
+
+    #[test]
+    fn removes_routes_on_shutdown() {
+        let default_route = default_route("192.168.1.1", "eth0", 100);
+        let mut test = TestManager::start(vec![default_route.clone()]);
+
+        let (finished_tx, finished_rx) = oneshot::channel();
+        test.shutdown_tx.take().unwrap().send(finished_tx).unwrap();
+        assert_eq!(test.poll(), Async::Ready(()));
+
+        finished_rx.wait().expect("Shutdown was not signaled");
+        let routes = test.routes.lock();
+        assert_eq!(routes.len(), 1);
+        assert!(routes.contains(&default_route));
+        assert!(test.journaled_routes().is_empty());
+    }
 }
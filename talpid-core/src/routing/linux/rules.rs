@@ -0,0 +1,156 @@
+//! Routing policy rules, which decide what routing table is used to route a packet. The rules are
+//! managed by sending netlink messages directly, since they are only changed when a tunnel is set
+//! up or torn down.
+
+use super::MAIN_TABLE_ID;
+use crate::netlink::{
+    NetlinkSocket, NETLINK_HEADER_LEN, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST,
+};
+
+pub use crate::netlink::{Error, Result};
+
+// FIB rule constants from the kernel headers.
+const RTM_NEWRULE: u16 = 32;
+const RTM_DELRULE: u16 = 33;
+const FR_ACT_TO_TBL: u8 = 1;
+const FIB_RULE_INVERT: u32 = 0x2;
+const FRA_FWMARK: u16 = 10;
+const FRA_SUPPRESS_PREFIXLEN: u16 = 14;
+const FRA_TABLE: u16 = 15;
+const RT_TABLE_UNSPEC: u8 = 0;
+
+/// Size of `struct fib_rule_hdr`.
+const RULE_HEADER_LEN: usize = 12;
+
+/// A rule that makes packets of one IP family look up their route in a given table.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rule {
+    family: u8,
+    table_id: u32,
+    /// Only packets that are not marked with this firewall mark match the rule.
+    not_fwmark: Option<u32>,
+    /// Routes with a prefix length less than or equal to this are ignored.
+    suppress_prefix_length: Option<u32>,
+}
+
+impl Rule {
+    /// Returns the rules that route packets without `fwmark` by `table_id`, as `wg-quick` does.
+    /// Routes in the main table that are more specific than a default route are still used, so
+    /// that the local network remains reachable. The rules must be added in the order returned.
+    pub fn policy_rules(fwmark: u32, table_id: u32) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        for &family in &[libc::AF_INET as u8, libc::AF_INET6 as u8] {
+            rules.push(Rule {
+                family,
+                table_id,
+                not_fwmark: Some(fwmark),
+                suppress_prefix_length: None,
+            });
+            rules.push(Rule {
+                family,
+                table_id: MAIN_TABLE_ID,
+                not_fwmark: None,
+                suppress_prefix_length: Some(0),
+            });
+        }
+        rules
+    }
+
+    /// Returns a request about the rule. The sequence number is set by the socket.
+    fn message(&self, message_type: u16, flags: u16) -> Vec<u8> {
+        let mut attributes = Vec::new();
+        add_attribute(&mut attributes, FRA_TABLE, self.table_id);
+        if let Some(fwmark) = self.not_fwmark {
+            add_attribute(&mut attributes, FRA_FWMARK, fwmark);
+        }
+        if let Some(prefix_length) = self.suppress_prefix_length {
+            add_attribute(&mut attributes, FRA_SUPPRESS_PREFIXLEN, prefix_length);
+        }
+
+        let length = NETLINK_HEADER_LEN + RULE_HEADER_LEN + attributes.len();
+        let mut message = Vec::with_capacity(length);
+        message.extend_from_slice(&(length as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+
+        // Table IDs that don't fit in the header are only given as an attribute.
+        let header_table = if self.table_id < 256 {
+            self.table_id as u8
+        } else {
+            RT_TABLE_UNSPEC
+        };
+        message.extend_from_slice(&[self.family, 0, 0, 0, header_table, 0, 0, FR_ACT_TO_TBL]);
+        let rule_flags = if self.not_fwmark.is_some() {
+            FIB_RULE_INVERT
+        } else {
+            0
+        };
+        message.extend_from_slice(&rule_flags.to_ne_bytes());
+        message.extend_from_slice(&attributes);
+        message
+    }
+}
+
+fn add_attribute(attributes: &mut Vec<u8>, kind: u16, value: u32) {
+    const ATTRIBUTE_LEN: u16 = 8;
+    attributes.extend_from_slice(&ATTRIBUTE_LEN.to_ne_bytes());
+    attributes.extend_from_slice(&kind.to_ne_bytes());
+    attributes.extend_from_slice(&value.to_ne_bytes());
+}
+
+/// Adds the rules in order. Copies of the rules that are left from an earlier run are removed
+/// first, so that each rule is only present once.
+pub fn add_rules(rules: &[Rule]) -> Result<()> {
+    let mut socket = NetlinkSocket::open(libc::NETLINK_ROUTE)?;
+    for rule in rules {
+        delete_all(&mut socket, rule)?;
+        socket.request(rule.message(RTM_NEWRULE, NLM_F_CREATE | NLM_F_EXCL))?;
+    }
+    Ok(())
+}
+
+/// Removes the rules in reverse order. Rules that don't exist are ignored.
+pub fn delete_rules(rules: &[Rule]) -> Result<()> {
+    let mut socket = NetlinkSocket::open(libc::NETLINK_ROUTE)?;
+    for rule in rules.iter().rev() {
+        delete_all(&mut socket, rule)?;
+    }
+    Ok(())
+}
+
+fn delete_all(socket: &mut NetlinkSocket, rule: &Rule) -> Result<()> {
+    loop {
+        match socket.request(rule.message(RTM_DELRULE, 0)) {
+            Ok(_) => continue,
+            Err(Error::Rejected(ref error)) if error.raw_os_error() == Some(libc::ENOENT) => {
+                return Ok(())
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::netlink::read_u32;
+
+    #[test]
+    fn test_not_fwmark_rule_message() {
+        let rules = Rule::policy_rules(0x6d6f6c65, 0x6d6f6c65);
+        let message = rules[0].message(RTM_NEWRULE, NLM_F_CREATE);
+
+        assert_eq!(message.len(), 44);
+        assert_eq!(read_u32(&message[0..4]), 44);
+        // The table ID doesn't fit in the header, so it's only in the attribute.
+        assert_eq!(message[16], libc::AF_INET as u8);
+        assert_eq!(message[20], RT_TABLE_UNSPEC);
+        assert_eq!(message[23], FR_ACT_TO_TBL);
+        assert_eq!(read_u32(&message[24..28]), FIB_RULE_INVERT);
+        assert_eq!(read_u32(&message[32..36]), 0x6d6f6c65);
+        assert_eq!(u16::from_ne_bytes([message[38], message[39]]), FRA_FWMARK);
+        assert_eq!(read_u32(&message[40..44]), 0x6d6f6c65);
+    }
+}
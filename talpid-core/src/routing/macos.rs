@@ -1,4 +1,5 @@
 use super::{NetNode, Node, Route};
+use crate::journal::{JournalRoute, SystemStateJournal};
 
 use ipnetwork::IpNetwork;
 use std::{
@@ -68,6 +69,7 @@ pub struct RouteManagerImpl {
     current_state: RouteManagerState,
     v4_gateway: Option<Node>,
     v6_gateway: Option<Node>,
+    journal: SystemStateJournal,
     shutdown_rx: Option<oneshot::Receiver<oneshot::Sender<()>>>,
 }
 
@@ -75,6 +77,7 @@ pub struct RouteManagerImpl {
 impl RouteManagerImpl {
     pub fn new(
         required_routes: HashMap<IpNetwork, NetNode>,
+        journal: SystemStateJournal,
         shutdown_rx: oneshot::Receiver<oneshot::Sender<()>>,
     ) -> Result<Self> {
         let mut applied_routes = HashSet::new();
@@ -129,6 +132,11 @@ impl RouteManagerImpl {
             }
             return Err(e);
         }
+        // Routes through the default node are replaced when it changes, but always keep their
+        // destination, which is all that's needed to remove them.
+        let journal_routes: Vec<_> = applied_routes.iter().map(journal_route).collect();
+        journal.update(|journal| journal.routes = journal_routes);
+
         let change_listener = ChangeListener::new().map_err(Error::FailedToMonitorRoutes)?;
 
         Ok(Self {
@@ -138,6 +146,7 @@ impl RouteManagerImpl {
             shutdown_rx: Some(shutdown_rx),
             v4_gateway,
             v6_gateway,
+            journal,
         })
     }
 
@@ -260,9 +269,11 @@ impl RouteManagerImpl {
                 _ => None,
             }
         }));
+        let journal = self.journal.clone();
         stream::futures_ordered(routes_to_remove)
             .for_each(|_| Ok(()))
-            .and_then(|_| {
+            .and_then(move |_| {
+                journal.update(|journal| journal.routes.clear());
                 if let Some(tx) = shutdown_done_tx {
                     if tx.send(()).is_err() {
                         log::debug!("RouteManager already dropped")
@@ -386,6 +397,47 @@ impl Future for RouteManagerImpl {
     }
 }
 
+/// Removes routes that were added by a previous run, which was shut down before it could remove
+/// them.
+pub fn remove_stale_routes(routes: &[JournalRoute]) {
+    for route in routes {
+        let destination: IpNetwork = match route.destination.parse() {
+            Ok(destination) => destination,
+            Err(_) => {
+                log::error!(
+                    "Invalid destination in journaled route: {}",
+                    route.destination
+                );
+                continue;
+            }
+        };
+        let status = Command::new("route")
+            .arg("-q")
+            .arg("-n")
+            .arg("delete")
+            .arg(ip_vers(destination))
+            .arg(destination.to_string())
+            .status();
+        match status {
+            Ok(status) if !status.success() => {
+                log::debug!("Stale route to {} was already removed", destination)
+            }
+            Ok(_) => log::debug!("Removed stale route to {}", destination),
+            Err(error) => log::error!("Failed to remove stale route - {}", error),
+        }
+    }
+}
+
+fn journal_route(route: &Route) -> JournalRoute {
+    JournalRoute {
+        destination: route.prefix.to_string(),
+        device: route.node.get_device().map(str::to_owned),
+        gateway: route.node.get_address(),
+        metric: None,
+        table_id: None,
+    }
+}
+
 fn ip_vers(prefix: IpNetwork) -> &'static str {
     if prefix.is_ipv4() {
         "-inet"
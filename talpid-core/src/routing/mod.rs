@@ -1,5 +1,6 @@
 #![cfg_attr(target_os = "android", allow(dead_code))]
 // TODO: remove the allow(dead_code) for android once it's up to scratch.
+use crate::journal::SystemStateJournal;
 use futures::{sync::oneshot, Future};
 use ipnetwork::IpNetwork;
 use std::{collections::HashMap, net::IpAddr};
@@ -40,16 +41,48 @@ pub struct RouteManager {
 impl RouteManager {
     /// Constructs a RouteManager and applies the required routes.
     /// Takes a map of network destinations and network nodes as an argument, and applies said
-    /// routes.
-    pub fn new(
+    /// routes. The applied routes are recorded in `journal`.
+    pub(crate) fn new(
         required_routes: HashMap<IpNetwork, NetNode>,
+        journal: SystemStateJournal,
         exec: &mut impl Executor,
     ) -> Result<Self, Error> {
         let (tx, rx) = oneshot::channel();
 
 
-        let route_manager = imp::RouteManagerImpl::new(required_routes, rx)
+        #[cfg(target_os = "linux")]
+        let route_manager = imp::RouteManagerImpl::new(required_routes, None, journal, rx);
+        #[cfg(not(target_os = "linux"))]
+        let route_manager = imp::RouteManagerImpl::new(required_routes, journal, rx);
+        Self::spawn(
+            route_manager.map_err(Error::FailedToInitializeManager)?,
+            tx,
+            exec,
+        )
+    }
+
+    /// Constructs a RouteManager that uses policy based routing. The routes through real nodes
+    /// are added to a separate routing table, which is used for all packets that aren't marked
+    /// with the firewall mark in `policy`. Marked packets are routed by the main table, so routes
+    /// for tunnel endpoints aren't needed.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_policy_routing(
+        required_routes: HashMap<IpNetwork, NetNode>,
+        policy: PolicyRouting,
+        journal: SystemStateJournal,
+        exec: &mut impl Executor,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = oneshot::channel();
+        let route_manager = imp::RouteManagerImpl::new(required_routes, Some(policy), journal, rx)
             .map_err(Error::FailedToInitializeManager)?;
+        Self::spawn(route_manager, tx, exec)
+    }
+
+    fn spawn(
+        route_manager: imp::RouteManagerImpl,
+        tx: oneshot::Sender<oneshot::Sender<()>>,
+        exec: &mut impl Executor,
+    ) -> Result<Self, Error> {
         exec.spawn(Box::new(
             route_manager.map_err(|e| log::error!("Routing manager failed - {}", e)),
         ))
@@ -80,13 +113,45 @@ impl Drop for RouteManager {
     }
 }
 
+/// Removes routes and routing rules that were added by a previous run, which was shut down before
+/// it could remove them.
+#[cfg(target_os = "linux")]
+pub(crate) fn remove_stale_routes(
+    routes: &[crate::journal::JournalRoute],
+    policy: Option<crate::journal::JournalPolicyRouting>,
+) {
+    imp::remove_stale_routes(routes, policy)
+}
+
+/// Removes routes that were added by a previous run, which was shut down before it could remove
+/// them. There is no policy based routing on macOS.
+#[cfg(target_os = "macos")]
+pub(crate) fn remove_stale_routes(
+    routes: &[crate::journal::JournalRoute],
+    _policy: Option<crate::journal::JournalPolicyRouting>,
+) {
+    imp::remove_stale_routes(routes)
+}
+
+/// Policy based routing on Linux. Packets without the firewall mark `fwmark` are routed by the
+/// routing table `table_id`, and marked packets by the main table.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PolicyRouting {
+    /// The firewall mark of packets that should bypass `table_id`.
+    pub fwmark: u32,
+    /// The ID of the routing table the required routes are added to.
+    pub table_id: u32,
+}
 
 /// A netowrk route with a specific network node, destinaiton and an optional metric.
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
-struct Route {
+pub(crate) struct Route {
     node: Node,
     prefix: IpNetwork,
     metric: Option<u32>,
+    #[cfg(target_os = "linux")]
+    table_id: u32,
 }
 
 impl Route {
@@ -95,8 +160,17 @@ impl Route {
             node,
             prefix,
             metric: None,
+            #[cfg(target_os = "linux")]
+            table_id: imp::MAIN_TABLE_ID,
         }
     }
+
+    /// Places the route in the routing table `table_id` instead of the main table.
+    #[cfg(target_os = "linux")]
+    fn table(mut self, table_id: u32) -> Self {
+        self.table_id = table_id;
+        self
+    }
 }
 
 /// A network route that should be applied by the RouteManager.
@@ -1,5 +1,5 @@
 use self::tun_provider::TunProvider;
-use crate::logging;
+use crate::{journal::SystemStateJournal, logging};
 #[cfg(not(target_os = "android"))]
 use std::collections::HashMap;
 use std::{
@@ -11,7 +11,10 @@ use std::{
 use talpid_types::net::openvpn as openvpn_types;
 #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
 use talpid_types::net::wireguard as wireguard_types;
-use talpid_types::net::{GenericTunnelOptions, TunnelParameters};
+use talpid_types::{
+    net::{GenericTunnelOptions, TunnelParameters},
+    tunnel::TunnelStats,
+};
 
 /// A module for all OpenVPN related tunnel management.
 #[cfg(not(target_os = "android"))]
@@ -20,6 +23,9 @@ pub mod openvpn;
 #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
 pub mod wireguard;
 
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod interface_stats;
+
 /// A module for low level platform specific tunnel device management.
 pub mod tun_provider;
 
@@ -83,6 +89,8 @@ pub struct TunnelMetadata {
     pub ipv4_gateway: Ipv4Addr,
     /// The IP to the IPv6 default gateway on the tunnel interface.
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// The WireGuard implementation running the tunnel, if it is a WireGuard tunnel.
+    pub wireguard_backend: Option<talpid_types::net::wireguard::Backend>,
 }
 
 #[cfg(not(target_os = "android"))]
@@ -123,6 +131,7 @@ impl TunnelEvent {
                     ips,
                     ipv4_gateway,
                     ipv6_gateway,
+                    wireguard_backend: None,
                 }))
             }
             openvpn_plugin::EventType::RoutePredown => Some(TunnelEvent::Down),
@@ -138,14 +147,15 @@ pub struct TunnelMonitor {
 // TODO(emilsp) move most of the openvpn tunnel details to OpenVpnTunnelMonitor
 impl TunnelMonitor {
     /// Creates a new `TunnelMonitor` that connects to the given remote and notifies `on_event`
-    /// on tunnel state changes.
+    /// on tunnel state changes. The routes set up for the tunnel are recorded in `journal`.
     #[cfg_attr(any(target_os = "android", windows), allow(unused_variables))]
-    pub fn start<L>(
+    pub(crate) fn start<L>(
         tunnel_parameters: &TunnelParameters,
         log_dir: &Option<PathBuf>,
         resource_dir: &Path,
         on_event: L,
         tun_provider: &dyn TunProvider,
+        journal: &SystemStateJournal,
     ) -> Result<Self>
     where
         L: Fn(TunnelEvent) + Send + Clone + Sync + 'static,
@@ -163,7 +173,7 @@ impl TunnelMonitor {
 
             #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
             TunnelParameters::Wireguard(config) => {
-                Self::start_wireguard_tunnel(&config, log_file, on_event, tun_provider)
+                Self::start_wireguard_tunnel(&config, log_file, on_event, tun_provider, journal)
             }
             #[cfg(windows)]
             TunnelParameters::Wireguard(_) => Err(Error::UnsupportedPlatform),
@@ -176,6 +186,7 @@ impl TunnelMonitor {
         log: Option<PathBuf>,
         on_event: L,
         tun_provider: &dyn TunProvider,
+        journal: &SystemStateJournal,
     ) -> Result<Self>
     where
         L: Fn(TunnelEvent) + Send + Sync + Clone + 'static,
@@ -186,6 +197,7 @@ impl TunnelMonitor {
             log.as_ref().map(|p| p.as_path()),
             on_event,
             tun_provider,
+            journal,
         )?;
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::Wireguard(monitor),
@@ -241,6 +253,13 @@ impl TunnelMonitor {
         self.monitor.close_handle()
     }
 
+    /// Creates a handle for reading the traffic counters of the tunnel, if the tunnel type
+    /// provides them. Otherwise the counters of the tunnel interface can be read with
+    /// `StatsHandle::from_interface` once it is up.
+    pub fn stats_handle(&self) -> Option<StatsHandle> {
+        self.monitor.stats_handle()
+    }
+
     /// Consumes the monitor and blocks until the tunnel exits or there is an error.
     pub fn wait(self) -> Result<()> {
         self.monitor.wait().map_err(Error::from)
@@ -273,6 +292,52 @@ impl CloseHandle {
     }
 }
 
+/// A handle for reading the traffic counters of a tunnel monitored by a `TunnelMonitor`.
+#[derive(Clone)]
+pub enum StatsHandle {
+    #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+    /// Wireguard stats handle
+    Wireguard(wireguard::StatsHandle),
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    /// Reads the counters of the tunnel interface
+    Interface(String),
+}
+
+impl StatsHandle {
+    /// Creates a handle that reads the counters of the given tunnel interface, for tunnels that
+    /// don't provide their own. Returns `None` where that isn't supported.
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "macos")),
+        allow(unused_variables)
+    )]
+    pub fn from_interface(interface: &str) -> Option<Self> {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            Some(StatsHandle::Interface(interface.to_owned()))
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            None
+        }
+    }
+
+    /// Reads the traffic counters. Returns `None` if the tunnel is gone or they can't be read.
+    pub fn get(&self) -> Option<TunnelStats> {
+        match *self {
+            #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+            StatsHandle::Wireguard(ref handle) => handle.get(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            StatsHandle::Interface(ref interface) => match interface_stats::get(interface) {
+                Ok(stats) => Some(stats),
+                Err(error) => {
+                    log::debug!("Failed to read the counters of {}: {}", interface, error);
+                    None
+                }
+            },
+        }
+    }
+}
+
 enum InternalTunnelMonitor {
     #[cfg(not(target_os = "android"))]
     OpenVpn(openvpn::OpenVpnMonitor),
@@ -290,6 +355,17 @@ impl InternalTunnelMonitor {
         }
     }
 
+    fn stats_handle(&self) -> Option<StatsHandle> {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(_) => None,
+            #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+            InternalTunnelMonitor::Wireguard(tun) => {
+                Some(StatsHandle::Wireguard(tun.stats_handle()))
+            }
+        }
+    }
+
     fn wait(self) -> Result<()> {
         match self {
             #[cfg(not(target_os = "android"))]
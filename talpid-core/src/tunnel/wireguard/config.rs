@@ -1,16 +1,25 @@
+use super::mtu;
 use std::{
     borrow::Cow,
     ffi::CString,
     net::{Ipv4Addr, Ipv6Addr},
 };
-use talpid_types::net::{wireguard, GenericTunnelOptions};
+use talpid_types::net::{wireguard, GenericTunnelOptions, TransportProtocol};
 
+#[derive(Clone)]
 pub struct Config {
     pub tunnel: wireguard::TunnelConfig,
     pub peers: Vec<wireguard::PeerConfig>,
     pub ipv4_gateway: Ipv4Addr,
     pub ipv6_gateway: Option<Ipv6Addr>,
     pub mtu: u16,
+    /// Whether the MTU was picked automatically, and should be lowered if packets of that size
+    /// are dropped.
+    pub auto_mtu: bool,
+    /// Implementation to run the tunnel with, or `None` to pick the best available one.
+    pub backend: Option<wireguard::Backend>,
+    /// Transport used to reach the peers. Over TCP, the tunnel is carried through a local proxy.
+    pub protocol: TransportProtocol,
 }
 
 /// Smallest MTU that supports IPv6
@@ -52,7 +61,15 @@ impl Config {
         if peers.is_empty() {
             return Err(Error::NoPeersSuppliedError);
         }
-        let mtu = wg_options.mtu.unwrap_or(DEFAULT_MTU);
+        // Over TCP, the path MTU doesn't limit the size of the tunnel packets.
+        let auto_mtu = wg_options.mtu.is_none()
+            && wg_options.auto_mtu
+            && connection_config.protocol == TransportProtocol::Udp;
+        let mtu = match wg_options.mtu {
+            Some(mtu) => mtu,
+            None if auto_mtu => mtu::initial_mtu(peers[0].endpoint),
+            None => DEFAULT_MTU,
+        };
         let is_ipv6_enabled = mtu >= SMALLEST_IPV6_MTU && generic_options.enable_ipv6;
 
         for peer in &mut peers {
@@ -65,6 +82,9 @@ impl Config {
             if peer.allowed_ips.is_empty() {
                 return Err(Error::InvalidPeerIpError);
             }
+            peer.persistent_keepalive = peer
+                .persistent_keepalive
+                .or(wg_options.persistent_keepalive);
         }
 
         tunnel.addresses = tunnel
@@ -87,6 +107,9 @@ impl Config {
                 None
             },
             mtu,
+            auto_mtu,
+            backend: wg_options.backend,
+            protocol: connection_config.protocol,
         })
     }
 
@@ -98,13 +121,25 @@ impl Config {
             .add("private_key", self.tunnel.private_key.as_bytes().as_ref())
             .add("listen_port", "0");
 
+        #[cfg(target_os = "linux")]
+        wg_conf.add("fwmark", crate::linux::TUNNEL_FWMARK.to_string().as_str());
+
         wg_conf.add("replace_peers", "true");
 
         for peer in &self.peers {
             wg_conf
                 .add("public_key", peer.public_key.as_bytes().as_ref())
-                .add("endpoint", peer.endpoint.to_string().as_str())
-                .add("replace_allowed_ips", "true");
+                .add("endpoint", peer.endpoint.to_string().as_str());
+            if let Some(preshared_key) = &peer.preshared_key {
+                wg_conf.add("preshared_key", preshared_key.as_bytes().as_ref());
+            }
+            if let Some(interval) = peer.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
+            wg_conf.add("replace_allowed_ips", "true");
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
@@ -0,0 +1,278 @@
+//! Tells whether a WireGuard tunnel works by watching its traffic counters and handshakes.
+//!
+//! Traffic that is sent without anything coming back makes the monitor probe the tunnel gateway
+//! with pings. Idle tunnels are left alone, since no traffic is expected on them. If the counters
+//! can't be read, the gateway is pinged continuously instead.
+
+use super::{
+    pinger::{self, Pinger},
+    Tunnel,
+};
+use parking_lot::Mutex;
+use std::{
+    net::Ipv4Addr,
+    sync::Weak,
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{tunnel::TunnelStats, ErrorExt};
+
+/// How often the traffic counters are read.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for the first handshake, or the first ping reply, after starting the tunnel.
+const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(7);
+/// How long traffic can be sent without anything being received before the tunnel is probed.
+const RX_TIMEOUT: Duration = Duration::from_secs(4);
+/// How long to wait for traffic in response to the probe before giving up on the tunnel.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(4);
+/// How long the tunnel can go without a ping reply when only pings are used.
+const PING_TIMEOUT: Duration = Duration::from_secs(7);
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to ping the tunnel gateway")]
+    PingError(#[error(cause)] pinger::Error),
+
+    #[error(display = "Timed out waiting for the tunnel to come up")]
+    EstablishTimeout,
+
+    #[error(display = "Nothing was received through the tunnel in response to probes")]
+    ProbeTimeout,
+
+    #[error(display = "The tunnel gateway stopped replying to pings")]
+    PingTimeout,
+}
+
+pub struct ConnectivityMonitor {
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    pinger: Pinger,
+    /// Cleared once reading the traffic counters fails, after which only pings are used.
+    use_stats: bool,
+}
+
+impl ConnectivityMonitor {
+    pub fn new(
+        gateway: Ipv4Addr,
+        interface: &str,
+        tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    ) -> Result<Self, Error> {
+        Ok(ConnectivityMonitor {
+            tunnel,
+            pinger: Pinger::new(gateway, interface).map_err(Error::PingError)?,
+            use_stats: true,
+        })
+    }
+
+    /// Blocks until a handshake has completed or the gateway has replied to a ping. Returns
+    /// `Ok(false)` if the tunnel was closed first.
+    pub fn establish(&mut self) -> Result<bool, Error> {
+        let start = Instant::now();
+        while start.elapsed() < ESTABLISH_TIMEOUT {
+            // Data has to be sent for WireGuard to initiate a handshake.
+            self.pinger.send_ping().map_err(Error::PingError)?;
+            thread::sleep(CHECK_INTERVAL);
+
+            if self.pinger.receive_replies().map_err(Error::PingError)? {
+                return Ok(true);
+            }
+            match self.read_stats() {
+                Some(Some(stats)) if stats.last_handshake.is_some() => return Ok(true),
+                Some(_) => (),
+                None => return Ok(false),
+            }
+        }
+        Err(Error::EstablishTimeout)
+    }
+
+    /// Blocks while the tunnel works. Returns `Ok` once the tunnel has been closed.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let mut tracker = None;
+        let mut last_reply = Instant::now();
+
+        loop {
+            thread::sleep(CHECK_INTERVAL);
+            let now = Instant::now();
+
+            let received_reply = self.pinger.receive_replies().map_err(Error::PingError)?;
+            if received_reply {
+                last_reply = now;
+            }
+
+            let probe = match self.read_stats() {
+                None => return Ok(()),
+                Some(Some(stats)) => {
+                    let tracker = tracker.get_or_insert_with(|| TrafficTracker::new(stats, now));
+                    match tracker.update(stats, now) {
+                        Action::Nothing => false,
+                        Action::Probe => true,
+                        Action::Dead => return Err(Error::ProbeTimeout),
+                    }
+                }
+                Some(None) => {
+                    if now.duration_since(last_reply) >= PING_TIMEOUT {
+                        return Err(Error::PingTimeout);
+                    }
+                    true
+                }
+            };
+            if probe {
+                self.pinger.send_ping().map_err(Error::PingError)?;
+            }
+        }
+    }
+
+    /// Returns `None` if the tunnel has been closed, and `Some(None)` if the traffic counters
+    /// aren't available.
+    fn read_stats(&mut self) -> Option<Option<TunnelStats>> {
+        let tunnel = self.tunnel.upgrade()?;
+        let tunnel = tunnel.lock();
+        let tunnel = tunnel.as_ref()?;
+        if !self.use_stats {
+            return Some(None);
+        }
+        match tunnel.get_stats() {
+            Ok(stats) => Some(Some(stats)),
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to read tunnel statistics, pinging the gateway instead"
+                    )
+                );
+                self.use_stats = false;
+                Some(None)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// The tunnel works, or nothing is expected to be received.
+    Nothing,
+    /// Traffic is sent without anything being received, so the tunnel has to be probed.
+    Probe,
+    /// Nothing has been received in response to the probes.
+    Dead,
+}
+
+/// Keeps track of when traffic was last sent and received.
+struct TrafficTracker {
+    stats: TunnelStats,
+    last_rx: Instant,
+    last_tx: Instant,
+    probe_started: Option<Instant>,
+}
+
+impl TrafficTracker {
+    fn new(stats: TunnelStats, now: Instant) -> Self {
+        TrafficTracker {
+            stats,
+            last_rx: now,
+            last_tx: now,
+            probe_started: None,
+        }
+    }
+
+    fn update(&mut self, stats: TunnelStats, now: Instant) -> Action {
+        // Handshake responses count as received traffic, but check the time as well in case the
+        // counters lag behind.
+        if stats.rx_bytes > self.stats.rx_bytes || stats.last_handshake > self.stats.last_handshake
+        {
+            self.last_rx = now;
+            self.probe_started = None;
+        }
+        if stats.tx_bytes > self.stats.tx_bytes {
+            self.last_tx = now;
+        }
+        self.stats = stats;
+
+        if let Some(probe_started) = self.probe_started {
+            if now.duration_since(probe_started) >= PROBE_TIMEOUT {
+                return Action::Dead;
+            }
+            return Action::Probe;
+        }
+        if self.last_tx > self.last_rx && now.duration_since(self.last_rx) >= RX_TIMEOUT {
+            self.probe_started = Some(now);
+            return Action::Probe;
+        }
+        Action::Nothing
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn stats(tx_bytes: u64, rx_bytes: u64) -> TunnelStats {
+        TunnelStats {
+            tx_bytes,
+            rx_bytes,
+            last_handshake: None,
+        }
+    }
+
+    #[test]
+    fn test_idle_tunnel_is_not_probed() {
+        let start = Instant::now();
+        let mut tracker = TrafficTracker::new(stats(100, 100), start);
+        for second in 1..60 {
+            let now = start + Duration::from_secs(second);
+            assert_eq!(tracker.update(stats(100, 100), now), Action::Nothing);
+        }
+    }
+
+    #[test]
+    fn test_tunnel_is_probed_when_nothing_is_received() {
+        let start = Instant::now();
+        let mut tracker = TrafficTracker::new(stats(100, 100), start);
+        assert_eq!(
+            tracker.update(stats(200, 100), start + Duration::from_secs(1)),
+            Action::Nothing
+        );
+        assert_eq!(
+            tracker.update(stats(300, 100), start + RX_TIMEOUT),
+            Action::Probe
+        );
+        assert_eq!(
+            tracker.update(stats(400, 100), start + RX_TIMEOUT + PROBE_TIMEOUT),
+            Action::Dead
+        );
+    }
+
+    #[test]
+    fn test_received_traffic_ends_probe() {
+        let start = Instant::now();
+        let mut tracker = TrafficTracker::new(stats(100, 100), start);
+        assert_eq!(
+            tracker.update(stats(200, 100), start + RX_TIMEOUT),
+            Action::Probe
+        );
+        let reply = start + RX_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(tracker.update(stats(300, 200), reply), Action::Nothing);
+        assert_eq!(
+            tracker.update(stats(300, 200), reply + PROBE_TIMEOUT),
+            Action::Nothing
+        );
+    }
+
+    #[test]
+    fn test_handshake_ends_probe() {
+        let start = Instant::now();
+        let mut tracker = TrafficTracker::new(stats(100, 100), start);
+        assert_eq!(
+            tracker.update(stats(200, 100), start + RX_TIMEOUT),
+            Action::Probe
+        );
+        let handshake = TunnelStats {
+            last_handshake: Some(SystemTime::now()),
+            ..stats(300, 100)
+        };
+        assert_eq!(
+            tracker.update(handshake, start + RX_TIMEOUT + Duration::from_secs(1)),
+            Action::Nothing
+        );
+    }
+}
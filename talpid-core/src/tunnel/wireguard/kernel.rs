@@ -0,0 +1,627 @@
+//! WireGuard tunnels run by the kernel module. The interface is created over rtnetlink and
+//! configured through the `wireguard` generic netlink family, the same way `ip` and `wg` do it.
+
+use super::{stats, Config, Tunnel};
+use crate::netlink::{
+    self, NetlinkSocket, NETLINK_HEADER_LEN, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL,
+    NLM_F_REQUEST,
+};
+use std::net::{IpAddr, SocketAddr};
+use talpid_types::{net::wireguard::Backend, tunnel::TunnelStats};
+
+/// Name of the interface created for the tunnel.
+const INTERFACE_NAME: &str = "wg-mullvad";
+
+// Netlink constants from the kernel headers.
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_F_NET_BYTEORDER: u16 = 0x4000;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_INFO_KIND: u16 = 1;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_F_NODAD: u8 = 0x2;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_GET_DEVICE: u8 = 0;
+const WG_CMD_SET_DEVICE: u8 = 1;
+const WGDEVICE_A_IFINDEX: u16 = 1;
+const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+const WGDEVICE_A_FLAGS: u16 = 5;
+const WGDEVICE_A_FWMARK: u16 = 7;
+const WGDEVICE_A_PEERS: u16 = 8;
+const WGDEVICE_F_REPLACE_PEERS: u32 = 0x1;
+const WGPEER_A_PUBLIC_KEY: u16 = 1;
+const WGPEER_A_PRESHARED_KEY: u16 = 2;
+const WGPEER_A_FLAGS: u16 = 3;
+const WGPEER_A_ENDPOINT: u16 = 4;
+const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+const WGPEER_A_LAST_HANDSHAKE_TIME: u16 = 6;
+const WGPEER_A_RX_BYTES: u16 = 7;
+const WGPEER_A_TX_BYTES: u16 = 8;
+const WGPEER_A_ALLOWEDIPS: u16 = 9;
+const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 0x2;
+const WGALLOWEDIP_A_FAMILY: u16 = 1;
+const WGALLOWEDIP_A_IPADDR: u16 = 2;
+const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to open netlink socket")]
+    OpenSocket(#[error(cause)] netlink::Error),
+
+    #[error(display = "Netlink request failed while {}", _0)]
+    Request(&'static str, #[error(cause)] netlink::Error),
+
+    #[error(display = "The wireguard generic netlink family is missing")]
+    NoWireguardFamily,
+
+    #[error(display = "The tunnel interface has been removed")]
+    NoInterface,
+
+    #[error(display = "Failed to find the index of the tunnel interface")]
+    InterfaceIndex(#[error(cause)] crate::linux::IfaceIndexLookupError),
+}
+
+pub struct KernelTunnel {
+    interface_name: String,
+    interface_index: Option<u32>,
+    /// ID of the `wireguard` generic netlink family.
+    family_id: u16,
+}
+
+impl KernelTunnel {
+    /// Creates and configures a kernel WireGuard interface. Fails if the kernel lacks WireGuard
+    /// support.
+    pub fn start_tunnel(config: &Config) -> Result<Self> {
+        let mut route_socket = open_socket(libc::NETLINK_ROUTE)?;
+
+        // An interface can be left behind if the daemon wasn't shut down cleanly.
+        match request(
+            &mut route_socket,
+            delete_link_by_name_message(INTERFACE_NAME),
+        ) {
+            Err(Error::Request(_, netlink::Error::Rejected(ref error)))
+                if error.raw_os_error() == Some(libc::ENODEV) => {}
+            result => {
+                result?;
+            }
+        }
+        request(
+            &mut route_socket,
+            new_link_message(INTERFACE_NAME, config.mtu),
+        )?;
+        let interface_index =
+            crate::linux::iface_index(INTERFACE_NAME).map_err(Error::InterfaceIndex)?;
+
+        // Dropping the tunnel removes the interface, so a failed configuration cleans up.
+        let mut tunnel = KernelTunnel {
+            interface_name: INTERFACE_NAME.to_owned(),
+            interface_index: Some(interface_index),
+            family_id: 0,
+        };
+        tunnel.configure(&mut route_socket, interface_index, config)?;
+        Ok(tunnel)
+    }
+
+    fn configure(
+        &mut self,
+        route_socket: &mut NetlinkSocket,
+        interface_index: u32,
+        config: &Config,
+    ) -> Result<()> {
+        for address in &config.tunnel.addresses {
+            request(route_socket, new_address_message(interface_index, *address))?;
+        }
+
+        let mut generic_socket = open_socket(libc::NETLINK_GENERIC)?;
+        let replies = request(&mut generic_socket, get_family_message(WG_GENL_NAME))?;
+        self.family_id = replies
+            .iter()
+            .filter_map(|reply| parse_family_id(reply))
+            .next()
+            .ok_or(Error::NoWireguardFamily)?;
+        request(
+            &mut generic_socket,
+            set_device_message(self.family_id, interface_index, config),
+        )?;
+
+        request(route_socket, set_link_up_message(interface_index))?;
+        Ok(())
+    }
+
+    fn read_stats(&self) -> Result<TunnelStats> {
+        let interface_index = self.interface_index.ok_or(Error::NoInterface)?;
+        let mut generic_socket = open_socket(libc::NETLINK_GENERIC)?;
+        let replies = request(
+            &mut generic_socket,
+            get_device_message(self.family_id, interface_index),
+        )?;
+        let mut stats = TunnelStats::default();
+        for reply in &replies {
+            add_peer_stats(&mut stats, reply);
+        }
+        Ok(stats)
+    }
+
+    fn remove_interface(&mut self) -> Result<()> {
+        if let Some(interface_index) = self.interface_index.take() {
+            let mut route_socket = open_socket(libc::NETLINK_ROUTE)?;
+            request(&mut route_socket, delete_link_message(interface_index))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for KernelTunnel {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove_interface() {
+            log::error!("Failed to remove WireGuard interface - {}", e);
+        }
+    }
+}
+
+impl Tunnel for KernelTunnel {
+    fn get_interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Kernel
+    }
+
+    fn get_stats(&self) -> super::Result<TunnelStats> {
+        self.read_stats().map_err(super::Error::KernelTunnelError)
+    }
+
+    fn stop(mut self: Box<Self>) -> super::Result<()> {
+        self.remove_interface()
+            .map_err(super::Error::KernelTunnelError)
+    }
+}
+
+/// A netlink request that is built by appending headers and attributes to it.
+struct Message {
+    buffer: Vec<u8>,
+    /// Offsets of the nested attributes that haven't been ended yet.
+    nested: Vec<usize>,
+    /// What the request does, for error messages.
+    description: &'static str,
+}
+
+impl Message {
+    fn new(message_type: u16, flags: u16, description: &'static str) -> Self {
+        let mut buffer = vec![0u8; NETLINK_HEADER_LEN];
+        buffer[4..6].copy_from_slice(&message_type.to_ne_bytes());
+        buffer[6..8].copy_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        Message {
+            buffer,
+            nested: Vec::new(),
+            description,
+        }
+    }
+
+    /// Appends a fixed size header, such as `struct ifinfomsg`.
+    fn header(&mut self, header: &[u8]) -> &mut Self {
+        self.buffer.extend_from_slice(header);
+        self.align();
+        self
+    }
+
+    fn attribute(&mut self, kind: u16, value: &[u8]) -> &mut Self {
+        self.buffer
+            .extend_from_slice(&(4 + value.len() as u16).to_ne_bytes());
+        self.buffer.extend_from_slice(&kind.to_ne_bytes());
+        self.buffer.extend_from_slice(value);
+        self.align();
+        self
+    }
+
+    fn begin_nested(&mut self, kind: u16) -> &mut Self {
+        self.nested.push(self.buffer.len());
+        self.buffer.extend_from_slice(&0u16.to_ne_bytes());
+        self.buffer
+            .extend_from_slice(&(kind | NLA_F_NESTED).to_ne_bytes());
+        self
+    }
+
+    fn end_nested(&mut self) -> &mut Self {
+        let start = self.nested.pop().expect("No nested attribute to end");
+        let length = (self.buffer.len() - start) as u16;
+        self.buffer[start..start + 2].copy_from_slice(&length.to_ne_bytes());
+        self
+    }
+
+    fn align(&mut self) {
+        while self.buffer.len() % 4 != 0 {
+            self.buffer.push(0);
+        }
+    }
+
+    /// Returns the request. The sequence number is set by the socket.
+    fn finish(mut self) -> Vec<u8> {
+        let length = self.buffer.len() as u32;
+        self.buffer[0..4].copy_from_slice(&length.to_ne_bytes());
+        self.buffer
+    }
+}
+
+/// Returns a `struct ifinfomsg`.
+fn interface_info(index: u32, flags: u32, change: u32) -> Vec<u8> {
+    let mut header = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    header.extend_from_slice(&index.to_ne_bytes());
+    header.extend_from_slice(&flags.to_ne_bytes());
+    header.extend_from_slice(&change.to_ne_bytes());
+    header
+}
+
+fn nul_terminated(string: &str) -> Vec<u8> {
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+fn new_link_message(name: &str, mtu: u16) -> Message {
+    let mut message = Message::new(
+        RTM_NEWLINK,
+        NLM_F_CREATE | NLM_F_EXCL,
+        "creating the interface",
+    );
+    message
+        .header(&interface_info(0, 0, 0))
+        .attribute(IFLA_IFNAME, &nul_terminated(name))
+        .attribute(IFLA_MTU, &u32::from(mtu).to_ne_bytes())
+        .begin_nested(IFLA_LINKINFO)
+        .attribute(IFLA_INFO_KIND, WG_GENL_NAME.as_bytes())
+        .end_nested();
+    message
+}
+
+fn set_link_up_message(index: u32) -> Message {
+    let up = libc::IFF_UP as u32;
+    let mut message = Message::new(RTM_NEWLINK, 0, "bringing the interface up");
+    message.header(&interface_info(index, up, up));
+    message
+}
+
+fn delete_link_message(index: u32) -> Message {
+    let mut message = Message::new(RTM_DELLINK, 0, "removing the interface");
+    message.header(&interface_info(index, 0, 0));
+    message
+}
+
+fn delete_link_by_name_message(name: &str) -> Message {
+    let mut message = Message::new(RTM_DELLINK, 0, "removing a stale interface");
+    message
+        .header(&interface_info(0, 0, 0))
+        .attribute(IFLA_IFNAME, &nul_terminated(name));
+    message
+}
+
+fn new_address_message(index: u32, address: IpAddr) -> Message {
+    let (family, prefix_length, flags, bytes) = match address {
+        IpAddr::V4(address) => (libc::AF_INET, 32, 0, address.octets().to_vec()),
+        // Duplicate address detection would delay using the address, and there is nothing on
+        // the other end of the tunnel to collide with.
+        IpAddr::V6(address) => (libc::AF_INET6, 128, IFA_F_NODAD, address.octets().to_vec()),
+    };
+    let mut header = vec![family as u8, prefix_length, flags, 0];
+    header.extend_from_slice(&index.to_ne_bytes());
+
+    let mut message = Message::new(
+        RTM_NEWADDR,
+        NLM_F_CREATE | NLM_F_EXCL,
+        "adding an address to the interface",
+    );
+    message
+        .header(&header)
+        .attribute(IFA_LOCAL, &bytes)
+        .attribute(IFA_ADDRESS, &bytes);
+    message
+}
+
+fn get_family_message(name: &str) -> Message {
+    let mut message = Message::new(GENL_ID_CTRL, 0, "looking up the generic netlink family");
+    message
+        .header(&[CTRL_CMD_GETFAMILY, 1, 0, 0])
+        .attribute(CTRL_ATTR_FAMILY_NAME, &nul_terminated(name));
+    message
+}
+
+/// Returns the family ID in a reply to a `CTRL_CMD_GETFAMILY` request.
+fn parse_family_id(reply: &[u8]) -> Option<u16> {
+    // The attributes follow a `struct genlmsghdr`.
+    let attributes = reply.get(4..)?;
+    parse_attributes(attributes)
+        .into_iter()
+        .find(|(kind, value)| *kind == CTRL_ATTR_FAMILY_ID && value.len() >= 2)
+        .map(|(_, value)| u16::from_ne_bytes([value[0], value[1]]))
+}
+
+fn set_device_message(family_id: u16, index: u32, config: &Config) -> Message {
+    let mut message = Message::new(family_id, 0, "configuring the interface");
+    message
+        .header(&[WG_CMD_SET_DEVICE, WG_GENL_VERSION, 0, 0])
+        .attribute(WGDEVICE_A_IFINDEX, &index.to_ne_bytes())
+        .attribute(WGDEVICE_A_PRIVATE_KEY, config.tunnel.private_key.as_bytes())
+        .attribute(
+            WGDEVICE_A_FWMARK,
+            &crate::linux::TUNNEL_FWMARK.to_ne_bytes(),
+        )
+        .attribute(WGDEVICE_A_FLAGS, &WGDEVICE_F_REPLACE_PEERS.to_ne_bytes())
+        .begin_nested(WGDEVICE_A_PEERS);
+
+    for peer in &config.peers {
+        message
+            .begin_nested(0)
+            .attribute(WGPEER_A_PUBLIC_KEY, peer.public_key.as_bytes())
+            .attribute(WGPEER_A_ENDPOINT, &socket_address(peer.endpoint))
+            .attribute(WGPEER_A_FLAGS, &WGPEER_F_REPLACE_ALLOWEDIPS.to_ne_bytes());
+        if let Some(preshared_key) = &peer.preshared_key {
+            message.attribute(WGPEER_A_PRESHARED_KEY, preshared_key.as_bytes());
+        }
+        if let Some(interval) = peer.persistent_keepalive {
+            message.attribute(
+                WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+                &interval.to_ne_bytes(),
+            );
+        }
+        message.begin_nested(WGPEER_A_ALLOWEDIPS);
+        for network in &peer.allowed_ips {
+            let (family, bytes) = match network.ip() {
+                IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+                IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+            };
+            message
+                .begin_nested(0)
+                .attribute(WGALLOWEDIP_A_FAMILY, &(family as u16).to_ne_bytes())
+                .attribute(WGALLOWEDIP_A_IPADDR, &bytes)
+                .attribute(WGALLOWEDIP_A_CIDR_MASK, &[network.prefix()])
+                .end_nested();
+        }
+        message.end_nested().end_nested();
+    }
+
+    message.end_nested();
+    message
+}
+
+fn get_device_message(family_id: u16, index: u32) -> Message {
+    let mut message = Message::new(family_id, NLM_F_DUMP, "reading the interface");
+    message
+        .header(&[WG_CMD_GET_DEVICE, WG_GENL_VERSION, 0, 0])
+        .attribute(WGDEVICE_A_IFINDEX, &index.to_ne_bytes());
+    message
+}
+
+/// Adds the traffic counters and handshake times of the peers in a `WG_CMD_GET_DEVICE` reply.
+/// Devices with many peers are split over several replies.
+fn add_peer_stats(stats: &mut TunnelStats, reply: &[u8]) {
+    let device_attributes = match reply.get(4..) {
+        Some(attributes) => parse_attributes(attributes),
+        None => return,
+    };
+    let peers = device_attributes
+        .into_iter()
+        .filter(|(kind, _)| *kind == WGDEVICE_A_PEERS)
+        .flat_map(|(_, peers)| parse_attributes(peers));
+
+    for (_, peer) in peers {
+        for (kind, value) in parse_attributes(peer) {
+            match kind {
+                WGPEER_A_RX_BYTES if value.len() == 8 => stats.rx_bytes += read_u64(value),
+                WGPEER_A_TX_BYTES if value.len() == 8 => stats.tx_bytes += read_u64(value),
+                WGPEER_A_LAST_HANDSHAKE_TIME if value.len() == 16 => {
+                    let handshake = stats::handshake_time(
+                        read_u64(&value[0..8]) as i64,
+                        read_u64(&value[8..16]) as i64,
+                    );
+                    stats.last_handshake = stats.last_handshake.max(handshake);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Returns the address as a `struct sockaddr_in` or `struct sockaddr_in6`.
+fn socket_address(address: SocketAddr) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match address {
+        SocketAddr::V4(address) => {
+            bytes.extend_from_slice(&(libc::AF_INET as u16).to_ne_bytes());
+            bytes.extend_from_slice(&address.port().to_be_bytes());
+            bytes.extend_from_slice(&address.ip().octets());
+            bytes.extend_from_slice(&[0u8; 8]);
+        }
+        SocketAddr::V6(address) => {
+            bytes.extend_from_slice(&(libc::AF_INET6 as u16).to_ne_bytes());
+            bytes.extend_from_slice(&address.port().to_be_bytes());
+            bytes.extend_from_slice(&address.flowinfo().to_be_bytes());
+            bytes.extend_from_slice(&address.ip().octets());
+            bytes.extend_from_slice(&address.scope_id().to_ne_bytes());
+        }
+    }
+    bytes
+}
+
+/// Splits netlink attributes into their types and values.
+fn parse_attributes(mut bytes: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    while bytes.len() >= 4 {
+        let length = u16::from_ne_bytes([bytes[0], bytes[1]]) as usize;
+        let kind = u16::from_ne_bytes([bytes[2], bytes[3]]) & !(NLA_F_NESTED | NLA_F_NET_BYTEORDER);
+        if length < 4 || length > bytes.len() {
+            break;
+        }
+        attributes.push((kind, &bytes[4..length]));
+        // Attributes are aligned to four bytes.
+        let aligned_length = (length + 3) & !3;
+        bytes = &bytes[aligned_length.min(bytes.len())..];
+    }
+    attributes
+}
+
+fn open_socket(protocol: libc::c_int) -> Result<NetlinkSocket> {
+    NetlinkSocket::open(protocol).map_err(Error::OpenSocket)
+}
+
+/// Sends the request and waits for it to finish. Returns the payloads of the replies.
+fn request(socket: &mut NetlinkSocket, message: Message) -> Result<Vec<Vec<u8>>> {
+    let description = message.description;
+    socket
+        .request(message.finish())
+        .map_err(|error| Error::Request(description, error))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_ne_bytes(array)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::netlink::read_u32;
+    use talpid_types::net::{
+        wireguard::{PeerConfig, PresharedKey, PrivateKey, TunnelConfig},
+        TransportProtocol,
+    };
+
+    fn config() -> Config {
+        Config {
+            tunnel: TunnelConfig {
+                private_key: PrivateKey::from([1u8; 32]),
+                addresses: vec!["10.64.0.2".parse().unwrap()],
+            },
+            peers: vec![PeerConfig {
+                public_key: PrivateKey::from([2u8; 32]).public_key(),
+                allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+                endpoint: "1.2.3.4:51820".parse().unwrap(),
+                preshared_key: None,
+                persistent_keepalive: None,
+            }],
+            ipv4_gateway: "10.64.0.1".parse().unwrap(),
+            ipv6_gateway: None,
+            mtu: 1380,
+            auto_mtu: false,
+            backend: None,
+            protocol: TransportProtocol::Udp,
+        }
+    }
+
+    #[test]
+    fn test_set_device_message() {
+        let config = config();
+        let message = set_device_message(21, 7, &config).finish();
+        assert_eq!(read_u32(&message[0..4]) as usize, message.len());
+        assert_eq!(u16::from_ne_bytes([message[4], message[5]]), 21);
+        assert_eq!(message[16], WG_CMD_SET_DEVICE);
+
+        let attributes = parse_attributes(&message[20..]);
+        let kinds: Vec<u16> = attributes.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                WGDEVICE_A_IFINDEX,
+                WGDEVICE_A_PRIVATE_KEY,
+                WGDEVICE_A_FWMARK,
+                WGDEVICE_A_FLAGS,
+                WGDEVICE_A_PEERS,
+            ]
+        );
+        assert_eq!(attributes[1].1, config.tunnel.private_key.as_bytes());
+
+        let peers = parse_attributes(attributes[4].1);
+        assert_eq!(peers.len(), 1);
+        let peer = parse_attributes(peers[0].1);
+        assert_eq!(peer[0].1, config.peers[0].public_key.as_bytes());
+        assert_eq!(
+            peer[1].1,
+            &[2, 0, 0xca, 0x6c, 1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0][..]
+        );
+
+        let allowed_ips = parse_attributes(peer[3].1);
+        let allowed_ip = parse_attributes(allowed_ips[0].1);
+        assert_eq!(allowed_ip[1].1, &[0, 0, 0, 0][..]);
+        assert_eq!(allowed_ip[2].1, &[0][..]);
+    }
+
+    #[test]
+    fn test_set_device_message_peer_options() {
+        let mut config = config();
+        config.peers[0].preshared_key = Some(PresharedKey::from([3u8; 32]));
+        config.peers[0].persistent_keepalive = Some(25);
+        let message = set_device_message(21, 7, &config).finish();
+
+        let attributes = parse_attributes(&message[20..]);
+        let peers = parse_attributes(attributes[4].1);
+        let peer = parse_attributes(peers[0].1);
+        let kinds: Vec<u16> = peer.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                WGPEER_A_PUBLIC_KEY,
+                WGPEER_A_ENDPOINT,
+                WGPEER_A_FLAGS,
+                WGPEER_A_PRESHARED_KEY,
+                WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+                WGPEER_A_ALLOWEDIPS,
+            ]
+        );
+        assert_eq!(peer[3].1, &[3u8; 32][..]);
+        assert_eq!(peer[4].1, &25u16.to_ne_bytes()[..]);
+    }
+
+    #[test]
+    fn test_add_peer_stats() {
+        let mut message = Message::new(21, 0, "");
+        message
+            .header(&[WG_CMD_GET_DEVICE, WG_GENL_VERSION, 0, 0])
+            .attribute(WGDEVICE_A_IFINDEX, &7u32.to_ne_bytes())
+            .begin_nested(WGDEVICE_A_PEERS);
+        for (rx_bytes, handshake_secs) in &[(100u64, 1_565_000_000i64), (20, 0)] {
+            let mut handshake = handshake_secs.to_ne_bytes().to_vec();
+            handshake.extend_from_slice(&500i64.to_ne_bytes());
+            message
+                .begin_nested(0)
+                .attribute(WGPEER_A_LAST_HANDSHAKE_TIME, &handshake)
+                .attribute(WGPEER_A_RX_BYTES, &rx_bytes.to_ne_bytes())
+                .attribute(WGPEER_A_TX_BYTES, &30u64.to_ne_bytes())
+                .end_nested();
+        }
+        message.end_nested();
+        let message = message.finish();
+
+        let mut stats = TunnelStats::default();
+        add_peer_stats(&mut stats, &message[NETLINK_HEADER_LEN..]);
+        assert_eq!(stats.rx_bytes, 120);
+        assert_eq!(stats.tx_bytes, 60);
+        assert_eq!(
+            stats.last_handshake,
+            stats::handshake_time(1_565_000_000, 500)
+        );
+    }
+
+    #[test]
+    fn test_parse_family_id() {
+        let mut message = get_family_message(WG_GENL_NAME);
+        message.attribute(CTRL_ATTR_FAMILY_ID, &21u16.to_ne_bytes());
+        let message = message.finish();
+        assert_eq!(parse_family_id(&message[NETLINK_HEADER_LEN..]), Some(21));
+    }
+}
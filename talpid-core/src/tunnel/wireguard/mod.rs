@@ -2,19 +2,35 @@
 
 use self::config::Config;
 use super::{tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
-use crate::routing;
-use std::{collections::HashMap, io, path::Path, sync::mpsc};
-use talpid_types::BoxedError;
+#[cfg(not(target_os = "android"))]
+use crate::proxy::{ProxyMonitor, ProxyMonitorCloseHandle, Udp2TcpProxyMonitor, WaitResult};
+use crate::{journal::SystemStateJournal, routing};
+use parking_lot::Mutex;
+#[cfg(not(target_os = "android"))]
+use std::net::{Ipv4Addr, SocketAddr};
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::{mpsc, Arc, Weak},
+};
+use talpid_types::{
+    net::{wireguard::Backend, TransportProtocol},
+    tunnel::TunnelStats,
+    BoxedError, ErrorExt,
+};
 
 pub mod config;
-mod ping_monitor;
+mod connectivity_monitor;
+#[cfg(target_os = "linux")]
+mod kernel;
+mod mtu;
+mod pinger;
+mod stats;
 pub mod wireguard_go;
 
 pub use self::wireguard_go::WgGoTunnel;
 
-// amount of seconds to run `ping` until it returns.
-const PING_TIMEOUT: u16 = 7;
-
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Errors that can happen in the Wireguard tunnel monitor.
@@ -32,6 +48,11 @@ pub enum Error {
     #[error(display = "Failed to stop wireguard tunnel - {}", status)]
     StopWireguardError { status: i32 },
 
+    /// Failed to set up a tunnel with the WireGuard kernel module.
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to set up a kernel WireGuard tunnel")]
+    KernelTunnelError(#[error(cause)] kernel::Error),
+
     /// Failed to set up routing.
     #[error(display = "Failed to setup routing")]
     SetupRoutingError(#[error(cause)] crate::routing::Error),
@@ -49,42 +70,158 @@ pub enum Error {
     #[error(display = "Failed to configure Wireguard sockets to bypass the tunnel")]
     BypassError(#[error(cause)] BoxedError),
 
-    /// Pinging timed out.
-    #[error(display = "Ping timed out")]
-    PingTimeoutError,
+    /// Failed to read the traffic counters of the tunnel.
+    #[error(display = "Failed to read tunnel statistics")]
+    StatsError(#[error(cause)] stats::Error),
+
+    /// Failed to start monitoring the connectivity of the tunnel.
+    #[error(display = "Failed to start the connectivity monitor")]
+    ConnectivityMonitorError(#[error(cause)] connectivity_monitor::Error),
+
+    /// The tunnel stopped working.
+    #[error(display = "Lost connectivity through the tunnel")]
+    ConnectivityLostError(#[error(cause)] connectivity_monitor::Error),
+
+    /// Packets of the automatically picked MTU are dropped. The next tunnel uses a smaller one.
+    #[error(display = "The tunnel MTU is too large, only {} bytes get through", _0)]
+    MtuTooLargeError(u16),
+
+    /// Failed to start the proxy that carries the tunnel over TCP.
+    #[error(display = "Failed to start the WireGuard over TCP proxy")]
+    StartProxyError(#[error(cause)] io::Error),
+
+    /// The proxy that carries the tunnel over TCP stopped.
+    #[error(display = "The WireGuard over TCP proxy stopped - {}", _0)]
+    ProxyExitedError(String),
+
+    /// WireGuard over TCP is not available on this platform.
+    #[cfg(target_os = "android")]
+    #[error(display = "WireGuard over TCP is not supported")]
+    TcpNotSupportedError,
 }
 
 /// Spawns and monitors a wireguard tunnel
 pub struct WireguardMonitor {
-    /// Tunnel implementation
-    tunnel: Box<dyn Tunnel>,
+    /// Tunnel implementation, shared with the connectivity monitor. Taken when stopping.
+    tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>>,
     /// Route manager
     route_handle: routing::RouteManager,
     /// Callback to signal tunnel events
     event_callback: Box<dyn Fn(TunnelEvent) + Send + Sync + 'static>,
     close_msg_sender: mpsc::Sender<CloseMsg>,
     close_msg_receiver: mpsc::Receiver<CloseMsg>,
+    /// Stops the proxy that carries the tunnel over TCP, if there is one.
+    #[cfg(not(target_os = "android"))]
+    proxy_close_handle: Option<Box<dyn ProxyMonitorCloseHandle>>,
 }
 
 impl WireguardMonitor {
-    pub fn start<F: Fn(TunnelEvent) + Send + Sync + Clone + 'static>(
+    pub(crate) fn start<F: Fn(TunnelEvent) + Send + Sync + Clone + 'static>(
+        config: &Config,
+        log_path: Option<&Path>,
+        on_event: F,
+        tun_provider: &dyn TunProvider,
+        journal: &SystemStateJournal,
+    ) -> Result<WireguardMonitor> {
+        if config.protocol == TransportProtocol::Tcp {
+            #[cfg(not(target_os = "android"))]
+            return Self::start_over_tcp(config, log_path, on_event, tun_provider, journal);
+            #[cfg(target_os = "android")]
+            return Err(Error::TcpNotSupportedError);
+        }
+        Self::start_tunnel(config, config, log_path, on_event, tun_provider, journal)
+    }
+
+    /// Starts the proxy that carries the tunnel over TCP to the relay, and a tunnel that sends
+    /// its packets to the local end of the proxy.
+    #[cfg(not(target_os = "android"))]
+    fn start_over_tcp<F: Fn(TunnelEvent) + Send + Sync + Clone + 'static>(
         config: &Config,
         log_path: Option<&Path>,
         on_event: F,
         tun_provider: &dyn TunProvider,
+        journal: &SystemStateJournal,
     ) -> Result<WireguardMonitor> {
-        let tunnel = Box::new(WgGoTunnel::start_tunnel(
-            &config,
+        let mut proxy: Box<dyn ProxyMonitor> = Box::new(
+            Udp2TcpProxyMonitor::start(config.peers[0].endpoint).map_err(Error::StartProxyError)?,
+        );
+        let mut tunnel_config = config.clone();
+        tunnel_config.peers[0].endpoint = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), proxy.port());
+
+        let mut monitor = match Self::start_tunnel(
+            &tunnel_config,
+            config,
             log_path,
+            on_event,
             tun_provider,
-            Self::get_tunnel_routes(config),
-        )?);
+            journal,
+        ) {
+            Ok(monitor) => monitor,
+            Err(error) => {
+                if let Err(error) = proxy.close_handle().close() {
+                    log::error!("Failed to stop the WireGuard over TCP proxy - {}", error);
+                }
+                return Err(error);
+            }
+        };
+        monitor.proxy_close_handle = Some(proxy.close_handle());
+
+        let close_sender = monitor.close_msg_sender.clone();
+        ::std::thread::spawn(move || {
+            let reason = match proxy.wait() {
+                Ok(WaitResult::ProperShutdown) => return,
+                Ok(WaitResult::UnexpectedExit(reason)) => reason,
+                Err(error) => error.to_string(),
+            };
+            log::error!("WireGuard over TCP proxy stopped - {}", reason);
+            let _ = close_sender.send(CloseMsg::ProxyExited(reason));
+        });
+        Ok(monitor)
+    }
+
+    /// Starts a tunnel with `tunnel_config`. The routes and metadata are taken from `config`,
+    /// which has the real endpoint of the relay when the tunnel goes through a proxy.
+    fn start_tunnel<F: Fn(TunnelEvent) + Send + Sync + Clone + 'static>(
+        tunnel_config: &Config,
+        config: &Config,
+        log_path: Option<&Path>,
+        on_event: F,
+        tun_provider: &dyn TunProvider,
+        journal: &SystemStateJournal,
+    ) -> Result<WireguardMonitor> {
+        let tunnel = Self::open_tunnel(tunnel_config, log_path, tun_provider)?;
         let iface_name = tunnel.get_interface_name();
+        #[cfg(target_os = "linux")]
+        let route_handle = routing::RouteManager::with_policy_routing(
+            Self::get_routes(iface_name, &config),
+            routing::PolicyRouting {
+                fwmark: crate::linux::TUNNEL_FWMARK,
+                table_id: crate::linux::TUNNEL_TABLE_ID,
+            },
+            journal.clone(),
+            &mut tokio_executor::DefaultExecutor::current(),
+        );
+        #[cfg(not(target_os = "linux"))]
         let route_handle = routing::RouteManager::new(
             Self::get_routes(iface_name, &config),
+            journal.clone(),
             &mut tokio_executor::DefaultExecutor::current(),
+        );
+        let route_handle = route_handle.map_err(Error::SetupRoutingError)?;
+        let metadata = Self::tunnel_metadata(tunnel.as_ref(), &config);
+        let tunnel = Arc::new(Mutex::new(Some(tunnel)));
+        let mut connectivity_monitor = connectivity_monitor::ConnectivityMonitor::new(
+            config.ipv4_gateway,
+            &metadata.interface,
+            Arc::downgrade(&tunnel),
         )
-        .map_err(Error::SetupRoutingError)?;
+        .map_err(Error::ConnectivityMonitorError)?;
+
+        let auto_mtu = config.auto_mtu;
+        let tunnel_mtu = config.mtu;
+        let gateway = config.ipv4_gateway;
+        let endpoint = config.peers[0].endpoint.ip();
+
         let event_callback = Box::new(on_event.clone());
         let (close_msg_sender, close_msg_receiver) = mpsc::channel();
         let monitor = WireguardMonitor {
@@ -93,52 +230,140 @@ impl WireguardMonitor {
             event_callback,
             close_msg_sender,
             close_msg_receiver,
+            #[cfg(not(target_os = "android"))]
+            proxy_close_handle: None,
         };
-
-        let metadata = monitor.tunnel_metadata(&config);
-        let iface_name = monitor.tunnel.get_interface_name().to_string();
-        let gateway = config.ipv4_gateway.into();
         let close_sender = monitor.close_msg_sender.clone();
 
         ::std::thread::spawn(move || {
-            match ping_monitor::ping(gateway, PING_TIMEOUT, &iface_name, true) {
-                Ok(()) => {
-                    (on_event)(TunnelEvent::Up(metadata));
+            match connectivity_monitor.establish() {
+                Ok(true) => {
+                    if auto_mtu {
+                        if let Some(mtu) = mtu::check_tunnel_mtu(
+                            gateway,
+                            &metadata.interface,
+                            endpoint,
+                            tunnel_mtu,
+                        ) {
+                            let _ = close_sender.send(CloseMsg::MtuTooLarge(mtu));
+                            return;
+                        }
+                    }
+                    (on_event)(TunnelEvent::Up(metadata))
                 }
-                Err(e) => {
-                    log::error!("First ping to gateway failed - {}", e);
-                    let _ = close_sender.send(CloseMsg::PingErr);
+                Ok(false) => return,
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to establish the tunnel")
+                    );
+                    let _ = close_sender.send(CloseMsg::ConnectivityLost(error));
+                    return;
                 }
-            };
+            }
 
-            if let Err(e) = ping_monitor::monitor_ping(gateway, PING_TIMEOUT, &iface_name) {
-                log::trace!("Ping monitor failed - {}", e);
+            if let Err(error) = connectivity_monitor.run() {
+                log::warn!("{}", error.display_chain());
+                let _ = close_sender.send(CloseMsg::ConnectivityLost(error));
             }
-            let _ = close_sender.send(CloseMsg::PingErr);
         });
 
         Ok(monitor)
     }
 
+    /// Starts the tunnel with the backend in the config. Without one, the kernel module is
+    /// preferred and wireguard-go is used if it isn't available.
+    #[cfg(target_os = "linux")]
+    fn open_tunnel(
+        config: &Config,
+        log_path: Option<&Path>,
+        tun_provider: &dyn TunProvider,
+    ) -> Result<Box<dyn Tunnel>> {
+        match config.backend {
+            Some(Backend::Kernel) => Ok(Box::new(
+                kernel::KernelTunnel::start_tunnel(config).map_err(Error::KernelTunnelError)?,
+            )),
+            Some(Backend::WireguardGo) => {
+                Self::open_wireguard_go_tunnel(config, log_path, tun_provider)
+            }
+            None => match kernel::KernelTunnel::start_tunnel(config) {
+                Ok(tunnel) => Ok(Box::new(tunnel)),
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to use the WireGuard kernel module, using wireguard-go"
+                        )
+                    );
+                    Self::open_wireguard_go_tunnel(config, log_path, tun_provider)
+                }
+            },
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn open_tunnel(
+        config: &Config,
+        log_path: Option<&Path>,
+        tun_provider: &dyn TunProvider,
+    ) -> Result<Box<dyn Tunnel>> {
+        if config.backend == Some(Backend::Kernel) {
+            log::warn!(
+                "The WireGuard kernel module is only supported on Linux, using wireguard-go"
+            );
+        }
+        Self::open_wireguard_go_tunnel(config, log_path, tun_provider)
+    }
+
+    fn open_wireguard_go_tunnel(
+        config: &Config,
+        log_path: Option<&Path>,
+        tun_provider: &dyn TunProvider,
+    ) -> Result<Box<dyn Tunnel>> {
+        Ok(Box::new(WgGoTunnel::start_tunnel(
+            config,
+            log_path,
+            tun_provider,
+            Self::get_tunnel_routes(config),
+        )?))
+    }
+
     pub fn close_handle(&self) -> CloseHandle {
         CloseHandle {
             chan: self.close_msg_sender.clone(),
         }
     }
 
+    /// Returns a handle for reading the traffic counters of the tunnel.
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            tunnel: Arc::downgrade(&self.tunnel),
+        }
+    }
+
     pub fn wait(mut self) -> Result<()> {
         let wait_result = match self.close_msg_receiver.recv() {
-            Ok(CloseMsg::PingErr) => Err(Error::PingTimeoutError),
+            Ok(CloseMsg::ConnectivityLost(error)) => Err(Error::ConnectivityLostError(error)),
+            Ok(CloseMsg::MtuTooLarge(mtu)) => Err(Error::MtuTooLargeError(mtu)),
+            Ok(CloseMsg::ProxyExited(reason)) => Err(Error::ProxyExitedError(reason)),
             Ok(CloseMsg::Stop) => Ok(()),
             Err(_) => Ok(()),
         };
 
+        #[cfg(not(target_os = "android"))]
+        {
+            if let Some(Err(e)) = self.proxy_close_handle.take().map(|handle| handle.close()) {
+                log::error!("Failed to stop the WireGuard over TCP proxy - {}", e);
+            }
+        }
+
         // Clear routes manually - otherwise there will be some log spam since the tunnel device
         // can be removed before the routes are cleared, which automatically clears some of the
         // routes that were set.
         self.route_handle.stop();
 
-        if let Err(e) = self.tunnel.stop() {
+        let tunnel = self.tunnel.lock().take();
+        if let Some(Err(e)) = tunnel.map(|tunnel| tunnel.stop()) {
             log::error!("Failed to stop tunnel - {}", e);
         }
         (self.event_callback)(TunnelEvent::Down);
@@ -164,6 +389,24 @@ impl WireguardMonitor {
             })
     }
 
+    /// Routes the allowed IPs through the tunnel, in the routing table that is only used for
+    /// packets that aren't sent by the tunnel itself. So the endpoints need no routes of their
+    /// own, and nothing has to follow changes to the default route.
+    #[cfg(target_os = "linux")]
+    fn get_routes(
+        iface_name: &str,
+        config: &Config,
+    ) -> HashMap<ipnetwork::IpNetwork, crate::routing::NetNode> {
+        let node = routing::Node::device(iface_name.to_string());
+        config
+            .peers
+            .iter()
+            .flat_map(|peer| peer.allowed_ips.iter())
+            .map(|network| (*network, node.clone().into()))
+            .collect()
+    }
+
+    #[cfg(not(target_os = "linux"))]
     fn get_routes(
         iface_name: &str,
         config: &Config,
@@ -181,20 +424,22 @@ impl WireguardMonitor {
         routes
     }
 
-    fn tunnel_metadata(&self, config: &Config) -> TunnelMetadata {
-        let interface_name = self.tunnel.get_interface_name();
+    fn tunnel_metadata(tunnel: &dyn Tunnel, config: &Config) -> TunnelMetadata {
         TunnelMetadata {
-            interface: interface_name.to_string(),
+            interface: tunnel.get_interface_name().to_string(),
             ips: config.tunnel.addresses.clone(),
             ipv4_gateway: config.ipv4_gateway,
             ipv6_gateway: config.ipv6_gateway,
+            wireguard_backend: Some(tunnel.backend()),
         }
     }
 }
 
 enum CloseMsg {
     Stop,
-    PingErr,
+    ConnectivityLost(connectivity_monitor::Error),
+    MtuTooLarge(u16),
+    ProxyExited(String),
 }
 
 #[derive(Clone, Debug)]
@@ -210,7 +455,30 @@ impl CloseHandle {
     }
 }
 
+/// Reads the traffic counters of a tunnel while it is running.
+#[derive(Clone)]
+pub struct StatsHandle {
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+}
+
+impl StatsHandle {
+    /// Returns `None` if the tunnel has been stopped or the counters can't be read.
+    pub fn get(&self) -> Option<TunnelStats> {
+        let tunnel = self.tunnel.upgrade()?;
+        let tunnel = tunnel.lock();
+        match tunnel.as_ref()?.get_stats() {
+            Ok(stats) => Some(stats),
+            Err(error) => {
+                log::debug!("{}", error.display_chain());
+                None
+            }
+        }
+    }
+}
+
 pub trait Tunnel: Send {
     fn get_interface_name(&self) -> &str;
+    fn backend(&self) -> Backend;
+    fn get_stats(&self) -> Result<TunnelStats>;
     fn stop(self: Box<Self>) -> Result<()>;
 }
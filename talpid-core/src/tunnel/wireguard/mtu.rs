@@ -0,0 +1,332 @@
+//! Picks the tunnel MTU when it is set to be chosen automatically.
+//!
+//! Before the tunnel is started, the MTU is derived from the MTU of the route to the relay, or
+//! from an MTU that was discovered earlier. Once the tunnel is up, the gateway is pinged with
+//! packets of the full MTU. If those are dropped on the way, the largest size that gets through
+//! is searched for and remembered, so the tunnel can be restarted with it.
+
+use super::pinger::{self, Pinger};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::ErrorExt;
+
+/// Bytes that WireGuard adds to every packet. The outer IP and UDP headers, and the header and
+/// authentication tag of the data message.
+const IPV4_OVERHEAD: u16 = 20 + 8 + 32;
+const IPV6_OVERHEAD: u16 = 40 + 8 + 32;
+/// MTU of the path when nothing else is known. Most links are Ethernet.
+const DEFAULT_PATH_MTU: u16 = 1500;
+/// Smallest tunnel MTU that is probed. All IPv4 hosts have to accept packets of this size.
+pub const MIN_MTU: u16 = 576;
+/// How many times a size is probed before it is considered to be dropped.
+const PROBE_ATTEMPTS: usize = 2;
+/// How long to wait for the reply to a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1000);
+const RECEIVE_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a discovered MTU is used for. Paths change when the device changes networks.
+const DISCOVERED_MTU_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// MTUs found by `find_mtu`, by relay address, and when they were found.
+    static ref DISCOVERED_MTUS: Mutex<HashMap<IpAddr, (u16, Instant)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Tells whether packets of a given size make it through the tunnel and back.
+pub trait Probe {
+    fn probe(&mut self, packet_size: u16) -> bool;
+}
+
+/// Returns the largest packet size in `min..=max` that gets through, or `None` if not even `min`
+/// does. Assumes that all sizes up to the largest one get through, and retries sizes that don't
+/// in case the packets were just lost.
+pub fn find_mtu(probe: &mut dyn Probe, min: u16, max: u16) -> Option<u16> {
+    let mut passes = |size| (0..PROBE_ATTEMPTS).any(|_| probe.probe(size));
+
+    // The full size nearly always works, so it is tried first.
+    if passes(max) {
+        return Some(max);
+    }
+    if !passes(min) {
+        return None;
+    }
+    let (mut working, mut failing) = (min, max);
+    while failing - working > 1 {
+        let size = working + (failing - working) / 2;
+        if passes(size) {
+            working = size;
+        } else {
+            failing = size;
+        }
+    }
+    Some(working)
+}
+
+/// Returns the tunnel MTU to start with for a tunnel to `endpoint`. It is the largest one that
+/// fits in the route to the endpoint, unless a smaller MTU has been discovered for it.
+pub fn initial_mtu(endpoint: SocketAddr) -> u16 {
+    let path_mtu = route_mtu(endpoint).unwrap_or(DEFAULT_PATH_MTU);
+    let mtu = tunnel_mtu(path_mtu, endpoint.ip());
+    match discovered_mtu(endpoint.ip()) {
+        Some(discovered) if discovered < mtu => discovered,
+        _ => mtu,
+    }
+}
+
+/// Pings the gateway through the tunnel to find out if packets of the tunnel MTU get through.
+/// Returns the largest size that does if it is smaller, and remembers it for the next tunnel to
+/// `endpoint`.
+pub fn check_tunnel_mtu(
+    gateway: Ipv4Addr,
+    interface: &str,
+    endpoint: IpAddr,
+    tunnel_mtu: u16,
+) -> Option<u16> {
+    let mut probe = match PingProbe::new(gateway, interface) {
+        Ok(probe) => probe,
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to start probing the MTU")
+            );
+            return None;
+        }
+    };
+    match find_mtu(&mut probe, MIN_MTU, tunnel_mtu) {
+        Some(mtu) if mtu < tunnel_mtu => {
+            log::warn!(
+                "Packets larger than {} bytes are dropped on the way to the relay",
+                mtu
+            );
+            remember_mtu(endpoint, mtu);
+            Some(mtu)
+        }
+        Some(_) => None,
+        None => {
+            log::warn!("None of the MTU probes got through the tunnel");
+            None
+        }
+    }
+}
+
+/// Remembers an MTU that was found to work for tunnels to `endpoint`.
+fn remember_mtu(endpoint: IpAddr, mtu: u16) {
+    DISCOVERED_MTUS
+        .lock()
+        .insert(endpoint, (mtu, Instant::now()));
+}
+
+fn discovered_mtu(endpoint: IpAddr) -> Option<u16> {
+    let mut discovered = DISCOVERED_MTUS.lock();
+    match discovered.get(&endpoint) {
+        Some((mtu, found)) if found.elapsed() < DISCOVERED_MTU_LIFETIME => Some(*mtu),
+        Some(_) => {
+            discovered.remove(&endpoint);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Returns the largest tunnel MTU whose packets fit in `path_mtu` once encapsulated.
+fn tunnel_mtu(path_mtu: u16, endpoint: IpAddr) -> u16 {
+    let overhead = match endpoint {
+        IpAddr::V4(_) => IPV4_OVERHEAD,
+        IpAddr::V6(_) => IPV6_OVERHEAD,
+    };
+    path_mtu.saturating_sub(overhead).max(MIN_MTU)
+}
+
+/// Returns the MTU of the route to `endpoint`, as the kernel knows it. Routes through links with
+/// a small MTU, like PPPoE, are detected this way, but not smaller links further along the path.
+#[cfg(target_os = "linux")]
+fn route_mtu(endpoint: SocketAddr) -> Option<u16> {
+    use std::{mem, net::UdpSocket, os::unix::io::AsRawFd};
+
+    let result = (|| -> std::io::Result<u16> {
+        let bind_address: SocketAddr = match endpoint {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_address)?;
+        // Marked like the packets of the tunnel, so the same route is looked up.
+        let mark = crate::linux::TUNNEL_FWMARK;
+        if unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_MARK,
+                &mark as *const u32 as *const libc::c_void,
+                mem::size_of::<u32>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        socket.connect(endpoint)?;
+
+        let (level, option) = match endpoint {
+            SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_MTU),
+            SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_MTU),
+        };
+        let mut mtu: libc::c_int = 0;
+        let mut mtu_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        if unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &mut mtu as *mut libc::c_int as *mut libc::c_void,
+                &mut mtu_len,
+            )
+        } < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(mtu as u16)
+    })();
+
+    match result {
+        Ok(mtu) => Some(mtu),
+        Err(error) => {
+            log::warn!(
+                "Failed to read the MTU of the route to the relay: {}",
+                error
+            );
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn route_mtu(_endpoint: SocketAddr) -> Option<u16> {
+    None
+}
+
+/// Probes the tunnel by pinging the gateway with packets of the size being probed.
+pub struct PingProbe {
+    pinger: Pinger,
+}
+
+impl PingProbe {
+    pub fn new(gateway: Ipv4Addr, interface: &str) -> Result<Self, pinger::Error> {
+        Ok(PingProbe {
+            pinger: Pinger::new(gateway, interface)?,
+        })
+    }
+
+    fn ping(&mut self, packet_size: u16) -> Result<bool, pinger::Error> {
+        // Drops replies to earlier probes.
+        self.pinger.receive_replies()?;
+        let sequence = self.pinger.send_sized_ping(packet_size)?;
+        let start = Instant::now();
+        while start.elapsed() < PROBE_TIMEOUT {
+            thread::sleep(RECEIVE_INTERVAL);
+            if self.pinger.receive_reply_to(sequence)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Probe for PingProbe {
+    fn probe(&mut self, packet_size: u16) -> bool {
+        match self.ping(packet_size) {
+            Ok(received) => received,
+            Err(error) => {
+                log::debug!("Failed to probe the MTU: {}", error);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A path that drops packets larger than its MTU, and the first `lost` probes of other sizes.
+    struct SimulatedPath {
+        mtu: u16,
+        lost: usize,
+        probes: Vec<u16>,
+    }
+
+    impl SimulatedPath {
+        fn new(mtu: u16) -> Self {
+            SimulatedPath {
+                mtu,
+                lost: 0,
+                probes: Vec::new(),
+            }
+        }
+    }
+
+    impl Probe for SimulatedPath {
+        fn probe(&mut self, packet_size: u16) -> bool {
+            self.probes.push(packet_size);
+            if packet_size > self.mtu {
+                return false;
+            }
+            if self.lost > 0 {
+                self.lost -= 1;
+                return false;
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_full_size_passes() {
+        let mut path = SimulatedPath::new(1500);
+        assert_eq!(find_mtu(&mut path, MIN_MTU, 1420), Some(1420));
+        assert_eq!(path.probes, vec![1420]);
+    }
+
+    #[test]
+    fn test_finds_largest_size() {
+        for &mtu in &[MIN_MTU, 1280, 1332, 1419] {
+            let mut path = SimulatedPath::new(mtu);
+            assert_eq!(find_mtu(&mut path, MIN_MTU, 1420), Some(mtu));
+        }
+    }
+
+    #[test]
+    fn test_lost_probe_is_retried() {
+        let mut path = SimulatedPath::new(1500);
+        path.lost = 1;
+        assert_eq!(find_mtu(&mut path, MIN_MTU, 1420), Some(1420));
+        assert_eq!(path.probes, vec![1420, 1420]);
+    }
+
+    #[test]
+    fn test_broken_path() {
+        let mut path = SimulatedPath::new(500);
+        assert_eq!(find_mtu(&mut path, MIN_MTU, 1420), None);
+    }
+
+    #[test]
+    fn test_tunnel_mtu() {
+        let ipv4: IpAddr = "1.2.3.4".parse().unwrap();
+        let ipv6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(tunnel_mtu(1500, ipv4), 1440);
+        assert_eq!(tunnel_mtu(1500, ipv6), 1420);
+        assert_eq!(tunnel_mtu(1492, ipv4), 1432);
+        assert_eq!(tunnel_mtu(100, ipv4), MIN_MTU);
+    }
+
+    #[test]
+    fn test_discovered_mtu_is_used() {
+        let endpoint: SocketAddr = "192.0.2.1:51820".parse().unwrap();
+        let mtu = initial_mtu(endpoint);
+        remember_mtu(endpoint.ip(), mtu - 100);
+        assert_eq!(initial_mtu(endpoint), mtu - 100);
+    }
+}
@@ -0,0 +1,324 @@
+//! Sends ICMP echo requests through the tunnel without running the `ping` binary.
+
+use std::{io, mem, net::Ipv4Addr, os::unix::io::RawFd};
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_HEADER_LEN: usize = 8;
+const IPV4_HEADER_LEN: usize = 20;
+/// The echo request carries a few bytes of payload, like `ping` does.
+const PAYLOAD: &[u8] = b"mullvad-connectivity-check";
+
+/// Binds a socket to an interface index on macOS. Not exposed by the `libc` crate.
+#[cfg(target_os = "macos")]
+const IP_BOUND_IF: libc::c_int = 25;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to open ICMP socket")]
+    OpenSocket(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to bind ICMP socket to the tunnel interface")]
+    BindSocket(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to send ICMP echo request")]
+    Send(#[error(cause)] io::Error),
+
+    #[error(display = "Failed to receive ICMP echo reply")]
+    Receive(#[error(cause)] io::Error),
+}
+
+pub struct Pinger {
+    socket: RawFd,
+    destination: Ipv4Addr,
+    id: u16,
+    sequence: u16,
+}
+
+impl Pinger {
+    /// Opens a non-blocking ICMP socket that sends echo requests through the given interface.
+    pub fn new(destination: Ipv4Addr, interface: &str) -> Result<Self, Error> {
+        let socket = unsafe { libc::socket(libc::AF_INET, SOCKET_TYPE, libc::IPPROTO_ICMP) };
+        if socket < 0 {
+            return Err(Error::OpenSocket(io::Error::last_os_error()));
+        }
+        let pinger = Pinger {
+            socket,
+            destination,
+            id: std::process::id() as u16,
+            sequence: 0,
+        };
+        set_nonblocking(socket).map_err(Error::OpenSocket)?;
+        bind_to_interface(socket, interface).map_err(Error::BindSocket)?;
+        Ok(pinger)
+    }
+
+    pub fn send_ping(&mut self) -> Result<(), Error> {
+        self.send(PAYLOAD.len()).map(|_| ())
+    }
+
+    /// Sends an echo request that makes an IPv4 packet of `packet_size` bytes. Returns the
+    /// sequence number of the request.
+    pub fn send_sized_ping(&mut self, packet_size: u16) -> Result<u16, Error> {
+        let payload_len =
+            usize::from(packet_size).saturating_sub(IPV4_HEADER_LEN + ICMP_HEADER_LEN);
+        self.send(payload_len)
+    }
+
+    fn send(&mut self, payload_len: usize) -> Result<u16, Error> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let request = echo_request(self.id, self.sequence, payload_len);
+        let address = socket_address(self.destination);
+        let sent = unsafe {
+            libc::sendto(
+                self.socket,
+                request.as_ptr() as *const libc::c_void,
+                request.len(),
+                0,
+                &address as *const libc::sockaddr_in as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(Error::Send(io::Error::last_os_error()));
+        }
+        Ok(self.sequence)
+    }
+
+    /// Reads all packets that have arrived, and returns whether any of them is a reply to an echo
+    /// request from this pinger. Doesn't block.
+    pub fn receive_replies(&mut self) -> Result<bool, Error> {
+        self.receive(None)
+    }
+
+    /// Like `receive_replies`, but only counts the reply to the request with the given sequence
+    /// number.
+    pub fn receive_reply_to(&mut self, sequence: u16) -> Result<bool, Error> {
+        self.receive(Some(sequence))
+    }
+
+    fn receive(&mut self, sequence: Option<u16>) -> Result<bool, Error> {
+        let mut received_reply = false;
+        // Replies to large requests are truncated, but only the headers are read.
+        let mut buffer = [0u8; 1024];
+        loop {
+            let mut address: libc::sockaddr_in = unsafe { mem::zeroed() };
+            let mut address_len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            let received = unsafe {
+                libc::recvfrom(
+                    self.socket,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                    &mut address as *mut libc::sockaddr_in as *mut libc::sockaddr,
+                    &mut address_len,
+                )
+            };
+            if received < 0 {
+                let error = io::Error::last_os_error();
+                return match error.kind() {
+                    io::ErrorKind::WouldBlock => Ok(received_reply),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => Err(Error::Receive(error)),
+                };
+            }
+
+            let source = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+            if source == self.destination {
+                let reply_sequence = parse_echo_reply(&buffer[..received as usize], self.id);
+                received_reply |= match sequence {
+                    Some(sequence) => reply_sequence == Some(sequence),
+                    None => reply_sequence.is_some(),
+                };
+            }
+        }
+    }
+}
+
+impl Drop for Pinger {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.socket) };
+    }
+}
+
+/// Raw sockets are used where the daemon runs as root. On Android, and on macOS where it is
+/// allowed for everyone, datagram sockets are used instead.
+#[cfg(target_os = "linux")]
+const SOCKET_TYPE: libc::c_int = libc::SOCK_RAW;
+#[cfg(any(target_os = "android", target_os = "macos"))]
+const SOCKET_TYPE: libc::c_int = libc::SOCK_DGRAM;
+
+/// Whether received packets start with the IPv4 header.
+const RECEIVES_IP_HEADER: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+/// Linux rewrites the identifier of echo requests sent over datagram sockets, and only delivers
+/// the matching replies.
+const CHECKS_IDENTIFIER: bool = cfg!(not(target_os = "android"));
+
+fn set_nonblocking(socket: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(socket, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(socket, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(socket, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_interface(socket: RawFd, interface: &str) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn bind_to_interface(socket: RawFd, interface: &str) -> io::Result<()> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = unsafe {
+        libc::setsockopt(
+            socket,
+            libc::IPPROTO_IP,
+            IP_BOUND_IF,
+            &index as *const libc::c_uint as *const libc::c_void,
+            mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Binding requires privileges that the app doesn't have on Android, but all its traffic is
+/// routed through the tunnel anyway.
+#[cfg(target_os = "android")]
+fn bind_to_interface(_socket: RawFd, _interface: &str) -> io::Result<()> {
+    Ok(())
+}
+
+fn socket_address(address: Ipv4Addr) -> libc::sockaddr_in {
+    let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+    sockaddr.sin_addr.s_addr = u32::from(address).to_be();
+    #[cfg(target_os = "macos")]
+    {
+        sockaddr.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+    }
+    sockaddr
+}
+
+fn echo_request(id: u16, sequence: u16, payload_len: usize) -> Vec<u8> {
+    let mut packet = vec![ICMP_ECHO_REQUEST, 0, 0, 0];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend(PAYLOAD.iter().cycle().take(payload_len));
+    let checksum = checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+/// Returns the sequence number of the echo reply in the packet.
+fn parse_echo_reply(packet: &[u8], id: u16) -> Option<u16> {
+    let icmp = if RECEIVES_IP_HEADER {
+        match packet.first() {
+            Some(first) => packet.get(usize::from(first & 0x0f) * 4..),
+            None => None,
+        }
+    } else {
+        Some(packet)
+    };
+    match icmp {
+        Some(icmp)
+            if icmp.len() >= ICMP_HEADER_LEN
+                && icmp[0] == ICMP_ECHO_REPLY
+                && (!CHECKS_IDENTIFIER || u16::from_be_bytes([icmp[4], icmp[5]]) == id) =>
+        {
+            Some(u16::from_be_bytes([icmp[6], icmp[7]]))
+        }
+        _ => None,
+    }
+}
+
+/// The internet checksum from RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2).fold(0u32, |sum, chunk| {
+        let word = match *chunk {
+            [high, low] => u16::from_be_bytes([high, low]),
+            [high] => u16::from_be_bytes([high, 0]),
+            _ => 0,
+        };
+        sum + u32::from(word)
+    });
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_echo_request_checksum() {
+        let request = echo_request(0x1234, 1, PAYLOAD.len());
+        assert_eq!(request[0], ICMP_ECHO_REQUEST);
+        assert_eq!(&request[4..8], &[0x12, 0x34, 0, 1]);
+        // The checksum of a packet that includes its checksum is zero.
+        assert_eq!(checksum(&request), 0);
+    }
+
+    #[test]
+    fn test_checksum_of_odd_length() {
+        assert_eq!(checksum(&[0x01]), !0x0100);
+        assert_eq!(checksum(&[0xff, 0xff, 0x00, 0x01]), !0x0001);
+    }
+
+    #[test]
+    fn test_parse_echo_reply() {
+        let mut reply = echo_request(0x1234, 1, PAYLOAD.len());
+        reply[0] = ICMP_ECHO_REPLY;
+        let packet = if RECEIVES_IP_HEADER {
+            let mut packet = vec![0x45];
+            packet.extend_from_slice(&[0; 19]);
+            packet.extend_from_slice(&reply);
+            packet
+        } else {
+            reply.clone()
+        };
+        assert_eq!(parse_echo_reply(&packet, 0x1234), Some(1));
+
+        let request = if RECEIVES_IP_HEADER {
+            let mut request = packet.clone();
+            request[20] = ICMP_ECHO_REQUEST;
+            request
+        } else {
+            echo_request(0x1234, 1, PAYLOAD.len())
+        };
+        assert_eq!(parse_echo_reply(&request, 0x1234), None);
+        assert_eq!(parse_echo_reply(&[], 0x1234), None);
+    }
+
+    #[test]
+    fn test_sized_echo_request() {
+        let request = echo_request(0x1234, 1, 1000);
+        assert_eq!(request.len(), ICMP_HEADER_LEN + 1000);
+        assert_eq!(checksum(&request), 0);
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use talpid_types::tunnel::TunnelStats;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to read the configuration of the tunnel")]
+    NoConfig,
+
+    #[error(display = "Invalid value for {}: {}", _0, _1)]
+    InvalidValue(String, String),
+}
+
+/// Sums the traffic counters of all peers in the output of a userspace API `get` operation, and
+/// finds the latest handshake.
+pub fn parse_userspace_config(config: &str) -> Result<TunnelStats, Error> {
+    let mut stats = TunnelStats::default();
+    // The handshake time is split into seconds and nanoseconds, which come in that order.
+    let mut handshake_secs = 0;
+
+    for line in config.lines() {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = match parts.next() {
+            Some(value) => value,
+            None => continue,
+        };
+
+        match key {
+            "tx_bytes" => stats.tx_bytes += parse_number(key, value)?,
+            "rx_bytes" => stats.rx_bytes += parse_number(key, value)?,
+            "last_handshake_time_sec" => handshake_secs = parse_number(key, value)?,
+            "last_handshake_time_nsec" => {
+                let handshake_nanos = parse_number(key, value)?;
+                // A peer that hasn't completed a handshake reports zero.
+                if handshake_secs != 0 || handshake_nanos != 0 {
+                    let handshake = UNIX_EPOCH
+                        + Duration::from_secs(handshake_secs)
+                        + Duration::from_nanos(handshake_nanos);
+                    stats.last_handshake = stats.last_handshake.max(Some(handshake));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(stats)
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidValue(key.to_owned(), value.to_owned()))
+}
+
+/// Converts the handshake time reported by the kernel.
+#[cfg(target_os = "linux")]
+pub fn handshake_time(secs: i64, nanos: i64) -> Option<SystemTime> {
+    if secs <= 0 && nanos <= 0 {
+        None
+    } else {
+        Some(UNIX_EPOCH + Duration::from_secs(secs as u64) + Duration::from_nanos(nanos as u64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str =
+        "private_key=0101010101010101010101010101010101010101010101010101010101010101
+listen_port=51820
+public_key=0202020202020202020202020202020202020202020202020202020202020202
+endpoint=1.2.3.4:51820
+last_handshake_time_sec=1565000000
+last_handshake_time_nsec=500
+tx_bytes=1000
+rx_bytes=2000
+persistent_keepalive_interval=0
+allowed_ip=0.0.0.0/0
+public_key=0303030303030303030303030303030303030303030303030303030303030303
+endpoint=5.6.7.8:51820
+last_handshake_time_sec=0
+last_handshake_time_nsec=0
+tx_bytes=30
+rx_bytes=0
+protocol_version=1
+errno=0
+";
+
+    #[test]
+    fn test_parse_userspace_config() {
+        let stats = parse_userspace_config(CONFIG).unwrap();
+        assert_eq!(stats.tx_bytes, 1030);
+        assert_eq!(stats.rx_bytes, 2000);
+        assert_eq!(
+            stats.last_handshake,
+            Some(UNIX_EPOCH + Duration::new(1_565_000_000, 500))
+        );
+    }
+
+    #[test]
+    fn test_parse_config_without_handshake() {
+        let config = "public_key=02\nlast_handshake_time_sec=0\nlast_handshake_time_nsec=0\n";
+        let stats = parse_userspace_config(config).unwrap();
+        assert_eq!(stats.last_handshake, None);
+    }
+
+    #[test]
+    fn test_parse_invalid_counter() {
+        assert!(parse_userspace_config("tx_bytes=many\n").is_err());
+    }
+}
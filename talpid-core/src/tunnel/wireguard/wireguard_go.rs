@@ -1,9 +1,16 @@
-use super::{Config, Error, Result, Tunnel};
+use super::{stats, Config, Error, Result, Tunnel};
 use crate::tunnel::tun_provider::{Tun, TunConfig, TunProvider};
 use ipnetwork::IpNetwork;
-use std::{ffi::CString, fs, net::IpAddr, os::unix::io::AsRawFd, path::Path};
+use std::{
+    ffi::{CStr, CString},
+    fs,
+    net::IpAddr,
+    os::unix::io::AsRawFd,
+    path::Path,
+};
 #[cfg(target_os = "android")]
 use talpid_types::BoxedError;
+use talpid_types::{net::wireguard::Backend, tunnel::TunnelStats};
 
 pub struct WgGoTunnel {
     interface_name: String,
@@ -113,6 +120,26 @@ impl Tunnel for WgGoTunnel {
         &self.interface_name
     }
 
+    fn backend(&self) -> Backend {
+        Backend::WireguardGo
+    }
+
+    fn get_stats(&self) -> Result<TunnelStats> {
+        let handle = self
+            .handle
+            .ok_or(Error::StatsError(stats::Error::NoConfig))?;
+        let config_ptr = unsafe { wgGetConfig(handle) };
+        if config_ptr.is_null() {
+            return Err(Error::StatsError(stats::Error::NoConfig));
+        }
+        let config = unsafe { CStr::from_ptr(config_ptr) }
+            .to_string_lossy()
+            .into_owned();
+        // The string is allocated with `malloc` by cgo.
+        unsafe { libc::free(config_ptr as *mut libc::c_void) };
+        stats::parse_userspace_config(&config).map_err(Error::StatsError)
+    }
+
     fn stop(mut self: Box<Self>) -> Result<()> {
         self.stop_tunnel()
     }
@@ -149,6 +176,10 @@ extern "C" {
     // Pass a handle that was created by wgTurnOnWithFd to stop a wireguard tunnel.
     fn wgTurnOff(handle: i32) -> i32;
 
+    // Returns the output of a userspace API `get` operation, or null if the handle is invalid.
+    // The string has to be freed by the caller.
+    fn wgGetConfig(handle: i32) -> *mut libc::c_char;
+
     // Returns the file descriptor of the tunnel IPv4 socket.
     #[cfg(target_os = "android")]
     fn wgGetSocketV4(handle: i32) -> Fd;
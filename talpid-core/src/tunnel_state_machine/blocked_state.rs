@@ -2,7 +2,6 @@ use super::{
     ConnectingState, DisconnectedState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
     TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
-use crate::firewall::FirewallPolicy;
 use futures::{sync::mpsc, Stream};
 use talpid_types::{tunnel::BlockReason, ErrorExt};
 
@@ -13,9 +12,7 @@ pub struct BlockedState {
 
 impl BlockedState {
     fn set_firewall_policy(shared_values: &mut SharedTunnelStateValues) -> Option<BlockReason> {
-        let policy = FirewallPolicy::Blocked {
-            allow_lan: shared_values.allow_lan,
-        };
+        let policy = shared_values.blocking_firewall_policy();
 
         match shared_values.firewall.apply_policy(policy) {
             Ok(()) => None,
@@ -57,11 +54,19 @@ impl TunnelState for BlockedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                shared_values.allow_lan = allow_lan;
+            Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                shared_values.lan_policy = lan_policy;
                 Self::set_firewall_policy(shared_values);
                 SameState(self)
             }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                SameState(self)
+            }
+            Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                shared_values.local_resolver = local_resolver;
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -74,6 +79,35 @@ impl TunnelState for BlockedState {
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                shared_values.is_captive_portal = is_captive_portal;
+                // The probe only gets through the firewall while login traffic is allowed.
+                if !is_captive_portal
+                    && shared_values.allow_captive_portal
+                    && self.block_reason == BlockReason::CaptivePortal
+                {
+                    NewState(ConnectingState::enter(shared_values, 0))
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                shared_values.allow_captive_portal = allow_captive_portal;
+                Self::set_firewall_policy(shared_values);
+                if !allow_captive_portal
+                    && !shared_values.is_captive_portal
+                    && self.block_reason == BlockReason::CaptivePortal
+                {
+                    NewState(ConnectingState::enter(shared_values, 0))
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::DnsReapplied(_)) => SameState(self),
+            Ok(TunnelCommand::GetStats(tx)) => {
+                let _ = tx.send(None);
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Ok(TunnelCommand::Disconnect) | Err(_) => {
                 NewState(DisconnectedState::enter(shared_values, ()))
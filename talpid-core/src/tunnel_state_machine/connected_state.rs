@@ -1,18 +1,26 @@
 use super::{
-    AfterDisconnect, BlockedState, ConnectingState, DisconnectingState, EventConsequence,
-    SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connecting_state::TunnelCloseEvent, AfterDisconnect, BlockedState, ConnectingState,
+    DisconnectingState, EventConsequence, SharedTunnelStateValues, TunnelCommand, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
+    dns::{
+        Blocklist, DnsTransport, LocalResolver, UdpTransport, DNS_PORT, LOCAL_RESOLVER_ADDRESS,
+    },
     firewall::FirewallPolicy,
-    tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
+    tunnel::{CloseHandle, StatsHandle, TunnelEvent, TunnelMetadata},
 };
 use futures::{
     sync::{mpsc, oneshot},
     Async, Future, Stream,
 };
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::Arc,
+};
 use talpid_types::{
-    net::{Endpoint, TunnelParameters},
-    tunnel::BlockReason,
+    net::{dns::DnsUpstream, Endpoint, TunnelParameters},
+    tunnel::{BlockReason, Ipv6LeakCheck},
     ErrorExt,
 };
 
@@ -20,8 +28,10 @@ pub struct ConnectedStateBootstrap {
     pub metadata: TunnelMetadata,
     pub tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     pub tunnel_parameters: TunnelParameters,
-    pub tunnel_close_event: oneshot::Receiver<Option<BlockReason>>,
+    pub tunnel_close_event: oneshot::Receiver<TunnelCloseEvent>,
     pub close_handle: CloseHandle,
+    pub stats_handle: Option<StatsHandle>,
+    pub retry_attempt: u32,
 }
 
 /// The tunnel is up and working.
@@ -29,8 +39,13 @@ pub struct ConnectedState {
     metadata: TunnelMetadata,
     tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     tunnel_parameters: TunnelParameters,
-    tunnel_close_event: oneshot::Receiver<Option<BlockReason>>,
+    tunnel_close_event: oneshot::Receiver<TunnelCloseEvent>,
     close_handle: CloseHandle,
+    stats_handle: Option<StatsHandle>,
+    /// DNS servers outside the tunnel that resolve the split DNS domains.
+    split_dns_servers: Vec<IpAddr>,
+    /// Forwards the queries of the system through the tunnel, when enabled.
+    local_resolver: Option<LocalResolver>,
 }
 
 impl ConnectedState {
@@ -41,6 +56,9 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             close_handle: bootstrap.close_handle,
+            stats_handle: bootstrap.stats_handle,
+            split_dns_servers: Vec::new(),
+            local_resolver: None,
         }
     }
 
@@ -54,7 +72,11 @@ impl ConnectedState {
         let policy = FirewallPolicy::Connected {
             peer_endpoint,
             tunnel: self.metadata.clone(),
-            allow_lan: shared_values.allow_lan,
+            lan_policy: shared_values.lan_policy.clone(),
+            split_dns_servers: self.split_dns_servers.clone(),
+            allow_gateway_dns: !self.dns_upstream().is_encrypted(),
+            local_resolver_upstream: self.local_resolver_upstream(),
+            allow_ipv6: self.allow_ipv6(),
         };
         shared_values.firewall.apply_policy(policy)
     }
@@ -70,17 +92,150 @@ impl ConnectedState {
     }
 
     fn set_dns(
-        &self,
+        &mut self,
         shared_values: &mut SharedTunnelStateValues,
     ) -> Result<(), crate::dns::Error> {
-        let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-            dns_ips.push(ipv6_gateway.into());
+        let mut dns_ips = Vec::new();
+        if self.local_resolver.is_some() {
+            dns_ips.push(LOCAL_RESOLVER_ADDRESS.into());
+        } else {
+            dns_ips.push(self.metadata.ipv4_gateway.into());
+            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
+                dns_ips.push(ipv6_gateway.into());
+            };
+        }
+        // IPv6 DNS servers would be unreachable, or reached outside the tunnel.
+        if !self.allow_ipv6() {
+            dns_ips.retain(IpAddr::is_ipv4);
+        }
+
+        self.split_dns_servers = shared_values.dns_monitor.set(
+            &self.metadata.interface,
+            &dns_ips,
+            &shared_values.split_dns,
+        )?;
+        Ok(())
+    }
+
+    fn dns_upstream(&self) -> &DnsUpstream {
+        &self.tunnel_parameters.get_generic_options().dns_upstream
+    }
+
+    fn allow_ipv6(&self) -> bool {
+        self.tunnel_parameters.get_generic_options().enable_ipv6
+    }
+
+    /// The server the local resolver forwards queries to, if it's running.
+    fn local_resolver_upstream(&self) -> Option<SocketAddr> {
+        self.local_resolver.as_ref()?;
+        Some(match self.dns_upstream() {
+            DnsUpstream::Gateway => SocketAddr::new(self.metadata.ipv4_gateway.into(), DNS_PORT),
+            DnsUpstream::Encrypted(server) => SocketAddr::new(server.address, server.port()),
+        })
+    }
+
+    /// Starts the local resolver if it's enabled or DNS should be encrypted, and stops it
+    /// otherwise. A running resolver is restarted, so that changes to the blocklists are picked
+    /// up. The firewall policy is updated if the resolver was started or stopped, so that its
+    /// upstream can only be reached through the tunnel.
+    fn update_local_resolver(
+        &mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<(), BlockReason> {
+        let previous_upstream = self.local_resolver_upstream();
+        // The running resolver has to be stopped first, to free the address it listens on.
+        self.local_resolver = None;
+        let result = self.start_local_resolver(shared_values);
+        if self.local_resolver_upstream() != previous_upstream {
+            if let Err(error) = self.set_firewall_policy(shared_values) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply firewall policy for connected state"
+                    )
+                );
+                return Err(BlockReason::SetFirewallPolicyError);
+            }
+        }
+        result
+    }
+
+    fn start_local_resolver(
+        &mut self,
+        shared_values: &SharedTunnelStateValues,
+    ) -> Result<(), BlockReason> {
+        if !shared_values.local_resolver.enabled && !self.dns_upstream().is_encrypted() {
+            return Ok(());
+        }
+
+        let transport: Arc<dyn DnsTransport> = match self.dns_upstream() {
+            DnsUpstream::Gateway => Arc::new(UdpTransport::new(SocketAddr::new(
+                self.metadata.ipv4_gateway.into(),
+                DNS_PORT,
+            ))),
+            DnsUpstream::Encrypted(server) => {
+                match shared_values.encrypted_dns_provider.create_transport(server) {
+                    Ok(transport) => transport,
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(&format!(
+                                "Failed to connect to {}",
+                                server
+                            ))
+                        );
+                        return Err(BlockReason::SetDnsError);
+                    }
+                }
+            }
         };
+        let blocklist = if shared_values.local_resolver.enabled {
+            Blocklist::from_files(&shared_values.local_resolver.blocklists)
+        } else {
+            Blocklist::default()
+        };
+        let listen_address = SocketAddr::new(LOCAL_RESOLVER_ADDRESS.into(), DNS_PORT);
+        match LocalResolver::start(listen_address, transport, blocklist) {
+            Ok(local_resolver) => {
+                self.local_resolver = Some(local_resolver);
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start the local DNS resolver")
+                );
+                Err(BlockReason::SetDnsError)
+            }
+        }
+    }
 
-        shared_values
-            .dns_monitor
-            .set(&self.metadata.interface, &dns_ips)
+    /// Sets DNS, and lets the servers that resolve the split DNS domains through the firewall.
+    /// Returns the reason to block if either fails.
+    fn set_dns_and_firewall_policy(
+        &mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<(), BlockReason> {
+        let previous_split_dns_servers = self.split_dns_servers.clone();
+        if let Err(error) = self.set_dns(shared_values) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set system DNS settings")
+            );
+            return Err(BlockReason::SetDnsError);
+        }
+        if self.split_dns_servers != previous_split_dns_servers {
+            if let Err(error) = self.set_firewall_policy(shared_values) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply firewall policy for connected state"
+                    )
+                );
+                return Err(BlockReason::SetFirewallPolicyError);
+            }
+        }
+        Ok(())
     }
 
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
@@ -102,15 +257,15 @@ impl ConnectedState {
     }
 
     fn handle_commands(
-        self,
+        mut self,
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence<Self> {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                shared_values.allow_lan = allow_lan;
+            Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                shared_values.lan_policy = lan_policy;
 
                 match self.set_firewall_policy(shared_values) {
                     Ok(()) => SameState(self),
@@ -128,6 +283,29 @@ impl ConnectedState {
                     }
                 }
             }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                if shared_values.split_dns == split_dns {
+                    return SameState(self);
+                }
+                shared_values.split_dns = split_dns;
+                match self.set_dns_and_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self),
+                    Err(reason) => self.disconnect(shared_values, AfterDisconnect::Block(reason)),
+                }
+            }
+            Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                if shared_values.local_resolver == local_resolver {
+                    return SameState(self);
+                }
+                shared_values.local_resolver = local_resolver;
+                match self
+                    .update_local_resolver(shared_values)
+                    .and_then(|()| self.set_dns_and_firewall_policy(shared_values))
+                {
+                    Ok(()) => SameState(self),
+                    Err(reason) => self.disconnect(shared_values, AfterDisconnect::Block(reason)),
+                }
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -143,6 +321,57 @@ impl ConnectedState {
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                shared_values.is_captive_portal = is_captive_portal;
+                if is_captive_portal {
+                    self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(BlockReason::CaptivePortal),
+                    )
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                shared_values.allow_captive_portal = allow_captive_portal;
+                if allow_captive_portal {
+                    self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(BlockReason::CaptivePortal),
+                    )
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::DnsReapplied(Ok(split_dns_servers))) => {
+                if self.split_dns_servers == split_dns_servers {
+                    return SameState(self);
+                }
+                self.split_dns_servers = split_dns_servers;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self),
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to apply firewall policy for connected state"
+                            )
+                        );
+                        self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                        )
+                    }
+                }
+            }
+            Ok(TunnelCommand::DnsReapplied(Err(()))) => self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(BlockReason::SetDnsError),
+            ),
+            Ok(TunnelCommand::GetStats(tx)) => {
+                let _ = tx.send(self.stats_handle.as_ref().and_then(StatsHandle::get));
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
@@ -163,6 +392,7 @@ impl ConnectedState {
 
         match try_handle_event!(self, self.tunnel_events.poll()) {
             Ok(TunnelEvent::Down) | Err(_) => {
+                shared_values.connection_tracker.failed("Tunnel went down");
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
             Ok(_) => SameState(self),
@@ -176,13 +406,23 @@ impl ConnectedState {
         use self::EventConsequence::*;
 
         match self.tunnel_close_event.poll() {
-            Ok(Async::Ready(block_reason)) => {
-                if let Some(reason) = block_reason {
+            Ok(Async::Ready(close_event)) => {
+                if let Some(reason) = close_event.block_reason {
                     return NewState(BlockedState::enter(shared_values, reason));
                 }
+                shared_values.connection_tracker.failed(
+                    close_event
+                        .error
+                        .unwrap_or_else(|| "Tunnel closed unexpectedly".to_owned()),
+                );
             }
             Ok(Async::NotReady) => return NoEvents(self),
-            Err(_cancelled) => log::warn!("Tunnel monitor thread has stopped unexpectedly"),
+            Err(_cancelled) => {
+                log::warn!("Tunnel monitor thread has stopped unexpectedly");
+                shared_values
+                    .connection_tracker
+                    .failed("Tunnel monitor thread has stopped unexpectedly");
+            }
         }
 
         log::info!("Tunnel closed. Reconnecting.");
@@ -199,7 +439,8 @@ impl TunnelState for ConnectedState {
         shared_values: &mut SharedTunnelStateValues,
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        let connected_state = ConnectedState::from(bootstrap);
+        let retry_attempt = bootstrap.retry_attempt;
+        let mut connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
@@ -215,23 +456,31 @@ impl TunnelState for ConnectedState {
                     AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
                 ),
             )
-        } else if let Err(error) = connected_state.set_dns(shared_values) {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to set system DNS settings")
-            );
+        } else if let Err(reason) = connected_state
+            .update_local_resolver(shared_values)
+            .and_then(|()| connected_state.set_dns_and_firewall_policy(shared_values))
+        {
             DisconnectingState::enter(
                 shared_values,
                 (
                     connected_state.close_handle,
                     connected_state.tunnel_close_event,
-                    AfterDisconnect::Block(BlockReason::SetDnsError),
+                    AfterDisconnect::Block(reason),
                 ),
             )
         } else {
+            let ipv6_leak_check = if connected_state.allow_ipv6() {
+                None
+            } else {
+                Some(check_ipv6_routes(&connected_state.metadata))
+            };
+            shared_values
+                .connection_tracker
+                .connected(ipv6_leak_check, connected_state.metadata.wireguard_backend);
+            let diagnostics = shared_values.connection_tracker.diagnostics(retry_attempt);
             (
                 TunnelStateWrapper::from(connected_state),
-                TunnelStateTransition::Connected(tunnel_endpoint),
+                TunnelStateTransition::Connected(tunnel_endpoint, diagnostics),
             )
         }
     }
@@ -246,3 +495,32 @@ impl TunnelState for ConnectedState {
             .or_else(Self::handle_tunnel_close_event, shared_values)
     }
 }
+
+/// The first address in the global unicast range. Which source address a socket connected to it
+/// gets shows whether global IPv6 traffic would be routed outside the tunnel.
+const IPV6_ROUTE_CHECK_ADDRESS: Ipv6Addr = Ipv6Addr::new(0x2000, 0, 0, 0, 0, 0, 0, 1);
+
+/// Checks that the host has no global IPv6 route that bypasses the tunnel. Connecting a UDP
+/// socket only looks up the route, nothing is sent.
+fn check_ipv6_routes(metadata: &TunnelMetadata) -> Ipv6LeakCheck {
+    let source = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((IPV6_ROUTE_CHECK_ADDRESS, DNS_PORT))?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip());
+    match source {
+        Ok(source) if !metadata.ips.contains(&source) => {
+            log::warn!(
+                "Global IPv6 traffic is routed outside the tunnel, from {}",
+                source
+            );
+            Ipv6LeakCheck::BypassRoute(source)
+        }
+        Ok(_) => Ipv6LeakCheck::NoBypassRoute,
+        Err(error) => {
+            log::debug!("No global IPv6 route: {}", error);
+            Ipv6LeakCheck::NoBypassRoute
+        }
+    }
+}
@@ -5,8 +5,10 @@ use super::{
 };
 use crate::{
     firewall::FirewallPolicy,
+    journal::SystemStateJournal,
     tunnel::{
-        self, tun_provider::TunProvider, CloseHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
+        self, tun_provider::TunProvider, CloseHandle, StatsHandle, TunnelEvent, TunnelMetadata,
+        TunnelMonitor,
     },
 };
 use futures::{
@@ -30,12 +32,21 @@ use talpid_types::{
 
 const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
 
+/// Sent by the tunnel monitor wait thread when the tunnel has exited.
+pub struct TunnelCloseEvent {
+    /// Set if the state machine should block instead of reconnecting.
+    pub block_reason: Option<BlockReason>,
+    /// Describes the error that made the tunnel exit, if any.
+    pub error: Option<String>,
+}
+
 /// The tunnel has been started, but it is not established/functional.
 pub struct ConnectingState {
     tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     tunnel_parameters: TunnelParameters,
-    tunnel_close_event: oneshot::Receiver<Option<BlockReason>>,
+    tunnel_close_event: oneshot::Receiver<TunnelCloseEvent>,
     close_handle: CloseHandle,
+    stats_handle: Option<StatsHandle>,
     retry_attempt: u32,
 }
 
@@ -55,7 +66,8 @@ impl ConnectingState {
         let policy = FirewallPolicy::Connecting {
             peer_endpoint,
             pingable_hosts: gateway_list_from_params(params),
-            allow_lan: shared_values.allow_lan,
+            lan_policy: shared_values.lan_policy.clone(),
+            allow_ipv6: params.get_generic_options().enable_ipv6,
         };
         shared_values.firewall.apply_policy(policy)
     }
@@ -66,6 +78,7 @@ impl ConnectingState {
         log_dir: &Option<PathBuf>,
         resource_dir: &Path,
         tun_provider: &dyn TunProvider,
+        journal: &SystemStateJournal,
         retry_attempt: u32,
     ) -> crate::tunnel::Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded();
@@ -78,8 +91,10 @@ impl ConnectingState {
             resource_dir,
             on_tunnel_event,
             tun_provider,
+            journal,
         )?;
         let close_handle = monitor.close_handle();
+        let stats_handle = monitor.stats_handle();
         let tunnel_close_event = Self::spawn_tunnel_monitor_wait_thread(monitor);
 
         Ok(ConnectingState {
@@ -87,27 +102,28 @@ impl ConnectingState {
             tunnel_parameters: parameters,
             tunnel_close_event,
             close_handle,
+            stats_handle,
             retry_attempt,
         })
     }
 
     fn spawn_tunnel_monitor_wait_thread(
         tunnel_monitor: TunnelMonitor,
-    ) -> oneshot::Receiver<Option<BlockReason>> {
+    ) -> oneshot::Receiver<TunnelCloseEvent> {
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
 
         thread::spawn(move || {
             let start = Instant::now();
 
-            let block_reason = Self::wait_for_tunnel_monitor(tunnel_monitor);
+            let close_event = Self::wait_for_tunnel_monitor(tunnel_monitor);
 
-            if block_reason.is_none() {
+            if close_event.block_reason.is_none() {
                 if let Some(remaining_time) = MIN_TUNNEL_ALIVE_TIME.checked_sub(start.elapsed()) {
                     thread::sleep(remaining_time);
                 }
             }
 
-            if tunnel_close_event_tx.send(block_reason).is_err() {
+            if tunnel_close_event_tx.send(close_event).is_err() {
                 warn!("Tunnel state machine stopped before receiving tunnel closed event");
             }
 
@@ -117,9 +133,12 @@ impl ConnectingState {
         tunnel_close_event_rx
     }
 
-    fn wait_for_tunnel_monitor(tunnel_monitor: TunnelMonitor) -> Option<BlockReason> {
+    fn wait_for_tunnel_monitor(tunnel_monitor: TunnelMonitor) -> TunnelCloseEvent {
         match tunnel_monitor.wait() {
-            Ok(_) => None,
+            Ok(_) => TunnelCloseEvent {
+                block_reason: None,
+                error: None,
+            },
             Err(error) => match error {
                 #[cfg(windows)]
                 error @ tunnel::Error::OpenVpnTunnelMonitoringError(
@@ -132,26 +151,37 @@ impl ConnectingState {
                         "{}",
                         error.display_chain_with_msg("TAP adapter problem detected")
                     );
-                    Some(BlockReason::TapAdapterProblem)
+                    TunnelCloseEvent {
+                        block_reason: Some(BlockReason::TapAdapterProblem),
+                        error: Some(error.display_chain()),
+                    }
                 }
                 error => {
                     warn!(
                         "{}",
                         error.display_chain_with_msg("Tunnel has stopped unexpectedly")
                     );
-                    None
+                    TunnelCloseEvent {
+                        block_reason: None,
+                        error: Some(error.display_chain()),
+                    }
                 }
             },
         }
     }
 
     fn into_connected_state_bootstrap(self, metadata: TunnelMetadata) -> ConnectedStateBootstrap {
+        let stats_handle = self
+            .stats_handle
+            .or_else(|| StatsHandle::from_interface(&metadata.interface));
         ConnectedStateBootstrap {
             metadata,
             tunnel_events: self.tunnel_events,
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             close_handle: self.close_handle,
+            stats_handle,
+            retry_attempt: self.retry_attempt,
         }
     }
 
@@ -163,8 +193,8 @@ impl ConnectingState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                shared_values.allow_lan = allow_lan;
+            Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                shared_values.lan_policy = lan_policy;
                 match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
                     Ok(()) => SameState(self),
                     Err(error) => {
@@ -186,6 +216,14 @@ impl ConnectingState {
                     }
                 }
             }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                SameState(self)
+            }
+            Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                shared_values.local_resolver = local_resolver;
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -205,6 +243,41 @@ impl ConnectingState {
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                shared_values.is_captive_portal = is_captive_portal;
+                if is_captive_portal {
+                    NewState(DisconnectingState::enter(
+                        shared_values,
+                        (
+                            self.close_handle,
+                            self.tunnel_close_event,
+                            AfterDisconnect::Block(BlockReason::CaptivePortal),
+                        ),
+                    ))
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                shared_values.allow_captive_portal = allow_captive_portal;
+                if allow_captive_portal {
+                    NewState(DisconnectingState::enter(
+                        shared_values,
+                        (
+                            self.close_handle,
+                            self.tunnel_close_event,
+                            AfterDisconnect::Block(BlockReason::CaptivePortal),
+                        ),
+                    ))
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::DnsReapplied(_)) => SameState(self),
+            Ok(TunnelCommand::GetStats(tx)) => {
+                let _ = tx.send(self.stats_handle.as_ref().and_then(StatsHandle::get));
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => NewState(DisconnectingState::enter(
                 shared_values,
                 (
@@ -255,6 +328,9 @@ impl ConnectingState {
             Ok(_) => SameState(self),
             Err(_) => {
                 debug!("The OpenVPN tunnel event plugin disconnected");
+                shared_values
+                    .connection_tracker
+                    .failed("The OpenVPN tunnel event plugin disconnected");
                 NewState(DisconnectingState::enter(
                     shared_values,
                     (
@@ -272,13 +348,23 @@ impl ConnectingState {
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence<Self> {
         match self.tunnel_close_event.poll() {
-            Ok(Async::Ready(block_reason)) => {
-                if let Some(reason) = block_reason {
+            Ok(Async::Ready(close_event)) => {
+                if let Some(reason) = close_event.block_reason {
                     return EventConsequence::NewState(BlockedState::enter(shared_values, reason));
                 }
+                shared_values.connection_tracker.failed(
+                    close_event
+                        .error
+                        .unwrap_or_else(|| "Tunnel exited before it was established".to_owned()),
+                );
             }
             Ok(Async::NotReady) => return EventConsequence::NoEvents(self),
-            Err(_cancelled) => warn!("Tunnel monitor thread has stopped unexpectedly"),
+            Err(_cancelled) => {
+                warn!("Tunnel monitor thread has stopped unexpectedly");
+                shared_values
+                    .connection_tracker
+                    .failed("Tunnel monitor thread has stopped unexpectedly");
+            }
         }
 
         info!(
@@ -311,6 +397,12 @@ impl TunnelState for ConnectingState {
         if shared_values.is_offline {
             return BlockedState::enter(shared_values, BlockReason::IsOffline);
         }
+        if shared_values.is_captive_portal {
+            return BlockedState::enter(shared_values, BlockReason::CaptivePortal);
+        }
+        shared_values
+            .connection_tracker
+            .start_attempt(retry_attempt);
         match shared_values
             .tunnel_parameters_generator
             .generate(retry_attempt)
@@ -331,17 +423,26 @@ impl TunnelState for ConnectingState {
                         &shared_values.log_dir,
                         &shared_values.resource_dir,
                         shared_values.tun_provider.borrow(),
+                        &shared_values.journal,
                         retry_attempt,
                     ) {
                         Ok(connecting_state) => {
                             let params = connecting_state.tunnel_parameters.clone();
+                            let diagnostics =
+                                shared_values.connection_tracker.diagnostics(retry_attempt);
                             (
                                 TunnelStateWrapper::from(connecting_state),
-                                TunnelStateTransition::Connecting(params.get_tunnel_endpoint()),
+                                TunnelStateTransition::Connecting(
+                                    params.get_tunnel_endpoint(),
+                                    diagnostics,
+                                ),
                             )
                         }
                         Err(error) => {
                             log::error!("Failed to start tunnel: {}", error);
+                            shared_values
+                                .connection_tracker
+                                .failed(error.display_chain_with_msg("Failed to start tunnel"));
                             let block_reason = match error {
                                 tunnel::Error::EnableIpv6Error => BlockReason::Ipv6Unavailable,
                                 _ => BlockReason::StartTunnelError,
@@ -2,7 +2,6 @@ use super::{
     BlockedState, ConnectingState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
     TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
-use crate::firewall::FirewallPolicy;
 use futures::{sync::mpsc, Stream};
 use talpid_types::ErrorExt;
 
@@ -12,9 +11,7 @@ pub struct DisconnectedState;
 impl DisconnectedState {
     fn set_firewall_policy(shared_values: &mut SharedTunnelStateValues) {
         let result = if shared_values.block_when_disconnected {
-            let policy = FirewallPolicy::Blocked {
-                allow_lan: shared_values.allow_lan,
-            };
+            let policy = shared_values.blocking_firewall_policy();
             shared_values.firewall.apply_policy(policy).map_err(|e| {
                 e.display_chain_with_msg(
                     "Failed to apply blocking firewall policy for disconnected state",
@@ -40,6 +37,7 @@ impl TunnelState for DisconnectedState {
         _: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         Self::set_firewall_policy(shared_values);
+        shared_values.connection_tracker.reset();
         (
             TunnelStateWrapper::from(DisconnectedState),
             TunnelStateTransition::Disconnected,
@@ -54,13 +52,21 @@ impl TunnelState for DisconnectedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                if shared_values.allow_lan != allow_lan {
-                    shared_values.allow_lan = allow_lan;
+            Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                if shared_values.lan_policy != lan_policy {
+                    shared_values.lan_policy = lan_policy;
                     Self::set_firewall_policy(shared_values);
                 }
                 SameState(self)
             }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                SameState(self)
+            }
+            Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                shared_values.local_resolver = local_resolver;
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
                     shared_values.block_when_disconnected = block_when_disconnected;
@@ -72,6 +78,22 @@ impl TunnelState for DisconnectedState {
                 shared_values.is_offline = is_offline;
                 SameState(self)
             }
+            Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                shared_values.is_captive_portal = is_captive_portal;
+                SameState(self)
+            }
+            Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                if shared_values.allow_captive_portal != allow_captive_portal {
+                    shared_values.allow_captive_portal = allow_captive_portal;
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
+            Ok(TunnelCommand::DnsReapplied(_)) => SameState(self),
+            Ok(TunnelCommand::GetStats(tx)) => {
+                let _ = tx.send(None);
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Ok(TunnelCommand::Block(reason)) => {
                 NewState(BlockedState::enter(shared_values, reason))
@@ -1,6 +1,7 @@
 use super::{
-    BlockedState, ConnectingState, DisconnectedState, EventConsequence, SharedTunnelStateValues,
-    TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connecting_state::TunnelCloseEvent, BlockedState, ConnectingState, DisconnectedState,
+    EventConsequence, SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition,
+    TunnelStateWrapper,
 };
 use crate::tunnel::CloseHandle;
 use futures::{
@@ -16,7 +17,7 @@ use talpid_types::{
 /// This state is active from when we manually trigger a tunnel kill until the tunnel wait
 /// operation (TunnelExit) returned.
 pub struct DisconnectingState {
-    exited: oneshot::Receiver<Option<BlockReason>>,
+    exited: oneshot::Receiver<TunnelCloseEvent>,
     after_disconnect: AfterDisconnect,
 }
 
@@ -31,8 +32,16 @@ impl DisconnectingState {
 
         self.after_disconnect = match after_disconnect {
             AfterDisconnect::Nothing => match event {
-                Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                    shared_values.allow_lan = allow_lan;
+                Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                    shared_values.lan_policy = lan_policy;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::SplitDns(split_dns)) => {
+                    shared_values.split_dns = split_dns;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                    shared_values.local_resolver = local_resolver;
                     AfterDisconnect::Nothing
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -43,13 +52,34 @@ impl DisconnectingState {
                     shared_values.is_offline = is_offline;
                     AfterDisconnect::Nothing
                 }
+                Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                    shared_values.is_captive_portal = is_captive_portal;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                    shared_values.allow_captive_portal = allow_captive_portal;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::DnsReapplied(_)) => AfterDisconnect::Nothing,
+                Ok(TunnelCommand::GetStats(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Nothing
+                }
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
                 _ => AfterDisconnect::Nothing,
             },
             AfterDisconnect::Block(reason) => match event {
-                Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                    shared_values.allow_lan = allow_lan;
+                Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                    shared_values.lan_policy = lan_policy;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::SplitDns(split_dns)) => {
+                    shared_values.split_dns = split_dns;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                    shared_values.local_resolver = local_resolver;
                     AfterDisconnect::Block(reason)
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -64,14 +94,46 @@ impl DisconnectingState {
                         AfterDisconnect::Block(reason)
                     }
                 }
+                Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                    shared_values.is_captive_portal = is_captive_portal;
+                    if !is_captive_portal && reason == BlockReason::CaptivePortal {
+                        AfterDisconnect::Reconnect(0)
+                    } else {
+                        AfterDisconnect::Block(reason)
+                    }
+                }
+                Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                    shared_values.allow_captive_portal = allow_captive_portal;
+                    if !allow_captive_portal
+                        && !shared_values.is_captive_portal
+                        && reason == BlockReason::CaptivePortal
+                    {
+                        AfterDisconnect::Reconnect(0)
+                    } else {
+                        AfterDisconnect::Block(reason)
+                    }
+                }
+                Ok(TunnelCommand::DnsReapplied(_)) => AfterDisconnect::Block(reason),
+                Ok(TunnelCommand::GetStats(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Block(reason)
+                }
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Ok(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
                 Err(_) => AfterDisconnect::Block(reason),
             },
             AfterDisconnect::Reconnect(retry_attempt) => match event {
-                Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                    shared_values.allow_lan = allow_lan;
+                Ok(TunnelCommand::LanPolicy(lan_policy)) => {
+                    shared_values.lan_policy = lan_policy;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::SplitDns(split_dns)) => {
+                    shared_values.split_dns = split_dns;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::LocalResolver(local_resolver)) => {
+                    shared_values.local_resolver = local_resolver;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -86,6 +148,27 @@ impl DisconnectingState {
                         AfterDisconnect::Reconnect(retry_attempt)
                     }
                 }
+                Ok(TunnelCommand::IsCaptivePortal(is_captive_portal)) => {
+                    shared_values.is_captive_portal = is_captive_portal;
+                    if is_captive_portal {
+                        AfterDisconnect::Block(BlockReason::CaptivePortal)
+                    } else {
+                        AfterDisconnect::Reconnect(retry_attempt)
+                    }
+                }
+                Ok(TunnelCommand::AllowCaptivePortal(allow_captive_portal)) => {
+                    shared_values.allow_captive_portal = allow_captive_portal;
+                    if allow_captive_portal {
+                        AfterDisconnect::Block(BlockReason::CaptivePortal)
+                    } else {
+                        AfterDisconnect::Reconnect(retry_attempt)
+                    }
+                }
+                Ok(TunnelCommand::DnsReapplied(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                Ok(TunnelCommand::GetStats(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
                 Ok(TunnelCommand::Disconnect) | Err(_) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
@@ -103,8 +186,8 @@ impl DisconnectingState {
 
         match self.exited.poll() {
             Ok(Async::NotReady) => NoEvents(self),
-            Ok(Async::Ready(block_reason)) => {
-                NewState(self.after_disconnect(block_reason, shared_values))
+            Ok(Async::Ready(close_event)) => {
+                NewState(self.after_disconnect(close_event.block_reason, shared_values))
             }
             Err(_) => NewState(self.after_disconnect(None, shared_values)),
         }
@@ -132,7 +215,7 @@ impl DisconnectingState {
 impl TunnelState for DisconnectingState {
     type Bootstrap = (
         CloseHandle,
-        oneshot::Receiver<Option<BlockReason>>,
+        oneshot::Receiver<TunnelCloseEvent>,
         AfterDisconnect,
     );
 
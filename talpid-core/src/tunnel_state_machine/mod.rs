@@ -15,26 +15,40 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
 };
 use crate::{
-    dns::DnsMonitor,
-    firewall::{Firewall, FirewallArguments},
+    dns::{DnsMonitor, EncryptedDnsProvider},
+    firewall::{Firewall, FirewallArguments, FirewallPolicy},
+    journal::{self, JournalDns, JournalPolicyRouting, JournalRoute, SystemStateJournal},
     mpsc::IntoSender,
     offline,
     tunnel::tun_provider::TunProvider,
 };
-use futures::{sync::mpsc, Async, Future, Poll, Stream};
+use futures::{
+    sync::{mpsc, oneshot},
+    Async, Future, Poll, Stream,
+};
 use std::{
     io,
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::mpsc as sync_mpsc,
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 use talpid_types::{
-    net::TunnelParameters,
-    tunnel::{BlockReason, TunnelStateTransition},
+    net::{
+        dns::{LocalResolverSettings, SplitDns},
+        lan::LanPolicy,
+        wireguard, TunnelParameters,
+    },
+    tunnel::{
+        BlockReason, ConnectionDiagnostics, Ipv6LeakCheck, TunnelStateTransition, TunnelStats,
+    },
     ErrorExt,
 };
 use tokio_core::reactor::Core;
 
+pub use crate::offline::NetworkInfoListener;
+
 /// Errors that can happen when setting up or using the state machine.
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -61,35 +75,44 @@ pub enum Error {
 
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
 pub fn spawn<P, T>(
-    allow_lan: bool,
+    lan_policy: LanPolicy,
+    split_dns: SplitDns,
+    local_resolver: LocalResolverSettings,
     block_when_disconnected: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     tun_provider: impl TunProvider,
+    encrypted_dns_provider: impl EncryptedDnsProvider,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
     cache_dir: P,
     state_change_listener: IntoSender<TunnelStateTransition, T>,
+    network_info_listener: Option<NetworkInfoListener>,
 ) -> Result<mpsc::UnboundedSender<TunnelCommand>, Error>
 where
     P: AsRef<Path> + Send + 'static,
     T: From<TunnelStateTransition> + Send + 'static,
 {
     let (command_tx, command_rx) = mpsc::unbounded();
-    let offline_monitor =
-        offline::spawn_monitor(command_tx.clone()).map_err(Error::OfflineMonitorError)?;
+    let offline_monitor = offline::spawn_monitor(command_tx.clone(), network_info_listener)
+        .map_err(Error::OfflineMonitorError)?;
     let is_offline = offline::is_offline();
+    let command_tx_clone = command_tx.clone();
 
     let (startup_result_tx, startup_result_rx) = sync_mpsc::channel();
     thread::spawn(move || {
         match create_event_loop(
-            allow_lan,
+            lan_policy,
+            split_dns,
+            local_resolver,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator,
             tun_provider,
+            encrypted_dns_provider,
             log_dir,
             resource_dir,
             cache_dir,
+            command_tx_clone,
             command_rx,
             state_change_listener,
         ) {
@@ -121,14 +144,18 @@ where
 }
 
 fn create_event_loop<T>(
-    allow_lan: bool,
+    lan_policy: LanPolicy,
+    split_dns: SplitDns,
+    local_resolver: LocalResolverSettings,
     block_when_disconnected: bool,
     is_offline: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     tun_provider: impl TunProvider,
+    encrypted_dns_provider: impl EncryptedDnsProvider,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
     cache_dir: impl AsRef<Path>,
+    command_tx: mpsc::UnboundedSender<TunnelCommand>,
     commands: mpsc::UnboundedReceiver<TunnelCommand>,
     state_change_listener: IntoSender<TunnelStateTransition, T>,
 ) -> Result<(Core, impl Future<Item = (), Error = Error>), Error>
@@ -137,14 +164,18 @@ where
 {
     let reactor = Core::new().map_err(Error::ReactorError)?;
     let state_machine = TunnelStateMachine::new(
-        allow_lan,
+        lan_policy,
+        split_dns,
+        local_resolver,
         block_when_disconnected,
         is_offline,
         tunnel_parameters_generator,
         tun_provider,
+        encrypted_dns_provider,
         log_dir,
         resource_dir,
         cache_dir,
+        command_tx,
         commands,
     )?;
 
@@ -159,12 +190,28 @@ where
 
 /// Representation of external commands for the tunnel state machine.
 pub enum TunnelCommand {
-    /// Enable or disable LAN access in the firewall.
-    AllowLan(bool),
+    /// Change what LAN access is allowed in the firewall.
+    LanPolicy(LanPolicy),
+    /// Change which domains are resolved outside the tunnel.
+    SplitDns(SplitDns),
+    /// Change the local DNS resolver settings.
+    LocalResolver(LocalResolverSettings),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
     /// Notify the state machine of the connectivity of the device.
     IsOffline(bool),
+    /// Notify the state machine of whether the network is behind a captive portal.
+    IsCaptivePortal(bool),
+    /// Allow or stop allowing the traffic needed to log in to a captive portal while blocking.
+    /// Allowing it disconnects any open tunnel.
+    AllowCaptivePortal(bool),
+    /// Notify the state machine that the DNS monitor re-applied DNS on its own, after the system
+    /// changed how it manages DNS. Holds the servers that now resolve the split DNS domains, or an
+    /// error if DNS could not be set again.
+    DnsReapplied(Result<Vec<IpAddr>, ()>),
+    /// Read the traffic counters of the tunnel. Answered with `None` when there is no tunnel, or
+    /// when the tunnel type doesn't provide them.
+    GetStats(oneshot::Sender<Option<TunnelStats>>),
     /// Open tunnel connection.
     Connect,
     /// Close tunnel connection.
@@ -187,39 +234,63 @@ struct TunnelStateMachine {
 
 impl TunnelStateMachine {
     fn new(
-        allow_lan: bool,
+        lan_policy: LanPolicy,
+        split_dns: SplitDns,
+        local_resolver: LocalResolverSettings,
         block_when_disconnected: bool,
         is_offline: bool,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         tun_provider: impl TunProvider,
+        encrypted_dns_provider: impl EncryptedDnsProvider,
         log_dir: Option<PathBuf>,
         resource_dir: PathBuf,
         cache_dir: impl AsRef<Path>,
+        command_tx: mpsc::UnboundedSender<TunnelCommand>,
         commands: mpsc::UnboundedReceiver<TunnelCommand>,
     ) -> Result<Self, Error> {
+        let (journal, unclean_journal) = SystemStateJournal::open(cache_dir.as_ref());
+
         let args = if block_when_disconnected {
             FirewallArguments {
                 initialize_blocked: true,
-                allow_lan: Some(allow_lan),
+                lan_policy: Some(lan_policy.clone()),
             }
         } else {
             FirewallArguments {
                 initialize_blocked: false,
-                allow_lan: None,
+                lan_policy: None,
             }
         };
-        let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
-        let dns_monitor = DnsMonitor::new(cache_dir).map_err(Error::InitDnsMonitorError)?;
+        let mut firewall =
+            Firewall::new(args, journal.clone()).map_err(Error::InitFirewallError)?;
+        let dns_monitor = DnsMonitor::new(cache_dir, journal.clone(), command_tx)
+            .map_err(Error::InitDnsMonitorError)?;
+        if let Some(unclean_journal) = unclean_journal {
+            journal.restore(
+                &unclean_journal,
+                &mut SystemRestorer {
+                    firewall: &mut firewall,
+                    initialized_blocked: block_when_disconnected,
+                },
+            );
+        }
         let mut shared_values = SharedTunnelStateValues {
             firewall,
             dns_monitor,
-            allow_lan,
+            journal,
+            lan_policy,
+            split_dns,
+            local_resolver,
             block_when_disconnected,
             is_offline,
+            is_captive_portal: false,
+            allow_captive_portal: false,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             tun_provider: Box::new(tun_provider),
+            encrypted_dns_provider: Box::new(encrypted_dns_provider),
             log_dir,
             resource_dir,
+            connection_tracker: ConnectionTracker::default(),
         };
 
         let (initial_state, _) = DisconnectedState::enter(&mut shared_values, ());
@@ -231,6 +302,41 @@ impl TunnelStateMachine {
     }
 }
 
+/// Undoes changes recorded in the system state journal.
+struct SystemRestorer<'a> {
+    firewall: &'a mut Firewall,
+    /// True if the firewall was initialized with a blocking policy, which already replaced any
+    /// policy left by the previous run.
+    initialized_blocked: bool,
+}
+
+impl<'a> journal::Restore for SystemRestorer<'a> {
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "macos")),
+        allow(unused_variables)
+    )]
+    fn remove_routes(&mut self, routes: &[JournalRoute], policy: Option<JournalPolicyRouting>) {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        crate::routing::remove_stale_routes(routes, policy);
+    }
+
+    fn reset_dns(&mut self, dns: &JournalDns) {
+        crate::dns::reset_stale(dns);
+    }
+
+    fn reset_firewall(&mut self) {
+        if self.initialized_blocked {
+            return;
+        }
+        if let Err(error) = self.firewall.reset_policy() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to reset the firewall policy left in place")
+            );
+        }
+    }
+}
+
 impl Stream for TunnelStateMachine {
     type Item = TunnelStateTransition;
     type Error = Error;
@@ -292,20 +398,108 @@ pub trait TunnelParametersGenerator: Send + 'static {
 struct SharedTunnelStateValues {
     firewall: Firewall,
     dns_monitor: DnsMonitor,
-    /// Should LAN access be allowed outside the tunnel.
-    allow_lan: bool,
+    /// Records the changes made to the system, so that they can be undone after a crash.
+    journal: SystemStateJournal,
+    /// What LAN access should be allowed outside the tunnel.
+    lan_policy: LanPolicy,
+    /// Domains that should be resolved outside the tunnel.
+    split_dns: SplitDns,
+    /// If the system should resolve through the local resolver, and what it should block.
+    local_resolver: LocalResolverSettings,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
     /// True when the computer is known to be offline.
     is_offline: bool,
+    /// True when the network is known to be behind a captive portal.
+    is_captive_portal: bool,
+    /// True when traffic to a captive portal should be let through while blocking.
+    allow_captive_portal: bool,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
     /// The provider of tunnel devices.
     tun_provider: Box<dyn TunProvider>,
+    /// The provider of transports to encrypted DNS servers.
+    encrypted_dns_provider: Box<dyn EncryptedDnsProvider>,
     /// Directory to store tunnel log file.
     log_dir: Option<PathBuf>,
     /// Resource directory path.
     resource_dir: PathBuf,
+    /// Diagnostics about the current connection sequence.
+    connection_tracker: ConnectionTracker,
+}
+
+impl SharedTunnelStateValues {
+    /// Returns the firewall policy to apply when blocking, which lets captive portal login
+    /// traffic through while that is allowed.
+    fn blocking_firewall_policy(&self) -> FirewallPolicy {
+        if self.allow_captive_portal {
+            FirewallPolicy::CaptivePortal {
+                gateway_networks: offline::gateway_networks(),
+                lan_policy: self.lan_policy.clone(),
+            }
+        } else {
+            FirewallPolicy::Blocked {
+                lan_policy: self.lan_policy.clone(),
+            }
+        }
+    }
+}
+
+/// Keeps track of when the current connection sequence started and why tunnels failed, so that
+/// it can be reported in `ConnectionDiagnostics`.
+#[derive(Default)]
+struct ConnectionTracker {
+    started: Option<(SystemTime, Instant)>,
+    connected_after: Option<Duration>,
+    last_failure: Option<String>,
+    ipv6_leak_check: Option<Ipv6LeakCheck>,
+    wireguard_backend: Option<wireguard::Backend>,
+}
+
+impl ConnectionTracker {
+    /// Registers that a new tunnel is being started. A retry attempt of zero starts a new
+    /// connection sequence.
+    fn start_attempt(&mut self, retry_attempt: u32) {
+        if retry_attempt == 0 || self.started.is_none() {
+            self.started = Some((SystemTime::now(), Instant::now()));
+        }
+        self.connected_after = None;
+        self.ipv6_leak_check = None;
+        self.wireguard_backend = None;
+    }
+
+    fn connected(
+        &mut self,
+        ipv6_leak_check: Option<Ipv6LeakCheck>,
+        wireguard_backend: Option<wireguard::Backend>,
+    ) {
+        self.connected_after = self.started.map(|(_, instant)| instant.elapsed());
+        self.ipv6_leak_check = ipv6_leak_check;
+        self.wireguard_backend = wireguard_backend;
+    }
+
+    fn failed(&mut self, reason: impl Into<String>) {
+        self.last_failure = Some(reason.into());
+    }
+
+    /// Forgets the current connection sequence, including previous failures.
+    fn reset(&mut self) {
+        *self = ConnectionTracker::default();
+    }
+
+    fn diagnostics(&self, retry_attempt: u32) -> ConnectionDiagnostics {
+        ConnectionDiagnostics {
+            retry_attempt,
+            connecting_since: self
+                .started
+                .map(|(time, _)| time)
+                .unwrap_or_else(SystemTime::now),
+            connect_duration: self.connected_after,
+            previous_failure: self.last_failure.clone(),
+            ipv6_leak_check: self.ipv6_leak_check.clone(),
+            wireguard_backend: self.wireguard_backend,
+        }
+    }
 }
 
 /// Asynchronous result of an attempt to progress a state.
@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, net::IpAddr, path::PathBuf};
+
+/// Describes which DNS queries should be resolved by the DNS servers of the physical network
+/// instead of through the tunnel.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitDns {
+    /// Names, and all names below them, that should be resolved outside the tunnel. For example
+    /// `corp.example` to reach services on a corporate network.
+    pub domains: Vec<String>,
+}
+
+impl SplitDns {
+    /// Returns true if any queries should be resolved outside the tunnel.
+    pub fn is_enabled(&self) -> bool {
+        !self.domains.is_empty()
+    }
+
+    /// Checks that all domains are valid, lowercase domain names without leading or trailing
+    /// dots. Returns the first offending domain otherwise.
+    pub fn validate(&self) -> Result<(), InvalidSplitDnsDomain> {
+        match self.domains.iter().find(|domain| !is_valid_domain(domain)) {
+            Some(domain) => Err(InvalidSplitDnsDomain(domain.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for SplitDns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_enabled() {
+            write!(
+                f,
+                "Resolving {} outside the tunnel",
+                self.domains.join(", ")
+            )
+        } else {
+            write!(f, "Resolving all domains through the tunnel")
+        }
+    }
+}
+
+/// Returned by `SplitDns::validate` when a domain is not a valid domain name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSplitDnsDomain(pub String);
+
+impl fmt::Display for InvalidSplitDnsDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\" is not a valid domain name", self.0)
+    }
+}
+
+impl Error for InvalidSplitDnsDomain {
+    fn description(&self) -> &str {
+        "Invalid domain name"
+    }
+}
+
+/// Settings for the local DNS resolver. When enabled, the system is pointed at a resolver on the
+/// loopback interface that forwards queries through the tunnel and refuses to resolve blocked
+/// domains.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalResolverSettings {
+    pub enabled: bool,
+    /// Files listing domains to block, and all names below them. Either in hosts file format or
+    /// with one domain per line.
+    pub blocklists: Vec<PathBuf>,
+}
+
+impl fmt::Display for LocalResolverSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.enabled {
+            return write!(f, "off");
+        }
+        if self.blocklists.is_empty() {
+            write!(f, "on, without blocklists")
+        } else {
+            write!(f, "on, blocking domains listed in ")?;
+            let paths = self
+                .blocklists
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            write!(f, "{}", paths.join(", "))
+        }
+    }
+}
+
+/// Where DNS queries from the host are sent once connected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsUpstream {
+    /// Plain DNS to the DNS server on the tunnel gateway.
+    Gateway,
+    /// Encrypted DNS, through the tunnel, to the given server. The daemon proxies the queries
+    /// from the host.
+    Encrypted(EncryptedDnsServer),
+}
+
+impl Default for DnsUpstream {
+    fn default() -> Self {
+        DnsUpstream::Gateway
+    }
+}
+
+impl DnsUpstream {
+    /// Returns true if the queries are encrypted all the way to the DNS server.
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            DnsUpstream::Gateway => false,
+            DnsUpstream::Encrypted(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for DnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsUpstream::Gateway => write!(f, "tunnel gateway"),
+            DnsUpstream::Encrypted(server) => server.fmt(f),
+        }
+    }
+}
+
+/// A DNS-over-HTTPS or DNS-over-TLS server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EncryptedDnsServer {
+    pub protocol: EncryptedDnsProtocol,
+    /// Address of the server. It can't be given as a name, since there's nothing to resolve the
+    /// name with.
+    pub address: IpAddr,
+    /// Port of the server, if it doesn't use the default port of the protocol.
+    pub port: Option<u16>,
+    /// Name the certificate of the server is verified against.
+    pub hostname: String,
+}
+
+impl EncryptedDnsServer {
+    /// Returns the port of the server.
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.protocol.default_port())
+    }
+}
+
+impl fmt::Display for EncryptedDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}:{})",
+            self.protocol,
+            self.hostname,
+            self.address,
+            self.port()
+        )
+    }
+}
+
+/// How queries are encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsProtocol {
+    /// DNS-over-HTTPS, as defined in RFC 8484.
+    Https,
+    /// DNS-over-TLS, as defined in RFC 7858.
+    Tls,
+}
+
+impl EncryptedDnsProtocol {
+    pub fn default_port(self) -> u16 {
+        match self {
+            EncryptedDnsProtocol::Https => 443,
+            EncryptedDnsProtocol::Tls => 853,
+        }
+    }
+}
+
+impl fmt::Display for EncryptedDnsProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptedDnsProtocol::Https => write!(f, "DNS-over-HTTPS"),
+            EncryptedDnsProtocol::Tls => write!(f, "DNS-over-TLS"),
+        }
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split_dns(domains: &[&str]) -> SplitDns {
+        SplitDns {
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_domains() {
+        assert_eq!(
+            split_dns(&["corp.example", "intranet", "a-b.c1"]).validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn invalid_domains() {
+        for domain in &[
+            "",
+            ".corp.example",
+            "corp.example.",
+            "Corp.example",
+            "-a.b",
+            "a b",
+        ] {
+            assert_eq!(
+                split_dns(&["corp.example", domain]).validate(),
+                Err(InvalidSplitDnsDomain(domain.to_string()))
+            );
+        }
+    }
+}
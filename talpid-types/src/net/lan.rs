@@ -0,0 +1,233 @@
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Describes what local network traffic should be let through the firewall, in addition to the
+/// traffic allowed by the policy itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanPolicy {
+    /// Private networks that traffic should be allowed to, and from. Each network must be
+    /// contained in one of the ranges returned by `private_networks`.
+    #[serde(deserialize_with = "deserialize_networks")]
+    pub allowed_networks: Vec<IpNetwork>,
+    /// Allow outgoing traffic to local multicast addresses, such as mDNS and SSDP discovery.
+    pub allow_multicast: bool,
+    /// Allow acting as a DHCPv4 server on the local network.
+    pub allow_dhcp_server: bool,
+    /// Allow traffic to, and from, the networks returned by `link_local_networks`.
+    pub allow_link_local: bool,
+}
+
+impl Default for LanPolicy {
+    fn default() -> Self {
+        Self::block_all()
+    }
+}
+
+impl LanPolicy {
+    /// A policy that blocks all local network traffic.
+    pub fn block_all() -> Self {
+        LanPolicy {
+            allowed_networks: Vec::new(),
+            allow_multicast: false,
+            allow_dhcp_server: false,
+            allow_link_local: false,
+        }
+    }
+
+    /// A policy that allows what the old allow LAN setting did. That is traffic to and from the
+    /// private IPv4 networks, including multicast, DHCP server and link-local traffic. Unique local
+    /// IPv6 networks are not included, and have to be allowed explicitly.
+    pub fn allow_all() -> Self {
+        LanPolicy {
+            allowed_networks: ipv4_private_networks().to_vec(),
+            allow_multicast: true,
+            allow_dhcp_server: true,
+            allow_link_local: true,
+        }
+    }
+
+    /// Returns true if the policy lets any local network traffic through.
+    pub fn allows_any(&self) -> bool {
+        *self != Self::block_all()
+    }
+
+    /// Checks that all allowed networks are private. Returns the first offending network
+    /// otherwise.
+    pub fn validate(&self) -> Result<(), InvalidLanNetwork> {
+        match self
+            .allowed_networks
+            .iter()
+            .find(|network| !is_private_network(network))
+        {
+            Some(network) => Err(InvalidLanNetwork(*network)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for LanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.allows_any() {
+            return write!(f, "Blocking LAN");
+        }
+
+        write!(f, "Allowing LAN")?;
+        if !self.allowed_networks.is_empty() {
+            let networks = self
+                .allowed_networks
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            write!(f, " networks {}", networks.join(","))?;
+        }
+
+        let mut extras = Vec::new();
+        if self.allow_multicast {
+            extras.push("multicast");
+        }
+        if self.allow_dhcp_server {
+            extras.push("DHCP server");
+        }
+        if self.allow_link_local {
+            extras.push("link-local");
+        }
+        if !extras.is_empty() {
+            write!(f, " ({})", extras.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// `IpNetwork` can only be deserialized from borrowed strings, which fails when the policy is read
+/// from an owned JSON value, such as RPC parameters.
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Vec<IpNetwork>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|network| network.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Returned by `LanPolicy::validate` when a network that is not private is allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLanNetwork(pub IpNetwork);
+
+impl fmt::Display for InvalidLanNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not a private network", self.0)
+    }
+}
+
+impl Error for InvalidLanNetwork {
+    fn description(&self) -> &str {
+        "Not a private network"
+    }
+}
+
+/// The address ranges that may be allowed by a `LanPolicy`.
+pub fn private_networks() -> [IpNetwork; 4] {
+    let [class_a, class_b, class_c] = ipv4_private_networks();
+    [
+        class_a,
+        class_b,
+        class_c,
+        // Unique local addresses.
+        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7).unwrap()),
+    ]
+}
+
+/// The private IPv4 address ranges, which the old allow LAN setting allowed.
+pub fn ipv4_private_networks() -> [IpNetwork; 3] {
+    [
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()),
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(172, 16, 0, 0), 12).unwrap()),
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 0, 0), 16).unwrap()),
+    ]
+}
+
+/// The IPv4 and IPv6 link-local address ranges.
+pub fn link_local_networks() -> [IpNetwork; 2] {
+    [
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(169, 254, 0, 0), 16).unwrap()),
+        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
+    ]
+}
+
+fn is_private_network(network: &IpNetwork) -> bool {
+    private_networks()
+        .iter()
+        .any(|private| private.prefix() <= network.prefix() && private.contains(network.network()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allow_all_is_valid() {
+        assert_eq!(LanPolicy::allow_all().validate(), Ok(()));
+    }
+
+    #[test]
+    fn allow_all_excludes_unique_local_addresses() {
+        let policy = LanPolicy::allow_all();
+        assert_eq!(policy.allowed_networks, ipv4_private_networks().to_vec());
+        let unique_local: IpNetwork = "fd00::/8".parse().unwrap();
+        assert!(!policy
+            .allowed_networks
+            .iter()
+            .any(|network| network.contains(unique_local.network())));
+    }
+
+    #[test]
+    fn subnet_of_private_network_is_valid() {
+        let policy = LanPolicy {
+            allowed_networks: vec![
+                "192.168.1.0/24".parse().unwrap(),
+                "10.10.0.0/16".parse().unwrap(),
+                "fd00:1234::/32".parse().unwrap(),
+            ],
+            ..LanPolicy::block_all()
+        };
+        assert_eq!(policy.validate(), Ok(()));
+    }
+
+    #[test]
+    fn public_network_is_invalid() {
+        let public_network: IpNetwork = "8.8.8.0/24".parse().unwrap();
+        let policy = LanPolicy {
+            allowed_networks: vec!["192.168.1.0/24".parse().unwrap(), public_network],
+            ..LanPolicy::block_all()
+        };
+        assert_eq!(policy.validate(), Err(InvalidLanNetwork(public_network)));
+    }
+
+    #[test]
+    fn supernet_of_private_network_is_invalid() {
+        let supernet: IpNetwork = "192.0.0.0/8".parse().unwrap();
+        let policy = LanPolicy {
+            allowed_networks: vec![supernet],
+            ..LanPolicy::block_all()
+        };
+        assert_eq!(policy.validate(), Err(InvalidLanNetwork(supernet)));
+    }
+
+    #[test]
+    fn block_all_allows_nothing() {
+        assert!(!LanPolicy::block_all().allows_any());
+        assert!(LanPolicy::allow_all().allows_any());
+        let multicast_only = LanPolicy {
+            allow_multicast: true,
+            ..LanPolicy::block_all()
+        };
+        assert!(multicast_only.allows_any());
+    }
+}
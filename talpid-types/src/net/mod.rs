@@ -6,6 +6,9 @@ use std::{
     str::FromStr,
 };
 
+pub mod dns;
+pub mod lan;
+pub mod network_info;
 pub mod openvpn;
 pub mod proxy;
 pub mod wireguard;
@@ -179,6 +182,9 @@ pub struct GenericTunnelOptions {
     /// Enable configuration of IPv6 on the tunnel interface, allowing IPv6 communication to be
     /// forwarded through the tunnel.
     pub enable_ipv6: bool,
+    /// Where DNS queries from the host are sent while connected.
+    #[serde(default)]
+    pub dns_upstream: dns::DnsUpstream,
 }
 
 /// Returns a vector of IP networks representing all of the internet.
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Describes the networks the device is currently connected to, outside of any tunnel.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkInfo {
+    pub networks: Vec<ActiveNetwork>,
+}
+
+impl NetworkInfo {
+    /// Returns true if the device isn't connected to any network.
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+}
+
+/// A network reachable through one of the physical interfaces that has a default route.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveNetwork {
+    /// Name of the interface the network is reached through.
+    pub interface: String,
+    pub kind: InterfaceKind,
+    /// Name of the wireless network, if the interface is associated with one.
+    pub ssid: Option<String>,
+    /// Hardware address of the default gateway, formatted as lowercase colon separated hex.
+    pub gateway_mac: Option<String>,
+}
+
+/// What type of link a network is reached through.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceKind {
+    Wired,
+    Wireless,
+    Other,
+}
+
+impl fmt::Display for InterfaceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceKind::Wired => "wired".fmt(f),
+            InterfaceKind::Wireless => "wireless".fmt(f),
+            InterfaceKind::Other => "other".fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for ActiveNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.interface, self.kind)?;
+        if let Some(ssid) = &self.ssid {
+            write!(f, " SSID {}", ssid)?;
+        }
+        if let Some(gateway_mac) = &self.gateway_mac {
+            write!(f, " gateway {}", gateway_mac)?;
+        }
+        Ok(())
+    }
+}
@@ -3,8 +3,10 @@ use ipnetwork::IpNetwork;
 use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
+    error::Error,
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
 };
 
 
@@ -22,22 +24,38 @@ pub struct ConnectionConfig {
     pub peer: PeerConfig,
     pub ipv4_gateway: Ipv4Addr,
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// Transport used to reach the peer. Over TCP, the datagrams are carried through a local
+    /// proxy to a counterpart on the relay, which listens on `peer.endpoint`.
+    #[serde(default = "default_protocol")]
+    pub protocol: TransportProtocol,
 }
 
 impl ConnectionConfig {
     pub fn get_endpoint(&self) -> Endpoint {
         Endpoint {
             address: self.peer.endpoint,
-            protocol: TransportProtocol::Udp,
+            protocol: self.protocol,
         }
     }
 }
 
+fn default_protocol() -> TransportProtocol {
+    TransportProtocol::Udp
+}
+
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug, Hash)]
 pub struct PeerConfig {
     pub public_key: PublicKey,
     pub allowed_ips: Vec<IpNetwork>,
     pub endpoint: SocketAddr,
+    /// Symmetric key mixed into the handshake, on top of the key pairs. The peer must be
+    /// configured with the same key.
+    #[serde(default)]
+    pub preshared_key: Option<PresharedKey>,
+    /// Seconds between keepalive packets sent to the peer, to keep NAT mappings open while the
+    /// tunnel is idle. `TunnelOptions::persistent_keepalive` is used if unset.
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
@@ -51,6 +69,36 @@ pub struct TunnelConfig {
 pub struct TunnelOptions {
     /// MTU for the wireguard tunnel
     pub mtu: Option<u16>,
+    /// Pick the largest MTU that the path to the relay can carry, instead of using the default.
+    /// Ignored if `mtu` is set.
+    #[serde(default)]
+    pub auto_mtu: bool,
+    /// Implementation to run the tunnel with. The best available one is used if unset.
+    #[serde(default)]
+    pub backend: Option<Backend>,
+    /// Seconds between keepalive packets sent to peers that don't set their own interval.
+    /// Keepalives are disabled if unset.
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// Implementation of WireGuard that runs a tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// The WireGuard module in the Linux kernel
+    Kernel,
+    /// The userspace implementation in wireguard-go
+    WireguardGo,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Kernel => write!(f, "kernel"),
+            Backend::WireguardGo => write!(f, "wireguard-go"),
+        }
+    }
 }
 
 /// Wireguard x25519 private key
@@ -129,6 +177,21 @@ impl From<[u8; 32]> for PublicKey {
     }
 }
 
+impl FromStr for PublicKey {
+    type Err = InvalidKey;
+
+    /// Parses a base64 encoded key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let buffer = base64::decode(s).map_err(|_| InvalidKey)?;
+        if buffer.len() != 32 {
+            return Err(InvalidKey);
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&buffer);
+        Ok(PublicKey(key))
+    }
+}
+
 impl Serialize for PublicKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -160,6 +223,71 @@ impl fmt::Display for PublicKey {
     }
 }
 
+/// Wireguard preshared symmetric key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Get the preshared key as bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for PresharedKey {
+    fn from(key: [u8; 32]) -> PresharedKey {
+        PresharedKey(key)
+    }
+}
+
+impl FromStr for PresharedKey {
+    type Err = InvalidKey;
+
+    /// Parses a base64 encoded key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let buffer = base64::decode(s).map_err(|_| InvalidKey)?;
+        if buffer.len() != 32 {
+            return Err(InvalidKey);
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&buffer);
+        Ok(PresharedKey(key))
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_key(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_key(deserializer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKey;
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
+}
+
+impl Error for InvalidKey {
+    fn description(&self) -> &str {
+        "Not a valid base64 encoded wireguard key"
+    }
+}
+
 fn serialize_key<S>(key: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -1,6 +1,10 @@
-use crate::net::TunnelEndpoint;
+use crate::net::{wireguard, TunnelEndpoint};
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
 
 /// Event resulting from a transition to a new tunnel state.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -10,15 +14,83 @@ pub enum TunnelStateTransition {
     /// No connection is established and network is unsecured.
     Disconnected,
     /// Network is secured but tunnel is still connecting.
-    Connecting(TunnelEndpoint),
+    Connecting(TunnelEndpoint, ConnectionDiagnostics),
     /// Tunnel is connected.
-    Connected(TunnelEndpoint),
+    Connected(TunnelEndpoint, ConnectionDiagnostics),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but secured by blocking all connections.
     Blocked(BlockReason),
 }
 
+/// Troubleshooting details about the current connection sequence, which spans all attempts made
+/// since the tunnel was last asked to connect.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionDiagnostics {
+    /// Number of failed attempts before the current one in this connection sequence.
+    pub retry_attempt: u32,
+    /// When the first attempt in this connection sequence started.
+    pub connecting_since: SystemTime,
+    /// How long it took to establish the tunnel. Only set once connected.
+    pub connect_duration: Option<Duration>,
+    /// Why the previous tunnel attempt failed, if any did.
+    pub previous_failure: Option<String>,
+    /// Whether the host has a global IPv6 route that bypasses the tunnel. Only checked once
+    /// connected with IPv6 disabled in the tunnel.
+    pub ipv6_leak_check: Option<Ipv6LeakCheck>,
+    /// The WireGuard implementation running the tunnel. Only set once connected over WireGuard.
+    #[serde(default)]
+    pub wireguard_backend: Option<wireguard::Backend>,
+}
+
+impl ConnectionDiagnostics {
+    /// Returns how long the tunnel has been connecting, or how long it took to connect.
+    pub fn elapsed(&self) -> Duration {
+        self.connect_duration.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(self.connecting_since)
+                .unwrap_or_else(|_| Duration::from_secs(0))
+        })
+    }
+}
+
+/// Result of checking whether IPv6 traffic would be routed outside a tunnel that doesn't carry
+/// IPv6. Such traffic is blocked by the firewall, so a bypassing route doesn't mean that anything
+/// leaks, but it means that only the firewall stops it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6LeakCheck {
+    /// The host has no global IPv6 route outside the tunnel.
+    NoBypassRoute,
+    /// The host has a global IPv6 route outside the tunnel, with the given source address.
+    BypassRoute(IpAddr),
+}
+
+impl fmt::Display for Ipv6LeakCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ipv6LeakCheck::NoBypassRoute => write!(f, "No IPv6 route bypasses the tunnel"),
+            Ipv6LeakCheck::BypassRoute(source) => write!(
+                f,
+                "IPv6 is routed outside the tunnel from {}, only the firewall blocks it",
+                source
+            ),
+        }
+    }
+}
+
+/// Traffic counters of a running tunnel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelStats {
+    /// Bytes sent through the tunnel, including protocol overhead.
+    pub tx_bytes: u64,
+    /// Bytes received through the tunnel, including protocol overhead.
+    pub rx_bytes: u64,
+    /// When the latest WireGuard handshake completed. Not set before the first handshake, and
+    /// never set for OpenVPN tunnels.
+    pub last_handshake: Option<SystemTime>,
+}
+
 /// Action that will be taken after disconnection is complete.
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -58,6 +130,8 @@ pub enum BlockReason {
     IsOffline,
     /// A problem with the TAP adapter has been detected.
     TapAdapterProblem,
+    /// The network requires logging in through a captive portal before it can be used.
+    CaptivePortal,
 }
 
 impl fmt::Display for BlockReason {
@@ -81,6 +155,7 @@ impl fmt::Display for BlockReason {
             NoMatchingRelay => "No relay server matches the current settings",
             IsOffline => "This device is offline, no tunnels can be established",
             TapAdapterProblem => "A problem with the TAP adapter has been detected",
+            CaptivePortal => "The network requires logging in through a captive portal",
         };
 
         write!(f, "{}", description)
@@ -4,22 +4,27 @@
 #include "objectpurger.h"
 #include "rules/blockall.h"
 #include "rules/ifirewallrule.h"
+#include "rules/permitcaptiveportal.h"
 #include "rules/permitdhcp.h"
 #include "rules/permitndp.h"
 #include "rules/permitdhcpserver.h"
 #include "rules/permitlan.h"
+#include "rules/permitlanmulticast.h"
 #include "rules/permitlanservice.h"
 #include "rules/permitloopback.h"
 #include "rules/permitvpnrelay.h"
 #include "rules/permitvpntunnel.h"
 #include "rules/permitvpntunnelservice.h"
 #include "rules/restrictdns.h"
+#include "rules/restrictresolverupstream.h"
 #include "libwfp/transaction.h"
 #include "libwfp/filterengine.h"
 #include "libwfp/ipaddress.h"
+#include "libwfp/ipnetwork.h"
 #include <functional>
 #include <stdexcept>
 #include <utility>
+#include <vector>
 
 namespace
 {
@@ -37,6 +42,24 @@ rules::PermitVpnRelay::Protocol TranslateProtocol(WinFwProtocol protocol)
 	};
 }
 
+void SplitNetworks
+(
+	const WinFwIpNetwork *networks,
+	uint32_t numNetworks,
+	std::vector<wfp::IpNetwork> &ipv4Networks,
+	std::vector<wfp::IpNetwork> &ipv6Networks
+)
+{
+	for (uint32_t i = 0; i < numNetworks; ++i)
+	{
+		const auto &network = networks[i];
+		const wfp::IpAddress address(network.address);
+
+		auto &target = (wfp::IpAddress::Type::Ipv4 == address.type() ? ipv4Networks : ipv6Networks);
+		target.emplace_back(address, network.prefix);
+	}
+}
+
 void AppendSettingsRules(FwContext::Ruleset &ruleset, const WinFwSettings &settings)
 {
 	if (settings.permitDhcp)
@@ -45,10 +68,24 @@ void AppendSettingsRules(FwContext::Ruleset &ruleset, const WinFwSettings &setti
 		ruleset.emplace_back(std::make_unique<rules::PermitNdp>());
 	}
 
-	if (settings.permitLan)
+	if (0 != settings.numLanNetworks)
+	{
+		std::vector<wfp::IpNetwork> ipv4Networks;
+		std::vector<wfp::IpNetwork> ipv6Networks;
+
+		SplitNetworks(settings.lanNetworks, settings.numLanNetworks, ipv4Networks, ipv6Networks);
+
+		ruleset.emplace_back(std::make_unique<rules::PermitLan>(ipv4Networks, ipv6Networks));
+		ruleset.emplace_back(std::make_unique<rules::PermitLanService>(ipv4Networks, ipv6Networks));
+	}
+
+	if (settings.permitLanMulticast)
+	{
+		ruleset.emplace_back(std::make_unique<rules::PermitLanMulticast>());
+	}
+
+	if (settings.permitDhcpServer)
 	{
-		ruleset.emplace_back(std::make_unique<rules::PermitLan>());
-		ruleset.emplace_back(std::make_unique<rules::PermitLanService>());
 		ruleset.emplace_back(rules::PermitDhcpServer::WithExtent(rules::PermitDhcpServer::Extent::IPv4Only));
 	}
 }
@@ -121,7 +158,10 @@ bool FwContext::applyPolicyConnected
 	const WinFwRelay &relay,
 	const wchar_t *tunnelInterfaceAlias,
 	const wchar_t *v4DnsHost,
-	const wchar_t *v6DnsHost
+	const wchar_t *v6DnsHost,
+	const wchar_t *resolverHost,
+	const WinFwEndpoint *resolverUpstream,
+	bool permitIpv6
 )
 {
 	Ruleset ruleset;
@@ -136,19 +176,31 @@ bool FwContext::applyPolicyConnected
 	));
 
 	ruleset.emplace_back(std::make_unique<rules::PermitVpnTunnel>(
-		tunnelInterfaceAlias
+		tunnelInterfaceAlias,
+		permitIpv6
 	));
 
 	ruleset.emplace_back(std::make_unique<rules::PermitVpnTunnelService>(
-		tunnelInterfaceAlias
+		tunnelInterfaceAlias,
+		permitIpv6
 	));
 
 	ruleset.emplace_back(std::make_unique<rules::RestrictDns>(
 		tunnelInterfaceAlias,
-		wfp::IpAddress(v4DnsHost),
-		(v6DnsHost != nullptr) ? std::make_unique<wfp::IpAddress>(v6DnsHost) : nullptr
+		(v4DnsHost != nullptr) ? std::make_unique<wfp::IpAddress>(v4DnsHost) : nullptr,
+		(v6DnsHost != nullptr) ? std::make_unique<wfp::IpAddress>(v6DnsHost) : nullptr,
+		(resolverHost != nullptr) ? std::make_unique<wfp::IpAddress>(resolverHost) : nullptr
 	));
 
+	if (nullptr != resolverUpstream)
+	{
+		ruleset.emplace_back(std::make_unique<rules::RestrictResolverUpstream>(
+			tunnelInterfaceAlias,
+			wfp::IpAddress(resolverUpstream->ip),
+			resolverUpstream->port
+		));
+	}
+
 	return applyRuleset(ruleset);
 }
 
@@ -157,6 +209,25 @@ bool FwContext::applyPolicyBlocked(const WinFwSettings &settings)
 	return applyRuleset(composePolicyBlocked(settings));
 }
 
+bool FwContext::applyPolicyCaptivePortal
+(
+	const WinFwSettings &settings,
+	const WinFwIpNetwork *gatewayNetworks,
+	uint32_t numGatewayNetworks
+)
+{
+	auto ruleset = composePolicyBlocked(settings);
+
+	std::vector<wfp::IpNetwork> ipv4Networks;
+	std::vector<wfp::IpNetwork> ipv6Networks;
+
+	SplitNetworks(gatewayNetworks, numGatewayNetworks, ipv4Networks, ipv6Networks);
+
+	ruleset.emplace_back(std::make_unique<rules::PermitCaptivePortal>(ipv4Networks, ipv6Networks));
+
+	return applyRuleset(ruleset);
+}
+
 bool FwContext::reset()
 {
 	return m_sessionController->executeTransaction([this](SessionController &controller, wfp::FilterEngine &)
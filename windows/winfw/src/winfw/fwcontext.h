@@ -23,9 +23,18 @@ public:
 		const WinFwRelay &relay,
 		const wchar_t *tunnelInterfaceAlias,
 		const wchar_t *v4DnsHost,
-		const wchar_t *v6DnsHost
+		const wchar_t *v6DnsHost,
+		const wchar_t *resolverHost,
+		const WinFwEndpoint *resolverUpstream,
+		bool permitIpv6
 	);
 	bool applyPolicyBlocked(const WinFwSettings &settings);
+	bool applyPolicyCaptivePortal
+	(
+		const WinFwSettings &settings,
+		const WinFwIpNetwork *gatewayNetworks,
+		uint32_t numGatewayNetworks
+	);
 
 	bool reset();
 
@@ -35,6 +35,10 @@ DetailedWfpObjectRegistry MullvadGuids::BuildDetailedRegistry()
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLan_Outbound_Multicast_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLan_Outbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLan_Outbound_Multicast_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitCaptivePortal_Outbound_Tcp_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitCaptivePortal_Outbound_Udp_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitCaptivePortal_Outbound_Tcp_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitCaptivePortal_Outbound_Udp_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLanService_Inbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLanService_Inbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLoopback_Outbound_Ipv4()));
@@ -54,6 +58,8 @@ DetailedWfpObjectRegistry MullvadGuids::BuildDetailedRegistry()
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterRestrictDns_Outbound_Tunnel_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterRestrictDns_Outbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterRestrictDns_Outbound_Tunnel_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitLocalResolver_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, FilterRestrictResolverUpstream()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitVpnTunnelService_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitVpnTunnelService_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, FilterPermitNdp_Outbound_Router_Solicitation()));
@@ -233,6 +239,58 @@ const GUID &MullvadGuids::FilterPermitLan_Outbound_Multicast_Ipv6()
 }
 
 //static
+const GUID &MullvadGuids::FilterPermitCaptivePortal_Outbound_Tcp_Ipv4()
+{
+	static const GUID g =
+	{
+		0x3faefadb,
+		0xd57a,
+		0x4d0f,
+		{ 0xbd, 0xfe, 0x5c, 0x6, 0x45, 0x93, 0x39, 0x61 }
+	};
+
+	return g;
+}
+
+const GUID &MullvadGuids::FilterPermitCaptivePortal_Outbound_Udp_Ipv4()
+{
+	static const GUID g =
+	{
+		0x1cd5178c,
+		0x2fe2,
+		0x4ed7,
+		{ 0xb5, 0x19, 0xd8, 0x4, 0x46, 0x7, 0xf4, 0xd }
+	};
+
+	return g;
+}
+
+const GUID &MullvadGuids::FilterPermitCaptivePortal_Outbound_Tcp_Ipv6()
+{
+	static const GUID g =
+	{
+		0x19d9ceaf,
+		0x000f,
+		0x4086,
+		{ 0x8a, 0xf, 0x1d, 0x61, 0x86, 0xad, 0x4b, 0x6b }
+	};
+
+	return g;
+}
+
+const GUID &MullvadGuids::FilterPermitCaptivePortal_Outbound_Udp_Ipv6()
+{
+	static const GUID g =
+	{
+		0x90d5bfd4,
+		0xefb7,
+		0x44db,
+		{ 0xba, 0xaa, 0x47, 0x4c, 0x31, 0x7d, 0x3c, 0x85 }
+	};
+
+	return g;
+}
+
 const GUID &MullvadGuids::FilterPermitLanService_Inbound_Ipv4()
 {
 	static const GUID g =
@@ -498,6 +556,34 @@ const GUID &MullvadGuids::FilterRestrictDns_Outbound_Tunnel_Ipv6()
 	return g;
 }
 
+//static
+const GUID &MullvadGuids::FilterPermitLocalResolver_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x5dab5c0a,
+		0x5fad,
+		0x4669,
+		{ 0xac, 0x8b, 0xce, 0xfd, 0x80, 0xc7, 0x1a, 0x90 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::FilterRestrictResolverUpstream()
+{
+	static const GUID g =
+	{
+		0x8deee232,
+		0x3401,
+		0x4a47,
+		{ 0x90, 0xb5, 0x20, 0x8d, 0x2e, 0xbf, 0x98, 0xdf }
+	};
+
+	return g;
+}
+
 //static
 const GUID &MullvadGuids::FilterPermitVpnTunnelService_Ipv4()
 {
@@ -35,6 +35,11 @@ public:
 	static const GUID &FilterPermitLan_Outbound_Ipv6();
 	static const GUID &FilterPermitLan_Outbound_Multicast_Ipv6();
 
+	static const GUID &FilterPermitCaptivePortal_Outbound_Tcp_Ipv4();
+	static const GUID &FilterPermitCaptivePortal_Outbound_Udp_Ipv4();
+	static const GUID &FilterPermitCaptivePortal_Outbound_Tcp_Ipv6();
+	static const GUID &FilterPermitCaptivePortal_Outbound_Udp_Ipv6();
+
 	static const GUID &FilterPermitLanService_Inbound_Ipv4();
 	static const GUID &FilterPermitLanService_Inbound_Ipv6();
 
@@ -60,6 +65,9 @@ public:
 	static const GUID &FilterRestrictDns_Outbound_Tunnel_Ipv4();
 	static const GUID &FilterRestrictDns_Outbound_Ipv6();
 	static const GUID &FilterRestrictDns_Outbound_Tunnel_Ipv6();
+	static const GUID &FilterPermitLocalResolver_Outbound_Ipv4();
+
+	static const GUID &FilterRestrictResolverUpstream();
 
 	static const GUID &FilterPermitVpnTunnelService_Ipv4();
 	static const GUID &FilterPermitVpnTunnelService_Ipv6();
@@ -0,0 +1,152 @@
+#include "stdafx.h"
+#include "permitcaptiveportal.h"
+#include "winfw/mullvadguids.h"
+#include "libwfp/filterbuilder.h"
+#include "libwfp/conditionbuilder.h"
+#include "libwfp/conditions/conditionip.h"
+#include "libwfp/conditions/conditionport.h"
+#include "libwfp/conditions/conditionprotocol.h"
+
+using namespace wfp::conditions;
+
+namespace rules
+{
+
+namespace
+{
+
+const uint16_t DNS_PORT = 53;
+const uint16_t HTTP_PORT = 80;
+const uint16_t HTTPS_PORT = 443;
+
+//
+// Conditions on the same field are OR-ed, and conditions on different fields are AND-ed.
+// So the TCP filter permits any of the ports, to any of the networks.
+//
+
+bool AddTcpFilter(IObjectInstaller &objectInstaller, wfp::FilterBuilder &filterBuilder,
+	const GUID &layer, const std::vector<wfp::IpNetwork> &networks)
+{
+	wfp::ConditionBuilder conditionBuilder(layer);
+
+	for (const auto &network : networks)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
+
+	conditionBuilder.add_condition(ConditionProtocol::Tcp());
+	conditionBuilder.add_condition(ConditionPort::Remote(HTTP_PORT));
+	conditionBuilder.add_condition(ConditionPort::Remote(HTTPS_PORT));
+	conditionBuilder.add_condition(ConditionPort::Remote(DNS_PORT));
+
+	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
+}
+
+bool AddUdpFilter(IObjectInstaller &objectInstaller, wfp::FilterBuilder &filterBuilder,
+	const GUID &layer, const std::vector<wfp::IpNetwork> &networks)
+{
+	wfp::ConditionBuilder conditionBuilder(layer);
+
+	for (const auto &network : networks)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
+
+	conditionBuilder.add_condition(ConditionProtocol::Udp());
+	conditionBuilder.add_condition(ConditionPort::Remote(DNS_PORT));
+
+	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
+}
+
+} // anonymous namespace
+
+PermitCaptivePortal::PermitCaptivePortal(const std::vector<wfp::IpNetwork> &ipv4Networks, const std::vector<wfp::IpNetwork> &ipv6Networks)
+	: m_ipv4Networks(ipv4Networks)
+	, m_ipv6Networks(ipv6Networks)
+{
+}
+
+bool PermitCaptivePortal::apply(IObjectInstaller &objectInstaller)
+{
+	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
+}
+
+bool PermitCaptivePortal::applyIpv4(IObjectInstaller &objectInstaller) const
+{
+	if (m_ipv4Networks.empty())
+	{
+		return true;
+	}
+
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// #1 HTTP, HTTPS and DNS over TCP
+	//
+
+	filterBuilder
+		.key(MullvadGuids::FilterPermitCaptivePortal_Outbound_Tcp_Ipv4())
+		.name(L"Permit outbound captive portal traffic over TCP (IPv4)")
+		.description(L"This filter is part of a rule that permits logging in through a captive portal")
+		.provider(MullvadGuids::Provider())
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+		.sublayer(MullvadGuids::SublayerWhitelist())
+		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.permit();
+
+	if (false == AddTcpFilter(objectInstaller, filterBuilder, FWPM_LAYER_ALE_AUTH_CONNECT_V4, m_ipv4Networks))
+	{
+		return false;
+	}
+
+	//
+	// #2 DNS over UDP
+	//
+
+	filterBuilder
+		.key(MullvadGuids::FilterPermitCaptivePortal_Outbound_Udp_Ipv4())
+		.name(L"Permit outbound captive portal DNS traffic over UDP (IPv4)");
+
+	return AddUdpFilter(objectInstaller, filterBuilder, FWPM_LAYER_ALE_AUTH_CONNECT_V4, m_ipv4Networks);
+}
+
+bool PermitCaptivePortal::applyIpv6(IObjectInstaller &objectInstaller) const
+{
+	if (m_ipv6Networks.empty())
+	{
+		return true;
+	}
+
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// #1 HTTP, HTTPS and DNS over TCP
+	//
+
+	filterBuilder
+		.key(MullvadGuids::FilterPermitCaptivePortal_Outbound_Tcp_Ipv6())
+		.name(L"Permit outbound captive portal traffic over TCP (IPv6)")
+		.description(L"This filter is part of a rule that permits logging in through a captive portal")
+		.provider(MullvadGuids::Provider())
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6)
+		.sublayer(MullvadGuids::SublayerWhitelist())
+		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.permit();
+
+	if (false == AddTcpFilter(objectInstaller, filterBuilder, FWPM_LAYER_ALE_AUTH_CONNECT_V6, m_ipv6Networks))
+	{
+		return false;
+	}
+
+	//
+	// #2 DNS over UDP
+	//
+
+	filterBuilder
+		.key(MullvadGuids::FilterPermitCaptivePortal_Outbound_Udp_Ipv6())
+		.name(L"Permit outbound captive portal DNS traffic over UDP (IPv6)");
+
+	return AddUdpFilter(objectInstaller, filterBuilder, FWPM_LAYER_ALE_AUTH_CONNECT_V6, m_ipv6Networks);
+}
+
+}
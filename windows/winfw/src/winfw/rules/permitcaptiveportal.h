@@ -0,0 +1,32 @@
+#pragma once
+
+#include "ifirewallrule.h"
+#include "libwfp/ipnetwork.h"
+#include <vector>
+
+namespace rules
+{
+
+//
+// Permits what is needed to log in through a captive portal on the gateway networks:
+// HTTP and HTTPS for the login page, and DNS to resolve its address.
+//
+class PermitCaptivePortal : public IFirewallRule
+{
+public:
+
+	PermitCaptivePortal(const std::vector<wfp::IpNetwork> &ipv4Networks, const std::vector<wfp::IpNetwork> &ipv6Networks);
+	~PermitCaptivePortal() = default;
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	bool applyIpv4(IObjectInstaller &objectInstaller) const;
+	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	const std::vector<wfp::IpNetwork> m_ipv4Networks;
+	const std::vector<wfp::IpNetwork> m_ipv6Networks;
+};
+
+}
\ No newline at end of file
@@ -3,8 +3,6 @@
 #include "winfw/mullvadguids.h"
 #include "libwfp/filterbuilder.h"
 #include "libwfp/conditionbuilder.h"
-#include "libwfp/ipaddress.h"
-#include "libwfp/ipnetwork.h"
 #include "libwfp/conditions/conditionip.h"
 
 using namespace wfp::conditions;
@@ -12,6 +10,12 @@ using namespace wfp::conditions;
 namespace rules
 {
 
+PermitLan::PermitLan(const std::vector<wfp::IpNetwork> &ipv4Networks, const std::vector<wfp::IpNetwork> &ipv6Networks)
+	: m_ipv4Networks(ipv4Networks)
+	, m_ipv6Networks(ipv6Networks)
+{
+}
+
 bool PermitLan::apply(IObjectInstaller &objectInstaller)
 {
 	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
@@ -19,6 +23,11 @@ bool PermitLan::apply(IObjectInstaller &objectInstaller)
 
 bool PermitLan::applyIpv4(IObjectInstaller &objectInstaller) const
 {
+	if (m_ipv4Networks.empty())
+	{
+		return true;
+	}
+
 	wfp::FilterBuilder filterBuilder;
 
 	//
@@ -37,40 +46,21 @@ bool PermitLan::applyIpv4(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16)));
-
-	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	for (const auto &network : m_ipv4Networks)
 	{
-		return false;
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
 	}
 
-	//
-	// #2 LAN to multicast
-	//
-
-	filterBuilder
-		.key(MullvadGuids::FilterPermitLan_Outbound_Multicast_Ipv4())
-		.name(L"Permit outbound LAN multicast traffic (IPv4)");
-
-	conditionBuilder.reset();
-
-	// Local subnet multicast.
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 224, 0, 0, 0 }), 24)));
-
-	// Simple Service Discovery Protocol (SSDP) address.
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 239, 255, 255, 250 }), 32)));
-
-	// mDNS Service Discovery address.
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 239, 255, 255, 251 }), 32)));
-
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
 
 bool PermitLan::applyIpv6(IObjectInstaller &objectInstaller) const
 {
+	if (m_ipv6Networks.empty())
+	{
+		return true;
+	}
+
 	wfp::FilterBuilder filterBuilder;
 
 	//
@@ -89,32 +79,12 @@ bool PermitLan::applyIpv6(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
 
-	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6({ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 10);
-
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocal));
-
-	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	for (const auto &network : m_ipv6Networks)
 	{
-		return false;
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
 	}
 
-	//
-	// #2 LAN to multicast
-	//
-
-	filterBuilder
-		.key(MullvadGuids::FilterPermitLan_Outbound_Multicast_Ipv6())
-		.name(L"Permit outbound LAN multicast traffic (IPv6)");
-
-	conditionBuilder.reset();
-
-	const wfp::IpNetwork linkLocalMulticast(wfp::IpAddress::Literal6({ 0xFF02, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 16);
-	const wfp::IpNetwork siteLocalMulticast(wfp::IpAddress::Literal6({ 0xFF05, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 16);
-
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocalMulticast));
-	conditionBuilder.add_condition(ConditionIp::Remote(siteLocalMulticast));
-
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
 
-}
+}
\ No newline at end of file
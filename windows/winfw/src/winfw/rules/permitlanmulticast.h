@@ -0,0 +1,23 @@
+#pragma once
+
+#include "ifirewallrule.h"
+
+namespace rules
+{
+
+class PermitLanMulticast : public IFirewallRule
+{
+public:
+
+	PermitLanMulticast() = default;
+	~PermitLanMulticast() = default;
+	
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	bool applyIpv4(IObjectInstaller &objectInstaller) const;
+	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+};
+
+}
\ No newline at end of file
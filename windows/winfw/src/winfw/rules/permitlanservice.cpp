@@ -3,8 +3,6 @@
 #include "winfw/mullvadguids.h"
 #include "libwfp/filterbuilder.h"
 #include "libwfp/conditionbuilder.h"
-#include "libwfp/ipaddress.h"
-#include "libwfp/ipnetwork.h"
 #include "libwfp/conditions/conditionip.h"
 
 using namespace wfp::conditions;
@@ -12,6 +10,12 @@ using namespace wfp::conditions;
 namespace rules
 {
 
+PermitLanService::PermitLanService(const std::vector<wfp::IpNetwork> &ipv4Networks, const std::vector<wfp::IpNetwork> &ipv6Networks)
+	: m_ipv4Networks(ipv4Networks)
+	, m_ipv6Networks(ipv6Networks)
+{
+}
+
 bool PermitLanService::apply(IObjectInstaller &objectInstaller)
 {
 	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
@@ -19,6 +23,11 @@ bool PermitLanService::apply(IObjectInstaller &objectInstaller)
 
 bool PermitLanService::applyIpv4(IObjectInstaller &objectInstaller) const
 {
+	if (m_ipv4Networks.empty())
+	{
+		return true;
+	}
+
 	wfp::FilterBuilder filterBuilder;
 
 	//
@@ -37,16 +46,21 @@ bool PermitLanService::applyIpv4(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16)));
+	for (const auto &network : m_ipv4Networks)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
 
 bool PermitLanService::applyIpv6(IObjectInstaller &objectInstaller) const
 {
+	if (m_ipv6Networks.empty())
+	{
+		return true;
+	}
+
 	wfp::FilterBuilder filterBuilder;
 
 	//
@@ -65,9 +79,10 @@ bool PermitLanService::applyIpv6(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
-	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6{ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }, 10);
-
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocal));
+	for (const auto &network : m_ipv6Networks)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
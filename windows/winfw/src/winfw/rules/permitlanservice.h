@@ -1,6 +1,8 @@
 #pragma once
 
 #include "ifirewallrule.h"
+#include "libwfp/ipnetwork.h"
+#include <vector>
 
 namespace rules
 {
@@ -9,7 +11,7 @@ class PermitLanService : public IFirewallRule
 {
 public:
 
-	PermitLanService() = default;
+	PermitLanService(const std::vector<wfp::IpNetwork> &ipv4Networks, const std::vector<wfp::IpNetwork> &ipv6Networks);
 	~PermitLanService() = default;
 	
 	bool apply(IObjectInstaller &objectInstaller) override;
@@ -18,6 +20,9 @@ private:
 
 	bool applyIpv4(IObjectInstaller &objectInstaller) const;
 	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	const std::vector<wfp::IpNetwork> m_ipv4Networks;
+	const std::vector<wfp::IpNetwork> m_ipv6Networks;
 };
 
 }
@@ -10,8 +10,9 @@ using namespace wfp::conditions;
 namespace rules
 {
 
-PermitVpnTunnel::PermitVpnTunnel(const std::wstring &tunnelInterfaceAlias)
+PermitVpnTunnel::PermitVpnTunnel(const std::wstring &tunnelInterfaceAlias, bool permitIpv6)
 	: m_tunnelInterfaceAlias(tunnelInterfaceAlias)
+	, m_permitIpv6(permitIpv6)
 {
 }
 
@@ -44,6 +45,11 @@ bool PermitVpnTunnel::apply(IObjectInstaller &objectInstaller)
 		}
 	}
 
+	if (false == m_permitIpv6)
+	{
+		return true;
+	}
+
 	//
 	// #2 permit locally-initiated traffic on tunnel interface, ipv6
 	//
@@ -10,8 +10,9 @@ using namespace wfp::conditions;
 namespace rules
 {
 
-PermitVpnTunnelService::PermitVpnTunnelService(const std::wstring &tunnelInterfaceAlias)
+PermitVpnTunnelService::PermitVpnTunnelService(const std::wstring &tunnelInterfaceAlias, bool permitIpv6)
 	: m_tunnelInterfaceAlias(tunnelInterfaceAlias)
+	, m_permitIpv6(permitIpv6)
 {
 }
 
@@ -42,6 +43,11 @@ bool PermitVpnTunnelService::apply(IObjectInstaller &objectInstaller)
 		return false;
 	}
 
+	if (false == m_permitIpv6)
+	{
+		return true;
+	}
+
 	//
 	// #2 incoming request on IPv6
 	//
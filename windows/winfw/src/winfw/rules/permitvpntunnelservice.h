@@ -10,13 +10,17 @@ class PermitVpnTunnelService : public IFirewallRule
 {
 public:
 
-	PermitVpnTunnelService(const std::wstring &tunnelInterfaceAlias);
+	//
+	// IPv6 is only permitted on the tunnel interface if permitIpv6 is set.
+	//
+	PermitVpnTunnelService(const std::wstring &tunnelInterfaceAlias, bool permitIpv6);
 
 	bool apply(IObjectInstaller &objectInstaller) override;
 
 private:
 
 	const std::wstring m_tunnelInterfaceAlias;
+	const bool m_permitIpv6;
 };
 
 }
@@ -5,6 +5,7 @@
 #include "libwfp/conditionbuilder.h"
 #include "libwfp/conditions/conditioninterface.h"
 #include "libwfp/conditions/conditionip.h"
+#include "libwfp/conditions/conditionloopback.h"
 #include "libwfp/conditions/conditionport.h"
 
 using namespace wfp::conditions;
@@ -12,10 +13,11 @@ using namespace wfp::conditions;
 namespace rules
 {
 
-RestrictDns::RestrictDns(const std::wstring &tunnelInterfaceAlias, const wfp::IpAddress v4DnsHost, std::unique_ptr<wfp::IpAddress> v6DnsHost)
+RestrictDns::RestrictDns(const std::wstring &tunnelInterfaceAlias, std::unique_ptr<wfp::IpAddress> v4DnsHost, std::unique_ptr<wfp::IpAddress> v6DnsHost, std::unique_ptr<wfp::IpAddress> resolverHost)
 	: m_tunnelInterfaceAlias(tunnelInterfaceAlias)
-	, m_v4DnsHost(v4DnsHost)
+	, m_v4DnsHost(std::move(v4DnsHost))
 	, m_v6DnsHost(std::move(v6DnsHost))
+	, m_resolverHost(std::move(resolverHost))
 
 {
 }
@@ -30,6 +32,11 @@ bool RestrictDns::apply(IObjectInstaller &objectInstaller)
 	// BlockAll
 	// PermitVpnTunnel
 	//
+	// DNS hosts that are not specified are not permitted at all inside the tunnel.
+	//
+	// The block filters are weighted below the filter that permits the local DNS
+	// resolver on the loopback interface, if there is one.
+	//
 	// TODO: Have each rule specify requirements?
 	//
 
@@ -40,7 +47,7 @@ bool RestrictDns::apply(IObjectInstaller &objectInstaller)
 		.provider(MullvadGuids::Provider())
 		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
 		.sublayer(MullvadGuids::SublayerBlacklist())
-		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.weight(wfp::FilterBuilder::WeightClass::Medium)
 		.block();
 
 	{
@@ -64,7 +71,11 @@ bool RestrictDns::apply(IObjectInstaller &objectInstaller)
 		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
 
 		conditionBuilder.add_condition(ConditionPort::Remote(53));
-		conditionBuilder.add_condition(ConditionIp::Remote(m_v4DnsHost, CompareNeq()));
+
+		if (m_v4DnsHost != nullptr)
+		{
+			conditionBuilder.add_condition(ConditionIp::Remote(*m_v4DnsHost, CompareNeq()));
+		}
 
 		if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
 		{
@@ -108,6 +119,35 @@ bool RestrictDns::apply(IObjectInstaller &objectInstaller)
 			conditionBuilder.add_condition(ConditionIp::Remote(*m_v6DnsHost, CompareNeq()));
 		}
 
+		if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+		{
+			return false;
+		}
+	}
+
+	if (m_resolverHost == nullptr)
+	{
+		return true;
+	}
+
+	//
+	// Permit DNS requests to the local DNS resolver.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::FilterPermitLocalResolver_Outbound_Ipv4())
+		.name(L"Permit DNS requests to the local DNS resolver on loopback (IPv4)")
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.permit();
+
+	{
+		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+		conditionBuilder.add_condition(std::make_unique<ConditionLoopback>());
+		conditionBuilder.add_condition(ConditionIp::Remote(*m_resolverHost));
+		conditionBuilder.add_condition(ConditionPort::Remote(53));
+
 		return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 	}
 }
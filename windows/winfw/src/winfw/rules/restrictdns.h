@@ -2,6 +2,7 @@
 
 #include "ifirewallrule.h"
 #include "libwfp/ipaddress.h"
+#include <memory>
 #include <string>
 
 namespace rules
@@ -11,15 +12,16 @@ class RestrictDns : public IFirewallRule
 {
 public:
 
-	RestrictDns(const std::wstring &tunnelInterfaceAlias, const wfp::IpAddress v4DnsHost, std::unique_ptr<wfp::IpAddress> v6DnsHost);
+	RestrictDns(const std::wstring &tunnelInterfaceAlias, std::unique_ptr<wfp::IpAddress> v4DnsHost, std::unique_ptr<wfp::IpAddress> v6DnsHost, std::unique_ptr<wfp::IpAddress> resolverHost);
 
 	bool apply(IObjectInstaller &objectInstaller) override;
 
 private:
 
 	const std::wstring m_tunnelInterfaceAlias;
-	const wfp::IpAddress m_v4DnsHost;
+	const std::unique_ptr<wfp::IpAddress> m_v4DnsHost;
 	const std::unique_ptr<wfp::IpAddress> m_v6DnsHost;
+	const std::unique_ptr<wfp::IpAddress> m_resolverHost;
 
 };
 
@@ -0,0 +1,69 @@
+#include "stdafx.h"
+#include "restrictresolverupstream.h"
+#include "winfw/mullvadguids.h"
+#include "libwfp/filterbuilder.h"
+#include "libwfp/conditionbuilder.h"
+#include "libwfp/conditions/conditioninterface.h"
+#include "libwfp/conditions/conditionip.h"
+#include "libwfp/conditions/conditionport.h"
+
+using namespace wfp::conditions;
+
+namespace rules
+{
+
+namespace
+{
+
+const GUID &LayerFromIp(const wfp::IpAddress &ip)
+{
+	switch (ip.type())
+	{
+		case wfp::IpAddress::Type::Ipv4: return FWPM_LAYER_ALE_AUTH_CONNECT_V4;
+		case wfp::IpAddress::Type::Ipv6: return FWPM_LAYER_ALE_AUTH_CONNECT_V6;
+		default:
+		{
+			throw std::logic_error("Missing case handler in switch clause");
+		}
+	};
+}
+
+} // anonymous namespace
+
+RestrictResolverUpstream::RestrictResolverUpstream(const std::wstring &tunnelInterfaceAlias, const wfp::IpAddress &upstream, uint16_t upstreamPort)
+	: m_tunnelInterfaceAlias(tunnelInterfaceAlias)
+	, m_upstream(upstream)
+	, m_upstreamPort(upstreamPort)
+{
+}
+
+bool RestrictResolverUpstream::apply(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// The local DNS resolver must only reach its upstream server inside the tunnel.
+	// This is a blacklist filter, so that it also applies when the server is on
+	// a LAN network that is otherwise permitted.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::FilterRestrictResolverUpstream())
+		.name(L"Block DNS resolver upstream outside the VPN tunnel")
+		.description(L"This filter is part of a rule that keeps the local DNS resolver inside the VPN tunnel")
+		.provider(MullvadGuids::Provider())
+		.layer(LayerFromIp(m_upstream))
+		.sublayer(MullvadGuids::SublayerBlacklist())
+		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.block();
+
+	wfp::ConditionBuilder conditionBuilder(LayerFromIp(m_upstream));
+
+	conditionBuilder.add_condition(ConditionIp::Remote(m_upstream));
+	conditionBuilder.add_condition(ConditionPort::Remote(m_upstreamPort));
+	conditionBuilder.add_condition(ConditionInterface::Alias(m_tunnelInterfaceAlias, CompareNeq()));
+
+	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
+}
+
+}
@@ -0,0 +1,26 @@
+#pragma once
+
+#include "ifirewallrule.h"
+#include "libwfp/ipaddress.h"
+#include <string>
+
+namespace rules
+{
+
+class RestrictResolverUpstream : public IFirewallRule
+{
+public:
+
+	RestrictResolverUpstream(const std::wstring &tunnelInterfaceAlias, const wfp::IpAddress &upstream, uint16_t upstreamPort);
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	const std::wstring m_tunnelInterfaceAlias;
+	const wfp::IpAddress m_upstream;
+	const uint16_t m_upstreamPort;
+
+};
+
+}
@@ -165,7 +165,10 @@ WinFw_ApplyPolicyConnected(
 	const WinFwRelay &relay,
 	const wchar_t *tunnelInterfaceAlias,
 	const wchar_t *v4DnsHost,
-	const wchar_t *v6DnsHost
+	const wchar_t *v6DnsHost,
+	const wchar_t *resolverHost,
+	const WinFwEndpoint *resolverUpstream,
+	bool permitIpv6
 )
 {
 	if (nullptr == g_fwContext)
@@ -175,7 +178,7 @@ WinFw_ApplyPolicyConnected(
 
 	try
 	{
-		return g_fwContext->applyPolicyConnected(settings, relay, tunnelInterfaceAlias, v4DnsHost, v6DnsHost);
+		return g_fwContext->applyPolicyConnected(settings, relay, tunnelInterfaceAlias, v4DnsHost, v6DnsHost, resolverHost, resolverUpstream, permitIpv6);
 	}
 	catch (std::exception &err)
 	{
@@ -223,6 +226,39 @@ WinFw_ApplyPolicyBlocked(
 	}
 }
 
+WINFW_LINKAGE
+bool
+WINFW_API
+WinFw_ApplyPolicyCaptivePortal(
+	const WinFwSettings &settings,
+	const WinFwIpNetwork *gatewayNetworks,
+	uint32_t numGatewayNetworks
+)
+{
+	if (nullptr == g_fwContext)
+	{
+		return false;
+	}
+
+	try
+	{
+		return g_fwContext->applyPolicyCaptivePortal(settings, gatewayNetworks, numGatewayNetworks);
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_errorSink)
+		{
+			g_errorSink(err.what(), g_errorContext);
+		}
+
+		return false;
+	}
+	catch (...)
+	{
+		return false;
+	}
+}
+
 WINFW_LINKAGE
 bool
 WINFW_API
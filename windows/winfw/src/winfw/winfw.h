@@ -19,13 +19,28 @@
 
 #pragma pack(push, 1)
 
+typedef struct tag_WinFwIpNetwork
+{
+	// String representation of the network address.
+	const wchar_t *address;
+	uint8_t prefix;
+}
+WinFwIpNetwork;
+
 typedef struct tag_WinFwSettings
 {
 	// Permit outbound DHCP requests and inbound DHCP responses on all interfaces.
 	bool permitDhcp;
 
-	// Permit all traffic to and from private address ranges.
-	bool permitLan;
+	// Permit all traffic to and from these local networks.
+	const WinFwIpNetwork *lanNetworks;
+	uint32_t numLanNetworks;
+
+	// Permit outbound traffic to local multicast addresses.
+	bool permitLanMulticast;
+
+	// Permit inbound DHCP requests and outbound DHCP responses on all interfaces.
+	bool permitDhcpServer;
 }
 WinFwSettings;
 
@@ -43,6 +58,13 @@ typedef struct tag_WinFwRelay
 }
 WinFwRelay;
 
+typedef struct tag_WinFwEndpoint
+{
+	const wchar_t *ip;
+	uint16_t port;
+}
+WinFwEndpoint;
+
 #pragma pack(pop)
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -134,7 +156,19 @@ WinFw_ApplyPolicyConnecting(
 // tunnelInterfaceAlias:
 //   Friendly name of VPN tunnel interface
 // v4DnsHost/v6DnsHost:
-//   String encoded IP address of DNS to use inside tunnel
+//   String encoded IP address of DNS to use inside tunnel.
+//   Pass nullptr to block all DNS requests of that family inside the tunnel.
+// resolverHost:
+//   String encoded IPv4 address that the local DNS resolver listens on, on port 53
+//   of the loopback interface. DNS requests to it are permitted.
+//   Pass nullptr if the local DNS resolver is not running.
+// resolverUpstream:
+//   Server that the local DNS resolver forwards queries to. Connections to it
+//   are blocked on all interfaces but the tunnel interface.
+//   Pass nullptr if the local DNS resolver is not running.
+// permitIpv6:
+//   Whether IPv6 is permitted inside the tunnel. If not, only IPv4 is permitted
+//   on the tunnel interface.
 //
 extern "C"
 WINFW_LINKAGE
@@ -145,7 +179,10 @@ WinFw_ApplyPolicyConnected(
 	const WinFwRelay &relay,
 	const wchar_t *tunnelInterfaceAlias,
 	const wchar_t *v4DnsHost,
-	const wchar_t *v6DnsHost
+	const wchar_t *v6DnsHost,
+	const wchar_t *resolverHost,
+	const WinFwEndpoint *resolverUpstream,
+	bool permitIpv6
 );
 
 //
@@ -162,6 +199,26 @@ WinFw_ApplyPolicyBlocked(
 	const WinFwSettings &settings
 );
 
+//
+// ApplyPolicyCaptivePortal:
+//
+// Apply restrictions in the firewall that block all traffic, except:
+// - What is specified by settings
+// - HTTP, HTTPS and DNS to the gateway networks, so a captive portal can be logged in to
+//
+// gatewayNetworks:
+//   Array of networks that the default gateways are on.
+//
+extern "C"
+WINFW_LINKAGE
+bool
+WINFW_API
+WinFw_ApplyPolicyCaptivePortal(
+	const WinFwSettings &settings,
+	const WinFwIpNetwork *gatewayNetworks,
+	uint32_t numGatewayNetworks
+);
+
 //
 // Reset:
 //